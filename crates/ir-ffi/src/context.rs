@@ -1,11 +1,18 @@
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use ir_tensor::CpuBackend;
+use ir_tensor::{ComputeBackend, CpuBackend, FallbackBackend, TensorError};
 use ir_model::llama::LlamaModel;
 use ir_model::tokenizer::bpe::BpeTokenizer;
 
+use crate::types::IRBackendType;
+
 /// Opaque context handle that owns the backend, model, and tokenizer.
 pub struct IRContext {
-    pub backend: Arc<CpuBackend>,
+    /// FFI entry points catch panics and report them as an internal error.
+    /// The backend trait object does not carry the unwind-safety markers,
+    /// but backends only take `&self` and keep any mutable state behind
+    /// locks or atomics, so one observed after a panic is still consistent.
+    pub backend: AssertUnwindSafe<Arc<dyn ComputeBackend>>,
     pub model: Option<LlamaModel>,
     pub tokenizer: Option<BpeTokenizer>,
 }
@@ -17,9 +24,58 @@ impl Default for IRContext {
 }
 
 impl IRContext {
+    /// Create a context whose CPU backend uses one worker per core.
     pub fn new() -> Self {
+        Self::with_backend(CpuBackend::new())
+    }
+
+    /// Create a context whose CPU backend uses `n_threads` workers.
+    ///
+    /// `0` selects one worker per available core.
+    ///
+    /// # Errors
+    /// Returns an error if the worker threads cannot be spawned.
+    pub fn with_threads(n_threads: usize) -> ir_tensor::Result<Self> {
+        if n_threads == 0 {
+            Ok(Self::new())
+        } else {
+            Ok(Self::with_backend(CpuBackend::with_threads(n_threads)?))
+        }
+    }
+
+    /// Create a context that runs each op on the first of `backends` that
+    /// supports it, and on a CPU backend (one worker per core) when none
+    /// does. `backends` may implement only some ops; see
+    /// [`ComputeBackend::capabilities`].
+    pub fn with_backends(mut backends: Vec<Box<dyn ComputeBackend>>) -> Self {
+        backends.push(Box::new(CpuBackend::new()));
+        Self::with_backend(FallbackBackend::new(backends))
+    }
+
+    /// Create a context that composes the backends selected by `types`, in
+    /// order, as [`with_backends`](Self::with_backends) does.
+    ///
+    /// # Errors
+    /// Returns an error if a selected backend is not available in this
+    /// build or cannot be started.
+    pub fn with_backend_types(types: &[IRBackendType]) -> ir_tensor::Result<Self> {
+        let backends = types
+            .iter()
+            .map(|ty| -> ir_tensor::Result<Box<dyn ComputeBackend>> {
+                match ty {
+                    IRBackendType::Cpu => Ok(Box::new(CpuBackend::new())),
+                    IRBackendType::Metal => Err(TensorError::Other(
+                        "metal backend is not available in this build".to_string(),
+                    )),
+                }
+            })
+            .collect::<ir_tensor::Result<_>>()?;
+        Ok(Self::with_backends(backends))
+    }
+
+    fn with_backend(backend: impl ComputeBackend + 'static) -> Self {
         Self {
-            backend: Arc::new(CpuBackend::new()),
+            backend: AssertUnwindSafe(Arc::new(backend)),
             model: None,
             tokenizer: None,
         }
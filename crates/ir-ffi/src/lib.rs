@@ -11,7 +11,7 @@ use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 use std::path::Path;
 
-// Import the ModelArchitecture trait so its methods (forward, reset_cache) are available.
+// Import the ModelArchitecture trait so its methods (forward_into, reset_cache) are available.
 use ir_model::ModelArchitecture;
 
 /// Execute a closure that returns an `IRStatus`, catching any panics
@@ -53,6 +53,87 @@ pub unsafe extern "C" fn ir_context_create(
     })
 }
 
+/// Create a new inference context with an explicit CPU worker count.
+///
+/// Behaves like `ir_context_create`, but the CPU backend runs on
+/// `n_threads` workers. Passing 0 uses one worker per available core.
+/// Returns `IRStatus::ErrorInternal` if the workers cannot be spawned.
+///
+/// # Safety
+///
+/// `ctx_out` must be a valid, non-null pointer to a `*mut IRContext`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ir_context_create_with_threads(
+    _backend: IRBackendType,
+    n_threads: u32,
+    ctx_out: *mut *mut IRContext,
+) -> IRStatus {
+    catch_panic(|| {
+        if ctx_out.is_null() {
+            set_last_error("ctx_out is null".to_string());
+            return IRStatus::ErrorInvalidArgument;
+        }
+        let ctx = match IRContext::with_threads(n_threads as usize) {
+            Ok(ctx) => Box::new(ctx),
+            Err(e) => {
+                set_last_error(e.to_string());
+                return IRStatus::ErrorInternal;
+            }
+        };
+        unsafe {
+            *ctx_out = Box::into_raw(ctx);
+        }
+        IRStatus::Ok
+    })
+}
+
+/// Create a new inference context that composes several backends.
+///
+/// Each op runs on the first of the `n_backends` backends in `backends`
+/// that supports it, and on a CPU backend (one worker per core) when none
+/// does, so a backend that implements only some ops can be listed ahead of
+/// the CPU. Passing no backends behaves like `ir_context_create`.
+/// Returns `IRStatus::ErrorInvalidArgument` if a listed backend is not
+/// available in this build.
+///
+/// # Safety
+///
+/// `backends` must point to `n_backends` valid `IRBackendType` values, or
+/// may be null when `n_backends` is 0. `ctx_out` must be a valid, non-null
+/// pointer to a `*mut IRContext`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ir_context_create_with_backends(
+    backends: *const IRBackendType,
+    n_backends: usize,
+    ctx_out: *mut *mut IRContext,
+) -> IRStatus {
+    catch_panic(|| {
+        if ctx_out.is_null() {
+            set_last_error("ctx_out is null".to_string());
+            return IRStatus::ErrorInvalidArgument;
+        }
+        let types = if n_backends == 0 {
+            &[][..]
+        } else if backends.is_null() {
+            set_last_error("backends is null".to_string());
+            return IRStatus::ErrorInvalidArgument;
+        } else {
+            unsafe { std::slice::from_raw_parts(backends, n_backends) }
+        };
+        let ctx = match IRContext::with_backend_types(types) {
+            Ok(ctx) => Box::new(ctx),
+            Err(e) => {
+                set_last_error(e.to_string());
+                return IRStatus::ErrorInvalidArgument;
+            }
+        };
+        unsafe {
+            *ctx_out = Box::into_raw(ctx);
+        }
+        IRStatus::Ok
+    })
+}
+
 /// Destroy a context previously created by `ir_context_create`.
 ///
 /// Passing a null pointer is a no-op and returns `IRStatus::Ok`.
@@ -194,14 +275,12 @@ pub unsafe extern "C" fn ir_generate(
         let backend = ctx.backend.as_ref();
 
         // Prefill: process all prompt tokens at once, starting at position 0.
-        let logits = match model.forward(&tokens, 0, backend) {
-            Ok(l) => l,
-            Err(e) => {
-                set_last_error(format!("forward pass failed: {}", e));
-                return IRStatus::ErrorGenerate;
-            }
-        };
-        let mut cur_pos = tokens.len();
+        // Logits buffer reused for every step.
+        let mut logits = Vec::new();
+        if let Err(e) = model.forward_into(&tokens, 0, backend, &mut logits) {
+            set_last_error(format!("forward pass failed: {}", e));
+            return IRStatus::ErrorGenerate;
+        }
 
         let mut next_token = chain.sample(&logits);
         if next_token == tokenizer.vocab.eos_id {
@@ -220,15 +299,13 @@ pub unsafe extern "C" fn ir_generate(
         generated.push(next_token);
 
         // Decode: generate one token at a time.
-        for _ in 1..params.max_tokens {
-            let logits = match model.forward(&[next_token], cur_pos, backend) {
-                Ok(l) => l,
-                Err(e) => {
-                    set_last_error(format!("forward pass failed: {}", e));
-                    return IRStatus::ErrorGenerate;
-                }
-            };
-            cur_pos += 1;
+        let decode_start = tokens.len();
+        let decode_end = decode_start + params.max_tokens.saturating_sub(1) as usize;
+        for cur_pos in decode_start..decode_end {
+            if let Err(e) = model.forward_into(&[next_token], cur_pos, backend, &mut logits) {
+                set_last_error(format!("forward pass failed: {}", e));
+                return IRStatus::ErrorGenerate;
+            }
 
             next_token = chain.sample(&logits);
 
@@ -307,14 +384,12 @@ pub unsafe extern "C" fn ir_generate_streaming(
         let backend = ctx.backend.as_ref();
 
         // Prefill: process all prompt tokens at once.
-        let logits = match model.forward(&tokens, 0, backend) {
-            Ok(l) => l,
-            Err(e) => {
-                set_last_error(format!("forward pass failed: {}", e));
-                return IRStatus::ErrorGenerate;
-            }
-        };
-        let mut cur_pos = tokens.len();
+        // Logits buffer reused for every step.
+        let mut logits = Vec::new();
+        if let Err(e) = model.forward_into(&tokens, 0, backend, &mut logits) {
+            set_last_error(format!("forward pass failed: {}", e));
+            return IRStatus::ErrorGenerate;
+        }
 
         let mut next_token = chain.sample(&logits);
         if next_token == tokenizer.vocab.eos_id {
@@ -327,15 +402,13 @@ pub unsafe extern "C" fn ir_generate_streaming(
         }
 
         // Decode: generate one token at a time.
-        for _ in 1..params.max_tokens {
-            let logits = match model.forward(&[next_token], cur_pos, backend) {
-                Ok(l) => l,
-                Err(e) => {
-                    set_last_error(format!("forward pass failed: {}", e));
-                    return IRStatus::ErrorGenerate;
-                }
-            };
-            cur_pos += 1;
+        let decode_start = tokens.len();
+        let decode_end = decode_start + params.max_tokens.saturating_sub(1) as usize;
+        for cur_pos in decode_start..decode_end {
+            if let Err(e) = model.forward_into(&[next_token], cur_pos, backend, &mut logits) {
+                set_last_error(format!("forward pass failed: {}", e));
+                return IRStatus::ErrorGenerate;
+            }
 
             next_token = chain.sample(&logits);
             if next_token == tokenizer.vocab.eos_id {
@@ -4,21 +4,51 @@ use ir_tensor::ComputeBackend;
 ///
 /// Implementations hold model weights and KV caches, and can process tokens
 /// through the full transformer forward pass to produce next-token logits.
+///
+/// [`forward`](Self::forward) and [`forward_into`](Self::forward_into) are
+/// each provided in terms of the other, so an implementation must override
+/// at least one of them. Overriding `forward_into` lets callers that reuse
+/// their logits vector avoid an allocation per step.
 pub trait ModelArchitecture: Send + Sync {
     /// Run the forward pass for a batch of input tokens starting at a given
-    /// position in the sequence.
+    /// position in the sequence, writing the logits for the last token into
+    /// `logits`.
     ///
-    /// Returns a vector of logits over the vocabulary for the last token.
+    /// `logits` is resized to the vocabulary size; reusing the same vector
+    /// across calls avoids reallocating it every step.
     ///
     /// - `tokens`: the input token IDs to process.
     /// - `pos`: the starting position in the sequence (for KV cache and RoPE).
     /// - `backend`: the compute backend to use for tensor operations.
+    ///
+    /// The default calls [`ModelArchitecture::forward`] and moves its result
+    /// into `logits`.
+    fn forward_into(
+        &mut self,
+        tokens: &[u32],
+        pos: usize,
+        backend: &dyn ComputeBackend,
+        logits: &mut Vec<f32>,
+    ) -> crate::Result<()> {
+        *logits = self.forward(tokens, pos, backend)?;
+        Ok(())
+    }
+
+    /// Run the forward pass for a batch of input tokens starting at a given
+    /// position in the sequence.
+    ///
+    /// Returns a vector of logits over the vocabulary for the last token.
+    /// See [`ModelArchitecture::forward_into`].
     fn forward(
         &mut self,
         tokens: &[u32],
         pos: usize,
         backend: &dyn ComputeBackend,
-    ) -> crate::Result<Vec<f32>>;
+    ) -> crate::Result<Vec<f32>> {
+        let mut logits = Vec::new();
+        self.forward_into(tokens, pos, backend, &mut logits)?;
+        Ok(logits)
+    }
 
     /// Returns the vocabulary size (number of output logits).
     fn vocab_size(&self) -> usize;
@@ -26,3 +56,40 @@ pub trait ModelArchitecture: Send + Sync {
     /// Reset all KV caches, clearing any stored context.
     fn reset_cache(&mut self);
 }
+
+#[cfg(test)]
+mod tests {
+    use ir_tensor::CpuBackend;
+
+    use super::*;
+
+    /// A model written against the trait before `forward_into` existed.
+    struct ForwardOnly;
+
+    impl ModelArchitecture for ForwardOnly {
+        fn forward(
+            &mut self,
+            tokens: &[u32],
+            pos: usize,
+            _backend: &dyn ComputeBackend,
+        ) -> crate::Result<Vec<f32>> {
+            Ok(vec![tokens.len() as f32, pos as f32])
+        }
+
+        fn vocab_size(&self) -> usize {
+            2
+        }
+
+        fn reset_cache(&mut self) {}
+    }
+
+    #[test]
+    fn test_forward_into_defaults_to_forward() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let mut logits = vec![9.0; 5];
+        ForwardOnly
+            .forward_into(&[1, 2, 3], 4, &backend, &mut logits)
+            .unwrap();
+        assert_eq!(logits, [3.0, 4.0]);
+    }
+}
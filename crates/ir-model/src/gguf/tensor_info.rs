@@ -26,10 +26,7 @@ impl GgufTensorInfo {
 
     /// Compute the total byte size of this tensor's raw data in the file.
     pub fn data_size(&self) -> usize {
-        let numel = self.numel();
-        let block_size = self.dtype.block_size();
-        let n_blocks = numel.div_ceil(block_size);
-        n_blocks * self.dtype.size_in_bytes()
+        self.dtype.storage_size(self.numel())
     }
 }
 
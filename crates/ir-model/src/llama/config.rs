@@ -1,4 +1,6 @@
-use crate::error::Result;
+use ir_tensor::{RopeConfig, RopeScaling, RopeStyle};
+
+use crate::error::{ModelError, Result};
 use crate::gguf::metadata::GgufMetadata;
 
 /// Configuration for a LLaMA model, parsed from GGUF metadata.
@@ -21,6 +23,15 @@ pub struct LlamaConfig {
     pub max_seq_len: usize,
     /// RoPE frequency base (theta).
     pub rope_theta: f32,
+    /// Number of leading dimensions of each head that RoPE rotates.
+    pub rope_dims: usize,
+    /// How RoPE pairs up the elements of a head.
+    pub rope_style: RopeStyle,
+    /// RoPE context-extension scaling.
+    pub rope_scaling: RopeScaling,
+    /// Per-pair RoPE frequency divisors (the `rope_freqs.weight` tensor
+    /// Llama 3.1 conversions carry), if the model has them.
+    pub rope_freq_factors: Option<Vec<f32>>,
     /// Dimension of each attention head (n_embd / n_heads).
     pub head_dim: usize,
 }
@@ -37,6 +48,8 @@ impl LlamaConfig {
     /// - `llama.attention.layer_norm_rms_epsilon` -> norm_eps
     /// - `llama.context_length` -> max_seq_len
     /// - `llama.rope.freq_base` -> rope_theta (default 10000.0)
+    /// - `llama.rope.dimension_count` -> rope_dims (default head_dim)
+    /// - `llama.rope.scaling.*` -> rope_scaling (see [`parse_rope_scaling`])
     /// - vocab size inferred from `tokenizer.ggml.tokens` array length
     pub fn from_gguf(metadata: &GgufMetadata) -> Result<LlamaConfig> {
         let n_embd = metadata.get_u32("llama.embedding_length")? as usize;
@@ -49,11 +62,17 @@ impl LlamaConfig {
 
         let rope_theta = metadata.get_f32("llama.rope.freq_base").unwrap_or(10000.0);
 
+        let rope_scaling = parse_rope_scaling(metadata, max_seq_len)?;
+
         // Infer vocab size from tokenizer token array.
         let tokens = metadata.get_string_array("tokenizer.ggml.tokens")?;
         let n_vocab = tokens.len();
 
         let head_dim = n_embd / n_heads;
+        let rope_dims = metadata
+            .get_u32("llama.rope.dimension_count")
+            .map(|n| n as usize)
+            .unwrap_or(head_dim);
 
         Ok(LlamaConfig {
             n_vocab,
@@ -65,7 +84,138 @@ impl LlamaConfig {
             norm_eps,
             max_seq_len,
             rope_theta,
+            rope_dims,
+            // GGUF conversion permutes LLaMA's Q/K weights so that adjacent
+            // elements form the rotated pairs.
+            rope_style: RopeStyle::Interleaved,
+            rope_scaling,
+            // Only stored as a tensor; filled in when the weights are loaded.
+            rope_freq_factors: None,
             head_dim,
         })
     }
+
+    /// The rotary embedding parameters described by this configuration.
+    pub fn rope_config(&self) -> RopeConfig {
+        let config = RopeConfig::new(self.head_dim)
+            .with_freq_base(self.rope_theta)
+            .with_dims(self.rope_dims)
+            .with_style(self.rope_style)
+            .with_scaling(self.rope_scaling);
+        match &self.rope_freq_factors {
+            Some(factors) => config.with_freq_factors(factors.as_slice()),
+            None => config,
+        }
+    }
+}
+
+/// Parse RoPE scaling from GGUF metadata.
+///
+/// Reads the following keys:
+/// - `llama.rope.scaling.type`: `none`, `linear`, `ntk` or `yarn`
+///   (absent means `none`, or `linear` if the legacy
+///   `llama.rope.scale_linear` factor is present)
+/// - `llama.rope.scaling.factor` (default 1.0)
+/// - `llama.rope.scaling.original_context_length` (default: context length)
+/// - `llama.rope.scaling.attn_factor` (YaRN, default 1.0)
+/// - `llama.rope.scaling.yarn_beta_fast` / `yarn_beta_slow` (YaRN, default
+///   32.0 / 1.0)
+pub fn parse_rope_scaling(metadata: &GgufMetadata, context_length: usize) -> Result<RopeScaling> {
+    let legacy_linear = metadata.get_f32("llama.rope.scale_linear").ok();
+    let kind = match metadata.get_string("llama.rope.scaling.type") {
+        Ok(kind) => kind,
+        Err(_) if legacy_linear.is_some() => "linear",
+        Err(_) => "none",
+    };
+    let factor = metadata
+        .get_f32("llama.rope.scaling.factor")
+        .ok()
+        .or(legacy_linear)
+        .unwrap_or(1.0);
+    let original_context = metadata
+        .get_u32("llama.rope.scaling.original_context_length")
+        .map(|n| n as usize)
+        .unwrap_or(context_length);
+
+    Ok(match kind {
+        "none" => RopeScaling::None,
+        "linear" => RopeScaling::Linear { factor },
+        "ntk" => RopeScaling::Ntk { factor },
+        "yarn" => RopeScaling::Yarn {
+            factor,
+            original_context,
+            beta_fast: metadata
+                .get_f32("llama.rope.scaling.yarn_beta_fast")
+                .unwrap_or(32.0),
+            beta_slow: metadata
+                .get_f32("llama.rope.scaling.yarn_beta_slow")
+                .unwrap_or(1.0),
+            attn_factor: metadata
+                .get_f32("llama.rope.scaling.attn_factor")
+                .unwrap_or(1.0),
+        },
+        other => {
+            return Err(ModelError::Other(format!(
+                "unsupported rope scaling type: {}",
+                other
+            )));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::gguf::metadata::GgufMetadataValue;
+
+    fn metadata(entries: &[(&str, GgufMetadataValue)]) -> GgufMetadata {
+        GgufMetadata {
+            entries: entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    fn string(s: &str) -> GgufMetadataValue {
+        GgufMetadataValue::String(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_rope_scaling() {
+        use GgufMetadataValue::{F32, U32};
+
+        assert_eq!(
+            parse_rope_scaling(&metadata(&[]), 4096).unwrap(),
+            RopeScaling::None
+        );
+        assert_eq!(
+            parse_rope_scaling(&metadata(&[("llama.rope.scale_linear", F32(2.0))]), 4096).unwrap(),
+            RopeScaling::Linear { factor: 2.0 }
+        );
+
+        let yarn = metadata(&[
+            ("llama.rope.scaling.type", string("yarn")),
+            ("llama.rope.scaling.factor", F32(4.0)),
+            ("llama.rope.scaling.original_context_length", U32(32768)),
+        ]);
+        assert_eq!(
+            parse_rope_scaling(&yarn, 131072).unwrap(),
+            RopeScaling::yarn(4.0, 32768)
+        );
+
+        let ntk = metadata(&[
+            ("llama.rope.scaling.type", string("ntk")),
+            ("llama.rope.scaling.factor", F32(8.0)),
+        ]);
+        assert_eq!(
+            parse_rope_scaling(&ntk, 4096).unwrap(),
+            RopeScaling::Ntk { factor: 8.0 }
+        );
+
+        let unknown = metadata(&[("llama.rope.scaling.type", string("longrope"))]);
+        assert!(parse_rope_scaling(&unknown, 4096).is_err());
+    }
 }
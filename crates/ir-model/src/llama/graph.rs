@@ -0,0 +1,138 @@
+use ir_tensor::{
+    AttentionParams, CompiledGraph, ComputeBackend, Graph, NodeId, RopeTable, RunState, Shape,
+    Tensor,
+};
+
+use super::config::LlamaConfig;
+use super::kv_cache::KvCache;
+use super::layers::LlamaWeights;
+use crate::error::{ModelError, Result};
+
+/// The per-token LLaMA forward pass compiled into replayable graphs.
+///
+/// Each run looks up one token, runs every layer against the KV cache and
+/// appends the token's keys and values to it. There are two graphs: one for
+/// the last token of a call, which also produces logits, and one for the
+/// tokens before it, which stops once the cache is updated.
+///
+/// The graphs hold their own references to the weights and a copy of the
+/// RoPE tables taken when they are built.
+pub struct StepGraphs {
+    hidden: CompiledGraph,
+    logits: CompiledGraph,
+    n_vocab: usize,
+}
+
+impl StepGraphs {
+    /// Build and compile the step graphs for a model.
+    pub fn new(config: &LlamaConfig, weights: &LlamaWeights, rope: &RopeTable) -> Result<Self> {
+        let mut g = Graph::new();
+        let (hidden, appends) = build_layers(&mut g, config, weights, rope)?;
+
+        let mut hidden_graph = g.clone();
+        for &append in &appends {
+            hidden_graph.output(append)?;
+        }
+
+        let output_norm = norm_weight(&weights.output_norm);
+        let normed = g.rms_norm(hidden, &output_norm, config.norm_eps)?;
+        let logits = g.matmul(&weights.output, normed)?;
+        g.output(logits)?;
+
+        Ok(StepGraphs {
+            hidden: hidden_graph.compile()?,
+            logits: g.compile()?,
+            n_vocab: config.n_vocab,
+        })
+    }
+
+    /// Run `token` at `pos`, updating `cache`, without computing logits.
+    pub fn advance(
+        &mut self,
+        backend: &dyn ComputeBackend,
+        token: u32,
+        pos: usize,
+        cache: &mut KvCache,
+    ) -> Result<()> {
+        self.check_token(token)?;
+        let state = RunState {
+            pos,
+            token: Some(token),
+            kv: Some(cache),
+        };
+        self.hidden.run_with(backend, &[], state)?;
+        Ok(())
+    }
+
+    /// Run `token` at `pos`, updating `cache`, and return the logits of the
+    /// next token.
+    pub fn logits(
+        &mut self,
+        backend: &dyn ComputeBackend,
+        token: u32,
+        pos: usize,
+        cache: &mut KvCache,
+    ) -> Result<&[f32]> {
+        self.check_token(token)?;
+        let state = RunState {
+            pos,
+            token: Some(token),
+            kv: Some(cache),
+        };
+        self.logits.run_with(backend, &[], state)?;
+        Ok(self.logits.output(0))
+    }
+
+    fn check_token(&self, token: u32) -> Result<()> {
+        if (token as usize) >= self.n_vocab {
+            return Err(ModelError::Other(format!(
+                "token id {} exceeds vocab size {}",
+                token, self.n_vocab
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Add the embedding lookup and every transformer layer to `g`, returning
+/// the final residual stream and each layer's KV-append node.
+fn build_layers(
+    g: &mut Graph,
+    cfg: &LlamaConfig,
+    weights: &LlamaWeights,
+    rope: &RopeTable,
+) -> Result<(NodeId, Vec<NodeId>)> {
+    let attn = AttentionParams::new(cfg.n_heads, cfg.n_kv_heads, cfg.head_dim);
+    let mut hidden = g.embedding(&weights.token_embd)?;
+    let mut appends = Vec::with_capacity(cfg.n_layers);
+
+    for (layer_idx, layer) in weights.layers.iter().enumerate() {
+        // Attention sub-layer.
+        let normed = g.rms_norm(hidden, &norm_weight(&layer.attn_norm), cfg.norm_eps)?;
+        let q = g.matmul(&layer.wq, normed)?;
+        let k = g.matmul(&layer.wk, normed)?;
+        let v = g.matmul(&layer.wv, normed)?;
+        let q = g.rope(q, rope, cfg.n_heads)?;
+        let k = g.rope(k, rope, cfg.n_kv_heads)?;
+        let append = g.kv_append(k, v, layer_idx)?;
+        let attn_out = g.attention(q, append, attn)?;
+        let proj = g.matmul(&layer.wo, attn_out)?;
+        hidden = g.add(hidden, proj)?;
+        appends.push(append);
+
+        // FFN sub-layer: SwiGLU.
+        let normed = g.rms_norm(hidden, &norm_weight(&layer.ffn_norm), cfg.norm_eps)?;
+        let gate = g.matmul(&layer.ffn_gate, normed)?;
+        let up = g.matmul(&layer.ffn_up, normed)?;
+        let gate = g.silu(gate)?;
+        let gated = g.mul(gate, up)?;
+        let proj = g.matmul(&layer.ffn_down, gated)?;
+        hidden = g.add(hidden, proj)?;
+    }
+
+    Ok((hidden, appends))
+}
+
+fn norm_weight(weight: &[f32]) -> Tensor {
+    Tensor::new(weight.to_vec(), Shape::new(vec![weight.len()]))
+}
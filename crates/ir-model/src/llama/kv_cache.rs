@@ -1,18 +1,78 @@
+use half::f16;
+use ir_tensor::{AttentionParams, ComputeBackend, DType, KvStore, KvView, KvViewF16, TensorError};
+
+use crate::error::{ModelError, Result};
+
+/// One layer's cached keys or values in the cache's storage dtype.
+#[derive(Debug, Clone)]
+pub enum KvBuffer {
+    /// f32 elements.
+    F32(Vec<f32>),
+    /// f16 elements as little-endian bytes, at half the memory of f32.
+    F16(Vec<u8>),
+}
+
+impl KvBuffer {
+    fn zeros(dtype: DType, n: usize) -> Self {
+        match dtype {
+            DType::F16 => KvBuffer::F16(vec![0u8; 2 * n]),
+            _ => KvBuffer::F32(vec![0.0f32; n]),
+        }
+    }
+
+    /// Store `data` at element `offset`, rounding to the storage dtype.
+    fn write(&mut self, offset: usize, data: &[f32]) {
+        match self {
+            KvBuffer::F32(buf) => buf[offset..offset + data.len()].copy_from_slice(data),
+            KvBuffer::F16(buf) => {
+                let dst = &mut buf[2 * offset..2 * (offset + data.len())];
+                for (d, &x) in dst.chunks_exact_mut(2).zip(data) {
+                    d.copy_from_slice(&f16::from_f32(x).to_le_bytes());
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            KvBuffer::F32(buf) => buf.fill(0.0),
+            KvBuffer::F16(buf) => buf.fill(0),
+        }
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        match self {
+            KvBuffer::F32(buf) => 4 * buf.len(),
+            KvBuffer::F16(buf) => buf.len(),
+        }
+    }
+
+    /// The elements of an f32 buffer, or `None` for f16.
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        match self {
+            KvBuffer::F32(buf) => Some(buf),
+            KvBuffer::F16(_) => None,
+        }
+    }
+}
+
 /// Key-Value cache for transformer attention layers.
 ///
 /// Stores previously computed key and value projections so they do not need
 /// to be recomputed for each new token during autoregressive generation.
+/// Entries are kept as f32 by default, or as f16 to halve the cache's memory
+/// and the bandwidth attention spends reading it.
 ///
 /// Layout for each layer:
 ///   k[layer]: flat array of shape [max_seq_len, n_kv_heads * head_dim]
 ///   v[layer]: flat array of shape [max_seq_len, n_kv_heads * head_dim]
 pub struct KvCache {
     /// Key cache for each layer.
-    /// k[layer] has size n_kv_heads * max_seq_len * head_dim.
-    pub k: Vec<Vec<f32>>,
+    /// k[layer] has n_kv_heads * max_seq_len * head_dim elements.
+    pub k: Vec<KvBuffer>,
     /// Value cache for each layer.
-    /// v[layer] has size n_kv_heads * max_seq_len * head_dim.
-    pub v: Vec<Vec<f32>>,
+    /// v[layer] has n_kv_heads * max_seq_len * head_dim elements.
+    pub v: Vec<KvBuffer>,
     /// Number of key/value attention heads.
     pub n_kv_heads: usize,
     /// Dimension of each attention head.
@@ -24,11 +84,51 @@ pub struct KvCache {
 }
 
 impl KvCache {
-    /// Create a new KV cache with all values initialized to zero.
+    /// Create a new f32 KV cache with all values initialized to zero.
     pub fn new(n_layers: usize, n_kv_heads: usize, head_dim: usize, max_seq_len: usize) -> Self {
+        Self::build(n_layers, n_kv_heads, head_dim, max_seq_len, DType::F32)
+    }
+
+    /// Create a zeroed KV cache that stores its entries as `dtype`.
+    ///
+    /// # Errors
+    /// Returns an error unless `dtype` is F32 or F16.
+    pub fn with_dtype(
+        n_layers: usize,
+        n_kv_heads: usize,
+        head_dim: usize,
+        max_seq_len: usize,
+        dtype: DType,
+    ) -> Result<Self> {
+        if !matches!(dtype, DType::F32 | DType::F16) {
+            return Err(ModelError::Other(format!(
+                "unsupported KV cache dtype {}, expected f32 or f16",
+                dtype
+            )));
+        }
+        Ok(Self::build(
+            n_layers,
+            n_kv_heads,
+            head_dim,
+            max_seq_len,
+            dtype,
+        ))
+    }
+
+    fn build(
+        n_layers: usize,
+        n_kv_heads: usize,
+        head_dim: usize,
+        max_seq_len: usize,
+        dtype: DType,
+    ) -> Self {
         let cache_size = n_kv_heads * max_seq_len * head_dim;
-        let k = (0..n_layers).map(|_| vec![0.0f32; cache_size]).collect();
-        let v = (0..n_layers).map(|_| vec![0.0f32; cache_size]).collect();
+        let k = (0..n_layers)
+            .map(|_| KvBuffer::zeros(dtype, cache_size))
+            .collect();
+        let v = (0..n_layers)
+            .map(|_| KvBuffer::zeros(dtype, cache_size))
+            .collect();
 
         KvCache {
             k,
@@ -40,6 +140,23 @@ impl KvCache {
         }
     }
 
+    /// The dtype entries are stored as.
+    pub fn dtype(&self) -> DType {
+        match self.k.first() {
+            Some(KvBuffer::F16(_)) => DType::F16,
+            _ => DType::F32,
+        }
+    }
+
+    /// Total memory held by the cached keys and values.
+    pub fn size_in_bytes(&self) -> usize {
+        self.k
+            .iter()
+            .chain(&self.v)
+            .map(KvBuffer::size_in_bytes)
+            .sum()
+    }
+
     /// Write key and value vectors for one token at a given position in the cache.
     ///
     /// - `layer`: the transformer layer index
@@ -50,8 +167,8 @@ impl KvCache {
         let kv_dim = self.n_kv_heads * self.head_dim;
         let offset = pos * kv_dim;
 
-        self.k[layer][offset..offset + kv_dim].copy_from_slice(k_data);
-        self.v[layer][offset..offset + kv_dim].copy_from_slice(v_data);
+        self.k[layer].write(offset, k_data);
+        self.v[layer].write(offset, v_data);
 
         // Update the current length if this position extends it.
         if pos + 1 > self.len {
@@ -62,27 +179,101 @@ impl KvCache {
     /// Get a slice of the key cache for positions 0..seq_len.
     ///
     /// Returns a slice of length seq_len * n_kv_heads * head_dim.
+    ///
+    /// # Panics
+    /// Panics if the cache stores f16 entries.
+    #[deprecated(note = "the cache may hold f16 entries; match on `KvCache::k` instead")]
     pub fn get_k(&self, layer: usize, seq_len: usize) -> &[f32] {
         let kv_dim = self.n_kv_heads * self.head_dim;
-        &self.k[layer][..seq_len * kv_dim]
+        let k = self.k[layer].as_f32().expect("get_k on an f16 KV cache");
+        &k[..seq_len * kv_dim]
     }
 
     /// Get a slice of the value cache for positions 0..seq_len.
     ///
     /// Returns a slice of length seq_len * n_kv_heads * head_dim.
+    ///
+    /// # Panics
+    /// Panics if the cache stores f16 entries.
+    #[deprecated(note = "the cache may hold f16 entries; match on `KvCache::v` instead")]
     pub fn get_v(&self, layer: usize, seq_len: usize) -> &[f32] {
         let kv_dim = self.n_kv_heads * self.head_dim;
-        &self.v[layer][..seq_len * kv_dim]
+        let v = self.v[layer].as_f32().expect("get_v on an f16 KV cache");
+        &v[..seq_len * kv_dim]
+    }
+
+    /// Attend `q` over positions 0..seq_len of `layer`, writing into `out`.
+    ///
+    /// Dispatches to the backend's attention op for the cache's dtype; see
+    /// [`ComputeBackend::attention_into`] for the arguments.
+    #[allow(clippy::too_many_arguments)]
+    pub fn attention_into(
+        &self,
+        backend: &dyn ComputeBackend,
+        layer: usize,
+        seq_len: usize,
+        q: &[f32],
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> ir_tensor::Result<()> {
+        let n = seq_len * self.n_kv_heads * self.head_dim;
+        match (&self.k[layer], &self.v[layer]) {
+            (KvBuffer::F32(k), KvBuffer::F32(v)) => {
+                let kv = KvView {
+                    k: &k[..n],
+                    v: &v[..n],
+                    n_positions: seq_len,
+                };
+                backend.attention_into(q, kv, q_pos, params, out)
+            }
+            (KvBuffer::F16(k), KvBuffer::F16(v)) => {
+                let kv = KvViewF16 {
+                    k: &k[..2 * n],
+                    v: &v[..2 * n],
+                    n_positions: seq_len,
+                };
+                backend.attention_f16_into(q, kv, q_pos, params, out)
+            }
+            _ => unreachable!("keys and values share one dtype"),
+        }
     }
 
     /// Reset the cache, zeroing all data and setting length to 0.
     pub fn reset(&mut self) {
         for layer_k in &mut self.k {
-            layer_k.fill(0.0);
+            layer_k.clear();
         }
         for layer_v in &mut self.v {
-            layer_v.fill(0.0);
+            layer_v.clear();
         }
         self.len = 0;
     }
 }
+
+/// Lets the `kv_append` and `attention` steps of a compiled graph use the
+/// cache.
+impl KvStore for KvCache {
+    fn append(&mut self, layer: usize, k: &[f32], v: &[f32], pos: usize) -> ir_tensor::Result<()> {
+        if pos >= self.max_seq_len {
+            return Err(TensorError::Other(format!(
+                "position {} exceeds KV cache length {}",
+                pos, self.max_seq_len
+            )));
+        }
+        self.update(layer, k, v, pos);
+        Ok(())
+    }
+
+    fn attention_into(
+        &self,
+        backend: &dyn ComputeBackend,
+        layer: usize,
+        q: &[f32],
+        pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> ir_tensor::Result<()> {
+        KvCache::attention_into(self, backend, layer, pos + 1, q, pos, params, out)
+    }
+}
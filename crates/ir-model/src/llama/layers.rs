@@ -1,39 +1,44 @@
+use ir_tensor::Tensor;
+
 use crate::error::Result;
 use crate::gguf::reader::GgufFile;
 use super::config::LlamaConfig;
 
 /// Weight tensors for a single LLaMA transformer layer.
 ///
-/// All weights are stored as flat f32 vectors in row-major order.
+/// Norm weights are flat f32 vectors. Projection matrices are row-major
+/// tensors kept in the dtype they were stored in (F32, F16, or quantized
+/// blocks).
 pub struct LlamaLayer {
     /// RMS norm weights for the attention sub-layer, length = n_embd.
     pub attn_norm: Vec<f32>,
     /// Query projection weights, shape [n_heads * head_dim, n_embd].
-    pub wq: Vec<f32>,
+    pub wq: Tensor,
     /// Key projection weights, shape [n_kv_heads * head_dim, n_embd].
-    pub wk: Vec<f32>,
+    pub wk: Tensor,
     /// Value projection weights, shape [n_kv_heads * head_dim, n_embd].
-    pub wv: Vec<f32>,
+    pub wv: Tensor,
     /// Output projection weights, shape [n_embd, n_heads * head_dim].
-    pub wo: Vec<f32>,
+    pub wo: Tensor,
     /// RMS norm weights for the FFN sub-layer, length = n_embd.
     pub ffn_norm: Vec<f32>,
     /// Gate projection weights (w1), shape [n_ff, n_embd].
-    pub ffn_gate: Vec<f32>,
+    pub ffn_gate: Tensor,
     /// Up projection weights (w3), shape [n_ff, n_embd].
-    pub ffn_up: Vec<f32>,
+    pub ffn_up: Tensor,
     /// Down projection weights (w2), shape [n_embd, n_ff].
-    pub ffn_down: Vec<f32>,
+    pub ffn_down: Tensor,
 }
 
 /// All weight tensors for a LLaMA model.
 pub struct LlamaWeights {
-    /// Token embedding matrix, shape [n_vocab, n_embd].
-    pub token_embd: Vec<f32>,
+    /// Token embedding matrix, shape [n_vocab, n_embd], in its stored dtype.
+    pub token_embd: Tensor,
     /// Final RMS norm weights, length = n_embd.
     pub output_norm: Vec<f32>,
-    /// Output (LM head) projection weights, shape [n_vocab, n_embd].
-    pub output: Vec<f32>,
+    /// Output (LM head) projection weights, shape [n_vocab, n_embd], in its
+    /// stored dtype.
+    pub output: Tensor,
     /// Per-layer weights.
     pub layers: Vec<LlamaLayer>,
 }
@@ -50,13 +55,16 @@ impl LlamaWeights {
     /// - `blk.{i}.attn_output.weight`
     /// - `blk.{i}.ffn_norm.weight`
     /// - `blk.{i}.ffn_gate.weight`, `blk.{i}.ffn_up.weight`, `blk.{i}.ffn_down.weight`
+    ///
+    /// Norm weights are dequantized to f32; everything else borrows its
+    /// native-dtype bytes from the GGUF memory map.
     pub fn from_gguf(gguf: &GgufFile, config: &LlamaConfig) -> Result<LlamaWeights> {
-        let token_embd = gguf.get_tensor_f32("token_embd.weight")?.data_f32().to_vec();
+        let token_embd = gguf.get_tensor("token_embd.weight")?;
         let output_norm = gguf.get_tensor_f32("output_norm.weight")?.data_f32().to_vec();
 
         // Output weights may not exist if embeddings are tied.
-        let output = match gguf.get_tensor_f32("output.weight") {
-            Ok(t) => t.data_f32().to_vec(),
+        let output = match gguf.get_tensor("output.weight") {
+            Ok(t) => t,
             Err(_) => token_embd.clone(),
         };
 
@@ -66,38 +74,17 @@ impl LlamaWeights {
                 .get_tensor_f32(&format!("blk.{}.attn_norm.weight", i))?
                 .data_f32()
                 .to_vec();
-            let wq = gguf
-                .get_tensor_f32(&format!("blk.{}.attn_q.weight", i))?
-                .data_f32()
-                .to_vec();
-            let wk = gguf
-                .get_tensor_f32(&format!("blk.{}.attn_k.weight", i))?
-                .data_f32()
-                .to_vec();
-            let wv = gguf
-                .get_tensor_f32(&format!("blk.{}.attn_v.weight", i))?
-                .data_f32()
-                .to_vec();
-            let wo = gguf
-                .get_tensor_f32(&format!("blk.{}.attn_output.weight", i))?
-                .data_f32()
-                .to_vec();
+            let wq = gguf.get_tensor(&format!("blk.{}.attn_q.weight", i))?;
+            let wk = gguf.get_tensor(&format!("blk.{}.attn_k.weight", i))?;
+            let wv = gguf.get_tensor(&format!("blk.{}.attn_v.weight", i))?;
+            let wo = gguf.get_tensor(&format!("blk.{}.attn_output.weight", i))?;
             let ffn_norm = gguf
                 .get_tensor_f32(&format!("blk.{}.ffn_norm.weight", i))?
                 .data_f32()
                 .to_vec();
-            let ffn_gate = gguf
-                .get_tensor_f32(&format!("blk.{}.ffn_gate.weight", i))?
-                .data_f32()
-                .to_vec();
-            let ffn_up = gguf
-                .get_tensor_f32(&format!("blk.{}.ffn_up.weight", i))?
-                .data_f32()
-                .to_vec();
-            let ffn_down = gguf
-                .get_tensor_f32(&format!("blk.{}.ffn_down.weight", i))?
-                .data_f32()
-                .to_vec();
+            let ffn_gate = gguf.get_tensor(&format!("blk.{}.ffn_gate.weight", i))?;
+            let ffn_up = gguf.get_tensor(&format!("blk.{}.ffn_up.weight", i))?;
+            let ffn_down = gguf.get_tensor(&format!("blk.{}.ffn_down.weight", i))?;
 
             layers.push(LlamaLayer {
                 attn_norm,
@@ -1,12 +1,16 @@
 pub mod config;
+pub mod graph;
 pub mod kv_cache;
 pub mod layers;
+pub mod scratch;
 
 pub use config::LlamaConfig;
-pub use kv_cache::KvCache;
+pub use graph::StepGraphs;
+pub use kv_cache::{KvBuffer, KvCache};
 pub use layers::{LlamaLayer, LlamaWeights};
+pub use scratch::Scratch;
 
-use ir_tensor::ComputeBackend;
+use ir_tensor::{AttentionParams, ComputeBackend, DType, RopeTable, Tensor, TensorError, quant};
 
 use crate::architecture::ModelArchitecture;
 use crate::error::{ModelError, Result};
@@ -14,39 +18,94 @@ use crate::gguf::reader::GgufFile;
 
 /// A LLaMA transformer model loaded from a GGUF file.
 ///
-/// Holds the configuration, dequantized weights (as f32), and a KV cache
-/// for autoregressive generation.
+/// Holds the configuration, weights in their stored dtype, a KV cache for
+/// autoregressive generation, and the scratch buffers the forward pass
+/// writes its activations into.
 pub struct LlamaModel {
     /// Model hyperparameters.
     pub config: LlamaConfig,
-    /// All weight tensors (dequantized to f32).
+    /// All weight tensors (projection matrices kept in their stored dtype).
     pub weights: LlamaWeights,
     /// Key-value cache for attention.
     pub cache: KvCache,
+    /// Activation buffers reused across forward passes.
+    pub scratch: Scratch,
+    /// Precomputed RoPE cos/sin tables.
+    pub rope: RopeTable,
+    /// Compiled forward pass, replayed instead of the eager one when set.
+    graph: Option<StepGraphs>,
 }
 
 impl LlamaModel {
     /// Load a LLaMA model from a parsed GGUF file.
     ///
-    /// Parses the configuration from metadata, loads and dequantizes all
-    /// weight tensors, and initializes an empty KV cache.
+    /// Parses the configuration from metadata, loads all weight tensors, and
+    /// initializes an empty KV cache.
     pub fn from_gguf(gguf: &GgufFile, _backend: &dyn ComputeBackend) -> Result<LlamaModel> {
-        let config = LlamaConfig::from_gguf(&gguf.metadata)?;
+        let mut config = LlamaConfig::from_gguf(&gguf.metadata)?;
+        // Llama 3.1 stores its RoPE frequency scaling as a tensor.
+        config.rope_freq_factors = match gguf.get_tensor_f32("rope_freqs.weight") {
+            Ok(t) => Some(t.data_f32().to_vec()),
+            Err(ModelError::TensorNotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
         let weights = LlamaWeights::from_gguf(gguf, &config)?;
+        LlamaModel::new(config, weights)
+    }
+
+    /// Build a model from a configuration and its weights, allocating an
+    /// empty KV cache and the scratch buffers and precomputing the RoPE
+    /// tables for the whole context window.
+    pub fn new(config: LlamaConfig, weights: LlamaWeights) -> Result<LlamaModel> {
         let cache = KvCache::new(
             config.n_layers,
             config.n_kv_heads,
             config.head_dim,
             config.max_seq_len,
         );
+        let scratch = Scratch::new(&config);
+        let rope = RopeTable::new(config.rope_config(), config.max_seq_len)?;
 
         Ok(LlamaModel {
             config,
             weights,
             cache,
+            scratch,
+            rope,
+            graph: None,
         })
     }
 
+    /// Store the KV cache as `dtype` (F32 or F16) instead of f32.
+    ///
+    /// F16 halves the memory of the cache and the bandwidth attention uses
+    /// to read it; scores and outputs are still computed in f32. The cache
+    /// is replaced, so call this before generating.
+    ///
+    /// This is the only activation storage with an F16 mode: the per-token
+    /// buffers in [`Scratch`] stay f32.
+    pub fn with_kv_dtype(mut self, dtype: DType) -> Result<LlamaModel> {
+        self.cache = KvCache::with_dtype(
+            self.config.n_layers,
+            self.config.n_kv_heads,
+            self.config.head_dim,
+            self.config.max_seq_len,
+            dtype,
+        )?;
+        Ok(self)
+    }
+
+    /// Run the forward pass by replaying compiled [`StepGraphs`] instead of
+    /// calling the backend op by op.
+    ///
+    /// The graphs capture the weights and RoPE tables as they are now, so
+    /// call this after any change to them. The KV cache is still read from
+    /// `self.cache` on every call.
+    pub fn with_graph(mut self) -> Result<LlamaModel> {
+        self.graph = Some(StepGraphs::new(&self.config, &self.weights, &self.rope)?);
+        Ok(self)
+    }
+
     /// Returns a reference to the model configuration.
     pub fn config(&self) -> &LlamaConfig {
         &self.config
@@ -61,27 +120,47 @@ impl ModelArchitecture for LlamaModel {
     /// and the output projection to produce logits for the last token.
     ///
     /// Supports Grouped Query Attention (GQA) where n_kv_heads <= n_heads.
-    fn forward(
+    /// All intermediate activations live in `self.scratch`, so once `logits`
+    /// has grown to the vocabulary size a call makes no heap allocations.
+    /// After [`LlamaModel::with_graph`] the same pass is replayed from the
+    /// compiled [`StepGraphs`], whose buffers are likewise allocated once.
+    fn forward_into(
         &mut self,
         tokens: &[u32],
         pos: usize,
         backend: &dyn ComputeBackend,
-    ) -> Result<Vec<f32>> {
-        let cfg = &self.config;
+        logits: &mut Vec<f32>,
+    ) -> Result<()> {
+        let LlamaModel {
+            config: cfg,
+            weights,
+            cache,
+            scratch,
+            rope,
+            graph,
+        } = self;
         let n_embd = cfg.n_embd;
         let n_heads = cfg.n_heads;
         let n_kv_heads = cfg.n_kv_heads;
         let head_dim = cfg.head_dim;
         let n_layers = cfg.n_layers;
-        let heads_per_kv = n_heads / n_kv_heads;
+        let attn = AttentionParams::new(n_heads, n_kv_heads, head_dim);
 
         let n_tokens = tokens.len();
         if n_tokens == 0 {
             return Err(ModelError::Other("no tokens to process".to_string()));
         }
 
-        // We will compute logits for the last token only.
-        let mut last_logits = Vec::new();
+        if let Some(graph) = graph {
+            let (&last, prompt) = tokens.split_last().unwrap();
+            for (t_idx, &token_id) in prompt.iter().enumerate() {
+                graph.advance(backend, token_id, pos + t_idx, cache)?;
+            }
+            let out = graph.logits(backend, last, pos + n_tokens - 1, cache)?;
+            logits.resize(cfg.n_vocab, 0.0);
+            logits.copy_from_slice(out);
+            return Ok(());
+        }
 
         for (t_idx, &token_id) in tokens.iter().enumerate() {
             let cur_pos = pos + t_idx;
@@ -93,116 +172,92 @@ impl ModelArchitecture for LlamaModel {
                     token_id, cfg.n_vocab
                 )));
             }
-            let embd_offset = token_id as usize * n_embd;
-            let mut hidden: Vec<f32> =
-                self.weights.token_embd[embd_offset..embd_offset + n_embd].to_vec();
+            weight_row_into(
+                &weights.token_embd,
+                token_id as usize,
+                n_embd,
+                &mut scratch.hidden,
+            )
+            .map_err(|e| ModelError::Other(format!("embedding lookup failed: {}", e)))?;
 
             // Step 2: Process each transformer layer.
             for layer_idx in 0..n_layers {
-                let layer = &self.weights.layers[layer_idx];
+                let layer = &weights.layers[layer_idx];
 
                 // 2a. RMS norm for attention sub-layer.
-                let normed = backend
-                    .rms_norm(&hidden, &layer.attn_norm, cfg.norm_eps, n_embd)
+                backend
+                    .rms_norm_into(
+                        &scratch.hidden,
+                        &layer.attn_norm,
+                        cfg.norm_eps,
+                        n_embd,
+                        &mut scratch.normed,
+                    )
                     .map_err(|e| ModelError::Other(format!("rms_norm failed: {}", e)))?;
 
                 // 2b. Compute Q, K, V projections.
                 //
                 // GGUF stores weight matrices in [out_dim, in_dim] row-major layout.
                 // For a single token (vector of length n_embd), we compute the
-                // matrix-vector product W @ x using matmul(W, x, out_dim, in_dim, 1).
-                let q_dim = n_heads * head_dim;
-                let kv_dim = n_kv_heads * head_dim;
-
-                let q = backend
-                    .matmul(&layer.wq, &normed, q_dim, n_embd, 1)
+                // matrix-vector product W @ x via `weight_matvec`, which handles
+                // any stored weight dtype.
+                let normed = &scratch.normed;
+                weight_matvec(backend, &layer.wq, normed, &mut scratch.q)
                     .map_err(|e| ModelError::Other(format!("q matmul failed: {}", e)))?;
-                let k = backend
-                    .matmul(&layer.wk, &normed, kv_dim, n_embd, 1)
+                weight_matvec(backend, &layer.wk, normed, &mut scratch.k)
                     .map_err(|e| ModelError::Other(format!("k matmul failed: {}", e)))?;
-                let v = backend
-                    .matmul(&layer.wv, &normed, kv_dim, n_embd, 1)
+                weight_matvec(backend, &layer.wv, normed, &mut scratch.v)
                     .map_err(|e| ModelError::Other(format!("v matmul failed: {}", e)))?;
 
                 // 2c. Apply RoPE to Q and K.
-                let (q_roped, k_roped) = backend
-                    .rope(&q, &k, head_dim, cur_pos, n_heads, n_kv_heads)
+                backend
+                    .rope_inplace(
+                        &mut scratch.q,
+                        &mut scratch.k,
+                        rope,
+                        cur_pos,
+                        n_heads,
+                        n_kv_heads,
+                    )
                     .map_err(|e| ModelError::Other(format!("rope failed: {}", e)))?;
 
                 // 2d. Update KV cache.
-                self.cache.update(layer_idx, &k_roped, &v, cur_pos);
+                cache.update(layer_idx, &scratch.k, &scratch.v, cur_pos);
 
-                // Total positions in cache including this one.
+                // 2e. Attend over every cached position, GQA included.
+                // Causal masking is implicit: the cache only contains
+                // positions 0..=cur_pos.
                 let seq_len = cur_pos + 1;
-
-                // 2e. Compute attention with GQA.
-                let cached_k = self.cache.get_k(layer_idx, seq_len);
-                let cached_v = self.cache.get_v(layer_idx, seq_len);
-
-                let mut attn_output = vec![0.0f32; q_dim];
-                let scale = 1.0 / (head_dim as f32).sqrt();
-
-                for h in 0..n_heads {
-                    let kv_h = h / heads_per_kv;
-
-                    // Query vector for this head.
-                    let q_start = h * head_dim;
-                    let q_head = &q_roped[q_start..q_start + head_dim];
-
-                    // Compute attention scores against all cached keys.
-                    let mut scores = Vec::with_capacity(seq_len);
-                    for s in 0..seq_len {
-                        let k_offset = s * kv_dim + kv_h * head_dim;
-                        let mut dot = 0.0f32;
-                        for d in 0..head_dim {
-                            dot += q_head[d] * cached_k[k_offset + d];
-                        }
-                        scores.push(dot * scale);
-                    }
-
-                    // Causal masking is implicit: the cache only contains
-                    // positions 0..seq_len which are all <= cur_pos.
-
-                    // Softmax over scores (inline for efficiency with single head).
-                    let max_score = scores
-                        .iter()
-                        .copied()
-                        .fold(f32::NEG_INFINITY, f32::max);
-                    let mut exp_sum = 0.0f32;
-                    let mut probs = Vec::with_capacity(seq_len);
-                    for &s in &scores {
-                        let e = (s - max_score).exp();
-                        probs.push(e);
-                        exp_sum += e;
-                    }
-                    for p in &mut probs {
-                        *p /= exp_sum;
-                    }
-
-                    // Weighted sum of cached values.
-                    let attn_start = h * head_dim;
-                    for (s, &prob) in probs.iter().enumerate().take(seq_len) {
-                        let v_offset = s * kv_dim + kv_h * head_dim;
-                        for d in 0..head_dim {
-                            attn_output[attn_start + d] +=
-                                prob * cached_v[v_offset + d];
-                        }
-                    }
-                }
+                cache
+                    .attention_into(
+                        backend,
+                        layer_idx,
+                        seq_len,
+                        &scratch.q,
+                        cur_pos,
+                        &attn,
+                        &mut scratch.attn_out,
+                    )
+                    .map_err(|e| ModelError::Other(format!("attention failed: {}", e)))?;
 
                 // 2f. Output projection: wo @ attn_output -> [n_embd].
-                let attn_proj = backend
-                    .matmul(&layer.wo, &attn_output, n_embd, q_dim, 1)
+                weight_matvec(backend, &layer.wo, &scratch.attn_out, &mut scratch.proj)
                     .map_err(|e| ModelError::Other(format!("wo matmul failed: {}", e)))?;
 
                 // 2g. Residual connection.
-                hidden = backend
-                    .add(&hidden, &attn_proj)
+                backend
+                    .add_inplace(&mut scratch.hidden, &scratch.proj)
                     .map_err(|e| ModelError::Other(format!("residual add failed: {}", e)))?;
 
                 // 2h. RMS norm for FFN sub-layer.
-                let ffn_normed = backend
-                    .rms_norm(&hidden, &layer.ffn_norm, cfg.norm_eps, n_embd)
+                backend
+                    .rms_norm_into(
+                        &scratch.hidden,
+                        &layer.ffn_norm,
+                        cfg.norm_eps,
+                        n_embd,
+                        &mut scratch.normed,
+                    )
                     .map_err(|e| {
                         ModelError::Other(format!("ffn rms_norm failed: {}", e))
                     })?;
@@ -211,27 +266,23 @@ impl ModelArchitecture for LlamaModel {
                 //   gate = silu(ffn_gate @ normed)  -> [n_ff]
                 //   up   = ffn_up @ normed          -> [n_ff]
                 //   out  = ffn_down @ (gate * up)   -> [n_embd]
-                let gate = backend
-                    .matmul(&layer.ffn_gate, &ffn_normed, cfg.n_ff, n_embd, 1)
+                let normed = &scratch.normed;
+                weight_matvec(backend, &layer.ffn_gate, normed, &mut scratch.gate)
                     .map_err(|e| ModelError::Other(format!("gate matmul failed: {}", e)))?;
-                let up = backend
-                    .matmul(&layer.ffn_up, &ffn_normed, cfg.n_ff, n_embd, 1)
+                weight_matvec(backend, &layer.ffn_up, normed, &mut scratch.up)
                     .map_err(|e| ModelError::Other(format!("up matmul failed: {}", e)))?;
-                let gate_activated = backend
-                    .silu(&gate)
+                backend
+                    .silu_inplace(&mut scratch.gate)
                     .map_err(|e| ModelError::Other(format!("silu failed: {}", e)))?;
-                let gate_up = backend
-                    .mul(&gate_activated, &up)
+                backend
+                    .mul_inplace(&mut scratch.gate, &scratch.up)
                     .map_err(|e| ModelError::Other(format!("gate*up failed: {}", e)))?;
-                let ffn_out = backend
-                    .matmul(&layer.ffn_down, &gate_up, n_embd, cfg.n_ff, 1)
-                    .map_err(|e| {
-                        ModelError::Other(format!("down matmul failed: {}", e))
-                    })?;
+                weight_matvec(backend, &layer.ffn_down, &scratch.gate, &mut scratch.proj)
+                    .map_err(|e| ModelError::Other(format!("down matmul failed: {}", e)))?;
 
                 // 2j. Residual connection.
-                hidden = backend
-                    .add(&hidden, &ffn_out)
+                backend
+                    .add_inplace(&mut scratch.hidden, &scratch.proj)
                     .map_err(|e| {
                         ModelError::Other(format!("ffn residual add failed: {}", e))
                     })?;
@@ -239,33 +290,26 @@ impl ModelArchitecture for LlamaModel {
 
             // Step 3: Final RMS norm + LM head (only for last token).
             if t_idx == n_tokens - 1 {
-                let final_normed = backend
-                    .rms_norm(
-                        &hidden,
-                        &self.weights.output_norm,
+                backend
+                    .rms_norm_into(
+                        &scratch.hidden,
+                        &weights.output_norm,
                         cfg.norm_eps,
                         n_embd,
+                        &mut scratch.normed,
                     )
                     .map_err(|e| {
                         ModelError::Other(format!("output rms_norm failed: {}", e))
                     })?;
 
                 // Step 4: Output projection -> logits [n_vocab].
-                last_logits = backend
-                    .matmul(
-                        &self.weights.output,
-                        &final_normed,
-                        cfg.n_vocab,
-                        n_embd,
-                        1,
-                    )
-                    .map_err(|e| {
-                        ModelError::Other(format!("logits matmul failed: {}", e))
-                    })?;
+                logits.resize(cfg.n_vocab, 0.0);
+                weight_matvec(backend, &weights.output, &scratch.normed, logits)
+                    .map_err(|e| ModelError::Other(format!("logits matmul failed: {}", e)))?;
             }
         }
 
-        Ok(last_logits)
+        Ok(())
     }
 
     fn vocab_size(&self) -> usize {
@@ -276,3 +320,457 @@ impl ModelArchitecture for LlamaModel {
         self.cache.reset();
     }
 }
+
+/// Compute `out = w @ x` for a row-major weight matrix of shape
+/// [out.len(), x.len()].
+///
+/// F32 weights go to `backend.matmul_into`, F16 weights to
+/// `backend.matmul_f16_into` and every other dtype, still in its stored
+/// encoding, to `backend.matmul_quantized_into`.
+fn weight_matvec(
+    backend: &dyn ComputeBackend,
+    w: &Tensor,
+    x: &[f32],
+    out: &mut [f32],
+) -> ir_tensor::Result<()> {
+    let (out_dim, in_dim) = (out.len(), x.len());
+    match w.dtype() {
+        DType::F32 => backend.matmul_into(w.data_f32(), x, out, out_dim, in_dim, 1),
+        DType::F16 => backend.matmul_f16_into(w.raw_bytes()?, x, out, out_dim, in_dim, 1),
+        dtype => backend.matmul_quantized_into(w.raw_bytes()?, dtype, x, out, out_dim, in_dim),
+    }
+}
+
+/// Dequantize row `row` of a row-major weight matrix with rows of `width`
+/// elements into `out`, replacing its contents.
+fn weight_row_into(
+    w: &Tensor,
+    row: usize,
+    width: usize,
+    out: &mut Vec<f32>,
+) -> ir_tensor::Result<()> {
+    out.clear();
+    if w.dtype() == DType::F32 {
+        let data = w.data_f32();
+        let start = row * width;
+        if start + width > data.len() {
+            return Err(TensorError::Other(format!(
+                "row {} out of range for {} weights",
+                row,
+                w.shape()
+            )));
+        }
+        out.extend_from_slice(&data[start..start + width]);
+        return Ok(());
+    }
+
+    quant::dequantize_into(w.dtype(), weight_row_bytes(w, row, width)?, width, out);
+    Ok(())
+}
+
+/// Raw encoded bytes of row `row` of a non-f32 weight matrix.
+fn weight_row_bytes(w: &Tensor, row: usize, width: usize) -> ir_tensor::Result<&[u8]> {
+    let raw = w.raw_bytes()?;
+    let row_bytes = w.dtype().storage_size(width);
+    let start = row * row_bytes;
+    raw.get(start..start + row_bytes).ok_or_else(|| {
+        TensorError::Other(format!(
+            "row {} out of range for {} weights",
+            row,
+            w.shape()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use ir_tensor::cpu::quant::quantize_row_q8_0;
+    use ir_tensor::{
+        ByteBuffer, CpuBackend, CpuStorage, ProfilingBackend, Reduction, RopeScaling, RopeStyle,
+        Shape, SimdLevel, ValidatingBackend,
+    };
+
+    use super::*;
+
+    /// Counts heap allocations made by the current thread.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count_alloc() {
+        let _ = ALLOCS.try_with(|c| c.set(c.get() + 1));
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_alloc();
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count_alloc();
+            unsafe { System.alloc_zeroed(layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_alloc();
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+
+    fn allocs() -> usize {
+        ALLOCS.with(|c| c.get())
+    }
+
+    fn values(n: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..n)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                ((state >> 8) as f32 / (1u32 << 23) as f32 - 1.0) * 0.5
+            })
+            .collect()
+    }
+
+    fn f32_weight(rows: usize, cols: usize, seed: u32) -> Tensor {
+        Tensor::new(values(rows * cols, seed), Shape::new(vec![rows, cols]))
+    }
+
+    fn q8_0_weight(rows: usize, cols: usize, seed: u32) -> Tensor {
+        let mut raw = vec![0u8; DType::Q8_0.storage_size(rows * cols)];
+        quantize_row_q8_0(&values(rows * cols, seed), &mut raw);
+        let storage = CpuStorage::from_raw(DType::Q8_0, ByteBuffer::from_vec(raw)).unwrap();
+        Tensor::from_storage(storage, Shape::new(vec![rows, cols])).unwrap()
+    }
+
+    fn f16_weight(rows: usize, cols: usize, seed: u32) -> Tensor {
+        let raw = values(rows * cols, seed)
+            .into_iter()
+            .flat_map(|v| half::f16::from_f32(v).to_le_bytes())
+            .collect();
+        let storage = CpuStorage::from_raw(DType::F16, ByteBuffer::from_vec(raw)).unwrap();
+        Tensor::from_storage(storage, Shape::new(vec![rows, cols])).unwrap()
+    }
+
+    /// A `[rows, cols]` weight of any dtype, encoded from [`values`].
+    fn encoded_weight(dtype: DType, rows: usize, cols: usize, seed: u32) -> Tensor {
+        let raw = quant::quantize(dtype, &values(rows * cols, seed));
+        let storage = CpuStorage::from_raw(dtype, ByteBuffer::from_vec(raw)).unwrap();
+        Tensor::from_storage(storage, Shape::new(vec![rows, cols])).unwrap()
+    }
+
+    /// A two-layer GQA model with random weights that exercises the f32,
+    /// f16 and quantized matvec paths.
+    fn tiny_model() -> LlamaModel {
+        let config = LlamaConfig {
+            n_vocab: 48,
+            n_embd: 64,
+            n_heads: 4,
+            n_kv_heads: 2,
+            n_layers: 2,
+            n_ff: 96,
+            norm_eps: 1e-5,
+            max_seq_len: 16,
+            rope_theta: 10000.0,
+            rope_dims: 16,
+            rope_style: RopeStyle::Interleaved,
+            rope_scaling: RopeScaling::None,
+            rope_freq_factors: None,
+            head_dim: 16,
+        };
+        let (e, q, kv, ff) = (64, 64, 32, 96);
+        let layers = (0..config.n_layers as u32)
+            .map(|i| LlamaLayer {
+                attn_norm: values(e, 100 + i).iter().map(|v| 1.0 + v).collect(),
+                wq: q8_0_weight(q, e, 10 * i + 1),
+                wk: f32_weight(kv, e, 10 * i + 2),
+                wv: f32_weight(kv, e, 10 * i + 3),
+                wo: f32_weight(e, q, 10 * i + 4),
+                ffn_norm: values(e, 200 + i).iter().map(|v| 1.0 + v).collect(),
+                ffn_gate: f32_weight(ff, e, 10 * i + 5),
+                ffn_up: f16_weight(ff, e, 10 * i + 6),
+                ffn_down: q8_0_weight(e, ff, 10 * i + 7),
+            })
+            .collect();
+        let weights = LlamaWeights {
+            token_embd: f32_weight(config.n_vocab, e, 1000),
+            output_norm: vec![1.0; e],
+            output: f32_weight(config.n_vocab, e, 1001),
+            layers,
+        };
+        LlamaModel::new(config, weights).unwrap()
+    }
+
+    /// A one-layer model wide enough for the 256-element k-quant blocks,
+    /// with every matrix in a different format.
+    fn k_quant_model() -> LlamaModel {
+        let config = LlamaConfig {
+            n_vocab: 48,
+            n_embd: 256,
+            n_heads: 4,
+            n_kv_heads: 2,
+            n_layers: 1,
+            n_ff: 512,
+            norm_eps: 1e-5,
+            max_seq_len: 16,
+            rope_theta: 10000.0,
+            rope_dims: 64,
+            rope_style: RopeStyle::Interleaved,
+            rope_scaling: RopeScaling::None,
+            rope_freq_factors: None,
+            head_dim: 64,
+        };
+        let (e, kv, ff) = (256, 128, 512);
+        let layer = LlamaLayer {
+            attn_norm: values(e, 100).iter().map(|v| 1.0 + v).collect(),
+            wq: encoded_weight(DType::Q4K, e, e, 1),
+            wk: encoded_weight(DType::Q5K, kv, e, 2),
+            wv: encoded_weight(DType::Q6K, kv, e, 3),
+            wo: encoded_weight(DType::Q2K, e, e, 4),
+            ffn_norm: values(e, 200).iter().map(|v| 1.0 + v).collect(),
+            ffn_gate: encoded_weight(DType::Q4_1, ff, e, 5),
+            ffn_up: encoded_weight(DType::Q5_1, ff, e, 6),
+            ffn_down: encoded_weight(DType::Q3K, e, ff, 7),
+        };
+        let weights = LlamaWeights {
+            token_embd: encoded_weight(DType::Q5_0, config.n_vocab, e, 1000),
+            output_norm: vec![1.0; e],
+            output: encoded_weight(DType::BF16, config.n_vocab, e, 1001),
+            layers: vec![layer],
+        };
+        LlamaModel::new(config, weights).unwrap()
+    }
+
+    #[test]
+    fn test_k_quant_weights_run_on_the_backend() {
+        let scalar = CpuBackend::with_threads(1)
+            .unwrap()
+            .with_simd_level(SimdLevel::Scalar)
+            .unwrap();
+        let validating = ValidatingBackend::new(CpuBackend::with_threads(2).unwrap(), scalar);
+        let backend = ProfilingBackend::new(&validating);
+        let mut model = k_quant_model();
+        let logits = model.forward(&[1, 2, 3], 0, &backend).unwrap();
+        assert!(logits.iter().all(|v| v.is_finite()));
+        model.forward(&[4], 3, &backend).unwrap();
+        assert_eq!(validating.divergence_count(), 0);
+
+        // Every matrix went through the backend rather than a model-side loop.
+        let profile = backend.profile();
+        let seen: Vec<DType> = profile
+            .iter()
+            .filter(|p| p.op == "matmul_quantized")
+            .filter_map(|p| p.dtype)
+            .collect();
+        for dtype in [
+            DType::Q4K,
+            DType::Q5K,
+            DType::Q6K,
+            DType::Q2K,
+            DType::Q3K,
+            DType::Q4_1,
+            DType::Q5_1,
+            DType::BF16,
+        ] {
+            assert!(seen.contains(&dtype), "{} matmul not on the backend", dtype);
+        }
+    }
+
+    #[test]
+    fn test_forward_into_matches_forward() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let mut a = tiny_model();
+        let mut b = tiny_model();
+
+        let want = a.forward(&[1, 2, 3], 0, &backend).unwrap();
+        let mut logits = vec![9.0; 3];
+        b.forward_into(&[1, 2, 3], 0, &backend, &mut logits)
+            .unwrap();
+        assert_eq!(logits.len(), 48);
+        assert_eq!(logits, want);
+
+        let want = a.forward(&[4], 3, &backend).unwrap();
+        b.forward_into(&[4], 3, &backend, &mut logits).unwrap();
+        assert_eq!(logits, want);
+        assert!(logits.iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn test_forward_validates_against_scalar_backend() {
+        let scalar = CpuBackend::with_threads(1)
+            .unwrap()
+            .with_simd_level(SimdLevel::Scalar)
+            .unwrap();
+        let backend = ValidatingBackend::new(CpuBackend::with_threads(2).unwrap(), scalar);
+        let mut model = tiny_model();
+        model.forward(&[1, 2, 3], 0, &backend).unwrap();
+        model.forward(&[4], 3, &backend).unwrap();
+        assert_eq!(backend.divergence_count(), 0);
+    }
+
+    #[test]
+    fn test_deterministic_logits_are_bit_exact() {
+        let logits = |threads: usize, level: SimdLevel| {
+            let backend = CpuBackend::with_threads(threads)
+                .unwrap()
+                .with_simd_level(level)
+                .unwrap()
+                .with_reduction(Reduction::Pairwise);
+            let mut model = tiny_model();
+            let mut out = model.forward(&[5, 6, 7], 0, &backend).unwrap();
+            out.extend(model.forward(&[8], 3, &backend).unwrap());
+            out
+        };
+        let want = logits(1, SimdLevel::Scalar);
+        for level in SimdLevel::supported() {
+            for threads in [1, 2, 4] {
+                assert_eq!(logits(threads, level), want, "{} threads={}", level, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn test_f16_kv_cache_stays_close_to_f32() {
+        let backend = CpuBackend::new();
+        let mut f32_model = tiny_model();
+        let mut f16_model = tiny_model().with_kv_dtype(DType::F16).unwrap();
+        assert_eq!(f16_model.cache.dtype(), DType::F16);
+        assert_eq!(
+            2 * f16_model.cache.size_in_bytes(),
+            f32_model.cache.size_in_bytes()
+        );
+
+        for (tokens, pos) in [(&[5u32, 6, 7][..], 0), (&[8], 3), (&[9], 4)] {
+            let want = f32_model.forward(tokens, pos, &backend).unwrap();
+            let got = f16_model.forward(tokens, pos, &backend).unwrap();
+            let max_err = got
+                .iter()
+                .zip(&want)
+                .map(|(g, w)| (g - w).abs())
+                .fold(0.0f32, f32::max);
+            // f16 keeps about three decimal digits, and the untrained
+            // weights amplify that rounding a little through the layers.
+            let scale = want.iter().fold(0.0f32, |m, w| m.max(w.abs()));
+            assert!(max_err < 2e-2 * scale, "pos {}: max error {}", pos, max_err);
+        }
+
+        assert!(tiny_model().with_kv_dtype(DType::Q8_0).is_err());
+    }
+
+    #[test]
+    fn test_decode_step_does_not_allocate() {
+        // A single thread keeps all work, and all allocations, on this thread.
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let mut model = tiny_model();
+        let mut logits = Vec::new();
+        model
+            .forward_into(&[1, 2], 0, &backend, &mut logits)
+            .unwrap();
+
+        let before = allocs();
+        for pos in 2..6 {
+            model
+                .forward_into(&[pos as u32], pos, &backend, &mut logits)
+                .unwrap();
+        }
+        assert_eq!(allocs() - before, 0);
+
+        // The allocating form still allocates its result, which shows the
+        // counter is live.
+        let before = allocs();
+        model.forward(&[6], 6, &backend).unwrap();
+        assert!(allocs() > before);
+    }
+
+    #[test]
+    fn test_graph_replay_matches_eager_forward() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let models: [fn() -> LlamaModel; 3] = [
+            tiny_model,
+            || tiny_model().with_kv_dtype(DType::F16).unwrap(),
+            k_quant_model,
+        ];
+        for make in models {
+            let mut eager = make();
+            let mut replay = make().with_graph().unwrap();
+            for (tokens, pos) in [(&[5u32, 6, 7][..], 0), (&[8], 3), (&[9, 10], 4)] {
+                let want = eager.forward(tokens, pos, &backend).unwrap();
+                let got = replay.forward(tokens, pos, &backend).unwrap();
+                assert_eq!(got, want, "pos {}", pos);
+            }
+            assert!(replay.forward(&[48], 6, &backend).is_err());
+        }
+    }
+
+    #[test]
+    fn test_graph_decode_step_does_not_allocate() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let mut model = tiny_model().with_graph().unwrap();
+        let mut logits = Vec::new();
+        model
+            .forward_into(&[1, 2], 0, &backend, &mut logits)
+            .unwrap();
+
+        let before = allocs();
+        for pos in 2..6 {
+            model
+                .forward_into(&[pos as u32], pos, &backend, &mut logits)
+                .unwrap();
+        }
+        assert_eq!(allocs() - before, 0);
+    }
+
+    #[test]
+    fn test_graph_rejects_positions_past_the_cache() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let mut model = tiny_model().with_graph().unwrap();
+        assert!(model.forward(&[1], 15, &backend).is_ok());
+        assert!(model.forward(&[1], 16, &backend).is_err());
+    }
+
+    #[test]
+    fn test_forward_rejects_bad_tokens() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let mut model = tiny_model();
+        let mut logits = Vec::new();
+        assert!(model.forward_into(&[], 0, &backend, &mut logits).is_err());
+        assert!(model.forward_into(&[48], 0, &backend, &mut logits).is_err());
+    }
+
+    #[test]
+    fn test_rope_config_is_honored() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        let tokens = [3, 14, 15, 9];
+        let logits = |model: &mut LlamaModel| model.forward(&tokens, 0, &backend).unwrap();
+
+        let base = logits(&mut tiny_model());
+        let mut llama3 = tiny_model();
+        llama3.config.rope_theta = 500000.0;
+        llama3.rope = RopeTable::new(llama3.config.rope_config(), 16).unwrap();
+        assert_ne!(logits(&mut llama3), base);
+
+        let mut partial = tiny_model();
+        partial.config.rope_dims = 8;
+        let cfg = partial.config.rope_config();
+        assert_eq!((cfg.head_dim, cfg.n_dims), (16, 8));
+        partial.rope = RopeTable::new(cfg, 16).unwrap();
+        assert_ne!(logits(&mut partial), base);
+
+        let mut yarn = tiny_model();
+        yarn.config.rope_scaling = RopeScaling::yarn(4.0, 4);
+        yarn.rope = RopeTable::new(yarn.config.rope_config(), 16).unwrap();
+        assert_ne!(logits(&mut yarn), base);
+    }
+}
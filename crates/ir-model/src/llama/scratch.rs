@@ -0,0 +1,53 @@
+use super::config::LlamaConfig;
+
+/// Reusable activation buffers for the LLaMA forward pass.
+///
+/// Every intermediate result of a decode step is written into one of these
+/// buffers, which are sized from the config once when the model is created.
+/// After that, `LlamaModel::forward_into` does not touch the heap.
+///
+/// The buffers are always f32, since every backend op reads and writes f32
+/// activations: F16 copies would need an f32 staging buffer per operand and
+/// save nothing. They hold one token's activations, a few KiB even for large
+/// models; the activation state that grows with the sequence is the KV cache,
+/// which has an F16 storage mode set with `LlamaModel::with_kv_dtype`.
+pub struct Scratch {
+    /// Residual stream, length = n_embd.
+    pub hidden: Vec<f32>,
+    /// RMS-normed input of the current sub-layer, length = n_embd.
+    pub normed: Vec<f32>,
+    /// Query projection, length = n_heads * head_dim.
+    pub q: Vec<f32>,
+    /// Key projection, length = n_kv_heads * head_dim.
+    pub k: Vec<f32>,
+    /// Value projection, length = n_kv_heads * head_dim.
+    pub v: Vec<f32>,
+    /// Concatenated attention heads, length = n_heads * head_dim.
+    pub attn_out: Vec<f32>,
+    /// Output of the attention and FFN down projections, length = n_embd.
+    pub proj: Vec<f32>,
+    /// FFN gate projection, length = n_ff.
+    pub gate: Vec<f32>,
+    /// FFN up projection, length = n_ff.
+    pub up: Vec<f32>,
+}
+
+impl Scratch {
+    /// Allocate all buffers for a model with the given configuration.
+    pub fn new(config: &LlamaConfig) -> Self {
+        let q_dim = config.n_heads * config.head_dim;
+        let kv_dim = config.n_kv_heads * config.head_dim;
+
+        Scratch {
+            hidden: vec![0.0; config.n_embd],
+            normed: vec![0.0; config.n_embd],
+            q: vec![0.0; q_dim],
+            k: vec![0.0; kv_dim],
+            v: vec![0.0; kv_dim],
+            attn_out: vec![0.0; q_dim],
+            proj: vec![0.0; config.n_embd],
+            gate: vec![0.0; config.n_ff],
+            up: vec![0.0; config.n_ff],
+        }
+    }
+}
@@ -106,11 +106,11 @@ impl BpeTokenizer {
 
             for i in 0..tokens.len() - 1 {
                 let pair = (tokens[i].clone(), tokens[i + 1].clone());
-                if let Some(&rank) = self.merge_ranks.get(&pair) {
-                    if rank < best_rank {
-                        best_rank = rank;
-                        best_idx = i;
-                    }
+                if let Some(&rank) = self.merge_ranks.get(&pair)
+                    && rank < best_rank
+                {
+                    best_rank = rank;
+                    best_idx = i;
                 }
             }
 
@@ -153,11 +153,13 @@ impl BpeTokenizer {
             let tok = &self.vocab.tokens[id];
 
             // Check if this is a byte-level token like <0xHH>.
-            if tok.starts_with("<0x") && tok.ends_with('>') && tok.len() == 6 {
-                if let Ok(byte_val) = u8::from_str_radix(&tok[3..5], 16) {
-                    bytes.push(byte_val);
-                    continue;
-                }
+            if tok.starts_with("<0x")
+                && tok.ends_with('>')
+                && tok.len() == 6
+                && let Ok(byte_val) = u8::from_str_radix(&tok[3..5], 16)
+            {
+                bytes.push(byte_val);
+                continue;
             }
 
             // Otherwise, append the token's UTF-8 bytes directly.
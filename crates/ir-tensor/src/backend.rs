@@ -1,77 +1,1187 @@
 use std::fmt::Debug;
+use std::ops::Range;
 
-use crate::error::Result;
+use crate::dtype::DType;
+use crate::error::{Result, TensorError};
+use crate::layout::Layout;
+use crate::rope::RopeTable;
+
+/// Element-wise binary operation for [`ComputeBackend::binary_into`].
+///
+/// Comparisons produce 1.0 where the condition holds and 0.0 elsewhere, so
+/// their results can be used directly as masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    /// `a + b`
+    Add,
+    /// `a - b`
+    Sub,
+    /// `a * b`
+    Mul,
+    /// `a / b`
+    Div,
+    /// `a == b`
+    Eq,
+    /// `a != b`
+    Ne,
+    /// `a < b`
+    Lt,
+    /// `a <= b`
+    Le,
+    /// `a > b`
+    Gt,
+    /// `a >= b`
+    Ge,
+}
+
+impl BinaryOp {
+    /// Apply the operation to one pair of elements.
+    #[inline]
+    pub fn apply(self, a: f32, b: f32) -> f32 {
+        let mask = |c: bool| if c { 1.0 } else { 0.0 };
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Eq => mask(a == b),
+            BinaryOp::Ne => mask(a != b),
+            BinaryOp::Lt => mask(a < b),
+            BinaryOp::Le => mask(a <= b),
+            BinaryOp::Gt => mask(a > b),
+            BinaryOp::Ge => mask(a >= b),
+        }
+    }
+
+    /// Lowercase name, e.g. `"add"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Eq => "eq",
+            BinaryOp::Ne => "ne",
+            BinaryOp::Lt => "lt",
+            BinaryOp::Le => "le",
+            BinaryOp::Gt => "gt",
+            BinaryOp::Ge => "ge",
+        }
+    }
+}
+
+/// Reduction along one axis for [`ComputeBackend::reduce_into`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReduceOp {
+    /// Sum of the elements.
+    Sum,
+    /// Arithmetic mean of the elements.
+    Mean,
+    /// Largest element.
+    Max,
+    /// Index of the largest element (the first one on ties), as f32.
+    ArgMax,
+}
+
+impl ReduceOp {
+    /// Lowercase name, e.g. `"sum"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ReduceOp::Sum => "sum",
+            ReduceOp::Mean => "mean",
+            ReduceOp::Max => "max",
+            ReduceOp::ArgMax => "argmax",
+        }
+    }
+}
+
+/// Which cached keys a query may attend to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AttentionMask {
+    /// Every cached key.
+    None,
+    /// Keys at positions up to and including the query's.
+    #[default]
+    Causal,
+    /// Causal, limited to the most recent `window` positions (the query's
+    /// own included), as in Mistral and Gemma 2 local layers.
+    SlidingWindow(usize),
+}
+
+impl AttentionMask {
+    /// Key positions visible to a query at `q_pos` when `n_keys` positions
+    /// are cached.
+    pub fn key_range(self, q_pos: usize, n_keys: usize) -> Range<usize> {
+        let causal_end = (q_pos + 1).min(n_keys);
+        match self {
+            AttentionMask::None => 0..n_keys,
+            AttentionMask::Causal => 0..causal_end,
+            AttentionMask::SlidingWindow(window) => {
+                (q_pos + 1).saturating_sub(window).min(causal_end)..causal_end
+            }
+        }
+    }
+}
+
+/// Shapes of a batched matrix product for
+/// [`ComputeBackend::batched_matmul_into`].
+///
+/// Entry `i` of the batch multiplies matrix `i / (batch / a_batch)` of `a`
+/// with matrix `i / (batch / b_batch)` of `b`, so an operand with fewer
+/// matrices than the batch is shared by consecutive entries. With
+/// `b_batch == n_kv_heads` and `batch == n_heads` this is the grouped-query
+/// attention layout; with `b_batch == 1` one matrix is broadcast over the
+/// whole batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MatmulParams {
+    /// Number of products.
+    pub batch: usize,
+    /// Number of [m, k] matrices in `a`; must divide `batch`.
+    pub a_batch: usize,
+    /// Number of matrices in `b`; must divide `batch`.
+    pub b_batch: usize,
+    /// Rows of each `a` matrix and of each output.
+    pub m: usize,
+    /// Columns of each `a` matrix.
+    pub k: usize,
+    /// Columns of each output.
+    pub n: usize,
+    /// If set, each `b` matrix is stored as [n, k] and used transposed, so
+    /// a product with `K^T` reads the cached keys as they are.
+    pub transpose_b: bool,
+}
+
+impl MatmulParams {
+    /// `batch` independent `[m, k] @ [k, n]` products with nothing shared.
+    pub fn new(batch: usize, m: usize, k: usize, n: usize) -> Self {
+        MatmulParams {
+            batch,
+            a_batch: batch,
+            b_batch: batch,
+            m,
+            k,
+            n,
+            transpose_b: false,
+        }
+    }
+
+    /// Index of the `a` matrix used by batch entry `i`.
+    pub fn a_index(&self, i: usize) -> usize {
+        i / (self.batch / self.a_batch)
+    }
+
+    /// Index of the `b` matrix used by batch entry `i`.
+    pub fn b_index(&self, i: usize) -> usize {
+        i / (self.batch / self.b_batch)
+    }
+
+    /// Check the batch counts and the lengths of `a`, `b` and `out`.
+    pub fn validate(&self, a: &[f32], b: &[f32], out: &[f32]) -> Result<()> {
+        for (name, count) in [("a_batch", self.a_batch), ("b_batch", self.b_batch)] {
+            if count == 0 || !self.batch.is_multiple_of(count) {
+                return Err(TensorError::Other(format!(
+                    "batched matmul: {}={} does not divide batch={}",
+                    name, count, self.batch
+                )));
+            }
+        }
+        let (m, k, n) = (self.m, self.k, self.n);
+        if a.len() != self.a_batch * m * k {
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![self.a_batch, m, k],
+                got: vec![a.len()],
+            });
+        }
+        if b.len() != self.b_batch * k * n {
+            let dims = if self.transpose_b { [n, k] } else { [k, n] };
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![self.b_batch, dims[0], dims[1]],
+                got: vec![b.len()],
+            });
+        }
+        if out.len() != self.batch * m * n {
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![self.batch, m, n],
+                got: vec![out.len()],
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Head layout, scaling and masking for [`ComputeBackend::attention_into`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttentionParams {
+    /// Number of query heads.
+    pub n_heads: usize,
+    /// Number of key/value heads; query head `h` reads KV head
+    /// `h / (n_heads / n_kv_heads)` (grouped-query attention).
+    pub n_kv_heads: usize,
+    /// Dimension of each head.
+    pub head_dim: usize,
+    /// Multiplier applied to every q·k score, usually `1 / sqrt(head_dim)`.
+    pub scale: f32,
+    /// Which keys each query sees.
+    pub mask: AttentionMask,
+    /// If set, scaled scores are soft-capped to `cap * tanh(score / cap)`
+    /// before the softmax (Gemma 2).
+    pub softcap: Option<f32>,
+}
+
+impl AttentionParams {
+    /// Causal attention with the usual `1 / sqrt(head_dim)` scale and no
+    /// soft-cap.
+    pub fn new(n_heads: usize, n_kv_heads: usize, head_dim: usize) -> Self {
+        AttentionParams {
+            n_heads,
+            n_kv_heads,
+            head_dim,
+            scale: 1.0 / (head_dim as f32).sqrt(),
+            mask: AttentionMask::Causal,
+            softcap: None,
+        }
+    }
+
+    /// Elements per cached position: `n_kv_heads * head_dim`.
+    pub fn kv_dim(&self) -> usize {
+        self.n_kv_heads * self.head_dim
+    }
+
+    /// Check the head layout and the lengths of the query, cache and
+    /// output buffers. `q` must hold a whole number of tokens.
+    pub fn validate(&self, q: &[f32], kv: &KvView<'_>, out: &[f32]) -> Result<()> {
+        self.validate_lens(q, kv.k.len(), kv.v.len(), kv.n_positions, out)
+    }
+
+    /// [`AttentionParams::validate`] for a cache stored as f16.
+    pub fn validate_f16(&self, q: &[f32], kv: &KvViewF16<'_>, out: &[f32]) -> Result<()> {
+        self.validate_lens(q, kv.k.len() / 2, kv.v.len() / 2, kv.n_positions, out)
+    }
+
+    /// Shared checks, with the cache lengths counted in elements.
+    fn validate_lens(
+        &self,
+        q: &[f32],
+        k_len: usize,
+        v_len: usize,
+        n_positions: usize,
+        out: &[f32],
+    ) -> Result<()> {
+        if self.n_kv_heads == 0 || !self.n_heads.is_multiple_of(self.n_kv_heads) {
+            return Err(TensorError::Other(format!(
+                "attention: n_heads={} is not a multiple of n_kv_heads={}",
+                self.n_heads, self.n_kv_heads
+            )));
+        }
+        if let Some(cap) = self.softcap
+            && (cap.is_nan() || cap <= 0.0)
+        {
+            return Err(TensorError::Other(format!(
+                "attention: softcap must be positive, got {}",
+                cap
+            )));
+        }
+        let q_dim = self.n_heads * self.head_dim;
+        if q_dim == 0 || q.is_empty() || !q.len().is_multiple_of(q_dim) {
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![self.n_heads, self.head_dim],
+                got: vec![q.len()],
+            });
+        }
+        let kv_len = n_positions * self.kv_dim();
+        if k_len < kv_len || v_len < kv_len {
+            return Err(TensorError::Other(format!(
+                "attention: cache holds {} keys and {} values, need {} for {} positions",
+                k_len, v_len, kv_len, n_positions
+            )));
+        }
+        if out.len() != q.len() {
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![q.len()],
+                got: vec![out.len()],
+            });
+        }
+        Ok(())
+    }
+}
+
+/// One layer's cached keys and values, each laid out as
+/// `[n_positions, n_kv_heads * head_dim]` row-major starting at position 0.
+#[derive(Debug, Clone, Copy)]
+pub struct KvView<'a> {
+    /// Cached keys.
+    pub k: &'a [f32],
+    /// Cached values.
+    pub v: &'a [f32],
+    /// Number of cached positions.
+    pub n_positions: usize,
+}
+
+/// [`KvView`] of a cache stored as f16, so that it takes half the memory and
+/// half the bandwidth to read. Keys and values are little-endian f16 bytes
+/// in the same layout.
+#[derive(Debug, Clone, Copy)]
+pub struct KvViewF16<'a> {
+    /// Cached keys, two bytes per element.
+    pub k: &'a [u8],
+    /// Cached values, two bytes per element.
+    pub v: &'a [u8],
+    /// Number of cached positions.
+    pub n_positions: usize,
+}
+
+/// One op of [`ComputeBackend`], for capability queries and per-op routing
+/// (see [`Capabilities`] and [`FallbackBackend`](crate::FallbackBackend)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendOp {
+    /// [`ComputeBackend::matmul_into`]
+    Matmul,
+    /// [`ComputeBackend::batched_matmul_into`]
+    BatchedMatmul,
+    /// [`ComputeBackend::matmul_quantized_into`]
+    MatmulQuantized,
+    /// [`ComputeBackend::matmul_f16_into`]
+    MatmulF16,
+    /// [`ComputeBackend::add_inplace`]
+    Add,
+    /// [`ComputeBackend::mul_inplace`]
+    Mul,
+    /// [`ComputeBackend::scale_inplace`]
+    Scale,
+    /// [`ComputeBackend::binary_into`]
+    Binary,
+    /// [`ComputeBackend::reduce_into`]
+    Reduce,
+    /// [`ComputeBackend::rms_norm_into`]
+    RmsNorm,
+    /// [`ComputeBackend::softmax_into`]
+    Softmax,
+    /// [`ComputeBackend::rope_inplace`]
+    Rope,
+    /// [`ComputeBackend::attention_into`]
+    Attention,
+    /// [`ComputeBackend::attention_f16_into`]
+    AttentionF16,
+    /// [`ComputeBackend::silu_inplace`]
+    Silu,
+    /// [`ComputeBackend::gelu_inplace`]
+    Gelu,
+    /// [`ComputeBackend::gelu_erf_inplace`]
+    GeluErf,
+    /// [`ComputeBackend::geglu_inplace`]
+    Geglu,
+    /// [`ComputeBackend::relu2_inplace`]
+    Relu2,
+    /// [`ComputeBackend::softcap_inplace`]
+    Softcap,
+    /// [`ComputeBackend::layer_norm_into`]
+    LayerNorm,
+}
+
+impl BackendOp {
+    /// Every op, in the order the trait declares them.
+    pub const ALL: [BackendOp; 21] = [
+        BackendOp::Matmul,
+        BackendOp::BatchedMatmul,
+        BackendOp::MatmulQuantized,
+        BackendOp::MatmulF16,
+        BackendOp::Add,
+        BackendOp::Mul,
+        BackendOp::Scale,
+        BackendOp::Binary,
+        BackendOp::Reduce,
+        BackendOp::RmsNorm,
+        BackendOp::Softmax,
+        BackendOp::Rope,
+        BackendOp::Attention,
+        BackendOp::AttentionF16,
+        BackendOp::Silu,
+        BackendOp::Gelu,
+        BackendOp::GeluErf,
+        BackendOp::Geglu,
+        BackendOp::Relu2,
+        BackendOp::Softcap,
+        BackendOp::LayerNorm,
+    ];
+
+    /// Lowercase name, e.g. `"matmul_f16"`, as used by
+    /// [`ProfilingBackend`](crate::ProfilingBackend).
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendOp::Matmul => "matmul",
+            BackendOp::BatchedMatmul => "batched_matmul",
+            BackendOp::MatmulQuantized => "matmul_quantized",
+            BackendOp::MatmulF16 => "matmul_f16",
+            BackendOp::Add => "add",
+            BackendOp::Mul => "mul",
+            BackendOp::Scale => "scale",
+            BackendOp::Binary => "binary",
+            BackendOp::Reduce => "reduce",
+            BackendOp::RmsNorm => "rms_norm",
+            BackendOp::Softmax => "softmax",
+            BackendOp::Rope => "rope",
+            BackendOp::Attention => "attention",
+            BackendOp::AttentionF16 => "attention_f16",
+            BackendOp::Silu => "silu",
+            BackendOp::Gelu => "gelu",
+            BackendOp::GeluErf => "gelu_erf",
+            BackendOp::Geglu => "geglu",
+            BackendOp::Relu2 => "relu2",
+            BackendOp::Softcap => "softcap",
+            BackendOp::LayerNorm => "layer_norm",
+        }
+    }
+
+    /// Whether the op does in one pass what would otherwise take several:
+    /// attention (scores, softmax and the weighted sum of values) and GeGLU
+    /// (GELU and the gating product).
+    pub fn is_fused(self) -> bool {
+        matches!(
+            self,
+            BackendOp::Attention | BackendOp::AttentionF16 | BackendOp::Geglu
+        )
+    }
+
+    /// The dtype the op reads its weights or cache in, or `None` for
+    /// [`BackendOp::MatmulQuantized`], whose dtype is an argument.
+    pub fn dtype(self) -> Option<DType> {
+        match self {
+            BackendOp::MatmulQuantized => None,
+            BackendOp::MatmulF16 | BackendOp::AttentionF16 => Some(DType::F16),
+            _ => Some(DType::F32),
+        }
+    }
+}
+
+/// What a backend can run, from [`ComputeBackend::capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Ops the backend implements, each with the dtypes it reads weights or
+    /// caches in: [`BackendOp::dtype`] for most ops, and the block formats
+    /// for [`BackendOp::MatmulQuantized`]. Other ops fail with
+    /// [`TensorError::UnsupportedOp`].
+    pub ops: Vec<(BackendOp, Vec<DType>)>,
+    /// Largest single buffer, in bytes, the backend accepts, or `None` if
+    /// only memory limits it.
+    pub max_buffer_size: Option<usize>,
+}
+
+impl Capabilities {
+    /// Every op, with `quantized` the block formats
+    /// [`ComputeBackend::matmul_quantized_into`] accepts, and no buffer
+    /// limit.
+    pub fn all(quantized: Vec<DType>) -> Self {
+        Capabilities::only(&BackendOp::ALL, quantized)
+    }
+
+    /// Just `ops`, with `quantized` the block formats
+    /// [`ComputeBackend::matmul_quantized_into`] accepts if it is one of
+    /// them, and no buffer limit.
+    pub fn only(ops: &[BackendOp], quantized: Vec<DType>) -> Self {
+        let ops = ops
+            .iter()
+            .map(|&op| match op.dtype() {
+                Some(dtype) => (op, vec![dtype]),
+                None => (op, quantized.clone()),
+            })
+            .collect();
+        Capabilities {
+            ops,
+            max_buffer_size: None,
+        }
+    }
+
+    /// The supported ops.
+    pub fn ops(&self) -> impl Iterator<Item = BackendOp> + '_ {
+        self.ops.iter().map(|(op, _)| *op)
+    }
+
+    /// The dtypes `op` runs on; empty if it is not supported.
+    pub fn dtypes(&self, op: BackendOp) -> &[DType] {
+        self.ops
+            .iter()
+            .find(|(o, _)| *o == op)
+            .map_or(&[], |(_, dtypes)| dtypes)
+    }
+
+    /// Whether `op` can run on weights or caches of `dtype`.
+    pub fn supports(&self, op: BackendOp, dtype: DType) -> bool {
+        self.dtypes(op).contains(&dtype)
+    }
+
+    /// Whether a buffer of `bytes` is within [`Capabilities::max_buffer_size`].
+    pub fn fits(&self, bytes: usize) -> bool {
+        self.max_buffer_size.is_none_or(|max| bytes <= max)
+    }
+
+    /// The supported ops that are fused kernels; see [`BackendOp::is_fused`].
+    pub fn fused_ops(&self) -> impl Iterator<Item = BackendOp> + '_ {
+        self.ops().filter(|op| op.is_fused())
+    }
+
+    /// What both `self` and `other` support.
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        let max_buffer_size = match (self.max_buffer_size, other.max_buffer_size) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let ops = self
+            .ops
+            .iter()
+            .filter_map(|(op, dtypes)| {
+                let theirs = other.dtypes(*op);
+                let both: Vec<DType> = dtypes
+                    .iter()
+                    .copied()
+                    .filter(|d| theirs.contains(d))
+                    .collect();
+                (!both.is_empty()).then_some((*op, both))
+            })
+            .collect();
+        Capabilities {
+            ops,
+            max_buffer_size,
+        }
+    }
+
+    /// What either `self` or `other` supports.
+    pub fn union(&self, other: &Capabilities) -> Capabilities {
+        let max_buffer_size = match (self.max_buffer_size, other.max_buffer_size) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+        let mut ops = self.ops.clone();
+        for (op, dtypes) in &other.ops {
+            match ops.iter_mut().find(|(o, _)| o == op) {
+                Some((_, ours)) => {
+                    let extra: Vec<DType> = dtypes
+                        .iter()
+                        .copied()
+                        .filter(|d| !ours.contains(d))
+                        .collect();
+                    ours.extend(extra);
+                }
+                None => ops.push((*op, dtypes.clone())),
+            }
+        }
+        Capabilities {
+            ops,
+            max_buffer_size,
+        }
+    }
+}
+
+/// The error for an op that `backend` does not implement.
+pub fn unsupported(backend: &str, op: BackendOp) -> TensorError {
+    TensorError::UnsupportedOp(format!("{} on the {} backend", op.name(), backend))
+}
 
 /// Trait for pluggable compute backends (CPU, Metal, CUDA, etc.).
 ///
-/// All operations work on f32 slices for Phase 1. Data is passed in as slices
-/// and returned as owned vectors. The backend is responsible for performing
-/// the computation and returning the result.
+/// Activations are always f32 slices. Weights may also be passed in their
+/// stored encoding: [`matmul_quantized_into`](Self::matmul_quantized_into)
+/// takes quantized blocks or BF16 and [`matmul_f16_into`](Self::matmul_f16_into) F16
+/// elements, both accumulating in f32, and
+/// [`attention_f16_into`](Self::attention_f16_into) reads an F16 KV cache.
+/// [`batched_matmul_into`](Self::batched_matmul_into) runs a stack of
+/// matmuls, optionally with a shared operand or transposed B.
+///
+/// Backends implement the `_into` and in-place forms, which write into
+/// caller-provided buffers, so a caller that keeps its buffers around (such
+/// as a model's scratch space) runs without heap allocations. The allocating
+/// forms are provided on top of them for convenience.
+///
+/// A backend need not implement every op: the defaults fail with
+/// [`TensorError::UnsupportedOp`], and [`ComputeBackend::capabilities`] lists
+/// the ops and weight dtypes a backend does implement.
+/// [`FallbackBackend`](crate::FallbackBackend) routes each op to the first of
+/// several backends whose capabilities cover it, so partial backends can be
+/// combined with a complete one such as the CPU backend.
+#[allow(unused_variables)]
 pub trait ComputeBackend: Send + Sync + Debug {
     /// Returns the name of this backend (e.g., "cpu", "metal").
     fn name(&self) -> &str;
 
-    /// Matrix multiplication: C = A @ B.
+    /// The ops, dtypes and buffer sizes this backend supports.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Matrix multiplication into `out`: C = A @ B.
     ///
     /// - `a`: row-major data of shape [m, k]
     /// - `b`: row-major data of shape [k, n]
-    /// - Returns: row-major data of shape [m, n]
-    fn matmul(&self, a: &[f32], b: &[f32], m: usize, k: usize, n: usize) -> Result<Vec<f32>>;
+    /// - `out`: row-major buffer of shape [m, n]; its contents are overwritten
+    fn matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Matmul))
+    }
 
-    /// Element-wise addition: result[i] = a[i] + b[i].
-    fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>>;
+    /// Batched matrix multiplication into `out`: C[i] = A[i] @ B[i] for each
+    /// entry of the batch, with operands shared between entries as
+    /// described in [`MatmulParams`].
+    ///
+    /// - `a`: `a_batch` row-major [m, k] matrices
+    /// - `b`: `b_batch` row-major [k, n] matrices, or [n, k] with
+    ///   `transpose_b`
+    /// - `out`: `batch` row-major [m, n] matrices; contents are overwritten
+    fn batched_matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        params: &MatmulParams,
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::BatchedMatmul))
+    }
 
-    /// Element-wise multiplication: result[i] = a[i] * b[i].
-    fn mul(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>>;
+    /// Quantized matrix-vector product into `out`: y = W @ x.
+    ///
+    /// The weights stay in their GGUF block encoding. The CPU backend
+    /// quantizes `x` to Q8_0 on the fly for Q4_0 and Q8_0 weights, so the
+    /// inner loop runs on integer block dot products, and decodes the rows
+    /// of other formats one at a time.
+    ///
+    /// - `w`: raw blocks of a row-major [m, k] matrix in `dtype` (any format
+    ///   but F32 and F16, which have [`matmul_into`](Self::matmul_into) and
+    ///   [`matmul_f16_into`](Self::matmul_f16_into))
+    /// - `x`: f32 activation vector of length k (k must be a multiple of
+    ///   `dtype.block_size()`)
+    /// - `out`: f32 buffer of length m
+    fn matmul_quantized_into(
+        &self,
+        w: &[u8],
+        dtype: DType,
+        x: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::MatmulQuantized))
+    }
 
-    /// Scalar multiplication: result[i] = a[i] * s.
-    fn scale(&self, a: &[f32], s: f32) -> Result<Vec<f32>>;
+    /// Mixed-precision matrix multiplication into `out`: C = A @ B with A
+    /// in f16, B in f32 and every product accumulated in f32.
+    ///
+    /// F16 weights are used in place, at half the memory and bandwidth of
+    /// widening them to f32 first.
+    ///
+    /// - `a`: little-endian f16 bytes of a row-major [m, k] matrix
+    /// - `b`: row-major data of shape [k, n]
+    /// - `out`: row-major buffer of shape [m, n]; its contents are overwritten
+    fn matmul_f16_into(
+        &self,
+        a: &[u8],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::MatmulF16))
+    }
+
+    /// In-place element-wise addition: a[i] += b[i].
+    fn add_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Add))
+    }
+
+    /// In-place element-wise multiplication: a[i] *= b[i].
+    fn mul_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Mul))
+    }
+
+    /// In-place scalar multiplication: a[i] *= s.
+    fn scale_inplace(&self, a: &mut [f32], s: f32) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Scale))
+    }
+
+    /// Strided element-wise binary operation into `out`:
+    /// out[i] = op(a[a_layout[i]], b[b_layout[i]]).
+    ///
+    /// Both layouts must already have the output's shape (see
+    /// [`Layout::broadcast_as`]); broadcast dimensions have stride 0. `out`
+    /// is contiguous with one element per position of that shape.
+    fn binary_into(
+        &self,
+        op: BinaryOp,
+        a: &[f32],
+        a_layout: &Layout,
+        b: &[f32],
+        b_layout: &Layout,
+        out: &mut [f32],
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Binary))
+    }
+
+    /// Strided reduction of dimension `dim` of `x` into `out`.
+    ///
+    /// `out` is contiguous with the layout's shape minus dimension `dim`.
+    /// `Max` and `ArgMax` need a non-empty dimension.
+    fn reduce_into(
+        &self,
+        op: ReduceOp,
+        x: &[f32],
+        layout: &Layout,
+        dim: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Reduce))
+    }
 
-    /// RMS normalization.
+    /// RMS normalization into `out`, which must be as long as `x`.
     ///
     /// For each row of `hidden_size` elements in `x`:
     ///   rms = sqrt(mean(x^2) + eps)
-    ///   result[i] = x[i] * weight[i] / rms
+    ///   out[i] = x[i] * weight[i] / rms
     ///
     /// - `x`: input data, length must be a multiple of `hidden_size`
     /// - `weight`: per-element scale weights, length == `hidden_size`
     /// - `eps`: small constant for numerical stability
     /// - `hidden_size`: size of each row to normalize
-    fn rms_norm(
+    fn rms_norm_into(
         &self,
         x: &[f32],
         weight: &[f32],
         eps: f32,
         hidden_size: usize,
-    ) -> Result<Vec<f32>>;
+        out: &mut [f32],
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::RmsNorm))
+    }
 
-    /// Softmax over chunks of `n_vocab` elements.
+    /// Softmax over chunks of `n_vocab` elements into `out`, which must be as
+    /// long as `x`.
     ///
-    /// For each chunk: result[i] = exp(x[i] - max(x)) / sum(exp(x[j] - max(x)))
-    fn softmax(&self, x: &[f32], n_vocab: usize) -> Result<Vec<f32>>;
+    /// For each chunk: out[i] = exp(x[i] - max(x)) / sum(exp(x[j] - max(x)))
+    fn softmax_into(&self, x: &[f32], n_vocab: usize, out: &mut [f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Softmax))
+    }
 
-    /// Rotary Position Embedding (RoPE).
-    ///
-    /// Applies rotary embeddings to query and key tensors.
+    /// Rotary Position Embedding (RoPE), applied in place.
     ///
     /// - `q`: query data, shape [n_heads_q, head_dim]
     /// - `k`: key data, shape [n_heads_k, head_dim]
-    /// - `head_dim`: dimension of each attention head
-    /// - `pos`: token position for computing rotation angles
+    /// - `rope`: head_dim, rotated dimensions, pairing and the precomputed
+    ///   cos/sin tables
+    /// - `pos`: token position, which must be covered by `rope`
     /// - `n_heads_q`: number of query heads
     /// - `n_heads_k`: number of key heads
+    fn rope_inplace(
+        &self,
+        q: &mut [f32],
+        k: &mut [f32],
+        rope: &RopeTable,
+        pos: usize,
+        n_heads_q: usize,
+        n_heads_k: usize,
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Rope))
+    }
+
+    /// Scaled dot-product attention of one or more query tokens over a KV
+    /// cache, written into `out`.
+    ///
+    /// - `q`: queries, shape [n_tokens, n_heads, head_dim]
+    /// - `kv`: cached keys and values, including the query tokens' own
+    /// - `q_pos`: position of the first query token; token t is at
+    ///   `q_pos + t`, which is what `params.mask` sees
+    /// - `out`: shape [n_tokens, n_heads, head_dim]
+    ///
+    /// For each token and head: out = softmax(mask(softcap(scale * q·K))) @ V
+    /// over the matching KV head. A head with no visible keys gets zeros.
+    /// With the causal mask a whole prompt can be processed in one call
+    /// once its keys and values are in the cache.
+    fn attention_into(
+        &self,
+        q: &[f32],
+        kv: KvView<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Attention))
+    }
+
+    /// [`ComputeBackend::attention_into`] over a cache stored as f16. Scores
+    /// and the weighted sum of values are accumulated in f32.
+    fn attention_f16_into(
+        &self,
+        q: &[f32],
+        kv: KvViewF16<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::AttentionF16))
+    }
+
+    /// In-place SiLU activation: x[i] = x[i] / (1 + exp(-x[i])).
+    fn silu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Silu))
+    }
+
+    /// In-place GELU activation, tanh approximation:
+    /// x[i] = 0.5 * x[i] * (1 + tanh(sqrt(2/pi) * (x[i] + 0.044715 * x[i]^3))).
+    fn gelu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Gelu))
+    }
+
+    /// In-place exact GELU activation: x[i] = 0.5 * x[i] * (1 + erf(x[i] / sqrt(2))).
+    fn gelu_erf_inplace(&self, x: &mut [f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::GeluErf))
+    }
+
+    /// In-place GeGLU gating: gate[i] = gelu(gate[i]) * up[i], with the tanh
+    /// GELU approximation. `gate` and `up` must have the same length.
+    fn geglu_inplace(&self, gate: &mut [f32], up: &[f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Geglu))
+    }
+
+    /// In-place squared ReLU: x[i] = max(x[i], 0)^2.
+    fn relu2_inplace(&self, x: &mut [f32]) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Relu2))
+    }
+
+    /// In-place tanh soft-cap: x[i] = cap * tanh(x[i] / cap). `cap` must be
+    /// positive.
+    fn softcap_inplace(&self, x: &mut [f32], cap: f32) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::Softcap))
+    }
+
+    /// LayerNorm over chunks of `hidden_size` elements into `out`, which must
+    /// be as long as `x`.
+    ///
+    /// For each chunk:
+    /// out[i] = (x[i] - mean(x)) / sqrt(var(x) + eps) * weight[i] + bias[i],
+    /// where the bias term is skipped if `bias` is `None`.
+    fn layer_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        Err(unsupported(self.name(), BackendOp::LayerNorm))
+    }
+
+    /// Matrix multiplication: C = A @ B.
+    ///
+    /// - `a`: row-major data of shape [m, k]
+    /// - `b`: row-major data of shape [k, n]
+    /// - Returns: row-major data of shape [m, n]
+    fn matmul(&self, a: &[f32], b: &[f32], m: usize, k: usize, n: usize) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; m * n];
+        self.matmul_into(a, b, &mut out, m, k, n)?;
+        Ok(out)
+    }
+
+    /// Batched matrix multiplication; see
+    /// [`ComputeBackend::batched_matmul_into`]. Returns `batch` row-major
+    /// [m, n] matrices.
+    fn batched_matmul(&self, a: &[f32], b: &[f32], params: &MatmulParams) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; params.batch * params.m * params.n];
+        self.batched_matmul_into(a, b, &mut out, params)?;
+        Ok(out)
+    }
+
+    /// Quantized matrix-vector product: y = W @ x.
+    ///
+    /// See [`ComputeBackend::matmul_quantized_into`]. Returns f32 data of
+    /// length m.
+    fn matmul_quantized(
+        &self,
+        w: &[u8],
+        dtype: DType,
+        x: &[f32],
+        m: usize,
+        k: usize,
+    ) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; m];
+        self.matmul_quantized_into(w, dtype, x, &mut out, m, k)?;
+        Ok(out)
+    }
+
+    /// Mixed-precision matrix multiplication; see
+    /// [`ComputeBackend::matmul_f16_into`]. Returns row-major data of shape
+    /// [m, n].
+    fn matmul_f16(&self, a: &[u8], b: &[f32], m: usize, k: usize, n: usize) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; m * n];
+        self.matmul_f16_into(a, b, &mut out, m, k, n)?;
+        Ok(out)
+    }
+
+    /// Element-wise addition: result[i] = a[i] + b[i].
+    fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>> {
+        let mut out = a.to_vec();
+        self.add_inplace(&mut out, b)?;
+        Ok(out)
+    }
+
+    /// Element-wise multiplication: result[i] = a[i] * b[i].
+    fn mul(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>> {
+        let mut out = a.to_vec();
+        self.mul_inplace(&mut out, b)?;
+        Ok(out)
+    }
+
+    /// Scalar multiplication: result[i] = a[i] * s.
+    fn scale(&self, a: &[f32], s: f32) -> Result<Vec<f32>> {
+        let mut out = a.to_vec();
+        self.scale_inplace(&mut out, s)?;
+        Ok(out)
+    }
+
+    /// RMS normalization; see [`ComputeBackend::rms_norm_into`].
+    fn rms_norm(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        eps: f32,
+        hidden_size: usize,
+    ) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; x.len()];
+        self.rms_norm_into(x, weight, eps, hidden_size, &mut out)?;
+        Ok(out)
+    }
+
+    /// LayerNorm; see [`ComputeBackend::layer_norm_into`].
+    fn layer_norm(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        eps: f32,
+        hidden_size: usize,
+    ) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; x.len()];
+        self.layer_norm_into(x, weight, bias, eps, hidden_size, &mut out)?;
+        Ok(out)
+    }
+
+    /// Softmax over chunks of `n_vocab` elements; see
+    /// [`ComputeBackend::softmax_into`].
+    fn softmax(&self, x: &[f32], n_vocab: usize) -> Result<Vec<f32>> {
+        let mut out = vec![0.0f32; x.len()];
+        self.softmax_into(x, n_vocab, &mut out)?;
+        Ok(out)
+    }
+
+    /// Rotary Position Embedding (RoPE); see [`ComputeBackend::rope_inplace`].
     ///
     /// Returns (rotated_q, rotated_k).
     fn rope(
         &self,
         q: &[f32],
         k: &[f32],
-        head_dim: usize,
+        rope: &RopeTable,
         pos: usize,
         n_heads_q: usize,
         n_heads_k: usize,
-    ) -> Result<(Vec<f32>, Vec<f32>)>;
+    ) -> Result<(Vec<f32>, Vec<f32>)> {
+        let mut q_out = q.to_vec();
+        let mut k_out = k.to_vec();
+        self.rope_inplace(&mut q_out, &mut k_out, rope, pos, n_heads_q, n_heads_k)?;
+        Ok((q_out, k_out))
+    }
 
     /// SiLU activation: result[i] = x[i] * sigmoid(x[i]) = x[i] / (1 + exp(-x[i])).
-    fn silu(&self, x: &[f32]) -> Result<Vec<f32>>;
+    fn silu(&self, x: &[f32]) -> Result<Vec<f32>> {
+        let mut out = x.to_vec();
+        self.silu_inplace(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Implement [`ComputeBackend`] for a pointer type by forwarding every op to
+/// the backend it points at, so wrappers such as
+/// [`ProfilingBackend`](crate::ProfilingBackend) accept `&dyn ComputeBackend`
+/// and `Box<dyn ComputeBackend>`.
+macro_rules! forward_backend {
+    ($($ptr:ty),*) => {$(
+        impl<T: ComputeBackend + ?Sized> ComputeBackend for $ptr {
+            fn name(&self) -> &str {
+                (**self).name()
+            }
+
+            fn capabilities(&self) -> Capabilities {
+                (**self).capabilities()
+            }
+
+            fn matmul_into(
+                &self,
+                a: &[f32],
+                b: &[f32],
+                out: &mut [f32],
+                m: usize,
+                k: usize,
+                n: usize,
+            ) -> Result<()> {
+                (**self).matmul_into(a, b, out, m, k, n)
+            }
+
+            fn batched_matmul_into(
+                &self,
+                a: &[f32],
+                b: &[f32],
+                out: &mut [f32],
+                params: &MatmulParams,
+            ) -> Result<()> {
+                (**self).batched_matmul_into(a, b, out, params)
+            }
+
+            fn matmul_quantized_into(
+                &self,
+                w: &[u8],
+                dtype: DType,
+                x: &[f32],
+                out: &mut [f32],
+                m: usize,
+                k: usize,
+            ) -> Result<()> {
+                (**self).matmul_quantized_into(w, dtype, x, out, m, k)
+            }
+
+            fn matmul_f16_into(
+                &self,
+                a: &[u8],
+                b: &[f32],
+                out: &mut [f32],
+                m: usize,
+                k: usize,
+                n: usize,
+            ) -> Result<()> {
+                (**self).matmul_f16_into(a, b, out, m, k, n)
+            }
+
+            fn add_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+                (**self).add_inplace(a, b)
+            }
+
+            fn mul_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+                (**self).mul_inplace(a, b)
+            }
+
+            fn scale_inplace(&self, a: &mut [f32], s: f32) -> Result<()> {
+                (**self).scale_inplace(a, s)
+            }
+
+            fn binary_into(
+                &self,
+                op: BinaryOp,
+                a: &[f32],
+                a_layout: &Layout,
+                b: &[f32],
+                b_layout: &Layout,
+                out: &mut [f32],
+            ) -> Result<()> {
+                (**self).binary_into(op, a, a_layout, b, b_layout, out)
+            }
+
+            fn reduce_into(
+                &self,
+                op: ReduceOp,
+                x: &[f32],
+                layout: &Layout,
+                dim: usize,
+                out: &mut [f32],
+            ) -> Result<()> {
+                (**self).reduce_into(op, x, layout, dim, out)
+            }
+
+            fn rms_norm_into(
+                &self,
+                x: &[f32],
+                weight: &[f32],
+                eps: f32,
+                hidden_size: usize,
+                out: &mut [f32],
+            ) -> Result<()> {
+                (**self).rms_norm_into(x, weight, eps, hidden_size, out)
+            }
+
+            fn softmax_into(&self, x: &[f32], n_vocab: usize, out: &mut [f32]) -> Result<()> {
+                (**self).softmax_into(x, n_vocab, out)
+            }
+
+            fn rope_inplace(
+                &self,
+                q: &mut [f32],
+                k: &mut [f32],
+                rope: &RopeTable,
+                pos: usize,
+                n_heads_q: usize,
+                n_heads_k: usize,
+            ) -> Result<()> {
+                (**self).rope_inplace(q, k, rope, pos, n_heads_q, n_heads_k)
+            }
+
+            fn attention_into(
+                &self,
+                q: &[f32],
+                kv: KvView<'_>,
+                q_pos: usize,
+                params: &AttentionParams,
+                out: &mut [f32],
+            ) -> Result<()> {
+                (**self).attention_into(q, kv, q_pos, params, out)
+            }
+
+            fn attention_f16_into(
+                &self,
+                q: &[f32],
+                kv: KvViewF16<'_>,
+                q_pos: usize,
+                params: &AttentionParams,
+                out: &mut [f32],
+            ) -> Result<()> {
+                (**self).attention_f16_into(q, kv, q_pos, params, out)
+            }
+
+            fn silu_inplace(&self, x: &mut [f32]) -> Result<()> {
+                (**self).silu_inplace(x)
+            }
+
+            fn gelu_inplace(&self, x: &mut [f32]) -> Result<()> {
+                (**self).gelu_inplace(x)
+            }
+
+            fn gelu_erf_inplace(&self, x: &mut [f32]) -> Result<()> {
+                (**self).gelu_erf_inplace(x)
+            }
+
+            fn geglu_inplace(&self, gate: &mut [f32], up: &[f32]) -> Result<()> {
+                (**self).geglu_inplace(gate, up)
+            }
+
+            fn relu2_inplace(&self, x: &mut [f32]) -> Result<()> {
+                (**self).relu2_inplace(x)
+            }
+
+            fn softcap_inplace(&self, x: &mut [f32], cap: f32) -> Result<()> {
+                (**self).softcap_inplace(x, cap)
+            }
+
+            fn layer_norm_into(
+                &self,
+                x: &[f32],
+                weight: &[f32],
+                bias: Option<&[f32]>,
+                eps: f32,
+                hidden_size: usize,
+                out: &mut [f32],
+            ) -> Result<()> {
+                (**self).layer_norm_into(x, weight, bias, eps, hidden_size, out)
+            }
+        }
+    )*};
 }
+
+forward_backend!(&T, Box<T>);
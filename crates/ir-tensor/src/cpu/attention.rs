@@ -0,0 +1,130 @@
+// Tiled scaled-dot-product attention for one query row.
+//
+// Keys are processed in tiles of KEY_TILE positions, flash-attention style:
+// a tile's scores go into a stack buffer, the running maximum is raised to
+// the tile maximum once, and the accumulator and running sum are rescaled
+// by a single correction factor before the tile's values are folded in.
+// Nothing is buffered per key beyond one tile, so memory use is
+// independent of the sequence length and the op never allocates.
+//
+// The cache is read through `KvRows`, so the same loop serves f32 and f16
+// caches; f16 rows are widened as they are used.
+
+use std::ops::Range;
+
+use half::f16;
+
+use crate::backend::{AttentionParams, KvView, KvViewF16};
+
+use super::simd::Kernels;
+use super::unary;
+
+/// Keys scored per tile. 64 scores fit in four cache lines, and for common
+/// head sizes a tile of keys and values stays in L1/L2 while it is reused.
+pub const KEY_TILE: usize = 64;
+
+/// Cached keys and values in one of the supported encodings.
+pub trait KvRows {
+    /// `q · k[offset..offset + q.len()]`.
+    fn dot_key(&self, kernels: &Kernels, q: &[f32], offset: usize) -> f32;
+
+    /// `out[i] += p * v[offset + i]`.
+    fn add_value(&self, out: &mut [f32], p: f32, offset: usize);
+}
+
+impl KvRows for KvView<'_> {
+    #[inline]
+    fn dot_key(&self, kernels: &Kernels, q: &[f32], offset: usize) -> f32 {
+        kernels.dot_f32(q, &self.k[offset..offset + q.len()])
+    }
+
+    #[inline]
+    fn add_value(&self, out: &mut [f32], p: f32, offset: usize) {
+        let v = &self.v[offset..offset + out.len()];
+        for (o, &v) in out.iter_mut().zip(v) {
+            *o += p * v;
+        }
+    }
+}
+
+impl KvRows for KvViewF16<'_> {
+    #[inline]
+    fn dot_key(&self, kernels: &Kernels, q: &[f32], offset: usize) -> f32 {
+        kernels.dot_f16_f32(&self.k[2 * offset..2 * (offset + q.len())], q)
+    }
+
+    #[inline]
+    fn add_value(&self, out: &mut [f32], p: f32, offset: usize) {
+        let v = &self.v[2 * offset..2 * (offset + out.len())];
+        for (o, v) in out.iter_mut().zip(v.chunks_exact(2)) {
+            *o += p * f16::from_le_bytes([v[0], v[1]]).to_f32();
+        }
+    }
+}
+
+/// Attend query head `h` of query row `q_head` (`head_dim` elements) over
+/// the keys in `keys`, writing the result to `out` (`head_dim` elements).
+/// With no keys in range, `out` is zeroed.
+pub fn attend_head(
+    kernels: &Kernels,
+    q_head: &[f32],
+    kv: &impl KvRows,
+    keys: Range<usize>,
+    params: &AttentionParams,
+    h: usize,
+    out: &mut [f32],
+) {
+    let head_dim = params.head_dim;
+    let kv_dim = params.kv_dim();
+    let head_offset = h / (params.n_heads / params.n_kv_heads) * head_dim;
+
+    out.fill(0.0);
+    let mut max = f32::NEG_INFINITY;
+    let mut sum = 0.0f32;
+    let mut scores = [0.0f32; KEY_TILE];
+
+    let mut tile_start = keys.start;
+    while tile_start < keys.end {
+        let tile_end = (tile_start + KEY_TILE).min(keys.end);
+        let scores = &mut scores[..tile_end - tile_start];
+
+        // Score the tile and find its maximum.
+        let mut tile_max = f32::NEG_INFINITY;
+        for (s, score) in (tile_start..tile_end).zip(scores.iter_mut()) {
+            let offset = s * kv_dim + head_offset;
+            let mut x = kv.dot_key(kernels, q_head, offset) * params.scale;
+            if let Some(cap) = params.softcap {
+                x = unary::softcap(x, cap);
+            }
+            *score = x;
+            tile_max = tile_max.max(x);
+        }
+
+        // Rescale what has been accumulated so far to the new maximum.
+        // On the first tile exp(-inf) = 0 multiplies zeros, which is a no-op.
+        if tile_max > max {
+            let correction = (max - tile_max).exp();
+            sum *= correction;
+            for o in out.iter_mut() {
+                *o *= correction;
+            }
+            max = tile_max;
+        }
+
+        // Fold the tile's values in with weights exp(score - max).
+        for (s, &score) in (tile_start..tile_end).zip(scores.iter()) {
+            let p = (score - max).exp();
+            sum += p;
+            kv.add_value(out, p, s * kv_dim + head_offset);
+        }
+
+        tile_start = tile_end;
+    }
+
+    if sum > 0.0 {
+        let inv = 1.0 / sum;
+        for o in out.iter_mut() {
+            *o *= inv;
+        }
+    }
+}
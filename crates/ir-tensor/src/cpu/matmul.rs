@@ -1,5 +1,256 @@
-// Matmul helper module.
+// Cache-blocked GEMM for the [m, k] @ [k, n] case with n > 1.
 //
-// The core matmul implementation lives in CpuBackend::matmul (cpu/mod.rs).
-// This module is reserved for future optimizations such as tiled/blocked
-// matmul, SIMD kernels, or cache-friendly access patterns.
+// Follows the usual Goto/BLIS structure: B is packed once into NR-wide
+// column strips per KC-deep block, each worker packs MC x KC blocks of its
+// rows of A into MR-tall row strips, and an MR x NR register-blocked
+// microkernel multiplies one A strip by one B strip. Strips at the matrix
+// edges are zero-padded, so the microkernel never sees a partial tile.
+//
+// Every element of C is accumulated in the same order (per KC block, then
+// across blocks) no matter how rows are split between workers, so results
+// do not depend on the thread count.
+
+/// Rows per microkernel tile.
+pub const MR: usize = 4;
+/// Columns per microkernel tile.
+pub const NR: usize = 16;
+/// Depth of one packed block (sized so a B strip stays in L1).
+const KC: usize = 256;
+/// Rows of A packed at a time (sized so an A block stays in L2).
+const MC: usize = 64;
+
+/// One MR x NR output tile.
+pub type Tile = [[f32; NR]; MR];
+
+/// Microkernel: `tile = a_strip @ b_strip` over `kc` steps, where `a_strip`
+/// holds `kc` groups of MR values and `b_strip` `kc` groups of NR values.
+pub type Microkernel = fn(usize, &[f32], &[f32], &mut Tile);
+
+/// Portable microkernel body. Marked `inline(always)` so the SIMD variants
+/// can instantiate it under their own `#[target_feature]` and let the
+/// compiler vectorize the NR-wide rows.
+#[inline(always)]
+pub fn microkernel(kc: usize, a: &[f32], b: &[f32], tile: &mut Tile) {
+    let a = &a[..kc * MR];
+    let b = &b[..kc * NR];
+    let mut acc = [[0.0f32; NR]; MR];
+    for (ap, bp) in a.chunks_exact(MR).zip(b.chunks_exact(NR)) {
+        for (acc_row, &av) in acc.iter_mut().zip(ap) {
+            for (c, &bv) in acc_row.iter_mut().zip(bp) {
+                *c += av * bv;
+            }
+        }
+    }
+    *tile = acc;
+}
+
+/// The microkernel compiled for the baseline target.
+pub fn microkernel_scalar(kc: usize, a: &[f32], b: &[f32], tile: &mut Tile) {
+    microkernel(kc, a, b, tile)
+}
+
+/// Pack row-major `b` ([k, n]) into KC-deep blocks of NR-wide column strips.
+///
+/// Block `pc` starts at offset `pc * n_strips * NR` and holds `n_strips`
+/// strips of `kc * NR` values each, zero-padded past column `n`.
+pub fn pack_b(b: &[f32], k: usize, n: usize) -> Vec<f32> {
+    let n_strips = n.div_ceil(NR);
+    let mut packed = vec![0.0f32; k * n_strips * NR];
+    let mut dst = packed.chunks_exact_mut(NR);
+    for pc in (0..k).step_by(KC) {
+        let kc = KC.min(k - pc);
+        for jr in 0..n_strips {
+            let j0 = jr * NR;
+            let width = NR.min(n - j0);
+            for p in pc..pc + kc {
+                let out = dst.next().expect("packed B sized for every strip");
+                out[..width].copy_from_slice(&b[p * n + j0..p * n + j0 + width]);
+            }
+        }
+    }
+    packed
+}
+
+/// Transpose row-major `b` ([k, n]) into row-major [n, k], so each column
+/// of `b` is a contiguous row.
+pub fn transpose(b: &[f32], k: usize, n: usize) -> Vec<f32> {
+    let mut out = vec![0.0f32; k * n];
+    for (p, row) in b.chunks_exact(n.max(1)).take(k).enumerate() {
+        for (j, &v) in row.iter().enumerate() {
+            out[j * k + p] = v;
+        }
+    }
+    out
+}
+
+/// Pack rows `row0..row0 + mc`, columns `pc..pc + kc` of row-major `a`
+/// ([_, k]) into MR-tall strips of `kc` groups of MR values, zero-padded past
+/// the last row.
+fn pack_a(a: &[f32], k: usize, row0: usize, mc: usize, pc: usize, kc: usize, out: &mut [f32]) {
+    for (s, strip) in out
+        .chunks_exact_mut(kc * MR)
+        .take(mc.div_ceil(MR))
+        .enumerate()
+    {
+        let rows = MR.min(mc - s * MR);
+        for (p, group) in strip.chunks_exact_mut(MR).enumerate() {
+            for (i, v) in group.iter_mut().enumerate() {
+                *v = if i < rows {
+                    a[(row0 + s * MR + i) * k + pc + p]
+                } else {
+                    0.0
+                };
+            }
+        }
+    }
+}
+
+/// Compute rows `row0..row0 + c.len() / n` of `C = A @ B` into `c`, which
+/// must start zeroed. `packed_b` comes from [`pack_b`].
+pub fn gemm_rows(
+    micro: Microkernel,
+    a: &[f32],
+    packed_b: &[f32],
+    c: &mut [f32],
+    row0: usize,
+    k: usize,
+    n: usize,
+) {
+    let rows = c.len() / n;
+    let n_strips = n.div_ceil(NR);
+    let mut a_pack = vec![0.0f32; MC.div_ceil(MR) * MR * KC.min(k)];
+    let mut tile = [[0.0f32; NR]; MR];
+
+    for pc in (0..k).step_by(KC) {
+        let kc = KC.min(k - pc);
+        let b_block = &packed_b[pc * n_strips * NR..];
+        for ic in (0..rows).step_by(MC) {
+            let mc = MC.min(rows - ic);
+            pack_a(a, k, row0 + ic, mc, pc, kc, &mut a_pack);
+            for jr in 0..n_strips {
+                let b_strip = &b_block[jr * kc * NR..(jr + 1) * kc * NR];
+                let j0 = jr * NR;
+                let width = NR.min(n - j0);
+                for ir in (0..mc).step_by(MR) {
+                    micro(kc, &a_pack[ir * kc..(ir + MR) * kc], b_strip, &mut tile);
+                    for (i, tile_row) in tile.iter().take(MR.min(mc - ir)).enumerate() {
+                        let start = (ic + ir + i) * n + j0;
+                        for (c, t) in c[start..start + width].iter_mut().zip(tile_row) {
+                            *c += t;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ComputeBackend;
+    use crate::cpu::CpuBackend;
+    use crate::cpu::simd::SimdLevel;
+    use crate::test_util::values;
+
+    /// Naive triple loop in f64, with the sum of |a||b| per element to scale
+    /// the tolerance.
+    fn reference(a: &[f32], b: &[f32], m: usize, k: usize, n: usize) -> Vec<(f64, f64)> {
+        let mut c = vec![(0.0, 0.0); m * n];
+        for i in 0..m {
+            for j in 0..n {
+                let (mut sum, mut mag) = (0.0f64, 0.0f64);
+                for p in 0..k {
+                    let prod = a[i * k + p] as f64 * b[p * n + j] as f64;
+                    sum += prod;
+                    mag += prod.abs();
+                }
+                c[i * n + j] = (sum, mag);
+            }
+        }
+        c
+    }
+
+    fn check(backend: &CpuBackend, m: usize, k: usize, n: usize) {
+        let a = values(m * k, (m * 31 + k) as u32);
+        let b = values(k * n, (n * 17 + k) as u32);
+        let got = backend.matmul(&a, &b, m, k, n).unwrap();
+        for (idx, (g, (want, mag))) in got.iter().zip(reference(&a, &b, m, k, n)).enumerate() {
+            let err = (*g as f64 - want).abs();
+            assert!(
+                err <= 1e-5 * mag.max(1.0),
+                "[{}x{}x{}] element {}: got {} want {}",
+                m,
+                k,
+                n,
+                idx,
+                g,
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn test_gemm_odd_shapes() {
+        let backend = CpuBackend::with_threads(1).unwrap();
+        for &(m, k, n) in &[
+            (1, 1, 2),
+            (2, 3, 2),
+            (3, 5, 7),
+            (MR, 8, NR),
+            (MR + 1, 9, NR + 1),
+            (MR - 1, 17, NR - 1),
+            (13, 1, 33),
+            (7, 31, 3),
+            (MC + 3, 19, 2 * NR + 5),
+            (5, KC, 18),
+            (6, KC + 1, 20),
+            (9, 2 * KC + 37, 35),
+            (70, 300, 50),
+        ] {
+            check(&backend, m, k, n);
+        }
+    }
+
+    #[test]
+    fn test_gemm_every_simd_level() {
+        for level in SimdLevel::supported() {
+            let backend = CpuBackend::with_threads(1)
+                .unwrap()
+                .with_simd_level(level)
+                .unwrap();
+            check(&backend, MC + 5, KC + 9, 3 * NR - 1);
+        }
+    }
+
+    #[test]
+    fn test_gemm_threaded_matches_single_thread() {
+        let (m, k, n) = (MC * 2 + 7, KC + 13, 2 * NR + 3);
+        let a = values(m * k, 1);
+        let b = values(k * n, 2);
+        let single = CpuBackend::with_threads(1)
+            .unwrap()
+            .matmul(&a, &b, m, k, n)
+            .unwrap();
+        let multi = CpuBackend::with_threads(3)
+            .unwrap()
+            .matmul(&a, &b, m, k, n)
+            .unwrap();
+        assert_eq!(single, multi);
+    }
+
+    #[test]
+    fn test_pack_b_layout() {
+        // k=2, n=NR+1: two strips, the second holding one real column.
+        let n = NR + 1;
+        let b: Vec<f32> = (0..2 * n).map(|v| v as f32).collect();
+        let packed = pack_b(&b, 2, n);
+        assert_eq!(packed.len(), 2 * 2 * NR);
+        assert_eq!(&packed[..NR], &b[..NR]);
+        assert_eq!(&packed[NR..2 * NR], &b[n..n + NR]);
+        // Second strip: column NR of each row, then zero padding.
+        assert_eq!(packed[2 * NR], NR as f32);
+        assert_eq!(packed[2 * NR + 1], 0.0);
+        assert_eq!(packed[3 * NR], (n + NR) as f32);
+    }
+}
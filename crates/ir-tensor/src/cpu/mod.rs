@@ -1,21 +1,255 @@
+pub mod attention;
 pub mod matmul;
+pub mod quant;
+pub mod reduce;
+pub mod simd;
 pub mod unary;
 
-use crate::backend::ComputeBackend;
+use std::cell::RefCell;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::backend::{
+    AttentionParams, BinaryOp, Capabilities, ComputeBackend, KvView, KvViewF16, MatmulParams,
+    ReduceOp,
+};
+use crate::dtype::DType;
 use crate::error::{Result, TensorError};
+use crate::layout::Layout;
+use crate::rope::RopeTable;
+use reduce::Reduction;
+use simd::{KernelVariant, Kernels, SimdLevel};
 
 /// Pure-Rust CPU compute backend.
 ///
 /// Implements all operations with straightforward loops optimized for
 /// correctness rather than peak performance. Intended as a reference
 /// implementation and fallback.
+///
+/// Row-independent work (matmul rows, rms_norm rows, softmax chunks) is
+/// split across a persistent worker pool owned by the backend. Each output
+/// row is always computed by the same sequential loop, so results do not
+/// depend on the thread count.
+///
+/// Inner loops (dot products, silu, softmax, rms_norm) go through a kernel
+/// table picked at construction from the CPU's SIMD features; see
+/// [`CpuBackend::kernel_variants`]. Those kernels sum in an order that
+/// suits the instruction set, so results can differ in the last bits
+/// between machines; [`CpuBackend::with_reduction`] trades speed for sums
+/// in a fixed order that give the same bits everywhere.
 #[derive(Debug, Clone)]
-pub struct CpuBackend;
+pub struct CpuBackend {
+    n_threads: usize,
+    kernels: Kernels,
+    /// Worker pool, or `None` when running single-threaded.
+    pool: Option<Arc<WorkerPool>>,
+}
+
+/// The rayon pool behind a multithreaded `CpuBackend`.
+///
+/// rayon re-raises a panicking job on the thread that submitted it and
+/// keeps the pool usable afterwards, so the backend stays unwind safe (the
+/// FFI layer relies on that to catch panics around a context).
+#[derive(Debug)]
+struct WorkerPool(rayon::ThreadPool);
+
+impl UnwindSafe for WorkerPool {}
+impl RefUnwindSafe for WorkerPool {}
 
 impl CpuBackend {
+    /// Create a backend using one worker per available CPU core.
+    ///
+    /// If the worker threads cannot be spawned, all work runs on the calling
+    /// thread instead; use [`CpuBackend::with_threads`] to get the error.
     pub fn new() -> Self {
-        CpuBackend
+        let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_threads(n_threads).unwrap_or_else(|_| Self::single_threaded())
+    }
+
+    /// Create a backend with `n_threads` workers.
+    ///
+    /// `0` is treated as `1`. With a single thread no pool is created and all
+    /// work runs on the calling thread.
+    ///
+    /// # Errors
+    /// Returns an error if the worker threads cannot be spawned.
+    pub fn with_threads(n_threads: usize) -> Result<Self> {
+        if n_threads <= 1 {
+            return Ok(Self::single_threaded());
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(n_threads)
+            .thread_name(|i| format!("ir-cpu-{}", i))
+            .build()
+            .map_err(|e| {
+                TensorError::Other(format!(
+                    "failed to spawn {} CPU backend worker threads: {}",
+                    n_threads, e
+                ))
+            })?;
+        Ok(CpuBackend {
+            n_threads,
+            kernels: Kernels::detect(),
+            pool: Some(Arc::new(WorkerPool(pool))),
+        })
+    }
+
+    fn single_threaded() -> Self {
+        CpuBackend {
+            n_threads: 1,
+            kernels: Kernels::detect(),
+            pool: None,
+        }
+    }
+
+    /// Restrict the kernels to `level`, e.g. `SimdLevel::Scalar` to run the
+    /// reference loops.
+    ///
+    /// # Errors
+    /// Returns an error if the running CPU does not support `level`.
+    pub fn with_simd_level(mut self, level: SimdLevel) -> Result<Self> {
+        let reduction = self.kernels.reduction();
+        self.kernels = Kernels::for_level(level)
+            .ok_or_else(|| {
+                TensorError::Other(format!("simd level {} is not supported by this CPU", level))
+            })?
+            .with_reduction(reduction);
+        Ok(self)
+    }
+
+    /// Sum dot products, norms, softmax denominators and reductions in
+    /// `reduction` order.
+    ///
+    /// Any mode but [`Reduction::Native`] uses scalar kernels and computes
+    /// every matmul output as a single dot product, which is slower but
+    /// makes logits bit-exact across machines and thread counts.
+    pub fn with_reduction(mut self, reduction: Reduction) -> Self {
+        self.kernels = self.kernels.with_reduction(reduction);
+        self
+    }
+
+    /// The summation order this backend uses.
+    pub fn reduction(&self) -> Reduction {
+        self.kernels.reduction()
+    }
+
+    /// The variant selected for each kernel.
+    pub fn kernel_variants(&self) -> Vec<KernelVariant> {
+        self.kernels.variants()
+    }
+
+    /// Number of worker threads this backend uses.
+    pub fn n_threads(&self) -> usize {
+        self.n_threads
+    }
+
+    /// `out = a @ b` for row-major [m, k] and [k, n] operands whose lengths
+    /// have been checked; `m` is implied by `out.len() / n`.
+    fn matmul_unchecked(&self, a: &[f32], b: &[f32], out: &mut [f32], k: usize, n: usize) {
+        if n == 1 {
+            // Matrix-vector product: each output is a contiguous dot product.
+            self.for_each_row(out, 1, |i, out| {
+                out[0] = self.kernels.dot_f32(&a[i * k..(i + 1) * k], b);
+            });
+            return;
+        }
+        if self.kernels.reduction().is_deterministic() {
+            // Each output is one dot product of a row of A with a column
+            // of B, summed in the reduction's order.
+            let b_t = matmul::transpose(b, k, n);
+            self.for_each_row(out, n, |i, row| {
+                let a_row = &a[i * k..(i + 1) * k];
+                for (j, o) in row.iter_mut().enumerate() {
+                    *o = self.kernels.dot_f32(a_row, &b_t[j * k..(j + 1) * k]);
+                }
+            });
+            return;
+        }
+        // Pack B once; each worker then packs its own blocks of A rows.
+        out.fill(0.0);
+        let packed_b = matmul::pack_b(b, k, n);
+        let micro = self.kernels.gemm_microkernel();
+        self.for_each_row_block(out, n, |row0, rows| {
+            matmul::gemm_rows(micro, a, &packed_b, rows, row0, k, n);
+        });
     }
+
+    /// Call `f(i, row)` for each `row_len`-element row of `out`, spreading
+    /// rows over the worker pool in contiguous groups.
+    fn for_each_row<F>(&self, out: &mut [f32], row_len: usize, f: F)
+    where
+        F: Fn(usize, &mut [f32]) + Sync,
+    {
+        let row_len = row_len.max(1);
+        self.for_each_row_block(out, row_len, |first, rows| {
+            for (r, row) in rows.chunks_mut(row_len).enumerate() {
+                f(first + r, row);
+            }
+        });
+    }
+
+    /// Apply `f` to every element of `x` in place, in parallel chunks.
+    fn map_inplace<F>(&self, x: &mut [f32], f: F)
+    where
+        F: Fn(f32) -> f32 + Sync,
+    {
+        self.for_each_row_block(x, ELEMENTWISE_CHUNK, |_, block| {
+            for v in block {
+                *v = f(*v);
+            }
+        });
+    }
+
+    /// Split `out` into contiguous blocks of whole `row_len`-element rows and
+    /// call `f(first_row, block)` for each, one block per pool task.
+    fn for_each_row_block<F>(&self, out: &mut [f32], row_len: usize, f: F)
+    where
+        F: Fn(usize, &mut [f32]) + Sync,
+    {
+        let row_len = row_len.max(1);
+        let n_rows = out.len().div_ceil(row_len);
+        match &self.pool {
+            Some(pool) if n_rows > 1 => {
+                // A few groups per worker keeps the load balanced without
+                // paying scheduling overhead per row.
+                let rows_per_task = n_rows.div_ceil(self.n_threads * 4);
+                pool.0.install(|| {
+                    out.par_chunks_mut(rows_per_task * row_len)
+                        .enumerate()
+                        .for_each(|(task, rows)| f(task * rows_per_task, rows));
+                });
+            }
+            _ => f(0, out),
+        }
+    }
+}
+
+/// Elements staged per step by the in-place activations.
+const INPLACE_CHUNK: usize = 256;
+
+/// Elements per row when element-wise ops are split across the pool, so
+/// short vectors stay on the calling thread.
+const ELEMENTWISE_CHUNK: usize = 4096;
+
+thread_local! {
+    /// Q8_0 encoding of the activation vector in `matmul_quantized_into`.
+    static Q8_SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    /// One decoded weight row in `matmul_quantized_into`, for formats
+    /// without a block dot product.
+    static DEQUANT_SCRATCH: RefCell<Vec<f32>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Check that a caller-provided output buffer holds exactly `len` elements.
+fn check_out_len(out: &[f32], len: usize) -> Result<()> {
+    if out.len() != len {
+        return Err(TensorError::ShapeMismatch {
+            expected: vec![len],
+            got: vec![out.len()],
+        });
+    }
+    Ok(())
 }
 
 impl Default for CpuBackend {
@@ -29,7 +263,24 @@ impl ComputeBackend for CpuBackend {
         "cpu"
     }
 
-    fn matmul(&self, a: &[f32], b: &[f32], m: usize, k: usize, n: usize) -> Result<Vec<f32>> {
+    fn capabilities(&self) -> Capabilities {
+        // Every encoded format but the two with their own matmul ops.
+        let quantized = (0..64)
+            .filter_map(DType::from_gguf_type)
+            .filter(|d| !matches!(d, DType::F32 | DType::F16))
+            .collect();
+        Capabilities::all(quantized)
+    }
+
+    fn matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
         if a.len() != m * k {
             return Err(TensorError::Other(format!(
                 "matmul: a.len()={} but expected m*k={}",
@@ -44,51 +295,334 @@ impl ComputeBackend for CpuBackend {
                 k * n
             )));
         }
+        check_out_len(out, m * n)?;
+        self.matmul_unchecked(a, b, out, k, n);
+        Ok(())
+    }
 
-        let mut c = vec![0.0f32; m * n];
-        for i in 0..m {
-            for j in 0..n {
-                let mut sum = 0.0f32;
-                for p in 0..k {
-                    sum += a[i * k + p] * b[p * n + j];
+    fn batched_matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        params: &MatmulParams,
+    ) -> Result<()> {
+        params.validate(a, b, out)?;
+        let (m, k, n) = (params.m, params.k, params.n);
+        if out.is_empty() {
+            return Ok(());
+        }
+
+        if params.transpose_b {
+            // Rows of each B matrix are columns of the product, so every
+            // output is a contiguous dot product and nothing is transposed.
+            self.for_each_row(out, n, |r, row| {
+                let i = r / m;
+                let a_row = &a[(params.a_index(i) * m + r % m) * k..][..k];
+                let b_mat = &b[params.b_index(i) * n * k..][..n * k];
+                for (j, o) in row.iter_mut().enumerate() {
+                    *o = self.kernels.dot_f32(a_row, &b_mat[j * k..(j + 1) * k]);
                 }
-                c[i * n + j] = sum;
+            });
+            return Ok(());
+        }
+        for (i, out) in out.chunks_exact_mut(m * n).enumerate() {
+            let a = &a[params.a_index(i) * m * k..][..m * k];
+            let b = &b[params.b_index(i) * k * n..][..k * n];
+            self.matmul_unchecked(a, b, out, k, n);
+        }
+        Ok(())
+    }
+
+    fn matmul_quantized_into(
+        &self,
+        w: &[u8],
+        dtype: DType,
+        x: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+    ) -> Result<()> {
+        let kernels = &self.kernels;
+        if matches!(dtype, DType::F32 | DType::F16) {
+            return Err(TensorError::UnsupportedDType(format!(
+                "matmul_quantized: {} weights",
+                dtype
+            )));
+        }
+        if !k.is_multiple_of(dtype.block_size()) {
+            return Err(TensorError::Other(format!(
+                "matmul_quantized: k={} is not a multiple of {}",
+                k,
+                dtype.block_size()
+            )));
+        }
+        if x.len() != k {
+            return Err(TensorError::Other(format!(
+                "matmul_quantized: x.len()={} but expected k={}",
+                x.len(),
+                k
+            )));
+        }
+        let row_bytes = dtype.storage_size(k);
+        if w.len() != m * row_bytes {
+            return Err(TensorError::Other(format!(
+                "matmul_quantized: w.len()={} but expected {} bytes for [{}, {}] {}",
+                w.len(),
+                m * row_bytes,
+                m,
+                k,
+                dtype
+            )));
+        }
+        check_out_len(out, m)?;
+
+        let vec_dot = match dtype {
+            DType::Q4_0 => Kernels::vec_dot_q4_0_q8_0,
+            DType::Q8_0 => Kernels::vec_dot_q8_0_q8_0,
+            _ => {
+                // No block dot product for this format: decode each row into
+                // a per-thread f32 buffer and take an f32 dot product with x.
+                self.for_each_row_block(out, 1, |first, rows| {
+                    DEQUANT_SCRATCH.with_borrow_mut(|row| {
+                        for (r, o) in rows.iter_mut().enumerate() {
+                            let i = first + r;
+                            row.clear();
+                            crate::quant::dequantize_into(
+                                dtype,
+                                &w[i * row_bytes..(i + 1) * row_bytes],
+                                k,
+                                row,
+                            );
+                            *o = kernels.dot_f32(row, x);
+                        }
+                    })
+                });
+                return Ok(());
             }
+        };
+
+        // The quantized activations live in a per-thread buffer that only
+        // grows, so repeated calls do not allocate. The buffer is moved out
+        // while the rows run: a thread waiting on the pool may run other
+        // jobs meanwhile, and those can call back in here.
+        let mut x_q = Q8_SCRATCH.take();
+        x_q.resize(DType::Q8_0.storage_size(k), 0);
+        quant::quantize_row_q8_0(x, &mut x_q);
+        self.for_each_row(out, 1, |i, out| {
+            out[0] = vec_dot(kernels, &w[i * row_bytes..(i + 1) * row_bytes], &x_q);
+        });
+        Q8_SCRATCH.set(x_q);
+        Ok(())
+    }
+
+    fn matmul_f16_into(
+        &self,
+        a: &[u8],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        if a.len() != DType::F16.storage_size(m * k) {
+            return Err(TensorError::Other(format!(
+                "matmul_f16: a.len()={} but expected {} bytes for [{}, {}] f16",
+                a.len(),
+                DType::F16.storage_size(m * k),
+                m,
+                k
+            )));
+        }
+        if b.len() != k * n {
+            return Err(TensorError::Other(format!(
+                "matmul_f16: b.len()={} but expected k*n={}",
+                b.len(),
+                k * n
+            )));
         }
-        Ok(c)
+        check_out_len(out, m * n)?;
+
+        let row_bytes = 2 * k;
+        if n == 1 {
+            self.for_each_row(out, 1, |i, out| {
+                out[0] = self
+                    .kernels
+                    .dot_f16_f32(&a[i * row_bytes..(i + 1) * row_bytes], b);
+            });
+            return Ok(());
+        }
+        // Columns of B become contiguous rows, so every output is one mixed
+        // dot product and A is read once per row of output.
+        let b_t = matmul::transpose(b, k, n);
+        self.for_each_row(out, n, |i, row| {
+            let a_row = &a[i * row_bytes..(i + 1) * row_bytes];
+            for (j, o) in row.iter_mut().enumerate() {
+                *o = self.kernels.dot_f16_f32(a_row, &b_t[j * k..(j + 1) * k]);
+            }
+        });
+        Ok(())
     }
 
-    fn add(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>> {
+    fn add_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
         if a.len() != b.len() {
             return Err(TensorError::ShapeMismatch {
                 expected: vec![a.len()],
                 got: vec![b.len()],
             });
         }
-        Ok(a.iter().zip(b.iter()).map(|(x, y)| x + y).collect())
+        for (x, y) in a.iter_mut().zip(b) {
+            *x += y;
+        }
+        Ok(())
     }
 
-    fn mul(&self, a: &[f32], b: &[f32]) -> Result<Vec<f32>> {
+    fn mul_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
         if a.len() != b.len() {
             return Err(TensorError::ShapeMismatch {
                 expected: vec![a.len()],
                 got: vec![b.len()],
             });
         }
-        Ok(a.iter().zip(b.iter()).map(|(x, y)| x * y).collect())
+        for (x, y) in a.iter_mut().zip(b) {
+            *x *= y;
+        }
+        Ok(())
+    }
+
+    fn scale_inplace(&self, a: &mut [f32], s: f32) -> Result<()> {
+        for x in a.iter_mut() {
+            *x *= s;
+        }
+        Ok(())
+    }
+
+    fn binary_into(
+        &self,
+        op: BinaryOp,
+        a: &[f32],
+        a_layout: &Layout,
+        b: &[f32],
+        b_layout: &Layout,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let shape = a_layout.shape();
+        if b_layout.shape() != shape {
+            return Err(TensorError::ShapeMismatch {
+                expected: shape.dims().to_vec(),
+                got: b_layout.shape().dims().to_vec(),
+            });
+        }
+        check_out_len(out, shape.numel())?;
+        for (name, data, layout) in [("a", a, a_layout), ("b", b, b_layout)] {
+            if data.len() < layout.min_storage_len() {
+                return Err(TensorError::Other(format!(
+                    "{}: {}.len()={} is too short for its layout (needs {})",
+                    op.name(),
+                    name,
+                    data.len(),
+                    layout.min_storage_len()
+                )));
+            }
+        }
+        if out.is_empty() {
+            return Ok(());
+        }
+
+        // Walk the output one innermost row at a time; within a row each
+        // operand advances by a fixed stride (0 when broadcast).
+        let inner = shape.dims().last().copied().unwrap_or(1);
+        let stride = |l: &Layout| l.strides().last().copied().unwrap_or(0);
+        let (sa, sb) = (stride(a_layout), stride(b_layout));
+        self.for_each_row(out, inner, |r, row| {
+            let (ra, rb) = (a_layout.row_start(r), b_layout.row_start(r));
+            if sa == 1 && sb == 1 {
+                let (a, b) = (&a[ra..ra + inner], &b[rb..rb + inner]);
+                for ((o, &x), &y) in row.iter_mut().zip(a).zip(b) {
+                    *o = op.apply(x, y);
+                }
+            } else {
+                for (j, o) in row.iter_mut().enumerate() {
+                    *o = op.apply(a[ra + j * sa], b[rb + j * sb]);
+                }
+            }
+        });
+        Ok(())
     }
 
-    fn scale(&self, a: &[f32], s: f32) -> Result<Vec<f32>> {
-        Ok(a.iter().map(|x| x * s).collect())
+    fn reduce_into(
+        &self,
+        op: ReduceOp,
+        x: &[f32],
+        layout: &Layout,
+        dim: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let ndim = layout.shape().ndim();
+        if dim >= ndim {
+            return Err(TensorError::InvalidAxis { axis: dim, ndim });
+        }
+        if x.len() < layout.min_storage_len() {
+            return Err(TensorError::Other(format!(
+                "{}: x.len()={} is too short for its layout (needs {})",
+                op.name(),
+                x.len(),
+                layout.min_storage_len()
+            )));
+        }
+        let dims = layout.shape().dims();
+        let n = dims[dim];
+        let out_len: usize = dims
+            .iter()
+            .enumerate()
+            .filter(|&(d, _)| d != dim)
+            .map(|(_, &size)| size)
+            .product();
+        check_out_len(out, out_len)?;
+        if n == 0 && matches!(op, ReduceOp::Max | ReduceOp::ArgMax) {
+            return Err(TensorError::Other(format!(
+                "{}: cannot reduce an empty dimension",
+                op.name()
+            )));
+        }
+
+        // Move the reduced dimension last, so each output element reduces
+        // one strided row.
+        let mut perm: Vec<usize> = (0..ndim).filter(|&d| d != dim).collect();
+        perm.push(dim);
+        let rows = layout.permute(&perm)?;
+        let stride = rows.strides()[ndim - 1];
+        let reduction = self.kernels.reduction();
+        self.for_each_row(out, 1, |r, out| {
+            let start = rows.row_start(r);
+            let values = (0..n).map(|j| x[start + j * stride]);
+            let sum = || reduction.sum(n, |j| x[start + j * stride]);
+            out[0] = match op {
+                ReduceOp::Sum => sum(),
+                ReduceOp::Mean => sum() / n as f32,
+                ReduceOp::Max => values.fold(f32::NEG_INFINITY, f32::max),
+                ReduceOp::ArgMax => {
+                    let mut best = (0, f32::NEG_INFINITY);
+                    for (j, v) in values.enumerate() {
+                        if v > best.1 {
+                            best = (j, v);
+                        }
+                    }
+                    best.0 as f32
+                }
+            };
+        });
+        Ok(())
     }
 
-    fn rms_norm(
+    fn rms_norm_into(
         &self,
         x: &[f32],
         weight: &[f32],
         eps: f32,
         hidden_size: usize,
-    ) -> Result<Vec<f32>> {
+        out: &mut [f32],
+    ) -> Result<()> {
         if weight.len() != hidden_size {
             return Err(TensorError::Other(format!(
                 "rms_norm: weight.len()={} but hidden_size={}",
@@ -96,88 +630,58 @@ impl ComputeBackend for CpuBackend {
                 hidden_size
             )));
         }
-        if x.len() % hidden_size != 0 {
+        if !x.len().is_multiple_of(hidden_size) {
             return Err(TensorError::Other(format!(
                 "rms_norm: x.len()={} is not a multiple of hidden_size={}",
                 x.len(),
                 hidden_size
             )));
         }
+        check_out_len(out, x.len())?;
 
-        let n_rows = x.len() / hidden_size;
-        let mut result = vec![0.0f32; x.len()];
-
-        for row in 0..n_rows {
+        self.for_each_row(out, hidden_size, |row, out| {
             let offset = row * hidden_size;
-            let row_data = &x[offset..offset + hidden_size];
-
-            // Compute mean of squares
-            let mean_sq: f32 =
-                row_data.iter().map(|v| v * v).sum::<f32>() / hidden_size as f32;
-            let rms = (mean_sq + eps).sqrt();
-
-            // Normalize and scale by weight
-            for i in 0..hidden_size {
-                result[offset + i] = row_data[i] * weight[i] / rms;
-            }
-        }
+            self.kernels
+                .rms_norm(&x[offset..offset + hidden_size], weight, eps, out);
+        });
 
-        Ok(result)
+        Ok(())
     }
 
-    fn softmax(&self, x: &[f32], n_vocab: usize) -> Result<Vec<f32>> {
+    fn softmax_into(&self, x: &[f32], n_vocab: usize, out: &mut [f32]) -> Result<()> {
         if n_vocab == 0 {
             return Err(TensorError::Other(
                 "softmax: n_vocab must be > 0".to_string(),
             ));
         }
-        if x.len() % n_vocab != 0 {
+        if !x.len().is_multiple_of(n_vocab) {
             return Err(TensorError::Other(format!(
                 "softmax: x.len()={} is not a multiple of n_vocab={}",
                 x.len(),
                 n_vocab
             )));
         }
+        check_out_len(out, x.len())?;
 
-        let n_chunks = x.len() / n_vocab;
-        let mut result = vec![0.0f32; x.len()];
-
-        for chunk in 0..n_chunks {
+        self.for_each_row(out, n_vocab, |chunk, out| {
             let offset = chunk * n_vocab;
-            let chunk_data = &x[offset..offset + n_vocab];
-
-            // Find max for numerical stability
-            let max_val = chunk_data
-                .iter()
-                .copied()
-                .fold(f32::NEG_INFINITY, f32::max);
-
-            // Compute exp(x - max) and sum
-            let mut sum = 0.0f32;
-            for i in 0..n_vocab {
-                let e = (chunk_data[i] - max_val).exp();
-                result[offset + i] = e;
-                sum += e;
-            }
+            self.kernels.softmax(&x[offset..offset + n_vocab], out);
+        });
 
-            // Normalize
-            for i in 0..n_vocab {
-                result[offset + i] /= sum;
-            }
-        }
-
-        Ok(result)
+        Ok(())
     }
 
-    fn rope(
+    fn rope_inplace(
         &self,
-        q: &[f32],
-        k: &[f32],
-        head_dim: usize,
+        q: &mut [f32],
+        k: &mut [f32],
+        rope: &RopeTable,
         pos: usize,
         n_heads_q: usize,
         n_heads_k: usize,
-    ) -> Result<(Vec<f32>, Vec<f32>)> {
+    ) -> Result<()> {
+        let cfg = rope.config();
+        let head_dim = cfg.head_dim;
         if q.len() != n_heads_q * head_dim {
             return Err(TensorError::Other(format!(
                 "rope: q.len()={} but expected n_heads_q*head_dim={}",
@@ -193,52 +697,172 @@ impl ComputeBackend for CpuBackend {
             )));
         }
 
-        let mut q_out = q.to_vec();
-        let mut k_out = k.to_vec();
+        let (cos, sin) = rope.cos_sin(pos)?;
+        if head_dim == 0 {
+            return Ok(());
+        }
+        // Query and key heads share the same rotation per pair; dimensions
+        // past n_dims are left as they are.
+        for head in q
+            .chunks_exact_mut(head_dim)
+            .chain(k.chunks_exact_mut(head_dim))
+        {
+            for (i, (&c, &s)) in cos.iter().zip(sin).enumerate() {
+                let (a, b) = cfg.pair(i);
+                let x0 = head[a];
+                let x1 = head[b];
+                head[a] = x0 * c - x1 * s;
+                head[b] = x0 * s + x1 * c;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn attention_into(
+        &self,
+        q: &[f32],
+        kv: KvView<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        params.validate(q, &kv, out)?;
+        let head_dim = params.head_dim;
+
+        // Every (token, head) pair is independent, so each is a row of the
+        // output. Token t sits at position q_pos + t.
+        self.for_each_row(out, head_dim, |row, out_head| {
+            let (t, h) = (row / params.n_heads, row % params.n_heads);
+            let keys = params.mask.key_range(q_pos + t, kv.n_positions);
+            let q_head = &q[row * head_dim..(row + 1) * head_dim];
+            attention::attend_head(&self.kernels, q_head, &kv, keys, params, h, out_head);
+        });
+        Ok(())
+    }
 
-        // Apply RoPE to query heads
-        for h in 0..n_heads_q {
-            let offset = h * head_dim;
-            for i in 0..head_dim / 2 {
-                let theta =
-                    pos as f32 * (1.0 / (10000.0f32).powf(2.0 * i as f32 / head_dim as f32));
-                let cos_theta = theta.cos();
-                let sin_theta = theta.sin();
+    fn attention_f16_into(
+        &self,
+        q: &[f32],
+        kv: KvViewF16<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        params.validate_f16(q, &kv, out)?;
+        let head_dim = params.head_dim;
+        self.for_each_row(out, head_dim, |row, out_head| {
+            let (t, h) = (row / params.n_heads, row % params.n_heads);
+            let keys = params.mask.key_range(q_pos + t, kv.n_positions);
+            let q_head = &q[row * head_dim..(row + 1) * head_dim];
+            attention::attend_head(&self.kernels, q_head, &kv, keys, params, h, out_head);
+        });
+        Ok(())
+    }
 
-                let x0 = q[offset + 2 * i];
-                let x1 = q[offset + 2 * i + 1];
-                q_out[offset + 2 * i] = x0 * cos_theta - x1 * sin_theta;
-                q_out[offset + 2 * i + 1] = x0 * sin_theta + x1 * cos_theta;
+    fn silu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        // The kernels read and write separate slices, so stage each chunk of
+        // input through a stack buffer.
+        self.for_each_row_block(x, ELEMENTWISE_CHUNK, |_, block| {
+            let mut tmp = [0.0f32; INPLACE_CHUNK];
+            for chunk in block.chunks_mut(INPLACE_CHUNK) {
+                let tmp = &mut tmp[..chunk.len()];
+                tmp.copy_from_slice(chunk);
+                self.kernels.silu(tmp, chunk);
             }
-        }
+        });
+        Ok(())
+    }
 
-        // Apply RoPE to key heads
-        for h in 0..n_heads_k {
-            let offset = h * head_dim;
-            for i in 0..head_dim / 2 {
-                let theta =
-                    pos as f32 * (1.0 / (10000.0f32).powf(2.0 * i as f32 / head_dim as f32));
-                let cos_theta = theta.cos();
-                let sin_theta = theta.sin();
+    fn gelu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.map_inplace(x, unary::gelu_tanh);
+        Ok(())
+    }
 
-                let x0 = k[offset + 2 * i];
-                let x1 = k[offset + 2 * i + 1];
-                k_out[offset + 2 * i] = x0 * cos_theta - x1 * sin_theta;
-                k_out[offset + 2 * i + 1] = x0 * sin_theta + x1 * cos_theta;
+    fn gelu_erf_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.map_inplace(x, unary::gelu_erf);
+        Ok(())
+    }
+
+    fn geglu_inplace(&self, gate: &mut [f32], up: &[f32]) -> Result<()> {
+        check_out_len(up, gate.len())?;
+        self.for_each_row(gate, ELEMENTWISE_CHUNK, |i, gate| {
+            let up = &up[i * ELEMENTWISE_CHUNK..][..gate.len()];
+            for (g, &u) in gate.iter_mut().zip(up) {
+                *g = unary::gelu_tanh(*g) * u;
             }
-        }
+        });
+        Ok(())
+    }
 
-        Ok((q_out, k_out))
+    fn relu2_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.map_inplace(x, unary::relu2);
+        Ok(())
     }
 
-    fn silu(&self, x: &[f32]) -> Result<Vec<f32>> {
-        Ok(x.iter().map(|&v| v / (1.0 + (-v).exp())).collect())
+    fn softcap_inplace(&self, x: &mut [f32], cap: f32) -> Result<()> {
+        if cap.is_nan() || cap <= 0.0 {
+            return Err(TensorError::Other(format!(
+                "softcap: cap must be positive, got {}",
+                cap
+            )));
+        }
+        self.map_inplace(x, |v| unary::softcap(v, cap));
+        Ok(())
+    }
+
+    fn layer_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        if weight.len() != hidden_size {
+            return Err(TensorError::Other(format!(
+                "layer_norm: weight.len()={} but hidden_size={}",
+                weight.len(),
+                hidden_size
+            )));
+        }
+        if let Some(bias) = bias
+            && bias.len() != hidden_size
+        {
+            return Err(TensorError::Other(format!(
+                "layer_norm: bias.len()={} but hidden_size={}",
+                bias.len(),
+                hidden_size
+            )));
+        }
+        if hidden_size == 0 || !x.len().is_multiple_of(hidden_size) {
+            return Err(TensorError::Other(format!(
+                "layer_norm: x.len()={} is not a multiple of hidden_size={}",
+                x.len(),
+                hidden_size
+            )));
+        }
+        check_out_len(out, x.len())?;
+
+        let reduction = self.kernels.reduction();
+        self.for_each_row(out, hidden_size, |row, out| {
+            let offset = row * hidden_size;
+            let x = &x[offset..offset + hidden_size];
+            unary::layer_norm(x, weight, bias, eps, reduction, out);
+        });
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::{AttentionMask, BackendOp};
+    use crate::rope::{RopeConfig, RopeStyle};
+    use crate::shape::Shape;
+    use crate::test_util::{assert_close_rel, values};
 
     fn backend() -> CpuBackend {
         CpuBackend::new()
@@ -265,6 +889,214 @@ mod tests {
         assert_eq!(c, vec![19.0, 22.0, 43.0, 50.0]);
     }
 
+    /// Reference Q4_0 quantizer (ggml's `quantize_row_q4_0_ref`).
+    fn quantize_q4_0(x: &[f32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for block in x.chunks_exact(32) {
+            let max = block
+                .iter()
+                .copied()
+                .fold(0.0f32, |m, v| if v.abs() > m.abs() { v } else { m });
+            let d = max / -8.0;
+            let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+            out.extend_from_slice(&half::f16::from_f32(d).to_le_bytes());
+            for j in 0..16 {
+                let q0 = ((block[j] * id + 8.5) as u8).min(15);
+                let q1 = ((block[j + 16] * id + 8.5) as u8).min(15);
+                out.push(q0 | (q1 << 4));
+            }
+        }
+        out
+    }
+
+    /// Dequantize Q4_0 or Q8_0 blocks with the same layout as the GGUF loader.
+    fn dequantize(dtype: DType, data: &[u8]) -> Vec<f32> {
+        let mut out = Vec::new();
+        for block in data.chunks_exact(dtype.size_in_bytes()) {
+            let d = half::f16::from_le_bytes([block[0], block[1]]).to_f32();
+            match dtype {
+                DType::Q4_0 => {
+                    out.extend(
+                        block[2..]
+                            .iter()
+                            .map(|&b| ((b & 0x0F) as i32 - 8) as f32 * d),
+                    );
+                    out.extend(block[2..].iter().map(|&b| ((b >> 4) as i32 - 8) as f32 * d));
+                }
+                DType::Q8_0 => out.extend(block[2..].iter().map(|&b| b as i8 as f32 * d)),
+                _ => unreachable!(),
+            }
+        }
+        out
+    }
+
+    fn check_quantized_matches_dequantized(dtype: DType, w_q: &[u8], m: usize, k: usize) {
+        let b = backend();
+        let x = values(k, 7);
+        let w_f32 = dequantize(dtype, w_q);
+
+        let expected = b.matmul(&w_f32, &x, m, k, 1).unwrap();
+        let got = b.matmul_quantized(w_q, dtype, &x, m, k).unwrap();
+
+        for row in 0..m {
+            // Only the activation is re-quantized, so the error is bounded
+            // by the Q8_0 rounding step on x.
+            let magnitude: f32 = w_f32[row * k..(row + 1) * k]
+                .iter()
+                .zip(&x)
+                .map(|(w, x)| (w * x).abs())
+                .sum();
+            let tol = 1e-2 * magnitude + 1e-4;
+            assert!(
+                (got[row] - expected[row]).abs() <= tol,
+                "{} row {}: got {} expected {} (tol {})",
+                dtype,
+                row,
+                got[row],
+                expected[row],
+                tol
+            );
+        }
+    }
+
+    #[test]
+    fn test_matmul_quantized_q4_0() {
+        let (m, k) = (8, 96);
+        let w_q = quantize_q4_0(&values(m * k, 1));
+        check_quantized_matches_dequantized(DType::Q4_0, &w_q, m, k);
+    }
+
+    #[test]
+    fn test_matmul_quantized_q8_0() {
+        let (m, k) = (5, 128);
+        let mut w_q = vec![0u8; DType::Q8_0.storage_size(m * k)];
+        quant::quantize_row_q8_0(&values(m * k, 3), &mut w_q);
+        check_quantized_matches_dequantized(DType::Q8_0, &w_q, m, k);
+    }
+
+    #[test]
+    fn test_matmul_quantized_exact_integers() {
+        // Integer inputs with max |v| = 127 quantize losslessly (scale 1.0).
+        let b = backend();
+        let k = 32;
+        let mut w: Vec<f32> = (0..k).map(|i| i as f32 * 4.0 - 62.0).collect();
+        w[0] = 127.0;
+        let x: Vec<f32> = (0..k)
+            .map(|i| if i == 0 { -127.0 } else { (i % 5) as f32 })
+            .collect();
+        let mut w_q = vec![0u8; 34];
+        quant::quantize_row_q8_0(&w, &mut w_q);
+
+        let expected: f32 = w.iter().zip(&x).map(|(a, b)| a * b).sum();
+        let got = b.matmul_quantized(&w_q, DType::Q8_0, &x, 1, k).unwrap();
+        assert_eq!(got, vec![expected]);
+    }
+
+    #[test]
+    fn test_matmul_quantized_reentrant_from_other_pool() {
+        // While a worker of another pool waits for this backend's pool, it
+        // runs the other jobs queued on its own pool, which call back into
+        // `matmul_quantized_into` on the same thread.
+        let b = CpuBackend::with_threads(2).unwrap();
+        let (m, k) = (64, 128);
+        let mut w_q = vec![0u8; DType::Q8_0.storage_size(m * k)];
+        quant::quantize_row_q8_0(&values(m * k, 3), &mut w_q);
+        let x = values(k, 5);
+        let want = b.matmul_quantized(&w_q, DType::Q8_0, &x, m, k).unwrap();
+
+        let outer = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        outer.scope(|s| {
+            for _ in 0..16 {
+                s.spawn(|_| {
+                    let got = b.matmul_quantized(&w_q, DType::Q8_0, &x, m, k).unwrap();
+                    assert_eq!(got, want);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_matmul_quantized_every_format() {
+        // Formats without a block dot product run on the decoded rows, so
+        // they match an f32 matmul on the dequantized weights.
+        let (m, k) = (5, 512);
+        let x = values(k, 2);
+        for dtype in (0..64).filter_map(DType::from_gguf_type) {
+            if matches!(dtype, DType::F32 | DType::F16 | DType::Q4_0 | DType::Q8_0) {
+                continue;
+            }
+            let w_q = crate::quant::quantize(dtype, &values(m * k, 1));
+            let w_f32 = crate::quant::dequantize(dtype, &w_q, m * k).unwrap();
+            for threads in [1, 3] {
+                let b = CpuBackend::with_threads(threads).unwrap();
+                let want = b.matmul(&w_f32, &x, m, k, 1).unwrap();
+                let got = b.matmul_quantized(&w_q, dtype, &x, m, k).unwrap();
+                assert_close_rel(&got, &want, 1e-5, &format!("{} threads={}", dtype, threads));
+            }
+        }
+    }
+
+    #[test]
+    fn test_matmul_quantized_rejects_bad_input() {
+        let b = backend();
+        let w = vec![0u8; 18];
+        assert!(
+            b.matmul_quantized(&w, DType::F16, &[0.0; 32], 1, 32)
+                .is_err()
+        );
+        assert!(
+            b.matmul_quantized(&w, DType::Q4_0, &[0.0; 16], 1, 16)
+                .is_err()
+        );
+        assert!(
+            b.matmul_quantized(&w, DType::Q4_0, &[0.0; 32], 2, 32)
+                .is_err()
+        );
+    }
+
+    fn to_f16_bytes(x: &[f32]) -> Vec<u8> {
+        x.iter()
+            .flat_map(|&v| half::f16::from_f32(v).to_le_bytes())
+            .collect()
+    }
+
+    /// Round each value to the nearest f16.
+    fn round_f16(x: &[f32]) -> Vec<f32> {
+        x.iter().map(|&v| half::f16::from_f32(v).to_f32()).collect()
+    }
+
+    #[test]
+    fn test_matmul_f16_matches_widened_f32() {
+        let (m, k) = (7, 67);
+        let a = values(m * k, 1);
+        let a_f16 = to_f16_bytes(&a);
+        let a_wide = round_f16(&a);
+        for threads in [1, 3] {
+            let b = CpuBackend::with_threads(threads).unwrap();
+            for n in [1, 5] {
+                let x = values(k * n, 2);
+                let got = b.matmul_f16(&a_f16, &x, m, k, n).unwrap();
+                // Widening is exact, so only the summation order differs.
+                let want = b.matmul(&a_wide, &x, m, k, n).unwrap();
+                for (g, w) in got.iter().zip(&want) {
+                    assert!((g - w).abs() < 1e-4, "n={}: {} vs {}", n, g, w);
+                }
+            }
+        }
+
+        let b = backend();
+        assert!(b.matmul_f16(&a_f16[1..], &[0.0; 67], m, k, 1).is_err());
+        assert!(b.matmul_f16(&a_f16, &[0.0; 66], m, k, 1).is_err());
+        let mut out = vec![0.0; m + 1];
+        assert!(
+            b.matmul_f16_into(&a_f16, &[0.0; 67], &mut out, m, k, 1)
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_add() {
         let b = backend();
@@ -327,16 +1159,916 @@ mod tests {
         let b = backend();
         let q = vec![1.0, 0.0, 0.0, 1.0]; // 1 head, head_dim=4
         let k = vec![1.0, 0.0, 0.0, 1.0];
-        let (q_out, k_out) = b.rope(&q, &k, 4, 0, 1, 1).unwrap();
+        let table = RopeTable::new(RopeConfig::new(4), 1).unwrap();
+        let (q_out, k_out) = b.rope(&q, &k, &table, 0, 1, 1).unwrap();
         // At pos=0, theta=0 for all pairs, so cos=1, sin=0 => no rotation
         assert!((q_out[0] - 1.0).abs() < 1e-6);
         assert!((q_out[1] - 0.0).abs() < 1e-6);
         assert!((k_out[0] - 1.0).abs() < 1e-6);
     }
 
+    /// Rotate one head with the textbook formula, computing every angle
+    /// from scratch.
+    fn rope_reference(head: &[f32], cfg: &RopeConfig, pos: usize) -> Vec<f32> {
+        let mut out = head.to_vec();
+        for i in 0..cfg.n_dims / 2 {
+            let theta =
+                pos as f64 * (cfg.freq_base as f64).powf(-2.0 * i as f64 / cfg.n_dims as f64);
+            let (a, b) = match cfg.style {
+                RopeStyle::Interleaved => (2 * i, 2 * i + 1),
+                RopeStyle::NeoX => (i, i + cfg.n_dims / 2),
+            };
+            let (x0, x1) = (head[a] as f64, head[b] as f64);
+            out[a] = (x0 * theta.cos() - x1 * theta.sin()) as f32;
+            out[b] = (x0 * theta.sin() + x1 * theta.cos()) as f32;
+        }
+        out
+    }
+
+    #[test]
+    fn test_rope_configs_match_reference() {
+        let b = backend();
+        let configs = [
+            RopeConfig::new(8),
+            RopeConfig::new(8).with_freq_base(500000.0),
+            RopeConfig::new(8).with_style(RopeStyle::NeoX),
+            RopeConfig::new(8).with_style(RopeStyle::NeoX).with_dims(4),
+            RopeConfig::new(8).with_dims(6),
+        ];
+        for cfg in configs {
+            let table = RopeTable::new(cfg.clone(), 100).unwrap();
+            let q = values(2 * 8, 1);
+            let k = values(8, 2);
+            for pos in [1, 17, 99] {
+                let (q_out, k_out) = b.rope(&q, &k, &table, pos, 2, 1).unwrap();
+                let heads = q.chunks(8).chain(k.chunks(8));
+                let rotated = q_out.chunks(8).chain(k_out.chunks(8));
+                for (head, got) in heads.zip(rotated) {
+                    let want = rope_reference(head, &cfg, pos);
+                    for (g, w) in got.iter().zip(&want) {
+                        assert!(
+                            (g - w).abs() < 1e-5,
+                            "{:?} pos {}: {} vs {}",
+                            cfg,
+                            pos,
+                            g,
+                            w
+                        );
+                    }
+                    // Dimensions past n_dims are not rotated.
+                    assert_eq!(&got[cfg.n_dims..], &head[cfg.n_dims..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rope_freq_base_changes_rotation() {
+        let b = backend();
+        let q = values(8, 3);
+        let default = RopeTable::new(RopeConfig::new(8), 10).unwrap();
+        let llama3 = RopeTable::new(RopeConfig::new(8).with_freq_base(500000.0), 10).unwrap();
+        let (a, _) = b.rope(&q, &[], &default, 9, 1, 0).unwrap();
+        let (c, _) = b.rope(&q, &[], &llama3, 9, 1, 0).unwrap();
+        // The first pair always rotates by pos radians; the others depend
+        // on the base.
+        assert_eq!(a[..2], c[..2]);
+        assert_ne!(a[2..], c[2..]);
+        assert!(b.rope(&q, &[], &default, 10, 1, 0).is_err());
+    }
+
     #[test]
     fn test_add_length_mismatch() {
         let b = backend();
         assert!(b.add(&[1.0], &[1.0, 2.0]).is_err());
     }
+
+    #[test]
+    fn test_into_matches_allocating() {
+        let b = CpuBackend::with_threads(1).unwrap();
+        let (m, k) = (6, 64);
+        let a = values(m * k, 1);
+        let x = values(k, 2);
+
+        // Stale contents of the output buffers must be overwritten.
+        let mut out = vec![7.0f32; m];
+        b.matmul_into(&a, &x, &mut out, m, k, 1).unwrap();
+        assert_eq!(out, b.matmul(&a, &x, m, k, 1).unwrap());
+
+        let mut out = vec![7.0f32; m * 3];
+        let x3 = values(k * 3, 3);
+        b.matmul_into(&a, &x3, &mut out, m, k, 3).unwrap();
+        assert_eq!(out, b.matmul(&a, &x3, m, k, 3).unwrap());
+
+        let w_q = quantize_q4_0(&a);
+        let mut out = vec![7.0f32; m];
+        b.matmul_quantized_into(&w_q, DType::Q4_0, &x, &mut out, m, k)
+            .unwrap();
+        assert_eq!(
+            out,
+            b.matmul_quantized(&w_q, DType::Q4_0, &x, m, k).unwrap()
+        );
+
+        let weight = values(k, 4);
+        let mut out = vec![7.0f32; m * k];
+        b.rms_norm_into(&a, &weight, 1e-5, k, &mut out).unwrap();
+        assert_eq!(out, b.rms_norm(&a, &weight, 1e-5, k).unwrap());
+        b.softmax_into(&a, k, &mut out).unwrap();
+        assert_eq!(out, b.softmax(&a, k).unwrap());
+    }
+
+    #[test]
+    fn test_inplace_ops() {
+        let b = backend();
+        let mut a = vec![1.0, 2.0];
+        b.add_inplace(&mut a, &[3.0, 4.0]).unwrap();
+        assert_eq!(a, vec![4.0, 6.0]);
+        b.mul_inplace(&mut a, &[0.5, 2.0]).unwrap();
+        assert_eq!(a, vec![2.0, 12.0]);
+        b.scale_inplace(&mut a, -1.0).unwrap();
+        assert_eq!(a, vec![-2.0, -12.0]);
+        assert!(b.add_inplace(&mut a, &[1.0]).is_err());
+        assert!(b.mul_inplace(&mut a, &[1.0]).is_err());
+
+        // Longer than one staging chunk, with a partial tail.
+        let x = values(2 * INPLACE_CHUNK + 5, 5);
+        let mut y = x.clone();
+        b.silu_inplace(&mut y).unwrap();
+        assert_eq!(y, b.silu(&x).unwrap());
+
+        let q = values(2 * 8, 6);
+        let k = values(8, 7);
+        let table = RopeTable::new(RopeConfig::new(8), 4).unwrap();
+        let (q_want, k_want) = b.rope(&q, &k, &table, 3, 2, 1).unwrap();
+        let (mut q_got, mut k_got) = (q.clone(), k.clone());
+        b.rope_inplace(&mut q_got, &mut k_got, &table, 3, 2, 1)
+            .unwrap();
+        assert_eq!((q_got, k_got), (q_want, k_want));
+    }
+
+    #[test]
+    fn test_into_rejects_wrong_out_len() {
+        let b = backend();
+        let mut out = vec![0.0f32; 3];
+        assert!(
+            b.matmul_into(&[1.0; 4], &[1.0; 2], &mut out, 2, 2, 1)
+                .is_err()
+        );
+        assert!(
+            b.rms_norm_into(&[1.0; 4], &[1.0; 2], 1e-5, 2, &mut out)
+                .is_err()
+        );
+        assert!(b.softmax_into(&[1.0; 4], 2, &mut out).is_err());
+    }
+
+    #[test]
+    fn test_with_simd_level() {
+        let b = CpuBackend::with_threads(1)
+            .unwrap()
+            .with_simd_level(SimdLevel::Scalar)
+            .unwrap();
+        assert!(
+            b.kernel_variants()
+                .iter()
+                .all(|v| v.level == SimdLevel::Scalar)
+        );
+
+        let detected = CpuBackend::with_threads(1).unwrap();
+        for v in detected.kernel_variants() {
+            assert!(
+                v.level <= SimdLevel::detect(),
+                "{} uses {}",
+                v.kernel,
+                v.level
+            );
+        }
+
+        // The matvec path goes through the dot kernel.
+        let a = values(5 * 70, 1);
+        let x = values(70, 2);
+        let want = b.matmul(&a, &x, 5, 70, 1).unwrap();
+        let got = detected.matmul(&a, &x, 5, 70, 1).unwrap();
+        for (g, w) in got.iter().zip(&want) {
+            assert!((g - w).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_deterministic_reductions_are_bit_exact() {
+        let (m, k, n) = (9, 300, 7);
+        let a = values(m * k, 1);
+        let b = values(k * n, 2);
+        let w_q = quantize_q4_0(&a[..m * 288]);
+        let x_q = values(288, 3);
+        let run = |backend: &CpuBackend| {
+            let mut out = backend.matmul(&a, &b, m, k, n).unwrap();
+            out.extend(backend.matmul(&a, &b[..k], m, k, 1).unwrap());
+            out.extend(
+                backend
+                    .matmul_quantized(&w_q, DType::Q4_0, &x_q, m, 288)
+                    .unwrap(),
+            );
+            out.extend(backend.softmax(&a, k).unwrap());
+            out.extend(backend.rms_norm(&a, &b[..k], 1e-5, k).unwrap());
+            out.extend(backend.layer_norm(&a, &b[..k], None, 1e-5, k).unwrap());
+            out
+        };
+
+        let native = run(&CpuBackend::with_threads(1).unwrap());
+        for reduction in [Reduction::Sequential, Reduction::Pairwise, Reduction::Kahan] {
+            let mut results = Vec::new();
+            for level in SimdLevel::supported() {
+                for threads in [1, 3] {
+                    let backend = CpuBackend::with_threads(threads)
+                        .unwrap()
+                        .with_reduction(reduction)
+                        .with_simd_level(level)
+                        .unwrap();
+                    assert_eq!(backend.reduction(), reduction);
+                    assert!(
+                        backend
+                            .kernel_variants()
+                            .iter()
+                            .all(|v| v.level == SimdLevel::Scalar)
+                    );
+                    results.push(run(&backend));
+                }
+            }
+            assert!(results.iter().all(|r| r == &results[0]), "{}", reduction);
+            for (g, w) in results[0].iter().zip(&native) {
+                assert!((g - w).abs() <= 1e-4 * w.abs().max(1.0), "{}", reduction);
+            }
+        }
+
+        // Switching back restores the detected kernels.
+        let back = CpuBackend::with_threads(1)
+            .unwrap()
+            .with_reduction(Reduction::Kahan)
+            .with_reduction(Reduction::Native);
+        assert_eq!(
+            back.kernel_variants(),
+            CpuBackend::with_threads(1).unwrap().kernel_variants()
+        );
+    }
+
+    #[test]
+    fn test_batched_matmul_into() {
+        let (m, k, n) = (3, 70, 6);
+        let a = values(4 * m * k, 1);
+        let b = values(2 * k * n, 2);
+        for threads in [1, 3] {
+            let backend = CpuBackend::with_threads(threads).unwrap();
+            // Eight products over four A matrices and two B matrices.
+            let params = MatmulParams {
+                a_batch: 4,
+                b_batch: 2,
+                ..MatmulParams::new(8, m, k, n)
+            };
+            let got = backend.batched_matmul(&a, &b, &params).unwrap();
+
+            // The same products with B stored as [n, k].
+            let b_t: Vec<f32> = b
+                .chunks_exact(k * n)
+                .flat_map(|b| matmul::transpose(b, k, n))
+                .collect();
+            let transposed = MatmulParams {
+                transpose_b: true,
+                ..params
+            };
+            let got_t = backend.batched_matmul(&a, &b_t, &transposed).unwrap();
+
+            for i in 0..8 {
+                let a_i = &a[i / 2 * m * k..][..m * k];
+                let b_i = &b[i / 4 * k * n..][..k * n];
+                let want = backend.matmul(a_i, b_i, m, k, n).unwrap();
+                assert_eq!(&got[i * m * n..][..m * n], &want[..]);
+                for (g, w) in got_t[i * m * n..][..m * n].iter().zip(&want) {
+                    assert!((g - w).abs() < 1e-4, "{} vs {}", g, w);
+                }
+            }
+        }
+
+        let backend = backend();
+        let params = MatmulParams::new(2, 2, 2, 2);
+        let mut out = vec![0.0; 8];
+        assert!(
+            backend
+                .batched_matmul_into(&[1.0; 8], &[1.0; 8], &mut out, &params)
+                .is_ok()
+        );
+        assert_eq!(out, vec![2.0; 8]);
+        let bad = MatmulParams {
+            b_batch: 3,
+            ..params
+        };
+        assert!(
+            backend
+                .batched_matmul_into(&[1.0; 8], &[1.0; 12], &mut out, &bad)
+                .is_err()
+        );
+        assert!(
+            backend
+                .batched_matmul_into(&[1.0; 8], &[1.0; 4], &mut out, &params)
+                .is_err()
+        );
+        assert!(
+            backend
+                .batched_matmul_into(&[1.0; 8], &[1.0; 8], &mut out[..4], &params)
+                .is_err()
+        );
+    }
+
+    /// Run `op` once on small valid inputs, reading weights as `dtype`
+    /// where the op takes a dtype.
+    fn run_op(b: &CpuBackend, op: BackendOp, dtype: DType) -> Result<()> {
+        let k = 256;
+        let x = values(k, 1);
+        let v = values(4, 2);
+        let mut io = v.clone();
+        let mut out = [0.0f32; 4];
+        let layout = Layout::contiguous(Shape::new(vec![2, 2]));
+        let table = RopeTable::new(RopeConfig::new(4), 2)?;
+        let attn = AttentionParams::new(1, 1, 4);
+        let kv = KvView {
+            k: &v,
+            v: &v,
+            n_positions: 1,
+        };
+        let kv_f16 = KvViewF16 {
+            k: &[0u8; 8],
+            v: &[0u8; 8],
+            n_positions: 1,
+        };
+        match op {
+            BackendOp::Matmul => b.matmul_into(&v, &v, &mut out[..1], 1, 4, 1),
+            BackendOp::BatchedMatmul => {
+                let params = MatmulParams::new(2, 1, 2, 1);
+                b.batched_matmul_into(&v, &v, &mut out[..2], &params)
+            }
+            BackendOp::MatmulQuantized => {
+                let w = vec![0u8; dtype.storage_size(2 * k)];
+                b.matmul_quantized_into(&w, dtype, &x, &mut out[..2], 2, k)
+            }
+            BackendOp::MatmulF16 => b.matmul_f16_into(&[0u8; 8], &v, &mut out[..1], 1, 4, 1),
+            BackendOp::Add => b.add_inplace(&mut io, &v),
+            BackendOp::Mul => b.mul_inplace(&mut io, &v),
+            BackendOp::Scale => b.scale_inplace(&mut io, 2.0),
+            BackendOp::Binary => b.binary_into(BinaryOp::Add, &v, &layout, &v, &layout, &mut out),
+            BackendOp::Reduce => b.reduce_into(ReduceOp::Sum, &v, &layout, 1, &mut out[..2]),
+            BackendOp::RmsNorm => b.rms_norm_into(&v, &[1.0; 2], 1e-5, 2, &mut out),
+            BackendOp::Softmax => b.softmax_into(&v, 2, &mut out),
+            BackendOp::Rope => b.rope_inplace(&mut io, &mut [], &table, 0, 1, 0),
+            BackendOp::Attention => b.attention_into(&v, kv, 0, &attn, &mut out),
+            BackendOp::AttentionF16 => b.attention_f16_into(&v, kv_f16, 0, &attn, &mut out),
+            BackendOp::Silu => b.silu_inplace(&mut io),
+            BackendOp::Gelu => b.gelu_inplace(&mut io),
+            BackendOp::GeluErf => b.gelu_erf_inplace(&mut io),
+            BackendOp::Geglu => b.geglu_inplace(&mut io, &v),
+            BackendOp::Relu2 => b.relu2_inplace(&mut io),
+            BackendOp::Softcap => b.softcap_inplace(&mut io, 1.0),
+            BackendOp::LayerNorm => b.layer_norm_into(&v, &[1.0; 2], None, 1e-5, 2, &mut out),
+        }
+    }
+
+    #[test]
+    fn test_capabilities_match_execution() {
+        let b = CpuBackend::with_threads(1).unwrap();
+        let caps = b.capabilities();
+        let dtypes: Vec<DType> = (0..64).filter_map(DType::from_gguf_type).collect();
+        for op in BackendOp::ALL {
+            for &dtype in &dtypes {
+                let claimed = caps.supports(op, dtype);
+                match op.dtype() {
+                    // The op reads no weights of other dtypes at all.
+                    Some(fixed) => {
+                        assert_eq!(claimed, dtype == fixed, "{} on {}", op.name(), dtype)
+                    }
+                    None => {
+                        let ran = run_op(&b, op, dtype);
+                        assert_eq!(
+                            ran.is_ok(),
+                            claimed,
+                            "{} on {}: {:?}",
+                            op.name(),
+                            dtype,
+                            ran
+                        );
+                    }
+                }
+            }
+            if let Some(fixed) = op.dtype() {
+                let ran = run_op(&b, op, fixed);
+                assert!(ran.is_ok(), "{}: {:?}", op.name(), ran);
+            }
+        }
+        assert!(!caps.supports(BackendOp::MatmulQuantized, DType::F16));
+        assert!(!caps.supports(BackendOp::RmsNorm, DType::Q8_0));
+    }
+
+    #[test]
+    fn test_with_threads() {
+        assert_eq!(CpuBackend::with_threads(0).unwrap().n_threads(), 1);
+        assert_eq!(CpuBackend::with_threads(3).unwrap().n_threads(), 3);
+        assert!(CpuBackend::new().n_threads() >= 1);
+    }
+
+    #[test]
+    fn test_threaded_matches_single_thread() {
+        let single = CpuBackend::with_threads(1).unwrap();
+        let multi = CpuBackend::with_threads(4).unwrap();
+
+        let (m, k, n) = (37, 64, 5);
+        let a = values(m * k, 1);
+        let x = values(k * n, 2);
+        assert_eq!(
+            single.matmul(&a, &x, m, k, n).unwrap(),
+            multi.matmul(&a, &x, m, k, n).unwrap()
+        );
+
+        let w_q = quantize_q4_0(&a);
+        let v = values(k, 3);
+        assert_eq!(
+            single
+                .matmul_quantized(&w_q, DType::Q4_0, &v, m, k)
+                .unwrap(),
+            multi.matmul_quantized(&w_q, DType::Q4_0, &v, m, k).unwrap()
+        );
+
+        let weight = values(k, 4);
+        assert_eq!(
+            single.rms_norm(&a, &weight, 1e-5, k).unwrap(),
+            multi.rms_norm(&a, &weight, 1e-5, k).unwrap()
+        );
+        assert_eq!(
+            single.softmax(&a, k).unwrap(),
+            multi.softmax(&a, k).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_for_each_row_visits_every_row() {
+        for b in [
+            CpuBackend::with_threads(1).unwrap(),
+            CpuBackend::with_threads(4).unwrap(),
+        ] {
+            let mut out = vec![0.0f32; 10 * 3 + 2];
+            b.for_each_row(&mut out, 3, |i, row| {
+                for (j, v) in row.iter_mut().enumerate() {
+                    *v = (i * 3 + j) as f32;
+                }
+            });
+            let expected: Vec<f32> = (0..out.len()).map(|i| i as f32).collect();
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn test_reduce_into_along_each_dim() {
+        let backend = CpuBackend::new();
+        let x: Vec<f32> = vec![1.0, 5.0, 3.0, 4.0, 2.0, 6.0];
+        let layout = Layout::contiguous(Shape::new(vec![2, 3]));
+
+        let mut rows = vec![0.0f32; 2];
+        backend
+            .reduce_into(ReduceOp::Sum, &x, &layout, 1, &mut rows)
+            .unwrap();
+        assert_eq!(rows, vec![9.0, 12.0]);
+        backend
+            .reduce_into(ReduceOp::ArgMax, &x, &layout, 1, &mut rows)
+            .unwrap();
+        assert_eq!(rows, vec![1.0, 2.0]);
+
+        let mut cols = vec![0.0f32; 3];
+        backend
+            .reduce_into(ReduceOp::Max, &x, &layout, 0, &mut cols)
+            .unwrap();
+        assert_eq!(cols, vec![4.0, 5.0, 6.0]);
+        backend
+            .reduce_into(ReduceOp::Mean, &x, &layout, 0, &mut cols)
+            .unwrap();
+        assert_eq!(cols, vec![2.5, 3.5, 4.5]);
+
+        assert!(
+            backend
+                .reduce_into(ReduceOp::Sum, &x, &layout, 2, &mut cols)
+                .is_err()
+        );
+        assert!(
+            backend
+                .reduce_into(ReduceOp::Sum, &x, &layout, 0, &mut rows)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_gelu_inplace() {
+        let backend = CpuBackend::new();
+        let mut x = vec![-3.0f32, -1.0, 0.0, 1.0, 3.0];
+        backend.gelu_inplace(&mut x).unwrap();
+        let expected = [-0.003_637, -0.158_808, 0.0, 0.841_192, 2.996_363];
+        for (g, e) in x.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-4, "got {} want {}", g, e);
+        }
+    }
+
+    #[test]
+    fn test_elementwise_activations() {
+        let b = backend();
+        let mut x = vec![-3.0f32, -1.0, 0.0, 1.0, 3.0];
+        b.gelu_erf_inplace(&mut x).unwrap();
+        let expected = [-0.004_049_7, -0.158_655_3, 0.0, 0.841_344_7, 2.995_950_3];
+        for (g, e) in x.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-6, "got {} want {}", g, e);
+        }
+
+        let mut x = vec![-2.0f32, -0.0, 0.5, 3.0];
+        b.relu2_inplace(&mut x).unwrap();
+        assert_eq!(x, vec![0.0, 0.0, 0.25, 9.0]);
+
+        let mut x = vec![-100.0f32, 0.0, 10.0];
+        b.softcap_inplace(&mut x, 30.0).unwrap();
+        let expected = [-29.923_74, 0.0, 9.645_382];
+        for (g, e) in x.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-3, "got {} want {}", g, e);
+        }
+        assert!(b.softcap_inplace(&mut x, 0.0).is_err());
+        assert!(b.softcap_inplace(&mut x, f32::NAN).is_err());
+
+        let mut gate = vec![-1.0f32, 0.0, 1.0];
+        b.geglu_inplace(&mut gate, &[2.0, 5.0, -1.0]).unwrap();
+        let expected = [-0.317_616, 0.0, -0.841_192];
+        for (g, e) in gate.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-5, "got {} want {}", g, e);
+        }
+        assert!(b.geglu_inplace(&mut gate, &[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_layer_norm_into() {
+        let b = backend();
+        let x = [1.0f32, 2.0, 3.0, 6.0, 4.0, 4.0, 4.0, 4.0];
+        let w = [1.0f32, 1.0, 2.0, 1.0];
+        let bias = [0.0f32, 1.0, 0.0, -1.0];
+        let out = b.layer_norm(&x, &w, Some(&bias), 1e-5, 4).unwrap();
+        let s = (3.5f32 + 1e-5).sqrt();
+        let expected = [
+            -2.0 / s,
+            -1.0 / s + 1.0,
+            0.0,
+            3.0 / s - 1.0,
+            0.0,
+            1.0,
+            0.0,
+            -1.0,
+        ];
+        for (g, e) in out.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-5, "got {} want {}", g, e);
+        }
+        let no_bias = b.layer_norm(&x, &w, None, 1e-5, 4).unwrap();
+        assert!((no_bias[1] - (-1.0 / s)).abs() < 1e-5);
+
+        assert!(b.layer_norm(&x, &w[..3], None, 1e-5, 4).is_err());
+        assert!(b.layer_norm(&x, &w, Some(&bias[..2]), 1e-5, 4).is_err());
+        assert!(b.layer_norm(&x[..6], &w, None, 1e-5, 4).is_err());
+        let mut short = vec![0.0; 4];
+        assert!(
+            b.layer_norm_into(&x, &w, None, 1e-5, 4, &mut short)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_new_kernels_threaded_match_single_thread() {
+        let single = CpuBackend::with_threads(1).unwrap();
+        let multi = CpuBackend::with_threads(4).unwrap();
+        let n = 3 * ELEMENTWISE_CHUNK + 17;
+        let x = values(n, 11);
+        let up = values(n, 12);
+
+        type Op = fn(&CpuBackend, &mut Vec<f32>, &[f32]);
+        let ops: [Op; 6] = [
+            |b, x, _| b.silu_inplace(x).unwrap(),
+            |b, x, _| b.gelu_inplace(x).unwrap(),
+            |b, x, _| b.gelu_erf_inplace(x).unwrap(),
+            |b, x, _| b.relu2_inplace(x).unwrap(),
+            |b, x, _| b.softcap_inplace(x, 0.5).unwrap(),
+            |b, x, up| b.geglu_inplace(x, up).unwrap(),
+        ];
+        for op in ops {
+            let (mut a, mut c) = (x.clone(), x.clone());
+            op(&single, &mut a, &up);
+            op(&multi, &mut c, &up);
+            assert_eq!(a, c);
+        }
+
+        let hidden = 64;
+        let w = values(hidden, 13);
+        let bias = values(hidden, 14);
+        assert_eq!(
+            single
+                .layer_norm(&x[..hidden * 50], &w, Some(&bias), 1e-5, hidden)
+                .unwrap(),
+            multi
+                .layer_norm(&x[..hidden * 50], &w, Some(&bias), 1e-5, hidden)
+                .unwrap()
+        );
+    }
+
+    /// Attention computed the textbook way: all scores, then softmax, then
+    /// the weighted sum of values, one query token at a time.
+    fn attention_reference(
+        q: &[f32],
+        k: &[f32],
+        v: &[f32],
+        q_pos: usize,
+        n_positions: usize,
+        params: &AttentionParams,
+    ) -> Vec<f32> {
+        let (hd, kv_dim) = (params.head_dim, params.kv_dim());
+        let q_dim = params.n_heads * hd;
+        let group = params.n_heads / params.n_kv_heads;
+        let mut out = vec![0.0f32; q.len()];
+        for t in 0..q.len() / q_dim {
+            let keys = params.mask.key_range(q_pos + t, n_positions);
+            for h in 0..params.n_heads {
+                let kv_h = h / group;
+                let q_head = &q[t * q_dim + h * hd..][..hd];
+                let scores: Vec<f32> = keys
+                    .clone()
+                    .map(|s| {
+                        let k = &k[s * kv_dim + kv_h * hd..][..hd];
+                        let dot: f32 = q_head.iter().zip(k).map(|(a, b)| a * b).sum();
+                        let score = dot * params.scale;
+                        params.softcap.map_or(score, |c| c * (score / c).tanh())
+                    })
+                    .collect();
+                let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let exps: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+                let sum: f32 = exps.iter().sum();
+                let out_head = &mut out[t * q_dim + h * hd..][..hd];
+                for (s, e) in keys.clone().zip(&exps) {
+                    let v = &v[s * kv_dim + kv_h * hd..][..hd];
+                    for d in 0..hd {
+                        out_head[d] += e / sum * v[d];
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_attention_matches_reference() {
+        let b = backend();
+        let (n_heads, n_kv_heads, hd, n_pos) = (4, 2, 8, 13);
+        let kv_dim = n_kv_heads * hd;
+        let q = values(n_heads * hd, 1);
+        let k = values(n_pos * kv_dim, 2);
+        let v = values(n_pos * kv_dim, 3);
+        let kv = KvView {
+            k: &k,
+            v: &v,
+            n_positions: n_pos,
+        };
+
+        let base = AttentionParams::new(n_heads, n_kv_heads, hd);
+        let cases = [
+            (base, 12),
+            // A causal query in the middle of the cache ignores later keys.
+            (base, 5),
+            (
+                AttentionParams {
+                    mask: AttentionMask::None,
+                    ..base
+                },
+                5,
+            ),
+            (
+                AttentionParams {
+                    mask: AttentionMask::SlidingWindow(4),
+                    ..base
+                },
+                12,
+            ),
+            (
+                AttentionParams {
+                    softcap: Some(0.3),
+                    scale: 2.0,
+                    ..base
+                },
+                12,
+            ),
+        ];
+        for (params, q_pos) in cases {
+            let mut out = vec![0.0f32; n_heads * hd];
+            b.attention_into(&q, kv, q_pos, &params, &mut out).unwrap();
+            let want = attention_reference(&q, &k, &v, q_pos, n_pos, &params);
+            for (g, w) in out.iter().zip(&want) {
+                assert!(
+                    (g - w).abs() < 1e-5,
+                    "{:?} at {}: {} vs {}",
+                    params,
+                    q_pos,
+                    g,
+                    w
+                );
+            }
+        }
+
+        // Changing a key the mask hides does not change the result.
+        let mut out = vec![0.0f32; n_heads * hd];
+        b.attention_into(&q, kv, 5, &base, &mut out).unwrap();
+        let mut k2 = k.clone();
+        k2[8 * kv_dim] += 10.0;
+        let mut out2 = vec![0.0f32; n_heads * hd];
+        let kv2 = KvView { k: &k2, ..kv };
+        b.attention_into(&q, kv2, 5, &base, &mut out2).unwrap();
+        assert_eq!(out, out2);
+    }
+
+    #[test]
+    fn test_attention_large_scores_and_empty_window() {
+        let b = backend();
+        let params = AttentionParams {
+            scale: 1.0,
+            ..AttentionParams::new(1, 1, 2)
+        };
+        // Scores of 0, 1000 and 2000: a naive exp would overflow.
+        let q = [1000.0f32, 0.0];
+        let k = [0.0f32, 0.0, 1.0, 0.0, 2.0, 0.0];
+        let v = [1.0f32, 1.0, 2.0, 2.0, 3.0, 3.0];
+        let kv = KvView {
+            k: &k,
+            v: &v,
+            n_positions: 3,
+        };
+        let mut out = [0.0f32; 2];
+        b.attention_into(&q, kv, 2, &params, &mut out).unwrap();
+        assert_eq!(out, [3.0, 3.0]);
+
+        let empty = AttentionParams {
+            mask: AttentionMask::SlidingWindow(0),
+            ..params
+        };
+        b.attention_into(&q, kv, 2, &empty, &mut out).unwrap();
+        assert_eq!(out, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_attention_rejects_bad_layouts() {
+        let b = backend();
+        let params = AttentionParams::new(3, 2, 4);
+        let buf = vec![0.0f32; 64];
+        let kv = KvView {
+            k: &buf,
+            v: &buf,
+            n_positions: 2,
+        };
+        let mut out = vec![0.0f32; 12];
+        assert!(
+            b.attention_into(&buf[..12], kv, 1, &params, &mut out)
+                .is_err()
+        );
+
+        let params = AttentionParams::new(4, 2, 4);
+        let mut out = vec![0.0f32; 16];
+        assert!(
+            b.attention_into(&buf[..15], kv, 1, &params, &mut out)
+                .is_err()
+        );
+        assert!(
+            b.attention_into(&buf[..16], kv, 1, &params, &mut out[..8])
+                .is_err()
+        );
+        let short = KvView {
+            n_positions: 9,
+            ..kv
+        };
+        assert!(
+            b.attention_into(&buf[..16], short, 1, &params, &mut out)
+                .is_err()
+        );
+        let capped = AttentionParams {
+            softcap: Some(0.0),
+            ..params
+        };
+        assert!(
+            b.attention_into(&buf[..16], kv, 1, &capped, &mut out)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_attention_prefill_matches_reference_across_tiles() {
+        let b = backend();
+        // Long enough that every query spans several key tiles.
+        let (n_heads, n_kv_heads, hd) = (4, 1, 16);
+        let (q_pos, n_tokens) = (150, 50);
+        let n_pos = q_pos + n_tokens;
+        let kv_dim = n_kv_heads * hd;
+        let q = values(n_tokens * n_heads * hd, 7);
+        let k = values(n_pos * kv_dim, 8);
+        let v = values(n_pos * kv_dim, 9);
+        let kv = KvView {
+            k: &k,
+            v: &v,
+            n_positions: n_pos,
+        };
+
+        let base = AttentionParams::new(n_heads, n_kv_heads, hd);
+        for params in [
+            base,
+            AttentionParams {
+                mask: AttentionMask::SlidingWindow(70),
+                ..base
+            },
+            AttentionParams {
+                softcap: Some(0.5),
+                scale: 4.0,
+                ..base
+            },
+        ] {
+            let mut out = vec![0.0f32; q.len()];
+            b.attention_into(&q, kv, q_pos, &params, &mut out).unwrap();
+            let want = attention_reference(&q, &k, &v, q_pos, n_pos, &params);
+            for (g, w) in out.iter().zip(&want) {
+                assert!((g - w).abs() < 1e-5, "{:?}: {} vs {}", params, g, w);
+            }
+
+            // A batch gives the same rows as one call per token.
+            let q_dim = n_heads * hd;
+            let mut row = vec![0.0f32; q_dim];
+            for t in [0, 17, n_tokens - 1] {
+                let q_t = &q[t * q_dim..(t + 1) * q_dim];
+                b.attention_into(q_t, kv, q_pos + t, &params, &mut row)
+                    .unwrap();
+                assert_eq!(&row[..], &out[t * q_dim..(t + 1) * q_dim]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_attention_threaded_matches_single_thread() {
+        let (n_heads, n_kv_heads, hd, n_pos) = (8, 2, 16, 40);
+        let q = values(n_heads * hd, 4);
+        let k = values(n_pos * n_kv_heads * hd, 5);
+        let v = values(n_pos * n_kv_heads * hd, 6);
+        let kv = KvView {
+            k: &k,
+            v: &v,
+            n_positions: n_pos,
+        };
+        let params = AttentionParams::new(n_heads, n_kv_heads, hd);
+        let mut a = vec![0.0f32; n_heads * hd];
+        let mut c = vec![0.0f32; n_heads * hd];
+        CpuBackend::with_threads(1)
+            .unwrap()
+            .attention_into(&q, kv, n_pos - 1, &params, &mut a)
+            .unwrap();
+        CpuBackend::with_threads(4)
+            .unwrap()
+            .attention_into(&q, kv, n_pos - 1, &params, &mut c)
+            .unwrap();
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn test_attention_f16_matches_f32() {
+        let b = backend();
+        let (n_heads, n_kv_heads, hd, n_pos) = (4, 2, 16, 70);
+        let kv_dim = n_kv_heads * hd;
+        let q = values(2 * n_heads * hd, 7);
+        let k = round_f16(&values(n_pos * kv_dim, 8));
+        let v = round_f16(&values(n_pos * kv_dim, 9));
+        let (k16, v16) = (to_f16_bytes(&k), to_f16_bytes(&v));
+        let kv = KvView {
+            k: &k,
+            v: &v,
+            n_positions: n_pos,
+        };
+        let kv16 = KvViewF16 {
+            k: &k16,
+            v: &v16,
+            n_positions: n_pos,
+        };
+        let params = AttentionParams::new(n_heads, n_kv_heads, hd);
+        let mut want = vec![0.0f32; q.len()];
+        let mut got = vec![0.0f32; q.len()];
+        b.attention_into(&q, kv, n_pos - 2, &params, &mut want)
+            .unwrap();
+        b.attention_f16_into(&q, kv16, n_pos - 2, &params, &mut got)
+            .unwrap();
+        for (g, w) in got.iter().zip(&want) {
+            assert!((g - w).abs() < 1e-5, "{} vs {}", g, w);
+        }
+
+        let short = KvViewF16 {
+            k: &k16[..k16.len() - 2],
+            ..kv16
+        };
+        assert!(
+            b.attention_f16_into(&q, short, n_pos - 2, &params, &mut got)
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,98 @@
+// Block-quantized dot product kernels.
+//
+// Weights stay in their GGUF encoding; activations are quantized to Q8_0 so
+// each 32-element block reduces to an integer dot product scaled by the two
+// block scales, the same scheme ggml uses on CPU.
+
+use half::f16;
+
+/// Number of elements in a Q4_0 / Q8_0 block.
+pub const QK: usize = 32;
+
+/// Bytes in one Q4_0 block: f16 scale + 16 bytes of packed nibbles.
+pub const Q4_0_BLOCK_BYTES: usize = 18;
+
+/// Bytes in one Q8_0 block: f16 scale + 32 signed bytes.
+pub const Q8_0_BLOCK_BYTES: usize = 34;
+
+/// Read the little-endian f16 scale at the start of a block.
+#[inline]
+pub(crate) fn block_scale(block: &[u8]) -> f32 {
+    f16::from_le_bytes([block[0], block[1]]).to_f32()
+}
+
+/// Quantize an f32 row into Q8_0 blocks.
+///
+/// `x.len()` must be a multiple of 32 and `out` must hold
+/// `x.len() / 32 * 34` bytes. Each block stores `d = max|x| / 127` as f16
+/// followed by `round(x / d)` as i8.
+pub fn quantize_row_q8_0(x: &[f32], out: &mut [u8]) {
+    debug_assert_eq!(x.len() % QK, 0);
+    debug_assert_eq!(out.len(), x.len() / QK * Q8_0_BLOCK_BYTES);
+
+    for (src, dst) in x
+        .chunks_exact(QK)
+        .zip(out.chunks_exact_mut(Q8_0_BLOCK_BYTES))
+    {
+        let amax = src.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        let d = amax / 127.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        dst[..2].copy_from_slice(&f16::from_f32(d).to_le_bytes());
+        for (q, &v) in dst[2..].iter_mut().zip(src) {
+            *q = (v * id).round() as i8 as u8;
+        }
+    }
+}
+
+/// Dot product of a Q4_0 row with a Q8_0 row of the same length.
+///
+/// Byte `j` of a Q4_0 block holds element `j` in its low nibble and element
+/// `j + 16` in its high nibble, both as unsigned values offset by 8.
+pub fn vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+    let mut sum = 0.0f32;
+    for (wb, xb) in w
+        .chunks_exact(Q4_0_BLOCK_BYTES)
+        .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+    {
+        sum += block_dot_q4_0_q8_0(wb, xb);
+    }
+    sum
+}
+
+/// Dot product of one Q4_0 block with one Q8_0 block.
+#[inline]
+pub fn block_dot_q4_0_q8_0(wb: &[u8], xb: &[u8]) -> f32 {
+    let xq = &xb[2..];
+    let mut sumi = 0i32;
+    for (j, &byte) in wb[2..Q4_0_BLOCK_BYTES].iter().enumerate() {
+        let lo = (byte & 0x0F) as i32 - 8;
+        let hi = (byte >> 4) as i32 - 8;
+        sumi += lo * xq[j] as i8 as i32;
+        sumi += hi * xq[j + QK / 2] as i8 as i32;
+    }
+    sumi as f32 * block_scale(wb) * block_scale(xb)
+}
+
+/// Dot product of two Q8_0 rows of the same length.
+pub fn vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+    let mut sum = 0.0f32;
+    for (wb, xb) in w
+        .chunks_exact(Q8_0_BLOCK_BYTES)
+        .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+    {
+        sum += block_dot_q8_0_q8_0(wb, xb);
+    }
+    sum
+}
+
+/// Dot product of two Q8_0 blocks.
+#[inline]
+pub fn block_dot_q8_0_q8_0(wb: &[u8], xb: &[u8]) -> f32 {
+    let sumi: i32 = wb[2..Q8_0_BLOCK_BYTES]
+        .iter()
+        .zip(&xb[2..Q8_0_BLOCK_BYTES])
+        .map(|(&a, &b)| a as i8 as i32 * b as i8 as i32)
+        .sum();
+    sumi as f32 * block_scale(wb) * block_scale(xb)
+}
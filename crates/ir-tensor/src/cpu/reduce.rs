@@ -0,0 +1,224 @@
+// Reductions with a fixed summation order.
+//
+// The SIMD kernels add up dot products, softmax denominators and sums of
+// squares in whatever order suits the instruction set (several lanes at a
+// time, with or without FMA), so their results differ in the last bits
+// between CPUs. The kernels here are scalar and add their terms in an order
+// that depends only on the length, so they give the same bits on every
+// machine and for every thread count. `Reduction` picks the order, and
+// `Kernels::with_reduction` installs the matching kernels.
+
+use std::fmt;
+
+use half::f16;
+
+use super::quant::{self, Q4_0_BLOCK_BYTES, Q8_0_BLOCK_BYTES};
+
+/// How `CpuBackend` sums f32 values in dot products, norms, softmax and
+/// reductions.
+///
+/// Every mode except `Native` runs the scalar kernels with a fixed order of
+/// additions, so logits are bit-for-bit reproducible across machines with
+/// different SIMD support and core counts (as long as the platform's `exp`
+/// agrees).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Reduction {
+    /// The fastest kernels for the CPU. Results do not depend on the thread
+    /// count but may differ between instruction sets.
+    #[default]
+    Native,
+    /// Left-to-right accumulation in f32.
+    Sequential,
+    /// Recursive halving, which keeps the rounding error growing with
+    /// `log n` rather than `n`.
+    Pairwise,
+    /// Left-to-right with Kahan-Babuska (Neumaier) compensation, which
+    /// carries the rounding error of each addition along in a second term.
+    Kahan,
+}
+
+impl Reduction {
+    /// Whether results are independent of the CPU's instruction set.
+    pub fn is_deterministic(self) -> bool {
+        self != Reduction::Native
+    }
+
+    /// `term(0) + term(1) + ... + term(n - 1)` in this mode's order.
+    /// `Native` sums left to right.
+    pub fn sum(self, n: usize, term: impl Fn(usize) -> f32) -> f32 {
+        match self {
+            Reduction::Native | Reduction::Sequential => SequentialSum::sum(n, term),
+            Reduction::Pairwise => PairwiseSum::sum(n, term),
+            Reduction::Kahan => KahanSum::sum(n, term),
+        }
+    }
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reduction::Native => write!(f, "native"),
+            Reduction::Sequential => write!(f, "sequential"),
+            Reduction::Pairwise => write!(f, "pairwise"),
+            Reduction::Kahan => write!(f, "kahan"),
+        }
+    }
+}
+
+/// A summation order, as a type so kernels can be instantiated per order
+/// and stored as plain function pointers.
+pub trait Summation {
+    /// `term(0) + ... + term(n - 1)`.
+    fn sum(n: usize, term: impl Fn(usize) -> f32) -> f32;
+}
+
+/// See [`Reduction::Sequential`].
+pub struct SequentialSum;
+
+/// See [`Reduction::Pairwise`].
+pub struct PairwiseSum;
+
+/// See [`Reduction::Kahan`].
+pub struct KahanSum;
+
+/// Ranges at most this long are summed left to right by [`PairwiseSum`].
+const PAIRWISE_BLOCK: usize = 16;
+
+impl Summation for SequentialSum {
+    fn sum(n: usize, term: impl Fn(usize) -> f32) -> f32 {
+        let mut sum = 0.0f32;
+        for i in 0..n {
+            sum += term(i);
+        }
+        sum
+    }
+}
+
+impl Summation for PairwiseSum {
+    fn sum(n: usize, term: impl Fn(usize) -> f32) -> f32 {
+        fn range(start: usize, end: usize, term: &impl Fn(usize) -> f32) -> f32 {
+            if end - start <= PAIRWISE_BLOCK {
+                let mut sum = 0.0f32;
+                for i in start..end {
+                    sum += term(i);
+                }
+                return sum;
+            }
+            let mid = start + (end - start) / 2;
+            range(start, mid, term) + range(mid, end, term)
+        }
+        range(0, n, &term)
+    }
+}
+
+impl Summation for KahanSum {
+    fn sum(n: usize, term: impl Fn(usize) -> f32) -> f32 {
+        let mut sum = 0.0f32;
+        let mut compensation = 0.0f32;
+        for i in 0..n {
+            let x = term(i);
+            let t = sum + x;
+            // Recover the low-order bits lost by whichever operand was
+            // smaller in magnitude.
+            if sum.abs() >= x.abs() {
+                compensation += (sum - t) + x;
+            } else {
+                compensation += (x - t) + sum;
+            }
+            sum = t;
+        }
+        sum + compensation
+    }
+}
+
+/// Dot product of two f32 slices of the same length.
+pub fn dot_f32<S: Summation>(a: &[f32], b: &[f32]) -> f32 {
+    S::sum(a.len().min(b.len()), |i| a[i] * b[i])
+}
+
+/// Dot product of little-endian f16 values with f32 values; see
+/// [`super::simd::scalar::dot_f16_f32`].
+pub fn dot_f16_f32<S: Summation>(a: &[u8], b: &[f32]) -> f32 {
+    S::sum((a.len() / 2).min(b.len()), |i| {
+        f16::from_le_bytes([a[2 * i], a[2 * i + 1]]).to_f32() * b[i]
+    })
+}
+
+/// Softmax of one row; see [`super::simd::scalar::softmax`].
+pub fn softmax<S: Summation>(x: &[f32], out: &mut [f32]) {
+    let max_val = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    for (o, &v) in out.iter_mut().zip(x) {
+        *o = (v - max_val).exp();
+    }
+    let sum = S::sum(out.len(), |i| out[i]);
+    for o in out.iter_mut() {
+        *o /= sum;
+    }
+}
+
+/// RMS normalization of one row; see [`super::simd::scalar::rms_norm`].
+pub fn rms_norm<S: Summation>(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+    let mean_sq = S::sum(x.len(), |i| x[i] * x[i]) / x.len() as f32;
+    let rms = (mean_sq + eps).sqrt();
+    for ((o, &v), &w) in out.iter_mut().zip(x).zip(weight) {
+        *o = v * w / rms;
+    }
+}
+
+/// Dot product of a Q4_0 row with a Q8_0 row, summing the per-block
+/// products in order `S`.
+pub fn vec_dot_q4_0_q8_0<S: Summation>(w: &[u8], x: &[u8]) -> f32 {
+    S::sum(x.len() / Q8_0_BLOCK_BYTES, |b| {
+        quant::block_dot_q4_0_q8_0(
+            &w[b * Q4_0_BLOCK_BYTES..(b + 1) * Q4_0_BLOCK_BYTES],
+            &x[b * Q8_0_BLOCK_BYTES..(b + 1) * Q8_0_BLOCK_BYTES],
+        )
+    })
+}
+
+/// Dot product of two Q8_0 rows, summing the per-block products in order `S`.
+pub fn vec_dot_q8_0_q8_0<S: Summation>(w: &[u8], x: &[u8]) -> f32 {
+    S::sum(x.len() / Q8_0_BLOCK_BYTES, |b| {
+        let block = b * Q8_0_BLOCK_BYTES..(b + 1) * Q8_0_BLOCK_BYTES;
+        quant::block_dot_q8_0_q8_0(&w[block.clone()], &x[block])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orders_agree_on_exact_sums() {
+        for n in [0, 1, 15, 16, 17, 100, 1000] {
+            let want = (n * (n + 1) / 2) as f32;
+            for r in [
+                Reduction::Native,
+                Reduction::Sequential,
+                Reduction::Pairwise,
+                Reduction::Kahan,
+            ] {
+                assert_eq!(r.sum(n, |i| (i + 1) as f32), want, "{} n={}", r, n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compensated_orders_are_more_accurate() {
+        // 1 followed by many terms each too small to change a running f32
+        // sum near 1 on their own.
+        let n = 1 << 20;
+        let term = |i: usize| if i == 0 { 1.0 } else { 1e-8 };
+        let exact = 1.0 + (n - 1) as f64 * 1e-8f32 as f64;
+        let err = |r: Reduction| (r.sum(n, term) as f64 - exact).abs();
+        assert_eq!(Reduction::Sequential.sum(n, term), 1.0);
+        assert!(err(Reduction::Pairwise) < 1e-6);
+        assert!(err(Reduction::Kahan) < err(Reduction::Sequential) / 100.0);
+    }
+
+    #[test]
+    fn test_kahan_handles_large_cancelling_terms() {
+        let values = [1.0f32, 1e8, 1.0, -1e8];
+        assert_eq!(KahanSum::sum(values.len(), |i| values[i]), 2.0);
+    }
+}
@@ -0,0 +1,286 @@
+// aarch64 NEON kernels.
+//
+// Same structure as the x86 kernels: `#[target_feature]` functions wrapped in
+// plain `fn`s that `Kernels` installs after NEON has been detected.
+
+use std::arch::aarch64::*;
+
+use crate::cpu::quant::{Q4_0_BLOCK_BYTES, Q8_0_BLOCK_BYTES, block_scale};
+
+const W: usize = 4;
+
+// Cephes-style expf, as in the x86 kernels.
+const EXP_HI: f32 = 88.0;
+const EXP_LO: f32 = -88.0;
+const LOG2E: f32 = std::f32::consts::LOG2_E;
+const LN2_HI: f32 = 0.693_359_4;
+const LN2_LO: f32 = -2.121_944_4e-4;
+const EXP_P: [f32; 6] = [
+    1.987_569_1e-4,
+    1.398_199_9e-3,
+    8.333_452e-3,
+    4.166_579_6e-2,
+    1.666_666_5e-1,
+    0.5,
+];
+
+/// Wrap target-feature kernels in plain `fn`s that fit the kernel table.
+macro_rules! wrappers {
+    ($($name:ident => $inner:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        $(
+            pub fn $name($($arg: $ty),*) $(-> $ret)? {
+                // SAFETY: `Kernels::for_level` only installs this wrapper
+                // once NEON support has been detected.
+                unsafe { $inner($($arg),*) }
+            }
+        )*
+    };
+}
+
+wrappers! {
+    dot_f32_neon => dot_f32(a: &[f32], b: &[f32]) -> f32;
+    dot_f16_f32_neon => dot_f16_f32(a: &[u8], b: &[f32]) -> f32;
+    vec_dot_q4_0_q8_0_neon => vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    vec_dot_q8_0_q8_0_neon => vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    silu_neon => silu(x: &[f32], out: &mut [f32]);
+    softmax_neon => softmax(x: &[f32], out: &mut [f32]);
+    rms_norm_neon => rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]);
+}
+
+#[inline]
+#[target_feature(enable = "neon")]
+fn load(s: &[f32]) -> float32x4_t {
+    let s = &s[..W];
+    // SAFETY: `s` holds W floats.
+    unsafe { vld1q_f32(s.as_ptr()) }
+}
+
+#[inline]
+#[target_feature(enable = "neon")]
+fn store(s: &mut [f32], v: float32x4_t) {
+    let s = &mut s[..W];
+    // SAFETY: `s` holds W floats.
+    unsafe { vst1q_f32(s.as_mut_ptr(), v) }
+}
+
+#[inline]
+#[target_feature(enable = "neon")]
+fn load_i8(s: &[u8]) -> int8x16_t {
+    let s = &s[..16];
+    // SAFETY: `s` holds 16 bytes.
+    unsafe { vld1q_s8(s.as_ptr() as *const i8) }
+}
+
+/// W little-endian f16 values widened to f32.
+///
+/// Stable Rust has no NEON f16 vector type, so the f32 bit patterns are
+/// rebuilt with integer ops: exponent and mantissa shifted into place and
+/// scaled by 2^112 rebias normals and subnormals alike, and infinities and
+/// NaNs get the all-ones f32 exponent.
+#[inline]
+#[target_feature(enable = "neon")]
+fn load_f16(s: &[u8]) -> float32x4_t {
+    let s = &s[..2 * W];
+    // SAFETY: `s` holds W f16 values.
+    let h = vmovl_u16(vreinterpret_u16_u8(unsafe { vld1_u8(s.as_ptr()) }));
+    let sign = vshlq_n_u32::<16>(vandq_u32(h, vdupq_n_u32(0x8000)));
+    let em = vshlq_n_u32::<13>(vandq_u32(h, vdupq_n_u32(0x7FFF)));
+    let v = vmulq_f32(
+        vreinterpretq_f32_u32(em),
+        vdupq_n_f32(f32::from_bits(0x7780_0000)),
+    );
+    let inf_nan = vandq_u32(
+        vcgeq_u32(em, vdupq_n_u32(0x7C00 << 13)),
+        vdupq_n_u32(0x7F80_0000),
+    );
+    vreinterpretq_f32_u32(vorrq_u32(
+        vorrq_u32(vreinterpretq_u32_f32(v), inf_nan),
+        sign,
+    ))
+}
+
+#[inline]
+#[target_feature(enable = "neon")]
+fn exp(x: float32x4_t) -> float32x4_t {
+    let x = vminq_f32(vmaxq_f32(x, vdupq_n_f32(EXP_LO)), vdupq_n_f32(EXP_HI));
+    let fx = vrndmq_f32(vfmaq_f32(vdupq_n_f32(0.5), x, vdupq_n_f32(LOG2E)));
+    let x = vfmsq_f32(x, fx, vdupq_n_f32(LN2_HI));
+    let x = vfmsq_f32(x, fx, vdupq_n_f32(LN2_LO));
+    let z = vmulq_f32(x, x);
+    let mut y = vdupq_n_f32(EXP_P[0]);
+    for &p in &EXP_P[1..] {
+        y = vfmaq_f32(vdupq_n_f32(p), y, x);
+    }
+    let y = vaddq_f32(vfmaq_f32(x, y, z), vdupq_n_f32(1.0));
+    let n = vaddq_s32(vcvtq_s32_f32(fx), vdupq_n_s32(127));
+    vmulq_f32(y, vreinterpretq_f32_s32(vshlq_n_s32::<23>(n)))
+}
+
+/// Sum of the products of 16 signed byte pairs, as four i32 partial sums.
+#[inline]
+#[target_feature(enable = "neon")]
+fn dot_i8(x: int8x16_t, y: int8x16_t) -> int32x4_t {
+    let lo = vmull_s8(vget_low_s8(x), vget_low_s8(y));
+    let hi = vmull_high_s8(x, y);
+    vaddq_s32(vpaddlq_s16(lo), vpaddlq_s16(hi))
+}
+
+#[target_feature(enable = "neon")]
+fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len().min(b.len());
+    let mut acc = [vdupq_n_f32(0.0); 4];
+    let mut i = 0;
+    while i + 4 * W <= n {
+        for (j, acc) in acc.iter_mut().enumerate() {
+            let off = i + j * W;
+            *acc = vfmaq_f32(*acc, load(&a[off..]), load(&b[off..]));
+        }
+        i += 4 * W;
+    }
+    let mut sum = vaddq_f32(vaddq_f32(acc[0], acc[1]), vaddq_f32(acc[2], acc[3]));
+    while i + W <= n {
+        sum = vfmaq_f32(sum, load(&a[i..]), load(&b[i..]));
+        i += W;
+    }
+    let mut total = vaddvq_f32(sum);
+    for j in i..n {
+        total += a[j] * b[j];
+    }
+    total
+}
+
+#[target_feature(enable = "neon")]
+fn dot_f16_f32(a: &[u8], b: &[f32]) -> f32 {
+    let n = (a.len() / 2).min(b.len());
+    let mut acc = [vdupq_n_f32(0.0); 2];
+    let mut i = 0;
+    while i + 2 * W <= n {
+        for (j, acc) in acc.iter_mut().enumerate() {
+            let off = i + j * W;
+            *acc = vfmaq_f32(*acc, load_f16(&a[2 * off..]), load(&b[off..]));
+        }
+        i += 2 * W;
+    }
+    let mut sum = vaddq_f32(acc[0], acc[1]);
+    while i + W <= n {
+        sum = vfmaq_f32(sum, load_f16(&a[2 * i..]), load(&b[i..]));
+        i += W;
+    }
+    let mut total = vaddvq_f32(sum);
+    for j in i..n {
+        total += half::f16::from_le_bytes([a[2 * j], a[2 * j + 1]]).to_f32() * b[j];
+    }
+    total
+}
+
+#[target_feature(enable = "neon")]
+fn vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+    let mask = vdupq_n_u8(0x0F);
+    let eight = vdupq_n_s8(8);
+    let mut sum = 0.0f32;
+    for (wb, xb) in w
+        .chunks_exact(Q4_0_BLOCK_BYTES)
+        .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+    {
+        // Low nibbles are elements 0..16, high nibbles 16..32.
+        let bytes = vreinterpretq_u8_s8(load_i8(&wb[2..]));
+        let lo = vsubq_s8(vreinterpretq_s8_u8(vandq_u8(bytes, mask)), eight);
+        let hi = vsubq_s8(vreinterpretq_s8_u8(vshrq_n_u8::<4>(bytes)), eight);
+        let sumi = vaddq_s32(
+            dot_i8(lo, load_i8(&xb[2..])),
+            dot_i8(hi, load_i8(&xb[18..])),
+        );
+        sum += vaddvq_s32(sumi) as f32 * block_scale(wb) * block_scale(xb);
+    }
+    sum
+}
+
+#[target_feature(enable = "neon")]
+fn vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+    let mut sum = 0.0f32;
+    for (wb, xb) in w
+        .chunks_exact(Q8_0_BLOCK_BYTES)
+        .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+    {
+        let lo = dot_i8(load_i8(&wb[2..]), load_i8(&xb[2..]));
+        let hi = dot_i8(load_i8(&wb[18..]), load_i8(&xb[18..]));
+        sum += vaddvq_s32(vaddq_s32(lo, hi)) as f32 * block_scale(wb) * block_scale(xb);
+    }
+    sum
+}
+
+#[target_feature(enable = "neon")]
+fn silu(x: &[f32], out: &mut [f32]) {
+    let n = x.len().min(out.len());
+    let one = vdupq_n_f32(1.0);
+    let mut i = 0;
+    while i + W <= n {
+        let v = load(&x[i..]);
+        let e = exp(vnegq_f32(v));
+        store(&mut out[i..], vdivq_f32(v, vaddq_f32(one, e)));
+        i += W;
+    }
+    for j in i..n {
+        out[j] = x[j] / (1.0 + (-x[j]).exp());
+    }
+}
+
+#[target_feature(enable = "neon")]
+fn softmax(x: &[f32], out: &mut [f32]) {
+    let n = x.len().min(out.len());
+
+    let mut vmax = vdupq_n_f32(f32::NEG_INFINITY);
+    let mut i = 0;
+    while i + W <= n {
+        vmax = vmaxq_f32(vmax, load(&x[i..]));
+        i += W;
+    }
+    let mut max_val = vmaxvq_f32(vmax);
+    for &v in &x[i..n] {
+        max_val = max_val.max(v);
+    }
+
+    let vm = vdupq_n_f32(max_val);
+    let mut vsum = vdupq_n_f32(0.0);
+    i = 0;
+    while i + W <= n {
+        let e = exp(vsubq_f32(load(&x[i..]), vm));
+        store(&mut out[i..], e);
+        vsum = vaddq_f32(vsum, e);
+        i += W;
+    }
+    let mut sum = vaddvq_f32(vsum);
+    for j in i..n {
+        let e = (x[j] - max_val).exp();
+        out[j] = e;
+        sum += e;
+    }
+
+    let vs = vdupq_n_f32(sum);
+    i = 0;
+    while i + W <= n {
+        let v = vdivq_f32(load(&out[i..]), vs);
+        store(&mut out[i..], v);
+        i += W;
+    }
+    for o in &mut out[i..n] {
+        *o /= sum;
+    }
+}
+
+#[target_feature(enable = "neon")]
+fn rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+    let n = x.len();
+    let sum_sq = dot_f32(x, x);
+    let rms = (sum_sq / n as f32 + eps).sqrt();
+    let vr = vdupq_n_f32(rms);
+    let mut i = 0;
+    while i + W <= n {
+        let v = vmulq_f32(load(&x[i..]), load(&weight[i..]));
+        store(&mut out[i..], vdivq_f32(v, vr));
+        i += W;
+    }
+    for j in i..n {
+        out[j] = x[j] * weight[j] / rms;
+    }
+}
@@ -0,0 +1,503 @@
+// Runtime-dispatched SIMD kernels.
+//
+// The hot inner loops of the CPU backend (f32 and f16 x f32 dot products,
+// quantized block dot products, silu, softmax rows, rms_norm rows and the GEMM microkernel)
+// have one scalar reference implementation plus vectorized variants per
+// instruction set.
+// `SimdLevel::detect` probes the CPU once, and `Kernels` holds function
+// pointers to the best variant of each kernel for a given level.
+
+pub mod scalar;
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86_64")]
+mod x86;
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use super::matmul::{self, Microkernel};
+use super::quant;
+use super::reduce::{self, KahanSum, PairwiseSum, Reduction, Summation};
+
+/// Instruction set a kernel variant is written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SimdLevel {
+    /// Portable Rust loops.
+    Scalar,
+    /// x86_64 SSE4.1 (with SSSE3).
+    Sse41,
+    /// x86_64 AVX2 with FMA.
+    Avx2,
+    /// x86_64 AVX-512F (on top of AVX2 and FMA).
+    Avx512,
+    /// aarch64 Advanced SIMD.
+    Neon,
+}
+
+impl SimdLevel {
+    /// The best level supported by the running CPU. Detected once and cached.
+    pub fn detect() -> SimdLevel {
+        static LEVEL: OnceLock<SimdLevel> = OnceLock::new();
+        *LEVEL.get_or_init(|| {
+            Self::supported()
+                .into_iter()
+                .max()
+                .unwrap_or(SimdLevel::Scalar)
+        })
+    }
+
+    /// Every level the running CPU can execute, including `Scalar`.
+    pub fn supported() -> Vec<SimdLevel> {
+        let mut levels = vec![SimdLevel::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("ssse3") && is_x86_feature_detected!("sse4.1") {
+                levels.push(SimdLevel::Sse41);
+            }
+            let avx2 = is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma");
+            if avx2 {
+                levels.push(SimdLevel::Avx2);
+            }
+            if avx2 && is_x86_feature_detected!("avx512f") {
+                levels.push(SimdLevel::Avx512);
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                levels.push(SimdLevel::Neon);
+            }
+        }
+        levels
+    }
+}
+
+impl fmt::Display for SimdLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimdLevel::Scalar => write!(f, "scalar"),
+            SimdLevel::Sse41 => write!(f, "sse4.1"),
+            SimdLevel::Avx2 => write!(f, "avx2"),
+            SimdLevel::Avx512 => write!(f, "avx512"),
+            SimdLevel::Neon => write!(f, "neon"),
+        }
+    }
+}
+
+/// The variant selected for one kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelVariant {
+    /// Kernel name, e.g. `"dot_f32"`.
+    pub kernel: &'static str,
+    /// Instruction set of the selected implementation.
+    pub level: SimdLevel,
+}
+
+type DotF32 = fn(&[f32], &[f32]) -> f32;
+type DotF16 = fn(&[u8], &[f32]) -> f32;
+type VecDotQ = fn(&[u8], &[u8]) -> f32;
+type Unary = fn(&[f32], &mut [f32]);
+type RmsNorm = fn(&[f32], &[f32], f32, &mut [f32]);
+
+/// Function table with one implementation per kernel.
+///
+/// A level may not have its own variant of every kernel (AVX-512 reuses the
+/// AVX2 f16 dot, and SSE4.1 has none), so each entry records the level of the
+/// implementation it points to.
+#[derive(Debug, Clone, Copy)]
+pub struct Kernels {
+    level: SimdLevel,
+    reduction: Reduction,
+    dot_f32: (SimdLevel, DotF32),
+    dot_f16_f32: (SimdLevel, DotF16),
+    vec_dot_q4_0_q8_0: (SimdLevel, VecDotQ),
+    vec_dot_q8_0_q8_0: (SimdLevel, VecDotQ),
+    silu: (SimdLevel, Unary),
+    softmax: (SimdLevel, Unary),
+    rms_norm: (SimdLevel, RmsNorm),
+    gemm_microkernel: (SimdLevel, Microkernel),
+}
+
+impl Kernels {
+    /// Portable scalar kernels.
+    pub fn scalar() -> Kernels {
+        Kernels {
+            level: SimdLevel::Scalar,
+            reduction: Reduction::Native,
+            dot_f32: (SimdLevel::Scalar, scalar::dot_f32),
+            dot_f16_f32: (SimdLevel::Scalar, scalar::dot_f16_f32),
+            vec_dot_q4_0_q8_0: (SimdLevel::Scalar, quant::vec_dot_q4_0_q8_0),
+            vec_dot_q8_0_q8_0: (SimdLevel::Scalar, quant::vec_dot_q8_0_q8_0),
+            silu: (SimdLevel::Scalar, scalar::silu),
+            softmax: (SimdLevel::Scalar, scalar::softmax),
+            rms_norm: (SimdLevel::Scalar, scalar::rms_norm),
+            gemm_microkernel: (SimdLevel::Scalar, matmul::microkernel_scalar),
+        }
+    }
+
+    /// The best kernels for the running CPU.
+    pub fn detect() -> Kernels {
+        Self::for_level(SimdLevel::detect()).unwrap_or_else(Self::scalar)
+    }
+
+    /// The best kernels that use at most `level`.
+    ///
+    /// Returns `None` if the running CPU does not support `level`, since
+    /// calling those kernels would execute unsupported instructions.
+    pub fn for_level(level: SimdLevel) -> Option<Kernels> {
+        if !SimdLevel::supported().contains(&level) {
+            return None;
+        }
+        let scalar = Self::scalar();
+        // The f16 conversions need F16C, which every AVX2 CPU in practice
+        // has but which is a separate feature bit.
+        #[cfg(target_arch = "x86_64")]
+        let dot_f16_f32_avx2 = if is_x86_feature_detected!("f16c") {
+            (SimdLevel::Avx2, x86::dot_f16_f32_avx2 as DotF16)
+        } else {
+            scalar.dot_f16_f32
+        };
+        // The AVX-512 byte arithmetic is in AVX-512BW; CPUs with only the
+        // foundation keep the AVX2 quantized dots.
+        #[cfg(target_arch = "x86_64")]
+        let (vec_dot_q4_0_q8_0_avx512, vec_dot_q8_0_q8_0_avx512) =
+            if is_x86_feature_detected!("avx512bw") {
+                (
+                    (SimdLevel::Avx512, x86::vec_dot_q4_0_q8_0_avx512 as VecDotQ),
+                    (SimdLevel::Avx512, x86::vec_dot_q8_0_q8_0_avx512 as VecDotQ),
+                )
+            } else {
+                (
+                    (SimdLevel::Avx2, x86::vec_dot_q4_0_q8_0_avx2 as VecDotQ),
+                    (SimdLevel::Avx2, x86::vec_dot_q8_0_q8_0_avx2 as VecDotQ),
+                )
+            };
+        Some(match level {
+            SimdLevel::Scalar => scalar,
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse41 => Kernels {
+                level,
+                reduction: Reduction::Native,
+                dot_f32: (level, x86::dot_f32_sse41),
+                dot_f16_f32: scalar.dot_f16_f32,
+                vec_dot_q4_0_q8_0: (level, x86::vec_dot_q4_0_q8_0_sse41),
+                vec_dot_q8_0_q8_0: (level, x86::vec_dot_q8_0_q8_0_sse41),
+                silu: (level, x86::silu_sse41),
+                softmax: (level, x86::softmax_sse41),
+                rms_norm: (level, x86::rms_norm_sse41),
+                // The baseline build already vectorizes the microkernel
+                // with SSE2.
+                gemm_microkernel: scalar.gemm_microkernel,
+            },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 => Kernels {
+                level,
+                reduction: Reduction::Native,
+                dot_f32: (level, x86::dot_f32_avx2),
+                dot_f16_f32: dot_f16_f32_avx2,
+                vec_dot_q4_0_q8_0: (level, x86::vec_dot_q4_0_q8_0_avx2),
+                vec_dot_q8_0_q8_0: (level, x86::vec_dot_q8_0_q8_0_avx2),
+                silu: (level, x86::silu_avx2),
+                softmax: (level, x86::softmax_avx2),
+                rms_norm: (level, x86::rms_norm_avx2),
+                gemm_microkernel: (level, x86::gemm_microkernel_avx2),
+            },
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx512 => Kernels {
+                level,
+                reduction: Reduction::Native,
+                dot_f32: (level, x86::dot_f32_avx512),
+                dot_f16_f32: dot_f16_f32_avx2,
+                vec_dot_q4_0_q8_0: vec_dot_q4_0_q8_0_avx512,
+                vec_dot_q8_0_q8_0: vec_dot_q8_0_q8_0_avx512,
+                silu: (level, x86::silu_avx512),
+                softmax: (level, x86::softmax_avx512),
+                rms_norm: (level, x86::rms_norm_avx512),
+                gemm_microkernel: (level, x86::gemm_microkernel_avx512),
+            },
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => Kernels {
+                level,
+                reduction: Reduction::Native,
+                dot_f32: (level, aarch64::dot_f32_neon),
+                dot_f16_f32: (level, aarch64::dot_f16_f32_neon),
+                vec_dot_q4_0_q8_0: (level, aarch64::vec_dot_q4_0_q8_0_neon),
+                vec_dot_q8_0_q8_0: (level, aarch64::vec_dot_q8_0_q8_0_neon),
+                silu: (level, aarch64::silu_neon),
+                softmax: (level, aarch64::softmax_neon),
+                rms_norm: (level, aarch64::rms_norm_neon),
+                // NEON is part of the aarch64 baseline, so the portable
+                // microkernel is already vectorized.
+                gemm_microkernel: scalar.gemm_microkernel,
+            },
+            // Levels for other architectures are never reported as supported.
+            #[allow(unreachable_patterns)]
+            _ => scalar,
+        })
+    }
+
+    /// These kernels with sums in `reduction` order.
+    ///
+    /// Any mode but `Native` swaps in the scalar kernels with that order,
+    /// and `Native` restores the best kernels for the level these were
+    /// built for.
+    pub fn with_reduction(self, reduction: Reduction) -> Kernels {
+        match reduction {
+            Reduction::Native => Self::for_level(self.level).unwrap_or_else(Self::scalar),
+            Reduction::Sequential => Kernels {
+                reduction,
+                level: self.level,
+                ..Self::scalar()
+            },
+            Reduction::Pairwise => Self::ordered::<PairwiseSum>(self.level, reduction),
+            Reduction::Kahan => Self::ordered::<KahanSum>(self.level, reduction),
+        }
+    }
+
+    /// Scalar kernels whose sums run in order `S`.
+    fn ordered<S: Summation>(level: SimdLevel, reduction: Reduction) -> Kernels {
+        let s = SimdLevel::Scalar;
+        Kernels {
+            level,
+            reduction,
+            dot_f32: (s, reduce::dot_f32::<S>),
+            dot_f16_f32: (s, reduce::dot_f16_f32::<S>),
+            vec_dot_q4_0_q8_0: (s, reduce::vec_dot_q4_0_q8_0::<S>),
+            vec_dot_q8_0_q8_0: (s, reduce::vec_dot_q8_0_q8_0::<S>),
+            softmax: (s, reduce::softmax::<S>),
+            rms_norm: (s, reduce::rms_norm::<S>),
+            ..Self::scalar()
+        }
+    }
+
+    /// The summation order of the reducing kernels.
+    pub fn reduction(&self) -> Reduction {
+        self.reduction
+    }
+
+    /// The selected variant of every kernel.
+    pub fn variants(&self) -> Vec<KernelVariant> {
+        [
+            ("dot_f32", self.dot_f32.0),
+            ("dot_f16_f32", self.dot_f16_f32.0),
+            ("vec_dot_q4_0_q8_0", self.vec_dot_q4_0_q8_0.0),
+            ("vec_dot_q8_0_q8_0", self.vec_dot_q8_0_q8_0.0),
+            ("silu", self.silu.0),
+            ("softmax", self.softmax.0),
+            ("rms_norm", self.rms_norm.0),
+            ("gemm_microkernel", self.gemm_microkernel.0),
+        ]
+        .into_iter()
+        .map(|(kernel, level)| KernelVariant { kernel, level })
+        .collect()
+    }
+
+    /// Dot product of two f32 slices of the same length.
+    #[inline]
+    pub fn dot_f32(&self, a: &[f32], b: &[f32]) -> f32 {
+        (self.dot_f32.1)(a, b)
+    }
+
+    /// Dot product of `b.len()` little-endian f16 values in `a` with `b`,
+    /// accumulated in f32.
+    #[inline]
+    pub fn dot_f16_f32(&self, a: &[u8], b: &[f32]) -> f32 {
+        (self.dot_f16_f32.1)(a, b)
+    }
+
+    /// Dot product of a Q4_0 row with a Q8_0 row of the same length.
+    #[inline]
+    pub fn vec_dot_q4_0_q8_0(&self, w: &[u8], x: &[u8]) -> f32 {
+        (self.vec_dot_q4_0_q8_0.1)(w, x)
+    }
+
+    /// Dot product of two Q8_0 rows of the same length.
+    #[inline]
+    pub fn vec_dot_q8_0_q8_0(&self, w: &[u8], x: &[u8]) -> f32 {
+        (self.vec_dot_q8_0_q8_0.1)(w, x)
+    }
+
+    /// `out[i] = silu(x[i])`; `out` must be as long as `x`.
+    #[inline]
+    pub fn silu(&self, x: &[f32], out: &mut [f32]) {
+        (self.silu.1)(x, out)
+    }
+
+    /// Softmax of one row; `out` must be as long as `x`.
+    #[inline]
+    pub fn softmax(&self, x: &[f32], out: &mut [f32]) {
+        (self.softmax.1)(x, out)
+    }
+
+    /// RMS normalization of one row; `weight` and `out` must be as long as `x`.
+    #[inline]
+    pub fn rms_norm(&self, x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+        (self.rms_norm.1)(x, weight, eps, out)
+    }
+
+    /// GEMM microkernel; see [`matmul::microkernel`].
+    #[inline]
+    pub fn gemm_microkernel(&self) -> Microkernel {
+        self.gemm_microkernel.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{assert_close_rel, values};
+
+    /// [`values`] scaled to [-amp, amp).
+    fn scaled(n: usize, seed: u32, amp: f32) -> Vec<f32> {
+        values(n, seed).into_iter().map(|v| v * amp).collect()
+    }
+
+    /// Every supported level, checked against scalar on lengths that hit
+    /// both the vector body and the scalar tail.
+    fn for_each_level(check: impl Fn(&Kernels, &Kernels, SimdLevel)) {
+        let scalar = Kernels::scalar();
+        for level in SimdLevel::supported() {
+            let k = Kernels::for_level(level).unwrap();
+            check(&k, &scalar, level);
+        }
+    }
+
+    #[test]
+    fn test_detect_is_supported() {
+        assert!(SimdLevel::supported().contains(&SimdLevel::detect()));
+        assert!(SimdLevel::supported().contains(&SimdLevel::Scalar));
+        let variants = Kernels::detect().variants();
+        assert_eq!(variants.len(), 8);
+        assert!(variants.iter().all(|v| v.level <= SimdLevel::detect()));
+    }
+
+    #[test]
+    fn test_quantized_dots_have_own_variants() {
+        for level in SimdLevel::supported() {
+            let k = Kernels::for_level(level).unwrap();
+            for v in k.variants() {
+                if !v.kernel.starts_with("vec_dot_") {
+                    continue;
+                }
+                #[cfg(target_arch = "x86_64")]
+                if level == SimdLevel::Avx512 && !is_x86_feature_detected!("avx512bw") {
+                    assert_eq!(v.level, SimdLevel::Avx2, "{}", v.kernel);
+                    continue;
+                }
+                assert_eq!(v.level, level, "{}", v.kernel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scalar_variants() {
+        for v in Kernels::scalar().variants() {
+            assert_eq!(v.level, SimdLevel::Scalar);
+        }
+    }
+
+    #[test]
+    fn test_dot_f32_matches_scalar() {
+        for_each_level(|k, s, level| {
+            for n in [0, 1, 3, 4, 15, 16, 31, 33, 64, 100, 257] {
+                let a = scaled(n, 1, 1.0);
+                let b = scaled(n, 2, 1.0);
+                let what = format!("dot_f32 {} n={}", level, n);
+                assert_close_rel(&[k.dot_f32(&a, &b)], &[s.dot_f32(&a, &b)], 1e-5, &what);
+            }
+        });
+    }
+
+    #[test]
+    fn test_dot_f16_f32_matches_scalar() {
+        for_each_level(|k, s, level| {
+            for n in [0, 1, 7, 8, 16, 17, 64, 100, 257] {
+                let a: Vec<u8> = scaled(n, 10, 4.0)
+                    .into_iter()
+                    .flat_map(|v| half::f16::from_f32(v).to_le_bytes())
+                    .collect();
+                let b = scaled(n, 11, 1.0);
+                let what = format!("dot_f16_f32 {} n={}", level, n);
+                let got = k.dot_f16_f32(&a, &b);
+                assert_close_rel(&[got], &[s.dot_f16_f32(&a, &b)], 1e-5, &what);
+            }
+        });
+    }
+
+    #[test]
+    fn test_quantized_dots_match_scalar() {
+        for_each_level(|k, s, level| {
+            for blocks in [1, 2, 7] {
+                let n = blocks * quant::QK;
+                let mut x = vec![0u8; blocks * quant::Q8_0_BLOCK_BYTES];
+                let mut w8 = vec![0u8; blocks * quant::Q8_0_BLOCK_BYTES];
+                quant::quantize_row_q8_0(&scaled(n, 3, 2.0), &mut x);
+                quant::quantize_row_q8_0(&scaled(n, 4, 0.5), &mut w8);
+                // Random bytes make a valid Q4_0 row once each block starts
+                // with a sane f16 scale.
+                let mut w4: Vec<u8> = scaled(blocks * quant::Q4_0_BLOCK_BYTES, 5, 1.0)
+                    .iter()
+                    .map(|v| (v.to_bits() >> 7) as u8)
+                    .collect();
+                for block in w4.chunks_exact_mut(quant::Q4_0_BLOCK_BYTES) {
+                    block[..2].copy_from_slice(&half::f16::from_f32(0.125).to_le_bytes());
+                }
+
+                let what = format!("q8_0 {} blocks={}", level, blocks);
+                let got = k.vec_dot_q8_0_q8_0(&w8, &x);
+                assert_close_rel(&[got], &[s.vec_dot_q8_0_q8_0(&w8, &x)], 1e-5, &what);
+                let what = format!("q4_0 {} blocks={}", level, blocks);
+                let got = k.vec_dot_q4_0_q8_0(&w4, &x);
+                assert_close_rel(&[got], &[s.vec_dot_q4_0_q8_0(&w4, &x)], 1e-5, &what);
+            }
+        });
+    }
+
+    #[test]
+    fn test_silu_matches_scalar() {
+        for_each_level(|k, s, level| {
+            for n in [1, 7, 8, 16, 35, 100] {
+                let mut x = scaled(n, 6, 20.0);
+                x[0] = -100.0;
+                let mut got = vec![0.0; n];
+                let mut want = vec![0.0; n];
+                k.silu(&x, &mut got);
+                s.silu(&x, &mut want);
+                assert_close_rel(&got, &want, 1e-6, &format!("silu {}", level));
+            }
+        });
+    }
+
+    #[test]
+    fn test_softmax_matches_scalar() {
+        for_each_level(|k, s, level| {
+            for n in [1, 5, 16, 17, 64, 1000] {
+                let x = scaled(n, 7, 30.0);
+                let mut got = vec![0.0; n];
+                let mut want = vec![0.0; n];
+                k.softmax(&x, &mut got);
+                s.softmax(&x, &mut want);
+                assert_close_rel(&got, &want, 1e-5, &format!("softmax {}", level));
+                let total: f32 = got.iter().sum();
+                assert!((total - 1.0).abs() < 1e-4);
+            }
+        });
+    }
+
+    #[test]
+    fn test_rms_norm_matches_scalar() {
+        for_each_level(|k, s, level| {
+            for n in [1, 4, 9, 32, 50, 4096] {
+                let x = scaled(n, 8, 3.0);
+                let w = scaled(n, 9, 1.0);
+                let mut got = vec![0.0; n];
+                let mut want = vec![0.0; n];
+                k.rms_norm(&x, &w, 1e-5, &mut got);
+                s.rms_norm(&x, &w, 1e-5, &mut want);
+                assert_close_rel(&got, &want, 1e-5, &format!("rms_norm {}", level));
+            }
+        });
+    }
+}
@@ -0,0 +1,63 @@
+// Scalar reference kernels.
+//
+// These define the semantics every SIMD variant must match and are used on
+// CPUs without a supported vector extension.
+
+use half::f16;
+
+/// Dot product of two f32 slices of the same length.
+pub fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0f32;
+    for (x, y) in a.iter().zip(b) {
+        sum += x * y;
+    }
+    sum
+}
+
+/// Dot product of `b.len()` little-endian f16 values in `a` with the f32
+/// values in `b`, accumulated in f32.
+pub fn dot_f16_f32(a: &[u8], b: &[f32]) -> f32 {
+    let mut sum = 0.0f32;
+    for (x, y) in a.chunks_exact(2).zip(b) {
+        sum += f16::from_le_bytes([x[0], x[1]]).to_f32() * y;
+    }
+    sum
+}
+
+/// `out[i] = x[i] / (1 + exp(-x[i]))`.
+pub fn silu(x: &[f32], out: &mut [f32]) {
+    for (o, &v) in out.iter_mut().zip(x) {
+        *o = v / (1.0 + (-v).exp());
+    }
+}
+
+/// Softmax of one row: `out[i] = exp(x[i] - max(x)) / sum(exp(x[j] - max(x)))`.
+pub fn softmax(x: &[f32], out: &mut [f32]) {
+    // Find max for numerical stability
+    let max_val = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+    // Compute exp(x - max) and sum
+    let mut sum = 0.0f32;
+    for (o, &v) in out.iter_mut().zip(x) {
+        let e = (v - max_val).exp();
+        *o = e;
+        sum += e;
+    }
+
+    // Normalize
+    for o in out.iter_mut() {
+        *o /= sum;
+    }
+}
+
+/// RMS normalization of one row: `out[i] = x[i] * weight[i] / sqrt(mean(x^2) + eps)`.
+pub fn rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+    // Compute mean of squares
+    let mean_sq: f32 = x.iter().map(|v| v * v).sum::<f32>() / x.len() as f32;
+    let rms = (mean_sq + eps).sqrt();
+
+    // Normalize and scale by weight
+    for ((o, &v), &w) in out.iter_mut().zip(x).zip(weight) {
+        *o = v * w / rms;
+    }
+}
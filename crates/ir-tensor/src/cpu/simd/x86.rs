@@ -0,0 +1,797 @@
+// x86_64 SSE4.1, AVX2 and AVX-512 kernels.
+//
+// Each instruction set lives in its own module of `#[target_feature]`
+// functions. The public wrappers below are what `Kernels` points to; they are
+// only installed after `SimdLevel::supported` has confirmed the features, so
+// calling into the target-feature code is sound.
+
+use crate::cpu::matmul::Tile;
+
+/// Wrap target-feature kernels in plain `fn`s that fit the kernel table.
+macro_rules! wrappers {
+    ($($name:ident => $isa:ident::$inner:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {
+        $(
+            pub fn $name($($arg: $ty),*) $(-> $ret)? {
+                // SAFETY: `Kernels::for_level` only installs this wrapper
+                // once the CPU is known to support the module's features.
+                unsafe { $isa::$inner($($arg),*) }
+            }
+        )*
+    };
+}
+
+wrappers! {
+    dot_f32_sse41 => sse41::dot_f32(a: &[f32], b: &[f32]) -> f32;
+    vec_dot_q4_0_q8_0_sse41 => sse41::vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    vec_dot_q8_0_q8_0_sse41 => sse41::vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    silu_sse41 => sse41::silu(x: &[f32], out: &mut [f32]);
+    softmax_sse41 => sse41::softmax(x: &[f32], out: &mut [f32]);
+    rms_norm_sse41 => sse41::rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]);
+
+    dot_f32_avx2 => avx2::dot_f32(a: &[f32], b: &[f32]) -> f32;
+    dot_f16_f32_avx2 => avx2::dot_f16_f32(a: &[u8], b: &[f32]) -> f32;
+    vec_dot_q4_0_q8_0_avx2 => avx2::vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    vec_dot_q8_0_q8_0_avx2 => avx2::vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    silu_avx2 => avx2::silu(x: &[f32], out: &mut [f32]);
+    softmax_avx2 => avx2::softmax(x: &[f32], out: &mut [f32]);
+    rms_norm_avx2 => avx2::rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]);
+    gemm_microkernel_avx2 => avx2::gemm_microkernel(kc: usize, a: &[f32], b: &[f32], tile: &mut Tile);
+
+    dot_f32_avx512 => avx512::dot_f32(a: &[f32], b: &[f32]) -> f32;
+    vec_dot_q4_0_q8_0_avx512 => avx512::vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    vec_dot_q8_0_q8_0_avx512 => avx512::vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32;
+    silu_avx512 => avx512::silu(x: &[f32], out: &mut [f32]);
+    softmax_avx512 => avx512::softmax(x: &[f32], out: &mut [f32]);
+    rms_norm_avx512 => avx512::rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]);
+    gemm_microkernel_avx512 => avx512::gemm_microkernel(kc: usize, a: &[f32], b: &[f32], tile: &mut Tile);
+}
+
+// Cephes-style expf: exp(x) = 2^n * exp(r) with |r| <= ln(2)/2, where exp(r)
+// is a degree-6 polynomial. Accurate to about 2 ulp over the clamped range.
+const EXP_HI: f32 = 88.0;
+const EXP_LO: f32 = -88.0;
+const LOG2E: f32 = std::f32::consts::LOG2_E;
+const LN2_HI: f32 = 0.693_359_4;
+const LN2_LO: f32 = -2.121_944_4e-4;
+const EXP_P: [f32; 6] = [
+    1.987_569_1e-4,
+    1.398_199_9e-3,
+    8.333_452e-3,
+    4.166_579_6e-2,
+    1.666_666_5e-1,
+    0.5,
+];
+
+mod sse41 {
+    use std::arch::x86_64::*;
+
+    use super::{EXP_HI, EXP_LO, EXP_P, LN2_HI, LN2_LO, LOG2E};
+    use crate::cpu::quant::{Q4_0_BLOCK_BYTES, Q8_0_BLOCK_BYTES, block_scale};
+
+    const W: usize = 4;
+
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn load(s: &[f32]) -> __m128 {
+        let s = &s[..W];
+        // SAFETY: `s` holds W floats; the load is unaligned.
+        unsafe { _mm_loadu_ps(s.as_ptr()) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn store(s: &mut [f32], v: __m128) {
+        let s = &mut s[..W];
+        // SAFETY: `s` holds W floats; the store is unaligned.
+        unsafe { _mm_storeu_ps(s.as_mut_ptr(), v) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn load_i8(s: &[u8]) -> __m128i {
+        let s = &s[..16];
+        // SAFETY: `s` holds 16 bytes; the load is unaligned.
+        unsafe { _mm_loadu_si128(s.as_ptr() as *const __m128i) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn lanes(v: __m128) -> [f32; W] {
+        let mut out = [0.0; W];
+        store(&mut out, v);
+        out
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn hsum(v: __m128) -> f32 {
+        let shuf = _mm_movehdup_ps(v);
+        let sums = _mm_add_ps(v, shuf);
+        let shuf = _mm_movehl_ps(shuf, sums);
+        _mm_cvtss_f32(_mm_add_ss(sums, shuf))
+    }
+
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn exp(x: __m128) -> __m128 {
+        let x = _mm_min_ps(_mm_max_ps(x, _mm_set1_ps(EXP_LO)), _mm_set1_ps(EXP_HI));
+        let fx = _mm_floor_ps(_mm_add_ps(
+            _mm_mul_ps(x, _mm_set1_ps(LOG2E)),
+            _mm_set1_ps(0.5),
+        ));
+        let x = _mm_sub_ps(x, _mm_mul_ps(fx, _mm_set1_ps(LN2_HI)));
+        let x = _mm_sub_ps(x, _mm_mul_ps(fx, _mm_set1_ps(LN2_LO)));
+        let z = _mm_mul_ps(x, x);
+        let mut y = _mm_set1_ps(EXP_P[0]);
+        for &p in &EXP_P[1..] {
+            y = _mm_add_ps(_mm_mul_ps(y, x), _mm_set1_ps(p));
+        }
+        let y = _mm_add_ps(_mm_add_ps(_mm_mul_ps(y, z), x), _mm_set1_ps(1.0));
+        let n = _mm_add_epi32(_mm_cvttps_epi32(fx), _mm_set1_epi32(127));
+        _mm_mul_ps(y, _mm_castsi128_ps(_mm_slli_epi32::<23>(n)))
+    }
+
+    /// Sum of the products of signed bytes, as four i32 partial sums.
+    #[inline]
+    #[target_feature(enable = "ssse3,sse4.1")]
+    fn dot_i8(x: __m128i, y: __m128i) -> __m128i {
+        // maddubs multiplies unsigned by signed bytes, so move the sign of x
+        // onto y first.
+        let ax = _mm_sign_epi8(x, x);
+        let sy = _mm_sign_epi8(y, x);
+        _mm_madd_epi16(_mm_maddubs_epi16(ax, sy), _mm_set1_epi16(1))
+    }
+
+    #[target_feature(enable = "ssse3,sse4.1")]
+    pub fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let mut acc = [_mm_setzero_ps(); 4];
+        let mut i = 0;
+        while i + 4 * W <= n {
+            for (j, acc) in acc.iter_mut().enumerate() {
+                let off = i + j * W;
+                *acc = _mm_add_ps(*acc, _mm_mul_ps(load(&a[off..]), load(&b[off..])));
+            }
+            i += 4 * W;
+        }
+        let mut sum = _mm_add_ps(_mm_add_ps(acc[0], acc[1]), _mm_add_ps(acc[2], acc[3]));
+        while i + W <= n {
+            sum = _mm_add_ps(sum, _mm_mul_ps(load(&a[i..]), load(&b[i..])));
+            i += W;
+        }
+        let mut total = hsum(sum);
+        for j in i..n {
+            total += a[j] * b[j];
+        }
+        total
+    }
+
+    #[target_feature(enable = "ssse3,sse4.1")]
+    pub fn vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+        let mask = _mm_set1_epi8(0x0F);
+        let eight = _mm_set1_epi8(8);
+        let mut acc = _mm_setzero_ps();
+        for (wb, xb) in w
+            .chunks_exact(Q4_0_BLOCK_BYTES)
+            .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+        {
+            let bytes = load_i8(&wb[2..]);
+            let lo = _mm_sub_epi8(_mm_and_si128(bytes, mask), eight);
+            let hi = _mm_sub_epi8(_mm_and_si128(_mm_srli_epi16::<4>(bytes), mask), eight);
+            let sumi = _mm_add_epi32(
+                dot_i8(lo, load_i8(&xb[2..])),
+                dot_i8(hi, load_i8(&xb[18..])),
+            );
+            let d = _mm_set1_ps(block_scale(wb) * block_scale(xb));
+            acc = _mm_add_ps(acc, _mm_mul_ps(d, _mm_cvtepi32_ps(sumi)));
+        }
+        hsum(acc)
+    }
+
+    #[target_feature(enable = "ssse3,sse4.1")]
+    pub fn vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+        let mut acc = _mm_setzero_ps();
+        for (wb, xb) in w
+            .chunks_exact(Q8_0_BLOCK_BYTES)
+            .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+        {
+            let lo = dot_i8(load_i8(&wb[2..]), load_i8(&xb[2..]));
+            let hi = dot_i8(load_i8(&wb[18..]), load_i8(&xb[18..]));
+            let d = _mm_set1_ps(block_scale(wb) * block_scale(xb));
+            acc = _mm_add_ps(acc, _mm_mul_ps(d, _mm_cvtepi32_ps(_mm_add_epi32(lo, hi))));
+        }
+        hsum(acc)
+    }
+
+    #[target_feature(enable = "ssse3,sse4.1")]
+    pub fn silu(x: &[f32], out: &mut [f32]) {
+        let n = x.len().min(out.len());
+        let one = _mm_set1_ps(1.0);
+        let mut i = 0;
+        while i + W <= n {
+            let v = load(&x[i..]);
+            let e = exp(_mm_sub_ps(_mm_setzero_ps(), v));
+            store(&mut out[i..], _mm_div_ps(v, _mm_add_ps(one, e)));
+            i += W;
+        }
+        for j in i..n {
+            out[j] = x[j] / (1.0 + (-x[j]).exp());
+        }
+    }
+
+    #[target_feature(enable = "ssse3,sse4.1")]
+    pub fn softmax(x: &[f32], out: &mut [f32]) {
+        let n = x.len().min(out.len());
+
+        let mut vmax = _mm_set1_ps(f32::NEG_INFINITY);
+        let mut i = 0;
+        while i + W <= n {
+            vmax = _mm_max_ps(vmax, load(&x[i..]));
+            i += W;
+        }
+        let mut max_val = lanes(vmax).into_iter().fold(f32::NEG_INFINITY, f32::max);
+        for &v in &x[i..n] {
+            max_val = max_val.max(v);
+        }
+
+        let vm = _mm_set1_ps(max_val);
+        let mut vsum = _mm_setzero_ps();
+        i = 0;
+        while i + W <= n {
+            let e = exp(_mm_sub_ps(load(&x[i..]), vm));
+            store(&mut out[i..], e);
+            vsum = _mm_add_ps(vsum, e);
+            i += W;
+        }
+        let mut sum = hsum(vsum);
+        for j in i..n {
+            let e = (x[j] - max_val).exp();
+            out[j] = e;
+            sum += e;
+        }
+
+        let vs = _mm_set1_ps(sum);
+        i = 0;
+        while i + W <= n {
+            let v = _mm_div_ps(load(&out[i..]), vs);
+            store(&mut out[i..], v);
+            i += W;
+        }
+        for o in &mut out[i..n] {
+            *o /= sum;
+        }
+    }
+
+    #[target_feature(enable = "ssse3,sse4.1")]
+    pub fn rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+        let n = x.len();
+        let sum_sq = dot_f32(x, x);
+        let rms = (sum_sq / n as f32 + eps).sqrt();
+        let vr = _mm_set1_ps(rms);
+        let mut i = 0;
+        while i + W <= n {
+            let v = _mm_mul_ps(load(&x[i..]), load(&weight[i..]));
+            store(&mut out[i..], _mm_div_ps(v, vr));
+            i += W;
+        }
+        for j in i..n {
+            out[j] = x[j] * weight[j] / rms;
+        }
+    }
+}
+
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::{EXP_HI, EXP_LO, EXP_P, LN2_HI, LN2_LO, LOG2E};
+    use crate::cpu::matmul::{self, Tile};
+    use crate::cpu::quant::{Q4_0_BLOCK_BYTES, Q8_0_BLOCK_BYTES, block_scale};
+
+    const W: usize = 8;
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn gemm_microkernel(kc: usize, a: &[f32], b: &[f32], tile: &mut Tile) {
+        matmul::microkernel(kc, a, b, tile)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn load(s: &[f32]) -> __m256 {
+        let s = &s[..W];
+        // SAFETY: `s` holds W floats; the load is unaligned.
+        unsafe { _mm256_loadu_ps(s.as_ptr()) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn store(s: &mut [f32], v: __m256) {
+        let s = &mut s[..W];
+        // SAFETY: `s` holds W floats; the store is unaligned.
+        unsafe { _mm256_storeu_ps(s.as_mut_ptr(), v) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn load_i8x16(s: &[u8]) -> __m128i {
+        let s = &s[..16];
+        // SAFETY: `s` holds 16 bytes; the load is unaligned.
+        unsafe { _mm_loadu_si128(s.as_ptr() as *const __m128i) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn load_i8x32(s: &[u8]) -> __m256i {
+        let s = &s[..32];
+        // SAFETY: `s` holds 32 bytes; the load is unaligned.
+        unsafe { _mm256_loadu_si256(s.as_ptr() as *const __m256i) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn lanes(v: __m256) -> [f32; W] {
+        let mut out = [0.0; W];
+        store(&mut out, v);
+        out
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn hsum(v: __m256) -> f32 {
+        let v = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps::<1>(v));
+        let shuf = _mm_movehdup_ps(v);
+        let sums = _mm_add_ps(v, shuf);
+        let shuf = _mm_movehl_ps(shuf, sums);
+        _mm_cvtss_f32(_mm_add_ss(sums, shuf))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn exp(x: __m256) -> __m256 {
+        let x = _mm256_min_ps(
+            _mm256_max_ps(x, _mm256_set1_ps(EXP_LO)),
+            _mm256_set1_ps(EXP_HI),
+        );
+        let fx = _mm256_floor_ps(_mm256_fmadd_ps(
+            x,
+            _mm256_set1_ps(LOG2E),
+            _mm256_set1_ps(0.5),
+        ));
+        let x = _mm256_fnmadd_ps(fx, _mm256_set1_ps(LN2_HI), x);
+        let x = _mm256_fnmadd_ps(fx, _mm256_set1_ps(LN2_LO), x);
+        let z = _mm256_mul_ps(x, x);
+        let mut y = _mm256_set1_ps(EXP_P[0]);
+        for &p in &EXP_P[1..] {
+            y = _mm256_fmadd_ps(y, x, _mm256_set1_ps(p));
+        }
+        let y = _mm256_add_ps(_mm256_fmadd_ps(y, z, x), _mm256_set1_ps(1.0));
+        let n = _mm256_add_epi32(_mm256_cvttps_epi32(fx), _mm256_set1_epi32(127));
+        _mm256_mul_ps(y, _mm256_castsi256_ps(_mm256_slli_epi32::<23>(n)))
+    }
+
+    /// Sum of the products of signed bytes, as eight f32 partial sums.
+    #[inline]
+    #[target_feature(enable = "avx2,fma")]
+    fn dot_i8(x: __m256i, y: __m256i) -> __m256 {
+        // maddubs multiplies unsigned by signed bytes, so move the sign of x
+        // onto y first.
+        let ax = _mm256_sign_epi8(x, x);
+        let sy = _mm256_sign_epi8(y, x);
+        let sumi = _mm256_madd_epi16(_mm256_maddubs_epi16(ax, sy), _mm256_set1_epi16(1));
+        _mm256_cvtepi32_ps(sumi)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let mut acc = [_mm256_setzero_ps(); 4];
+        let mut i = 0;
+        while i + 4 * W <= n {
+            for (j, acc) in acc.iter_mut().enumerate() {
+                let off = i + j * W;
+                *acc = _mm256_fmadd_ps(load(&a[off..]), load(&b[off..]), *acc);
+            }
+            i += 4 * W;
+        }
+        let mut sum = _mm256_add_ps(_mm256_add_ps(acc[0], acc[1]), _mm256_add_ps(acc[2], acc[3]));
+        while i + W <= n {
+            sum = _mm256_fmadd_ps(load(&a[i..]), load(&b[i..]), sum);
+            i += W;
+        }
+        let mut total = hsum(sum);
+        for j in i..n {
+            total += a[j] * b[j];
+        }
+        total
+    }
+
+    /// Also needs F16C, which `Kernels::for_level` checks separately.
+    #[target_feature(enable = "avx2,fma,f16c")]
+    pub fn dot_f16_f32(a: &[u8], b: &[f32]) -> f32 {
+        let n = (a.len() / 2).min(b.len());
+        let mut acc = [_mm256_setzero_ps(); 2];
+        let mut i = 0;
+        while i + 2 * W <= n {
+            for (j, acc) in acc.iter_mut().enumerate() {
+                let off = i + j * W;
+                let x = _mm256_cvtph_ps(load_i8x16(&a[2 * off..]));
+                *acc = _mm256_fmadd_ps(x, load(&b[off..]), *acc);
+            }
+            i += 2 * W;
+        }
+        let mut sum = _mm256_add_ps(acc[0], acc[1]);
+        while i + W <= n {
+            let x = _mm256_cvtph_ps(load_i8x16(&a[2 * i..]));
+            sum = _mm256_fmadd_ps(x, load(&b[i..]), sum);
+            i += W;
+        }
+        let mut total = hsum(sum);
+        for j in i..n {
+            total += half::f16::from_le_bytes([a[2 * j], a[2 * j + 1]]).to_f32() * b[j];
+        }
+        total
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+        let mask = _mm_set1_epi8(0x0F);
+        let eight = _mm256_set1_epi8(8);
+        let mut acc = _mm256_setzero_ps();
+        for (wb, xb) in w
+            .chunks_exact(Q4_0_BLOCK_BYTES)
+            .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+        {
+            // Low nibbles are elements 0..16, high nibbles 16..32.
+            let bytes = load_i8x16(&wb[2..]);
+            let lo = _mm_and_si128(bytes, mask);
+            let hi = _mm_and_si128(_mm_srli_epi16::<4>(bytes), mask);
+            let qw = _mm256_sub_epi8(_mm256_set_m128i(hi, lo), eight);
+            let d = _mm256_set1_ps(block_scale(wb) * block_scale(xb));
+            acc = _mm256_fmadd_ps(d, dot_i8(qw, load_i8x32(&xb[2..])), acc);
+        }
+        hsum(acc)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+        let mut acc = _mm256_setzero_ps();
+        for (wb, xb) in w
+            .chunks_exact(Q8_0_BLOCK_BYTES)
+            .zip(x.chunks_exact(Q8_0_BLOCK_BYTES))
+        {
+            let d = _mm256_set1_ps(block_scale(wb) * block_scale(xb));
+            let p = dot_i8(load_i8x32(&wb[2..]), load_i8x32(&xb[2..]));
+            acc = _mm256_fmadd_ps(d, p, acc);
+        }
+        hsum(acc)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn silu(x: &[f32], out: &mut [f32]) {
+        let n = x.len().min(out.len());
+        let one = _mm256_set1_ps(1.0);
+        let mut i = 0;
+        while i + W <= n {
+            let v = load(&x[i..]);
+            let e = exp(_mm256_sub_ps(_mm256_setzero_ps(), v));
+            store(&mut out[i..], _mm256_div_ps(v, _mm256_add_ps(one, e)));
+            i += W;
+        }
+        for j in i..n {
+            out[j] = x[j] / (1.0 + (-x[j]).exp());
+        }
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn softmax(x: &[f32], out: &mut [f32]) {
+        let n = x.len().min(out.len());
+
+        let mut vmax = _mm256_set1_ps(f32::NEG_INFINITY);
+        let mut i = 0;
+        while i + W <= n {
+            vmax = _mm256_max_ps(vmax, load(&x[i..]));
+            i += W;
+        }
+        let mut max_val = lanes(vmax).into_iter().fold(f32::NEG_INFINITY, f32::max);
+        for &v in &x[i..n] {
+            max_val = max_val.max(v);
+        }
+
+        let vm = _mm256_set1_ps(max_val);
+        let mut vsum = _mm256_setzero_ps();
+        i = 0;
+        while i + W <= n {
+            let e = exp(_mm256_sub_ps(load(&x[i..]), vm));
+            store(&mut out[i..], e);
+            vsum = _mm256_add_ps(vsum, e);
+            i += W;
+        }
+        let mut sum = hsum(vsum);
+        for j in i..n {
+            let e = (x[j] - max_val).exp();
+            out[j] = e;
+            sum += e;
+        }
+
+        let vs = _mm256_set1_ps(sum);
+        i = 0;
+        while i + W <= n {
+            let v = _mm256_div_ps(load(&out[i..]), vs);
+            store(&mut out[i..], v);
+            i += W;
+        }
+        for o in &mut out[i..n] {
+            *o /= sum;
+        }
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+        let n = x.len();
+        let sum_sq = dot_f32(x, x);
+        let rms = (sum_sq / n as f32 + eps).sqrt();
+        let vr = _mm256_set1_ps(rms);
+        let mut i = 0;
+        while i + W <= n {
+            let v = _mm256_mul_ps(load(&x[i..]), load(&weight[i..]));
+            store(&mut out[i..], _mm256_div_ps(v, vr));
+            i += W;
+        }
+        for j in i..n {
+            out[j] = x[j] * weight[j] / rms;
+        }
+    }
+}
+
+mod avx512 {
+    use std::arch::x86_64::*;
+
+    use super::{EXP_HI, EXP_LO, EXP_P, LN2_HI, LN2_LO, LOG2E};
+    use crate::cpu::matmul::{self, Tile};
+    use crate::cpu::quant::{Q4_0_BLOCK_BYTES, Q8_0_BLOCK_BYTES, block_scale};
+
+    const W: usize = 16;
+
+    /// All-zero blocks that pad an odd block count to whole pairs; their zero
+    /// scale makes them contribute nothing.
+    const ZERO_Q4_0: [u8; Q4_0_BLOCK_BYTES] = [0; Q4_0_BLOCK_BYTES];
+    const ZERO_Q8_0: [u8; Q8_0_BLOCK_BYTES] = [0; Q8_0_BLOCK_BYTES];
+
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    pub fn gemm_microkernel(kc: usize, a: &[f32], b: &[f32], tile: &mut Tile) {
+        matmul::microkernel(kc, a, b, tile)
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn load(s: &[f32]) -> __m512 {
+        let s = &s[..W];
+        // SAFETY: `s` holds W floats; the load is unaligned.
+        unsafe { _mm512_loadu_ps(s.as_ptr()) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn store(s: &mut [f32], v: __m512) {
+        let s = &mut s[..W];
+        // SAFETY: `s` holds W floats; the store is unaligned.
+        unsafe { _mm512_storeu_ps(s.as_mut_ptr(), v) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn load_i8x16(s: &[u8]) -> __m128i {
+        let s = &s[..16];
+        // SAFETY: `s` holds 16 bytes; the load is unaligned.
+        unsafe { _mm_loadu_si128(s.as_ptr() as *const __m128i) }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn load_i8x32(s: &[u8]) -> __m256i {
+        let s = &s[..32];
+        // SAFETY: `s` holds 32 bytes; the load is unaligned.
+        unsafe { _mm256_loadu_si256(s.as_ptr() as *const __m256i) }
+    }
+
+    /// `lo` in the low half and `hi` in the high half.
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn join(lo: __m256i, hi: __m256i) -> __m512i {
+        _mm512_inserti64x4::<1>(_mm512_castsi256_si512(lo), hi)
+    }
+
+    /// The combined scales of a pair of blocks, one per half.
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn pair_scale(d0: f32, d1: f32) -> __m512 {
+        _mm512_mask_blend_ps(0xFF00, _mm512_set1_ps(d0), _mm512_set1_ps(d1))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn exp(x: __m512) -> __m512 {
+        let x = _mm512_min_ps(
+            _mm512_max_ps(x, _mm512_set1_ps(EXP_LO)),
+            _mm512_set1_ps(EXP_HI),
+        );
+        let fx = _mm512_fmadd_ps(x, _mm512_set1_ps(LOG2E), _mm512_set1_ps(0.5));
+        let fx = _mm512_roundscale_ps::<{ _MM_FROUND_TO_NEG_INF | _MM_FROUND_NO_EXC }>(fx);
+        let x = _mm512_fnmadd_ps(fx, _mm512_set1_ps(LN2_HI), x);
+        let x = _mm512_fnmadd_ps(fx, _mm512_set1_ps(LN2_LO), x);
+        let z = _mm512_mul_ps(x, x);
+        let mut y = _mm512_set1_ps(EXP_P[0]);
+        for &p in &EXP_P[1..] {
+            y = _mm512_fmadd_ps(y, x, _mm512_set1_ps(p));
+        }
+        let y = _mm512_add_ps(_mm512_fmadd_ps(y, z, x), _mm512_set1_ps(1.0));
+        let n = _mm512_add_epi32(_mm512_cvttps_epi32(fx), _mm512_set1_epi32(127));
+        _mm512_mul_ps(y, _mm512_castsi512_ps(_mm512_slli_epi32::<23>(n)))
+    }
+
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    pub fn dot_f32(a: &[f32], b: &[f32]) -> f32 {
+        let n = a.len().min(b.len());
+        let mut acc = [_mm512_setzero_ps(); 4];
+        let mut i = 0;
+        while i + 4 * W <= n {
+            for (j, acc) in acc.iter_mut().enumerate() {
+                let off = i + j * W;
+                *acc = _mm512_fmadd_ps(load(&a[off..]), load(&b[off..]), *acc);
+            }
+            i += 4 * W;
+        }
+        let mut sum = _mm512_add_ps(_mm512_add_ps(acc[0], acc[1]), _mm512_add_ps(acc[2], acc[3]));
+        while i + W <= n {
+            sum = _mm512_fmadd_ps(load(&a[i..]), load(&b[i..]), sum);
+            i += W;
+        }
+        let mut total = _mm512_reduce_add_ps(sum);
+        for j in i..n {
+            total += a[j] * b[j];
+        }
+        total
+    }
+
+    /// Sum of the products of signed bytes, as sixteen f32 partial sums.
+    #[inline]
+    #[target_feature(enable = "avx512f,avx512bw,avx2,fma")]
+    fn dot_i8(x: __m512i, y: __m512i) -> __m512 {
+        // maddubs multiplies unsigned by signed bytes, so move the sign of x
+        // onto y first. AVX-512 has no sign_epi8, so negate y under a mask.
+        let neg = _mm512_movepi8_mask(x);
+        let ax = _mm512_abs_epi8(x);
+        let sy = _mm512_mask_sub_epi8(y, neg, _mm512_setzero_si512(), y);
+        let sumi = _mm512_madd_epi16(_mm512_maddubs_epi16(ax, sy), _mm512_set1_epi16(1));
+        _mm512_cvtepi32_ps(sumi)
+    }
+
+    /// The 32 quants of a Q4_0 block as signed bytes, offset by 8.
+    #[inline]
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    fn q4_0_quants(wb: &[u8]) -> __m256i {
+        // Low nibbles are elements 0..16, high nibbles 16..32.
+        let mask = _mm_set1_epi8(0x0F);
+        let bytes = load_i8x16(&wb[2..]);
+        let lo = _mm_and_si128(bytes, mask);
+        let hi = _mm_and_si128(_mm_srli_epi16::<4>(bytes), mask);
+        _mm256_set_m128i(hi, lo)
+    }
+
+    /// Two blocks per step, one in each 256-bit half. Also needs AVX-512BW,
+    /// which `Kernels::for_level` checks separately.
+    #[target_feature(enable = "avx512f,avx512bw,avx2,fma")]
+    pub fn vec_dot_q4_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+        let eight = _mm512_set1_epi8(8);
+        let mut acc = _mm512_setzero_ps();
+        let mut blocks = w
+            .chunks_exact(Q4_0_BLOCK_BYTES)
+            .zip(x.chunks_exact(Q8_0_BLOCK_BYTES));
+        while let Some((w0, x0)) = blocks.next() {
+            let (w1, x1) = blocks.next().unwrap_or((&ZERO_Q4_0, &ZERO_Q8_0));
+            let qw = _mm512_sub_epi8(join(q4_0_quants(w0), q4_0_quants(w1)), eight);
+            let qx = join(load_i8x32(&x0[2..]), load_i8x32(&x1[2..]));
+            let d = pair_scale(
+                block_scale(w0) * block_scale(x0),
+                block_scale(w1) * block_scale(x1),
+            );
+            acc = _mm512_fmadd_ps(d, dot_i8(qw, qx), acc);
+        }
+        _mm512_reduce_add_ps(acc)
+    }
+
+    /// Two blocks per step, as in [`vec_dot_q4_0_q8_0`].
+    #[target_feature(enable = "avx512f,avx512bw,avx2,fma")]
+    pub fn vec_dot_q8_0_q8_0(w: &[u8], x: &[u8]) -> f32 {
+        let mut acc = _mm512_setzero_ps();
+        let mut blocks = w
+            .chunks_exact(Q8_0_BLOCK_BYTES)
+            .zip(x.chunks_exact(Q8_0_BLOCK_BYTES));
+        while let Some((w0, x0)) = blocks.next() {
+            let (w1, x1) = blocks.next().unwrap_or((&ZERO_Q8_0, &ZERO_Q8_0));
+            let qw = join(load_i8x32(&w0[2..]), load_i8x32(&w1[2..]));
+            let qx = join(load_i8x32(&x0[2..]), load_i8x32(&x1[2..]));
+            let d = pair_scale(
+                block_scale(w0) * block_scale(x0),
+                block_scale(w1) * block_scale(x1),
+            );
+            acc = _mm512_fmadd_ps(d, dot_i8(qw, qx), acc);
+        }
+        _mm512_reduce_add_ps(acc)
+    }
+
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    pub fn silu(x: &[f32], out: &mut [f32]) {
+        let n = x.len().min(out.len());
+        let one = _mm512_set1_ps(1.0);
+        let mut i = 0;
+        while i + W <= n {
+            let v = load(&x[i..]);
+            let e = exp(_mm512_sub_ps(_mm512_setzero_ps(), v));
+            store(&mut out[i..], _mm512_div_ps(v, _mm512_add_ps(one, e)));
+            i += W;
+        }
+        for j in i..n {
+            out[j] = x[j] / (1.0 + (-x[j]).exp());
+        }
+    }
+
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    pub fn softmax(x: &[f32], out: &mut [f32]) {
+        let n = x.len().min(out.len());
+
+        let mut vmax = _mm512_set1_ps(f32::NEG_INFINITY);
+        let mut i = 0;
+        while i + W <= n {
+            vmax = _mm512_max_ps(vmax, load(&x[i..]));
+            i += W;
+        }
+        let mut max_val = _mm512_reduce_max_ps(vmax);
+        for &v in &x[i..n] {
+            max_val = max_val.max(v);
+        }
+
+        let vm = _mm512_set1_ps(max_val);
+        let mut vsum = _mm512_setzero_ps();
+        i = 0;
+        while i + W <= n {
+            let e = exp(_mm512_sub_ps(load(&x[i..]), vm));
+            store(&mut out[i..], e);
+            vsum = _mm512_add_ps(vsum, e);
+            i += W;
+        }
+        let mut sum = _mm512_reduce_add_ps(vsum);
+        for j in i..n {
+            let e = (x[j] - max_val).exp();
+            out[j] = e;
+            sum += e;
+        }
+
+        let vs = _mm512_set1_ps(sum);
+        i = 0;
+        while i + W <= n {
+            let v = _mm512_div_ps(load(&out[i..]), vs);
+            store(&mut out[i..], v);
+            i += W;
+        }
+        for o in &mut out[i..n] {
+            *o /= sum;
+        }
+    }
+
+    #[target_feature(enable = "avx512f,avx2,fma")]
+    pub fn rms_norm(x: &[f32], weight: &[f32], eps: f32, out: &mut [f32]) {
+        let n = x.len();
+        let sum_sq = dot_f32(x, x);
+        let rms = (sum_sq / n as f32 + eps).sqrt();
+        let vr = _mm512_set1_ps(rms);
+        let mut i = 0;
+        while i + W <= n {
+            let v = _mm512_mul_ps(load(&x[i..]), load(&weight[i..]));
+            store(&mut out[i..], _mm512_div_ps(v, vr));
+            i += W;
+        }
+        for j in i..n {
+            out[j] = x[j] * weight[j] / rms;
+        }
+    }
+}
@@ -1,5 +1,144 @@
-// Unary operation helper module.
+// Scalar activation and normalization kernels.
 //
-// The core unary operations (silu, etc.) live in CpuBackend (cpu/mod.rs).
-// This module is reserved for future optimizations such as SIMD-accelerated
-// activation functions or fused unary kernels.
+// The SIMD-dispatched ops (silu, rms_norm, softmax) live in cpu/simd. The
+// functions here back the remaining activations and LayerNorm; CpuBackend
+// applies them element- or row-wise on its worker pool.
+
+use super::reduce::Reduction;
+
+const SQRT_2_OVER_PI: f32 = 0.797_884_6;
+
+/// GELU, tanh approximation:
+/// 0.5 * x * (1 + tanh(sqrt(2/pi) * (x + 0.044715 * x^3))).
+#[inline]
+pub fn gelu_tanh(x: f32) -> f32 {
+    let inner = SQRT_2_OVER_PI * (x + 0.044715 * x * x * x);
+    0.5 * x * (1.0 + inner.tanh())
+}
+
+/// Exact GELU: 0.5 * x * (1 + erf(x / sqrt(2))).
+#[inline]
+pub fn gelu_erf(x: f32) -> f32 {
+    0.5 * x * (1.0 + erf(x * std::f32::consts::FRAC_1_SQRT_2))
+}
+
+/// Error function, via the Numerical Recipes `erfc` Chebyshev fit
+/// (|error| < 1.2e-7) evaluated in f64.
+#[inline]
+pub fn erf(x: f32) -> f32 {
+    let x = x as f64;
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = -x * x - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let erfc = t * poly.exp();
+    (if x >= 0.0 { 1.0 - erfc } else { erfc - 1.0 }) as f32
+}
+
+/// Squared ReLU: max(x, 0)^2.
+#[inline]
+pub fn relu2(x: f32) -> f32 {
+    let r = x.max(0.0);
+    r * r
+}
+
+/// Tanh soft-cap: cap * tanh(x / cap), which smoothly limits |x| to `cap`.
+#[inline]
+pub fn softcap(x: f32, cap: f32) -> f32 {
+    cap * (x / cap).tanh()
+}
+
+/// LayerNorm of one row:
+/// out = (x - mean) / sqrt(var + eps) * weight + bias, with the mean and
+/// variance summed in `reduction` order.
+pub fn layer_norm(
+    x: &[f32],
+    weight: &[f32],
+    bias: Option<&[f32]>,
+    eps: f32,
+    reduction: Reduction,
+    out: &mut [f32],
+) {
+    let n = x.len() as f32;
+    let mean = reduction.sum(x.len(), |i| x[i]) / n;
+    let var = reduction.sum(x.len(), |i| (x[i] - mean) * (x[i] - mean)) / n;
+    let inv_std = 1.0 / (var + eps).sqrt();
+
+    for ((o, &v), &w) in out.iter_mut().zip(x).zip(weight) {
+        *o = (v - mean) * inv_std * w;
+    }
+    if let Some(bias) = bias {
+        for (o, &b) in out.iter_mut().zip(bias) {
+            *o += b;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_erf_reference_values() {
+        // erf(x) from a high-precision reference.
+        let cases: [(f32, f64); 6] = [
+            (0.0, 0.0),
+            (0.1, 0.112_462_916),
+            (0.5, 0.520_499_878),
+            (1.0, 0.842_700_793),
+            (2.0, 0.995_322_265),
+            (3.5, 0.999_999_257),
+        ];
+        for (x, want) in cases {
+            assert!(
+                (erf(x) as f64 - want).abs() < 2e-7,
+                "erf({}) = {}",
+                x,
+                erf(x)
+            );
+            assert!((erf(-x) as f64 + want).abs() < 2e-7);
+        }
+    }
+
+    #[test]
+    fn test_gelu_variants() {
+        // GELU(1) = 0.5 * (1 + erf(1/sqrt(2))) = 0.841_344_7
+        assert!((gelu_erf(1.0) - 0.841_344_7).abs() < 1e-6);
+        assert!((gelu_erf(-1.0) + 0.158_655_25).abs() < 1e-6);
+        assert!((gelu_tanh(1.0) - 0.841_192).abs() < 1e-6);
+        // The two agree to within the tanh approximation's error.
+        for i in -40..=40 {
+            let x = i as f32 / 10.0;
+            assert!((gelu_erf(x) - gelu_tanh(x)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_relu2_and_softcap() {
+        assert_eq!(relu2(-3.0), 0.0);
+        assert_eq!(relu2(3.0), 9.0);
+        assert!((softcap(1.0, 30.0) - 30.0 * (1.0f32 / 30.0).tanh()).abs() < 1e-6);
+        assert!(softcap(1e6, 30.0) <= 30.0);
+        assert!(softcap(-1e6, 30.0) >= -30.0);
+    }
+
+    #[test]
+    fn test_layer_norm() {
+        let x = [1.0, 2.0, 3.0, 6.0];
+        let w = [1.0, 1.0, 2.0, 1.0];
+        let b = [0.0, 1.0, 0.0, 0.0];
+        let mut out = [0.0; 4];
+        layer_norm(&x, &w, Some(&b), 0.0, Reduction::Native, &mut out);
+        // mean 3, variance 3.5
+        let s = 3.5f32.sqrt();
+        let want = [-2.0 / s, -1.0 / s + 1.0, 0.0, 3.0 / s];
+        for (o, w) in out.iter().zip(want) {
+            assert!((o - w).abs() < 1e-6);
+        }
+    }
+}
@@ -7,10 +7,33 @@ pub enum DType {
     F32,
     /// 16-bit floating point (IEEE 754 half-precision, via the `half` crate).
     F16,
+    /// 16-bit brain floating point (truncated f32, via the `half` crate).
+    BF16,
     /// 4-bit quantized format (GGUF Q4_0 block type).
     Q4_0,
+    /// 4-bit quantized format with a per-block minimum (GGUF Q4_1 block type).
+    Q4_1,
+    /// 5-bit quantized format (GGUF Q5_0 block type).
+    Q5_0,
+    /// 5-bit quantized format with a per-block minimum (GGUF Q5_1 block type).
+    Q5_1,
     /// 8-bit quantized format (GGUF Q8_0 block type).
     Q8_0,
+    /// 8-bit quantized format with a stored block sum (GGUF Q8_1 block type).
+    Q8_1,
+    /// 2-bit k-quant (GGUF Q2_K super-block type).
+    Q2K,
+    /// 3-bit k-quant (GGUF Q3_K super-block type).
+    Q3K,
+    /// 4-bit k-quant (GGUF Q4_K super-block type).
+    Q4K,
+    /// 5-bit k-quant (GGUF Q5_K super-block type).
+    Q5K,
+    /// 6-bit k-quant (GGUF Q6_K super-block type).
+    Q6K,
+    /// 8-bit k-quant (GGUF Q8_K super-block type), mainly used for
+    /// intermediate activations.
+    Q8K,
 }
 
 impl DType {
@@ -19,14 +42,40 @@ impl DType {
     ///
     /// - F32: 4 bytes per element
     /// - F16: 2 bytes per element (using `half::f16`)
+    /// - BF16: 2 bytes per element (using `half::bf16`)
     /// - Q4_0: 18 bytes per block of 32 elements (2-byte scale + 16 bytes of nibbles)
+    /// - Q4_1: 20 bytes per block of 32 elements (2-byte scale + 2-byte min + 16 bytes of nibbles)
+    /// - Q5_0: 22 bytes per block of 32 elements (2-byte scale + 4 bytes of fifth
+    ///   bits + 16 bytes of nibbles)
+    /// - Q5_1: 24 bytes per block of 32 elements (Q5_0 plus a 2-byte min)
     /// - Q8_0: 34 bytes per block of 32 elements (2-byte scale + 32 bytes of quants)
+    /// - Q8_1: 36 bytes per block of 32 elements (2-byte scale + 2-byte sum + 32 bytes of quants)
+    ///
+    /// The k-quants use super-blocks of 256 elements:
+    /// - Q2_K: 84 bytes (16 scale/min bytes + 64 bytes of 2-bit quants + f16 d, dmin)
+    /// - Q3_K: 110 bytes (32-byte high-bit mask + 64 bytes of 2-bit quants +
+    ///   12 bytes of 6-bit scales + f16 d)
+    /// - Q4_K: 144 bytes (f16 d, dmin + 12 bytes of 6-bit scales/mins + 128 bytes of nibbles)
+    /// - Q5_K: 176 bytes (Q4_K layout plus a 32-byte array of fifth bits)
+    /// - Q6_K: 210 bytes (128 bytes of low nibbles + 64 bytes of high 2-bit
+    ///   pairs + 16 i8 scales + f16 d)
+    /// - Q8_K: 292 bytes (f32 d + 256 i8 quants + 16 i16 block sums)
     pub fn size_in_bytes(&self) -> usize {
         match self {
             DType::F32 => 4,
-            DType::F16 => 2,
+            DType::F16 | DType::BF16 => 2,
             DType::Q4_0 => 18,
+            DType::Q4_1 => 20,
+            DType::Q5_0 => 22,
+            DType::Q5_1 => 24,
             DType::Q8_0 => 34,
+            DType::Q8_1 => 36,
+            DType::Q2K => 84,
+            DType::Q3K => 110,
+            DType::Q4K => 144,
+            DType::Q5K => 176,
+            DType::Q6K => 210,
+            DType::Q8K => 292,
         }
     }
 
@@ -36,13 +85,30 @@ impl DType {
     /// - 0 => F32
     /// - 1 => F16
     /// - 2 => Q4_0
+    /// - 3 => Q4_1
+    /// - 6 => Q5_0
+    /// - 7 => Q5_1
     /// - 8 => Q8_0
+    /// - 9 => Q8_1
+    /// - 10..=15 => Q2_K, Q3_K, Q4_K, Q5_K, Q6_K, Q8_K
+    /// - 30 => BF16
     pub fn from_gguf_type(id: u32) -> Option<DType> {
         match id {
             0 => Some(DType::F32),
             1 => Some(DType::F16),
             2 => Some(DType::Q4_0),
+            3 => Some(DType::Q4_1),
+            6 => Some(DType::Q5_0),
+            7 => Some(DType::Q5_1),
             8 => Some(DType::Q8_0),
+            9 => Some(DType::Q8_1),
+            10 => Some(DType::Q2K),
+            11 => Some(DType::Q3K),
+            12 => Some(DType::Q4K),
+            13 => Some(DType::Q5K),
+            14 => Some(DType::Q6K),
+            15 => Some(DType::Q8K),
+            30 => Some(DType::BF16),
             _ => None,
         }
     }
@@ -53,7 +119,18 @@ impl DType {
             DType::F32 => 0,
             DType::F16 => 1,
             DType::Q4_0 => 2,
+            DType::Q4_1 => 3,
+            DType::Q5_0 => 6,
+            DType::Q5_1 => 7,
             DType::Q8_0 => 8,
+            DType::Q8_1 => 9,
+            DType::Q2K => 10,
+            DType::Q3K => 11,
+            DType::Q4K => 12,
+            DType::Q5K => 13,
+            DType::Q6K => 14,
+            DType::Q8K => 15,
+            DType::BF16 => 30,
         }
     }
 
@@ -61,14 +138,21 @@ impl DType {
     /// non-quantized types.
     pub fn block_size(&self) -> usize {
         match self {
-            DType::F32 | DType::F16 => 1,
-            DType::Q4_0 | DType::Q8_0 => 32,
+            DType::F32 | DType::F16 | DType::BF16 => 1,
+            DType::Q4_0 | DType::Q4_1 | DType::Q5_0 | DType::Q5_1 | DType::Q8_0 | DType::Q8_1 => 32,
+            DType::Q2K | DType::Q3K | DType::Q4K | DType::Q5K | DType::Q6K | DType::Q8K => 256,
         }
     }
 
+    /// Returns the number of bytes needed to store `numel` elements, rounding
+    /// up to whole blocks for quantized types.
+    pub fn storage_size(&self, numel: usize) -> usize {
+        numel.div_ceil(self.block_size()) * self.size_in_bytes()
+    }
+
     /// Returns true if this dtype is a quantized format.
     pub fn is_quantized(&self) -> bool {
-        matches!(self, DType::Q4_0 | DType::Q8_0)
+        !matches!(self, DType::F32 | DType::F16 | DType::BF16)
     }
 }
 
@@ -77,8 +161,19 @@ impl fmt::Display for DType {
         match self {
             DType::F32 => write!(f, "f32"),
             DType::F16 => write!(f, "f16"),
+            DType::BF16 => write!(f, "bf16"),
             DType::Q4_0 => write!(f, "q4_0"),
+            DType::Q4_1 => write!(f, "q4_1"),
+            DType::Q5_0 => write!(f, "q5_0"),
+            DType::Q5_1 => write!(f, "q5_1"),
             DType::Q8_0 => write!(f, "q8_0"),
+            DType::Q8_1 => write!(f, "q8_1"),
+            DType::Q2K => write!(f, "q2_k"),
+            DType::Q3K => write!(f, "q3_k"),
+            DType::Q4K => write!(f, "q4_k"),
+            DType::Q5K => write!(f, "q5_k"),
+            DType::Q6K => write!(f, "q6_k"),
+            DType::Q8K => write!(f, "q8_k"),
         }
     }
 }
@@ -91,13 +186,66 @@ mod tests {
     fn test_size_in_bytes() {
         assert_eq!(DType::F32.size_in_bytes(), 4);
         assert_eq!(DType::F16.size_in_bytes(), 2);
+        assert_eq!(DType::BF16.size_in_bytes(), 2);
         assert_eq!(DType::Q4_0.size_in_bytes(), 18);
         assert_eq!(DType::Q8_0.size_in_bytes(), 34);
+        assert_eq!(DType::Q4_1.size_in_bytes(), 20);
+        assert_eq!(DType::Q5_0.size_in_bytes(), 22);
+        assert_eq!(DType::Q5_1.size_in_bytes(), 24);
+        assert_eq!(DType::Q8_1.size_in_bytes(), 36);
+        assert_eq!(DType::Q2K.size_in_bytes(), 84);
+        assert_eq!(DType::Q3K.size_in_bytes(), 110);
+        assert_eq!(DType::Q4K.size_in_bytes(), 144);
+        assert_eq!(DType::Q5K.size_in_bytes(), 176);
+        assert_eq!(DType::Q6K.size_in_bytes(), 210);
+        assert_eq!(DType::Q8K.size_in_bytes(), 292);
+    }
+
+    #[test]
+    fn test_k_quant_block_size() {
+        for dtype in [
+            DType::Q2K,
+            DType::Q3K,
+            DType::Q4K,
+            DType::Q5K,
+            DType::Q6K,
+            DType::Q8K,
+        ] {
+            assert_eq!(dtype.block_size(), 256);
+            assert!(dtype.is_quantized());
+        }
+        // A 4096-wide Q4_K row is 16 super-blocks.
+        assert_eq!(DType::Q4K.storage_size(4096), 16 * 144);
+    }
+
+    #[test]
+    fn test_storage_size() {
+        assert_eq!(DType::F32.storage_size(10), 40);
+        assert_eq!(DType::F16.storage_size(10), 20);
+        assert_eq!(DType::Q4_0.storage_size(64), 36);
+        assert_eq!(DType::Q8_0.storage_size(33), 68);
+        assert_eq!(DType::Q5_1.storage_size(64), 48);
     }
 
     #[test]
     fn test_gguf_roundtrip() {
-        for dtype in &[DType::F32, DType::F16, DType::Q4_0, DType::Q8_0] {
+        for dtype in &[
+            DType::F32,
+            DType::F16,
+            DType::BF16,
+            DType::Q4_0,
+            DType::Q4_1,
+            DType::Q5_0,
+            DType::Q5_1,
+            DType::Q8_0,
+            DType::Q8_1,
+            DType::Q2K,
+            DType::Q3K,
+            DType::Q4K,
+            DType::Q5K,
+            DType::Q6K,
+            DType::Q8K,
+        ] {
             let id = dtype.to_gguf_type();
             let back = DType::from_gguf_type(id).unwrap();
             assert_eq!(*dtype, back);
@@ -19,6 +19,8 @@ pub enum TensorError {
     },
     #[error("unsupported dtype: {0}")]
     UnsupportedDType(String),
+    #[error("unsupported op: {0}")]
+    UnsupportedOp(String),
     #[error("{0}")]
     Other(String),
 }
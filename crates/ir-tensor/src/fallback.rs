@@ -0,0 +1,448 @@
+//! Routing each op to the first backend that can run it.
+//!
+//! [`FallbackBackend`] holds an ordered list of backends and is itself a
+//! [`ComputeBackend`]. Every call goes to the first backend whose
+//! [`Capabilities`] cover the op, the dtype it reads and the size of its
+//! largest buffer, so an accelerator that only implements matmul can sit in
+//! front of the CPU backend and take the matmuls while the CPU does the
+//! rest. Routing looks only at the capabilities: an op that fails on the
+//! backend it was routed to is not retried on the next one.
+
+use crate::backend::{
+    AttentionParams, BackendOp, BinaryOp, Capabilities, ComputeBackend, KvView, KvViewF16,
+    MatmulParams, ReduceOp, unsupported,
+};
+use crate::dtype::DType;
+use crate::error::Result;
+use crate::layout::Layout;
+use crate::rope::RopeTable;
+
+/// A backend that sends each op to the first of several backends that
+/// supports it.
+///
+/// ```
+/// use ir_tensor::{BackendOp, ComputeBackend, CpuBackend, DType, FallbackBackend};
+///
+/// let backend = FallbackBackend::new(vec![Box::new(CpuBackend::new())]);
+/// assert_eq!(backend.name(), "fallback(cpu)");
+/// let chosen = backend.backend_for(BackendOp::Matmul, DType::F32, 0).unwrap();
+/// assert_eq!(chosen.name(), "cpu");
+/// assert!(backend.backend_for(BackendOp::MatmulQuantized, DType::F32, 0).is_none());
+/// ```
+#[derive(Debug)]
+pub struct FallbackBackend {
+    backends: Vec<Box<dyn ComputeBackend>>,
+    capabilities: Vec<Capabilities>,
+    name: String,
+}
+
+impl FallbackBackend {
+    /// Route ops over `backends`, most preferred first. Each backend's
+    /// capabilities are read once, here.
+    pub fn new(backends: Vec<Box<dyn ComputeBackend>>) -> Self {
+        let names: Vec<&str> = backends.iter().map(|b| b.name()).collect();
+        let name = format!("fallback({})", names.join(", "));
+        let capabilities = backends.iter().map(|b| b.capabilities()).collect();
+        FallbackBackend {
+            backends,
+            capabilities,
+            name,
+        }
+    }
+
+    /// The backends, in order of preference.
+    pub fn backends(&self) -> &[Box<dyn ComputeBackend>] {
+        &self.backends
+    }
+
+    /// The first backend that runs `op` on `dtype` with no buffer larger
+    /// than `bytes`, if any.
+    pub fn backend_for(
+        &self,
+        op: BackendOp,
+        dtype: DType,
+        bytes: usize,
+    ) -> Option<&dyn ComputeBackend> {
+        self.backends
+            .iter()
+            .zip(&self.capabilities)
+            .find(|(_, caps)| caps.supports(op, dtype) && caps.fits(bytes))
+            .map(|(backend, _)| backend.as_ref())
+    }
+
+    /// [`Self::backend_for`] an op that reads `op.dtype()`, failing if no
+    /// backend supports it.
+    fn route(&self, op: BackendOp, bytes: usize) -> Result<&dyn ComputeBackend> {
+        let dtype = op.dtype().unwrap_or(DType::F32);
+        self.backend_for(op, dtype, bytes)
+            .ok_or_else(|| unsupported(&self.name, op))
+    }
+}
+
+/// Size in bytes of the largest of several f32 buffers with lengths `lens`.
+fn largest(lens: &[usize]) -> usize {
+    4 * lens.iter().copied().max().unwrap_or(0)
+}
+
+impl ComputeBackend for FallbackBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Everything at least one backend supports.
+    fn capabilities(&self) -> Capabilities {
+        let none = Capabilities {
+            ops: Vec::new(),
+            max_buffer_size: Some(0),
+        };
+        self.capabilities.iter().fold(none, |acc, c| acc.union(c))
+    }
+
+    fn matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        let bytes = largest(&[a.len(), b.len(), out.len()]);
+        self.route(BackendOp::Matmul, bytes)?
+            .matmul_into(a, b, out, m, k, n)
+    }
+
+    fn batched_matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        params: &MatmulParams,
+    ) -> Result<()> {
+        let bytes = largest(&[a.len(), b.len(), out.len()]);
+        self.route(BackendOp::BatchedMatmul, bytes)?
+            .batched_matmul_into(a, b, out, params)
+    }
+
+    fn matmul_quantized_into(
+        &self,
+        w: &[u8],
+        dtype: DType,
+        x: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+    ) -> Result<()> {
+        let op = BackendOp::MatmulQuantized;
+        let bytes = w.len().max(largest(&[x.len(), out.len()]));
+        self.backend_for(op, dtype, bytes)
+            .ok_or_else(|| unsupported(&self.name, op))?
+            .matmul_quantized_into(w, dtype, x, out, m, k)
+    }
+
+    fn matmul_f16_into(
+        &self,
+        a: &[u8],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        let bytes = a.len().max(largest(&[b.len(), out.len()]));
+        self.route(BackendOp::MatmulF16, bytes)?
+            .matmul_f16_into(a, b, out, m, k, n)
+    }
+
+    fn add_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        let bytes = largest(&[a.len(), b.len()]);
+        self.route(BackendOp::Add, bytes)?.add_inplace(a, b)
+    }
+
+    fn mul_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        let bytes = largest(&[a.len(), b.len()]);
+        self.route(BackendOp::Mul, bytes)?.mul_inplace(a, b)
+    }
+
+    fn scale_inplace(&self, a: &mut [f32], s: f32) -> Result<()> {
+        self.route(BackendOp::Scale, largest(&[a.len()]))?
+            .scale_inplace(a, s)
+    }
+
+    fn binary_into(
+        &self,
+        op: BinaryOp,
+        a: &[f32],
+        a_layout: &Layout,
+        b: &[f32],
+        b_layout: &Layout,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let bytes = largest(&[a.len(), b.len(), out.len()]);
+        self.route(BackendOp::Binary, bytes)?
+            .binary_into(op, a, a_layout, b, b_layout, out)
+    }
+
+    fn reduce_into(
+        &self,
+        op: ReduceOp,
+        x: &[f32],
+        layout: &Layout,
+        dim: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let bytes = largest(&[x.len(), out.len()]);
+        self.route(BackendOp::Reduce, bytes)?
+            .reduce_into(op, x, layout, dim, out)
+    }
+
+    fn rms_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let bytes = largest(&[x.len(), weight.len(), out.len()]);
+        self.route(BackendOp::RmsNorm, bytes)?
+            .rms_norm_into(x, weight, eps, hidden_size, out)
+    }
+
+    fn softmax_into(&self, x: &[f32], n_vocab: usize, out: &mut [f32]) -> Result<()> {
+        let bytes = largest(&[x.len(), out.len()]);
+        self.route(BackendOp::Softmax, bytes)?
+            .softmax_into(x, n_vocab, out)
+    }
+
+    fn rope_inplace(
+        &self,
+        q: &mut [f32],
+        k: &mut [f32],
+        rope: &RopeTable,
+        pos: usize,
+        n_heads_q: usize,
+        n_heads_k: usize,
+    ) -> Result<()> {
+        let bytes = largest(&[q.len(), k.len()]);
+        self.route(BackendOp::Rope, bytes)?
+            .rope_inplace(q, k, rope, pos, n_heads_q, n_heads_k)
+    }
+
+    fn attention_into(
+        &self,
+        q: &[f32],
+        kv: KvView<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let bytes = largest(&[q.len(), kv.k.len(), kv.v.len(), out.len()]);
+        self.route(BackendOp::Attention, bytes)?
+            .attention_into(q, kv, q_pos, params, out)
+    }
+
+    fn attention_f16_into(
+        &self,
+        q: &[f32],
+        kv: KvViewF16<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let cache = kv.k.len().max(kv.v.len());
+        let bytes = cache.max(largest(&[q.len(), out.len()]));
+        self.route(BackendOp::AttentionF16, bytes)?
+            .attention_f16_into(q, kv, q_pos, params, out)
+    }
+
+    fn silu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.route(BackendOp::Silu, largest(&[x.len()]))?
+            .silu_inplace(x)
+    }
+
+    fn gelu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.route(BackendOp::Gelu, largest(&[x.len()]))?
+            .gelu_inplace(x)
+    }
+
+    fn gelu_erf_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.route(BackendOp::GeluErf, largest(&[x.len()]))?
+            .gelu_erf_inplace(x)
+    }
+
+    fn geglu_inplace(&self, gate: &mut [f32], up: &[f32]) -> Result<()> {
+        let bytes = largest(&[gate.len(), up.len()]);
+        self.route(BackendOp::Geglu, bytes)?.geglu_inplace(gate, up)
+    }
+
+    fn relu2_inplace(&self, x: &mut [f32]) -> Result<()> {
+        self.route(BackendOp::Relu2, largest(&[x.len()]))?
+            .relu2_inplace(x)
+    }
+
+    fn softcap_inplace(&self, x: &mut [f32], cap: f32) -> Result<()> {
+        self.route(BackendOp::Softcap, largest(&[x.len()]))?
+            .softcap_inplace(x, cap)
+    }
+
+    fn layer_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let bytes = largest(&[x.len(), weight.len(), out.len()]);
+        self.route(BackendOp::LayerNorm, bytes)?.layer_norm_into(
+            x,
+            weight,
+            bias,
+            eps,
+            hidden_size,
+            out,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::CpuBackend;
+    use crate::error::TensorError;
+
+    /// A backend that only does f32 matmuls up to `max_bytes`, counting
+    /// them.
+    #[derive(Debug, Default)]
+    struct MatmulOnly {
+        calls: Arc<AtomicUsize>,
+        max_bytes: Option<usize>,
+    }
+
+    impl ComputeBackend for MatmulOnly {
+        fn name(&self) -> &str {
+            "matmul-only"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                max_buffer_size: self.max_bytes,
+                ..Capabilities::only(&[BackendOp::Matmul], Vec::new())
+            }
+        }
+
+        fn matmul_into(
+            &self,
+            a: &[f32],
+            b: &[f32],
+            out: &mut [f32],
+            m: usize,
+            k: usize,
+            n: usize,
+        ) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            CpuBackend::with_threads(1)
+                .unwrap()
+                .matmul_into(a, b, out, m, k, n)
+        }
+    }
+
+    /// `MatmulOnly` in front of the CPU backend, and its call counter.
+    fn with_cpu(max_bytes: Option<usize>) -> (FallbackBackend, Arc<AtomicUsize>) {
+        let front = MatmulOnly {
+            max_bytes,
+            ..Default::default()
+        };
+        let calls = front.calls.clone();
+        let backend = FallbackBackend::new(vec![
+            Box::new(front),
+            Box::new(CpuBackend::with_threads(1).unwrap()),
+        ]);
+        (backend, calls)
+    }
+
+    #[test]
+    fn test_partial_backend_rejects_other_ops() {
+        let backend = MatmulOnly::default();
+        assert_eq!(backend.matmul(&[2.0], &[3.0], 1, 1, 1).unwrap(), vec![6.0]);
+        let err = backend.silu(&[1.0]).unwrap_err();
+        assert!(matches!(err, TensorError::UnsupportedOp(_)), "{}", err);
+        assert!(err.to_string().contains("silu on the matmul-only backend"));
+    }
+
+    #[test]
+    fn test_routes_each_op_to_first_supporting_backend() {
+        let (backend, calls) = with_cpu(None);
+        assert_eq!(backend.name(), "fallback(matmul-only, cpu)");
+
+        let y = backend.matmul(&[1.0, 2.0], &[3.0, 4.0], 1, 2, 1).unwrap();
+        assert_eq!(y, vec![11.0]);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // Everything else falls through to the CPU.
+        assert_eq!(backend.scale(&[1.0, -2.0], 2.0).unwrap(), vec![2.0, -4.0]);
+        let w = crate::quant::quantize(DType::Q8_0, &[1.0; 32]);
+        let y = backend
+            .matmul_quantized(&w, DType::Q8_0, &[1.0; 32], 1, 32)
+            .unwrap();
+        assert!((y[0] - 32.0).abs() < 0.5);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        let cpu = backend.backend_for(BackendOp::Silu, DType::F32, 0).unwrap();
+        assert_eq!(cpu.name(), "cpu");
+    }
+
+    #[test]
+    fn test_large_buffers_skip_limited_backend() {
+        let (backend, calls) = with_cpu(Some(64));
+        backend.matmul(&[1.0; 16], &[1.0; 16], 4, 4, 4).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        // 17 f32 elements are 68 bytes, over the limit.
+        backend.matmul(&[1.0; 17], &[1.0], 17, 1, 1).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_unsupported_everywhere() {
+        let backend = FallbackBackend::new(vec![Box::new(MatmulOnly::default())]);
+        let err = backend.silu(&[1.0]).unwrap_err();
+        assert!(matches!(err, TensorError::UnsupportedOp(_)), "{}", err);
+
+        let (backend, _) = with_cpu(None);
+        let w = vec![0u8; DType::F32.storage_size(256)];
+        let err = backend
+            .matmul_quantized(&w, DType::F32, &[0.0; 256], 1, 256)
+            .unwrap_err();
+        assert!(matches!(err, TensorError::UnsupportedOp(_)), "{}", err);
+    }
+
+    #[test]
+    fn test_capabilities_are_the_union() {
+        let (backend, _) = with_cpu(Some(64));
+        let caps = backend.capabilities();
+        assert_eq!(caps, CpuBackend::with_threads(1).unwrap().capabilities());
+        let fused: Vec<BackendOp> = caps.fused_ops().collect();
+        assert_eq!(
+            fused,
+            [
+                BackendOp::Attention,
+                BackendOp::AttentionF16,
+                BackendOp::Geglu
+            ]
+        );
+
+        let front = FallbackBackend::new(vec![Box::new(MatmulOnly {
+            max_bytes: Some(64),
+            ..Default::default()
+        })]);
+        let caps = front.capabilities();
+        assert_eq!(caps.ops, vec![(BackendOp::Matmul, vec![DType::F32])]);
+        assert_eq!(caps.max_buffer_size, Some(64));
+        assert!(caps.fits(64) && !caps.fits(65));
+    }
+}
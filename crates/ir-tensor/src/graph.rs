@@ -0,0 +1,1209 @@
+//! Lazy compute graphs.
+//!
+//! A [`Graph`] records operations on flat f32 vectors instead of running
+//! them. [`Graph::compile`] then simplifies the recorded DAG and plans its
+//! memory once:
+//!
+//! - nodes that no output depends on are dropped;
+//! - `rms_norm` feeding a single `matmul` becomes one `rms_norm_matmul` step,
+//!   and `silu(gate) * up` becomes one `silu_mul` step;
+//! - every value gets a slot in a small pool of buffers, and element-wise
+//!   steps write into the slot of an operand that is not read again.
+//!
+//! The resulting [`CompiledGraph`] owns all of its buffers, so it can be
+//! replayed (for example once per generated token) without allocating.
+//! State that outlives a run stays outside the graph: each run gets the
+//! token for `embedding` steps and the [`KvStore`] that `kv_append` and
+//! `attention` steps write and read through a [`RunState`].
+
+use std::fmt;
+
+use crate::backend::{AttentionParams, ComputeBackend};
+use crate::dtype::DType;
+use crate::error::{Result, TensorError};
+use crate::rope::RopeTable;
+use crate::tensor::Tensor;
+
+/// Handle to a value recorded in a [`Graph`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone)]
+enum Op {
+    /// The `n`-th input passed to [`CompiledGraph::run`].
+    Input(usize),
+    /// Row of the run's token in a `[n_vocab, n]` table.
+    Embedding(Tensor),
+    /// `w @ x` for a `[m, k]` weight.
+    MatMul {
+        x: NodeId,
+        w: Tensor,
+    },
+    RmsNorm {
+        x: NodeId,
+        weight: Tensor,
+        eps: f32,
+    },
+    Softmax {
+        x: NodeId,
+        n: usize,
+    },
+    /// Rotation at the position passed to [`CompiledGraph::run`].
+    Rope {
+        x: NodeId,
+        table: RopeTable,
+        n_heads: usize,
+    },
+    Add(NodeId, NodeId),
+    Mul(NodeId, NodeId),
+    Scale(NodeId, f32),
+    Silu(NodeId),
+    Gelu(NodeId),
+    /// Store `n`-element `k` and `v` at the run position of `layer` in the
+    /// [`KvStore`]. Has no value; attention reads it only to run after it.
+    KvAppend {
+        k: NodeId,
+        v: NodeId,
+        layer: usize,
+        n: usize,
+    },
+    /// Attention of `q` over the positions up to the run position of
+    /// `layer`, once `append` has stored the current one.
+    Attention {
+        q: NodeId,
+        append: NodeId,
+        layer: usize,
+        params: AttentionParams,
+    },
+    /// Fused `matmul(rms_norm(x))`.
+    RmsNormMatMul {
+        x: NodeId,
+        weight: Tensor,
+        eps: f32,
+        w: Tensor,
+    },
+    /// Fused `silu(gate) * up`.
+    SiluMul {
+        gate: NodeId,
+        up: NodeId,
+    },
+}
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Input(_) => "input",
+            Op::Embedding(_) => "embedding",
+            Op::MatMul { .. } => "matmul",
+            Op::RmsNorm { .. } => "rms_norm",
+            Op::Softmax { .. } => "softmax",
+            Op::Rope { .. } => "rope",
+            Op::Add(..) => "add",
+            Op::Mul(..) => "mul",
+            Op::Scale(..) => "scale",
+            Op::Silu(_) => "silu",
+            Op::Gelu(_) => "gelu",
+            Op::KvAppend { .. } => "kv_append",
+            Op::Attention { .. } => "attention",
+            Op::RmsNormMatMul { .. } => "rms_norm_matmul",
+            Op::SiluMul { .. } => "silu_mul",
+        }
+    }
+
+    fn operands(&self) -> Vec<NodeId> {
+        match *self {
+            Op::Input(_) | Op::Embedding(_) => vec![],
+            Op::MatMul { x, .. }
+            | Op::RmsNorm { x, .. }
+            | Op::Softmax { x, .. }
+            | Op::Rope { x, .. }
+            | Op::Scale(x, _)
+            | Op::Silu(x)
+            | Op::Gelu(x)
+            | Op::RmsNormMatMul { x, .. } => vec![x],
+            Op::Add(a, b) | Op::Mul(a, b) | Op::KvAppend { k: a, v: b, .. } => vec![a, b],
+            Op::Attention { q, append, .. } => vec![q, append],
+            Op::SiluMul { gate, up } => vec![gate, up],
+        }
+    }
+
+    /// The operand this op can overwrite with its result, if any.
+    fn inplace_operand(&self) -> Option<NodeId> {
+        match *self {
+            Op::Add(a, _) | Op::Mul(a, _) | Op::Scale(a, _) => Some(a),
+            Op::Silu(x) | Op::Gelu(x) | Op::Rope { x, .. } => Some(x),
+            Op::SiluMul { gate, .. } => Some(gate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    op: Op,
+    len: usize,
+}
+
+/// A recorded DAG of operations on flat f32 vectors.
+///
+/// Nodes can only refer to nodes recorded before them, so the recording
+/// order is always a valid execution order.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    nodes: Vec<Node>,
+    inputs: Vec<usize>,
+    outputs: Vec<NodeId>,
+}
+
+impl Graph {
+    /// Create an empty graph.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare the next input, a vector of `len` elements.
+    pub fn input(&mut self, len: usize) -> NodeId {
+        let index = self.inputs.len();
+        self.inputs.push(len);
+        self.push(Op::Input(index), len)
+    }
+
+    /// Matrix-vector product with a `[m, k]` weight of any dtype;
+    /// `x` must have `k` elements and the result has `m`.
+    pub fn matmul(&mut self, w: &Tensor, x: NodeId) -> Result<NodeId> {
+        let (m, k) = match *w.shape().dims() {
+            [m, k] => (m, k),
+            _ => {
+                return Err(TensorError::Other(format!(
+                    "graph matmul: weight must be 2-D, got {}",
+                    w.shape()
+                )));
+            }
+        };
+        let x_len = self.len(x)?;
+        if x_len != k {
+            return Err(TensorError::MatmulMismatch {
+                m,
+                k,
+                k2: x_len,
+                n: 1,
+            });
+        }
+        let w = stored(w)?;
+        Ok(self.push(Op::MatMul { x, w }, m))
+    }
+
+    /// Row lookup in a `[n_vocab, n]` embedding table of any dtype, for the
+    /// token given to [`CompiledGraph::run_with`]; the result has `n`
+    /// elements.
+    pub fn embedding(&mut self, table: &Tensor) -> Result<NodeId> {
+        let n = match *table.shape().dims() {
+            [_, n] if n.is_multiple_of(table.dtype().block_size()) => n,
+            _ => {
+                return Err(TensorError::Other(format!(
+                    "graph embedding: cannot look up rows of a {} {} table",
+                    table.shape(),
+                    table.dtype()
+                )));
+            }
+        };
+        let table = stored(table)?;
+        Ok(self.push(Op::Embedding(table), n))
+    }
+
+    /// RMS normalization of each `weight.len()`-element row of `x`.
+    pub fn rms_norm(&mut self, x: NodeId, weight: &Tensor, eps: f32) -> Result<NodeId> {
+        let len = self.len(x)?;
+        if weight.dtype() != DType::F32 {
+            return Err(TensorError::DTypeMismatch {
+                expected: DType::F32.to_string(),
+                got: weight.dtype().to_string(),
+            });
+        }
+        if !matches!(*weight.shape().dims(), [h] if h > 0 && len.is_multiple_of(h)) {
+            return Err(TensorError::Other(format!(
+                "graph rms_norm: weight {} does not divide input of {} elements",
+                weight.shape(),
+                len
+            )));
+        }
+        let weight = weight.contiguous()?;
+        Ok(self.push(Op::RmsNorm { x, weight, eps }, len))
+    }
+
+    /// Softmax over each `n`-element row of `x`.
+    pub fn softmax(&mut self, x: NodeId, n: usize) -> Result<NodeId> {
+        let len = self.len(x)?;
+        if n == 0 || !len.is_multiple_of(n) {
+            return Err(TensorError::Other(format!(
+                "graph softmax: row length {} does not divide input of {} elements",
+                n, len
+            )));
+        }
+        Ok(self.push(Op::Softmax { x, n }, len))
+    }
+
+    /// Rotary position embedding of `n_heads` heads, at the position given
+    /// to [`CompiledGraph::run`].
+    pub fn rope(&mut self, x: NodeId, table: &RopeTable, n_heads: usize) -> Result<NodeId> {
+        let len = self.len(x)?;
+        let head_dim = table.config().head_dim;
+        if len != head_dim * n_heads {
+            return Err(TensorError::Other(format!(
+                "graph rope: cannot rotate {} elements as {} heads of {}",
+                len, n_heads, head_dim
+            )));
+        }
+        let table = table.clone();
+        Ok(self.push(Op::Rope { x, table, n_heads }, len))
+    }
+
+    /// Store `k` and `v` as layer `layer` of the [`KvStore`] at the run
+    /// position.
+    ///
+    /// The returned node has no elements; pass it to
+    /// [`attention`](Self::attention) so that attention runs after the
+    /// append.
+    pub fn kv_append(&mut self, k: NodeId, v: NodeId, layer: usize) -> Result<NodeId> {
+        let n = self.same_len(k, v)?;
+        Ok(self.push(Op::KvAppend { k, v, layer, n }, 0))
+    }
+
+    /// Attention of `q` over every position up to the run position of the
+    /// layer `append` stored into, with the head layout in `params`.
+    pub fn attention(
+        &mut self,
+        q: NodeId,
+        append: NodeId,
+        params: AttentionParams,
+    ) -> Result<NodeId> {
+        let (layer, kv_len) = match self.nodes.get(append.0).map(|node| &node.op) {
+            Some(&Op::KvAppend { layer, n, .. }) => (layer, n),
+            _ => {
+                return Err(TensorError::Other(format!(
+                    "graph attention: node {} is not a kv_append",
+                    append.0
+                )));
+            }
+        };
+        let len = params.n_heads * params.head_dim;
+        let (q_len, want_kv) = (self.len(q)?, params.n_kv_heads * params.head_dim);
+        if q_len != len || kv_len != want_kv {
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![len, want_kv],
+                got: vec![q_len, kv_len],
+            });
+        }
+        let op = Op::Attention {
+            q,
+            append,
+            layer,
+            params,
+        };
+        Ok(self.push(op, len))
+    }
+
+    /// Element-wise `a + b`.
+    pub fn add(&mut self, a: NodeId, b: NodeId) -> Result<NodeId> {
+        let len = self.same_len(a, b)?;
+        Ok(self.push(Op::Add(a, b), len))
+    }
+
+    /// Element-wise `a * b`.
+    pub fn mul(&mut self, a: NodeId, b: NodeId) -> Result<NodeId> {
+        let len = self.same_len(a, b)?;
+        Ok(self.push(Op::Mul(a, b), len))
+    }
+
+    /// `x * s`.
+    pub fn scale(&mut self, x: NodeId, s: f32) -> Result<NodeId> {
+        let len = self.len(x)?;
+        Ok(self.push(Op::Scale(x, s), len))
+    }
+
+    /// Element-wise SiLU.
+    pub fn silu(&mut self, x: NodeId) -> Result<NodeId> {
+        let len = self.len(x)?;
+        Ok(self.push(Op::Silu(x), len))
+    }
+
+    /// Element-wise GELU (tanh approximation).
+    pub fn gelu(&mut self, x: NodeId) -> Result<NodeId> {
+        let len = self.len(x)?;
+        Ok(self.push(Op::Gelu(x), len))
+    }
+
+    /// Mark `x` as an output. Outputs are numbered in the order they are
+    /// marked, see [`CompiledGraph::output`].
+    pub fn output(&mut self, x: NodeId) -> Result<()> {
+        self.len(x)?;
+        self.outputs.push(x);
+        Ok(())
+    }
+
+    /// Simplify the graph and plan its buffers.
+    pub fn compile(mut self) -> Result<CompiledGraph> {
+        if self.outputs.is_empty() {
+            return Err(TensorError::Other("graph has no outputs".to_string()));
+        }
+        self.fuse();
+        let live = self.live_nodes();
+        Ok(self.plan(&live))
+    }
+
+    fn push(&mut self, op: Op, len: usize) -> NodeId {
+        self.nodes.push(Node { op, len });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    fn len(&self, x: NodeId) -> Result<usize> {
+        self.nodes
+            .get(x.0)
+            .map(|node| node.len)
+            .ok_or_else(|| TensorError::Other(format!("graph has no node {}", x.0)))
+    }
+
+    fn same_len(&self, a: NodeId, b: NodeId) -> Result<usize> {
+        let (len_a, len_b) = (self.len(a)?, self.len(b)?);
+        if len_a != len_b {
+            return Err(TensorError::ShapeMismatch {
+                expected: vec![len_a],
+                got: vec![len_b],
+            });
+        }
+        Ok(len_a)
+    }
+
+    /// Number of times each node is read by a live node or an output.
+    fn use_counts(&self, live: &[bool]) -> Vec<usize> {
+        let mut uses = vec![0; self.nodes.len()];
+        for (node, _) in self.nodes.iter().zip(live).filter(|(_, l)| **l) {
+            for x in node.op.operands() {
+                uses[x.0] += 1;
+            }
+        }
+        for x in &self.outputs {
+            uses[x.0] += 1;
+        }
+        uses
+    }
+
+    fn live_nodes(&self) -> Vec<bool> {
+        let mut live = vec![false; self.nodes.len()];
+        for x in &self.outputs {
+            live[x.0] = true;
+        }
+        for i in (0..self.nodes.len()).rev() {
+            if live[i] {
+                for x in self.nodes[i].op.operands() {
+                    live[x.0] = true;
+                }
+            }
+        }
+        live
+    }
+
+    /// Rewrite fusable patterns whose intermediate value is read only by
+    /// the consuming op. The absorbed nodes become dead.
+    fn fuse(&mut self) {
+        let uses = self.use_counts(&self.live_nodes());
+        for i in 0..self.nodes.len() {
+            let fused = match &self.nodes[i].op {
+                Op::MatMul { x, w } if uses[x.0] == 1 => match &self.nodes[x.0].op {
+                    Op::RmsNorm { x, weight, eps } => Some(Op::RmsNormMatMul {
+                        x: *x,
+                        weight: weight.clone(),
+                        eps: *eps,
+                        w: w.clone(),
+                    }),
+                    _ => None,
+                },
+                &Op::Mul(a, b) => {
+                    let silu_of = |n: NodeId| match self.nodes[n.0].op {
+                        Op::Silu(gate) if uses[n.0] == 1 => Some(gate),
+                        _ => None,
+                    };
+                    if let Some(gate) = silu_of(a) {
+                        Some(Op::SiluMul { gate, up: b })
+                    } else {
+                        silu_of(b).map(|gate| Op::SiluMul { gate, up: a })
+                    }
+                }
+                _ => None,
+            };
+            if let Some(op) = fused {
+                self.nodes[i].op = op;
+            }
+        }
+    }
+
+    /// Assign every live node a buffer slot, reusing slots whose value has
+    /// been read for the last time.
+    fn plan(self, live: &[bool]) -> CompiledGraph {
+        let mut last_use = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate().filter(|(i, _)| live[*i]) {
+            for x in node.op.operands() {
+                last_use[x.0] = i;
+            }
+        }
+        for x in &self.outputs {
+            last_use[x.0] = usize::MAX;
+        }
+
+        let mut slot_of = vec![usize::MAX; self.nodes.len()];
+        let mut slot_len: Vec<usize> = Vec::new();
+        let mut free: Vec<usize> = Vec::new();
+        let mut steps = Vec::new();
+        let mut scratch_len = 0;
+        let mut row_len = 0;
+        let lens: Vec<usize> = self.nodes.iter().map(|node| node.len).collect();
+
+        for (i, node) in self.nodes.into_iter().enumerate() {
+            if !live[i] {
+                continue;
+            }
+            let mut op = node.op;
+            // Element-wise ops are commutative in their first two operands,
+            // so put a dying operand first to let the result take its slot.
+            if let Op::Add(a, b) | Op::Mul(a, b) = op
+                && last_use[a.0] != i
+                && last_use[b.0] == i
+            {
+                op = match op {
+                    Op::Add(..) => Op::Add(b, a),
+                    _ => Op::Mul(b, a),
+                };
+            }
+            match op {
+                Op::RmsNormMatMul { x, .. } => scratch_len = scratch_len.max(lens[x.0]),
+                Op::Embedding(_) => row_len = row_len.max(node.len),
+                _ => {}
+            }
+
+            let operands = op.operands();
+            let reuse = op
+                .inplace_operand()
+                .filter(|a| last_use[a.0] == i && operands.iter().filter(|x| *x == a).count() == 1);
+            let slot = match reuse {
+                Some(a) => slot_of[a.0],
+                // An empty value gets an empty slot of its own rather than
+                // holding on to a free buffer.
+                None if node.len == 0 => {
+                    slot_len.push(0);
+                    slot_len.len() - 1
+                }
+                None => take_slot(&mut free, &mut slot_len, node.len),
+            };
+            slot_len[slot] = slot_len[slot].max(node.len);
+            slot_of[i] = slot;
+
+            for x in operands {
+                if last_use[x.0] == i && slot_of[x.0] != slot && !free.contains(&slot_of[x.0]) {
+                    free.push(slot_of[x.0]);
+                }
+            }
+            steps.push(Step {
+                op,
+                node: i,
+                len: node.len,
+            });
+        }
+
+        CompiledGraph {
+            steps,
+            slot_of,
+            slots: slot_len.iter().map(|&len| vec![0.0; len]).collect(),
+            scratch: vec![0.0; scratch_len],
+            row: Vec::with_capacity(row_len),
+            inputs: self.inputs,
+            outputs: self.outputs,
+        }
+    }
+}
+
+/// `w` as steps read it: contiguous, in its stored dtype.
+fn stored(w: &Tensor) -> Result<Tensor> {
+    match w.dtype() {
+        DType::F32 => w.contiguous(),
+        _ if w.is_contiguous() => Ok(w.clone()),
+        dtype => Err(TensorError::UnsupportedDType(dtype.to_string())),
+    }
+}
+
+/// Pick a free slot for a value of `len` elements: the smallest free slot
+/// that is large enough, else the largest free slot (which will grow), else
+/// a new one.
+fn take_slot(free: &mut Vec<usize>, slot_len: &mut Vec<usize>, len: usize) -> usize {
+    let fits = free
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| slot_len[**s] >= len)
+        .min_by_key(|(_, s)| slot_len[**s]);
+    let pick = fits.or_else(|| free.iter().enumerate().max_by_key(|(_, s)| slot_len[**s]));
+    match pick.map(|(pos, _)| pos) {
+        Some(pos) => free.swap_remove(pos),
+        None => {
+            slot_len.push(0);
+            slot_len.len() - 1
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Step {
+    op: Op,
+    node: usize,
+    len: usize,
+}
+
+/// An optimized [`Graph`] with preallocated buffers, ready to be run any
+/// number of times.
+pub struct CompiledGraph {
+    steps: Vec<Step>,
+    slot_of: Vec<usize>,
+    slots: Vec<Vec<f32>>,
+    scratch: Vec<f32>,
+    /// A decoded embedding row, for tables that are not f32.
+    row: Vec<f32>,
+    inputs: Vec<usize>,
+    outputs: Vec<NodeId>,
+}
+
+/// Key/value cache that `kv_append` and `attention` steps use.
+///
+/// It lives outside the graph, so it persists across runs and can be shared
+/// with code that runs the same model eagerly.
+pub trait KvStore {
+    /// Store the keys and values of position `pos` in layer `layer`.
+    fn append(&mut self, layer: usize, k: &[f32], v: &[f32], pos: usize) -> Result<()>;
+
+    /// Attention of `q`, at position `pos`, over positions `0..=pos` of layer
+    /// `layer`, written to `out`.
+    fn attention_into(
+        &self,
+        backend: &dyn ComputeBackend,
+        layer: usize,
+        q: &[f32],
+        pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()>;
+}
+
+/// Per-run values for the steps that depend on state outside the graph.
+#[derive(Default)]
+pub struct RunState<'a> {
+    /// Position of the token being processed, for `rope`, `kv_append` and
+    /// `attention` steps.
+    pub pos: usize,
+    /// Token that `embedding` steps look up.
+    pub token: Option<u32>,
+    /// Cache that `kv_append` steps write and `attention` steps read.
+    pub kv: Option<&'a mut dyn KvStore>,
+}
+
+impl CompiledGraph {
+    /// Execute every step on `backend`.
+    ///
+    /// `inputs` holds one slice per [`Graph::input`], in declaration order;
+    /// `pos` is the position used by `rope` steps. Results are read back
+    /// with [`CompiledGraph::output`].
+    pub fn run(
+        &mut self,
+        backend: &dyn ComputeBackend,
+        inputs: &[&[f32]],
+        pos: usize,
+    ) -> Result<()> {
+        let state = RunState {
+            pos,
+            ..RunState::default()
+        };
+        self.run_with(backend, inputs, state)
+    }
+
+    /// Like [`run`](Self::run), also passing the token and KV cache that
+    /// `embedding`, `kv_append` and `attention` steps need.
+    pub fn run_with(
+        &mut self,
+        backend: &dyn ComputeBackend,
+        inputs: &[&[f32]],
+        mut state: RunState<'_>,
+    ) -> Result<()> {
+        if inputs.len() != self.inputs.len() {
+            return Err(TensorError::Other(format!(
+                "graph expects {} inputs, got {}",
+                self.inputs.len(),
+                inputs.len()
+            )));
+        }
+        for (input, &len) in inputs.iter().zip(&self.inputs) {
+            if input.len() != len {
+                return Err(TensorError::ShapeMismatch {
+                    expected: vec![len],
+                    got: vec![input.len()],
+                });
+            }
+        }
+
+        for step in &self.steps {
+            let slot = self.slot_of[step.node];
+            // Move the destination out so operands can be borrowed from
+            // the other slots; no operand shares its slot unless the step
+            // runs in place.
+            let mut buf = std::mem::take(&mut self.slots[slot]);
+            let result = execute(
+                backend,
+                step,
+                &mut buf[..step.len],
+                slot,
+                &self.slots,
+                &self.slot_of,
+                &mut self.scratch,
+                &mut self.row,
+                inputs,
+                &mut state,
+            );
+            self.slots[slot] = buf;
+            result?;
+        }
+        Ok(())
+    }
+
+    /// The value of the `i`-th output after the last [`run`](Self::run).
+    ///
+    /// # Panics
+    /// Panics if `i` is not a valid output index.
+    pub fn output(&self, i: usize) -> &[f32] {
+        let node = self.outputs[i].0;
+        let step = self
+            .steps
+            .iter()
+            .find(|s| s.node == node)
+            .expect("live output");
+        &self.slots[self.slot_of[node]][..step.len]
+    }
+
+    /// Number of outputs.
+    pub fn num_outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Names of the ops that run, in execution order.
+    pub fn step_names(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|s| s.op.name()).collect()
+    }
+
+    /// Number of distinct buffers the steps share.
+    pub fn num_buffers(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl fmt::Debug for CompiledGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledGraph")
+            .field("steps", &self.step_names())
+            .field("buffers", &self.slots.len())
+            .field("outputs", &self.outputs.len())
+            .finish()
+    }
+}
+
+/// Run one step, writing its result to `out`.
+///
+/// `out` was taken from slot `out_slot`; an operand stored there is already
+/// in `out` (the step runs in place).
+#[allow(clippy::too_many_arguments)]
+fn execute(
+    backend: &dyn ComputeBackend,
+    step: &Step,
+    out: &mut [f32],
+    out_slot: usize,
+    slots: &[Vec<f32>],
+    slot_of: &[usize],
+    scratch: &mut [f32],
+    row: &mut Vec<f32>,
+    inputs: &[&[f32]],
+    state: &mut RunState<'_>,
+) -> Result<()> {
+    let len = step.len;
+    let read = |x: NodeId| &slots[slot_of[x.0]][..len];
+    // Copy `x` into `out` unless the step runs in place on it.
+    let load = |x: NodeId, out: &mut [f32]| {
+        if slot_of[x.0] != out_slot {
+            out.copy_from_slice(read(x));
+        }
+    };
+
+    match step.op {
+        Op::Input(i) => out.copy_from_slice(inputs[i]),
+        Op::Embedding(ref table) => {
+            let token = state.token.ok_or_else(|| {
+                TensorError::Other("graph embedding: the run has no token".to_string())
+            })?;
+            embedding_row(table, token as usize, out, row)?;
+        }
+        Op::KvAppend { k, v, layer, n } => {
+            let kv = state.kv.as_deref_mut().ok_or_else(no_kv_store)?;
+            kv.append(
+                layer,
+                &slots[slot_of[k.0]][..n],
+                &slots[slot_of[v.0]][..n],
+                state.pos,
+            )?;
+        }
+        Op::Attention {
+            q,
+            layer,
+            ref params,
+            ..
+        } => {
+            let kv = state.kv.as_deref().ok_or_else(no_kv_store)?;
+            kv.attention_into(backend, layer, read(q), state.pos, params, out)?;
+        }
+        Op::MatMul { x, ref w } => {
+            let k = w.shape().dim(1);
+            matvec(backend, w, &slots[slot_of[x.0]][..k], out)?;
+        }
+        Op::RmsNorm { x, ref weight, eps } => {
+            let hidden = weight.shape().dim(0);
+            backend.rms_norm_into(read(x), weight.data_f32(), eps, hidden, out)?;
+        }
+        Op::RmsNormMatMul {
+            x,
+            ref weight,
+            eps,
+            ref w,
+        } => {
+            let (k, hidden) = (w.shape().dim(1), weight.shape().dim(0));
+            let normed = &mut scratch[..k];
+            let x = &slots[slot_of[x.0]][..k];
+            backend.rms_norm_into(x, weight.data_f32(), eps, hidden, normed)?;
+            matvec(backend, w, normed, out)?;
+        }
+        Op::Softmax { x, n } => backend.softmax_into(read(x), n, out)?,
+        Op::Rope {
+            x,
+            ref table,
+            n_heads,
+        } => {
+            load(x, out);
+            backend.rope_inplace(out, &mut [], table, state.pos, n_heads, 0)?;
+        }
+        Op::Add(a, b) => {
+            load(a, out);
+            backend.add_inplace(out, read(b))?;
+        }
+        Op::Mul(a, b) => {
+            load(a, out);
+            backend.mul_inplace(out, read(b))?;
+        }
+        Op::Scale(x, s) => {
+            load(x, out);
+            backend.scale_inplace(out, s)?;
+        }
+        Op::Silu(x) => {
+            load(x, out);
+            backend.silu_inplace(out)?;
+        }
+        Op::Gelu(x) => {
+            load(x, out);
+            backend.gelu_inplace(out)?;
+        }
+        Op::SiluMul { gate, up } => {
+            load(gate, out);
+            backend.silu_inplace(out)?;
+            backend.mul_inplace(out, read(up))?;
+        }
+    }
+    Ok(())
+}
+
+fn no_kv_store() -> TensorError {
+    TensorError::Other(
+        "graph has kv_append or attention steps but the run has no KvStore".to_string(),
+    )
+}
+
+/// Decode row `token` of `table` into `out`, staging rows that are not f32
+/// in `row`.
+fn embedding_row(table: &Tensor, token: usize, out: &mut [f32], row: &mut Vec<f32>) -> Result<()> {
+    let (n_rows, n) = (table.shape().dim(0), out.len());
+    if token >= n_rows {
+        return Err(TensorError::Other(format!(
+            "graph embedding: token {} out of range for {} rows",
+            token, n_rows
+        )));
+    }
+    if table.dtype() == DType::F32 {
+        out.copy_from_slice(&table.data_f32()[token * n..(token + 1) * n]);
+        return Ok(());
+    }
+    let row_bytes = table.dtype().storage_size(n);
+    let raw = &table.raw_bytes()?[token * row_bytes..(token + 1) * row_bytes];
+    row.clear();
+    crate::quant::dequantize_into(table.dtype(), raw, n, row);
+    out.copy_from_slice(row);
+    Ok(())
+}
+
+fn matvec(backend: &dyn ComputeBackend, w: &Tensor, x: &[f32], out: &mut [f32]) -> Result<()> {
+    let (m, k) = (out.len(), x.len());
+    match w.dtype() {
+        DType::F32 => backend.matmul_into(w.data_f32(), x, out, m, k, 1),
+        DType::F16 => backend.matmul_f16_into(w.raw_bytes()?, x, out, m, k, 1),
+        dtype => backend.matmul_quantized_into(w.raw_bytes()?, dtype, x, out, m, k),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::KvView;
+    use crate::cpu::CpuBackend;
+    use crate::quant;
+    use crate::rope::RopeConfig;
+    use crate::shape::Shape;
+    use crate::test_util::{assert_close, f16_weight, f32_weight, q8_0_weight, values};
+
+    fn eager_matvec(b: &CpuBackend, w: &Tensor, x: &[f32]) -> Vec<f32> {
+        let mut out = vec![0.0; w.shape().dim(0)];
+        matvec(b, w, x, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_ffn_block_matches_eager() {
+        let b = CpuBackend::new();
+        let (n_embd, n_ff) = (32, 64);
+        let norm = Tensor::new(values(n_embd, 1), Shape::new(vec![n_embd]));
+        let w_gate = f32_weight(n_ff, n_embd, 2);
+        let w_up = q8_0_weight(n_ff, n_embd, 3);
+        let w_down = f32_weight(n_embd, n_ff, 4);
+
+        let mut g = Graph::new();
+        let x = g.input(n_embd);
+        let normed = g.rms_norm(x, &norm, 1e-5).unwrap();
+        let gate = g.matmul(&w_gate, normed).unwrap();
+        let up = g.matmul(&w_up, normed).unwrap();
+        let gate = g.silu(gate).unwrap();
+        let act = g.mul(up, gate).unwrap();
+        let down = g.matmul(&w_down, act).unwrap();
+        let out = g.add(x, down).unwrap();
+        g.output(out).unwrap();
+
+        let mut plan = g.compile().unwrap();
+        assert_eq!(
+            plan.step_names(),
+            [
+                "input", "rms_norm", "matmul", "matmul", "silu_mul", "matmul", "add"
+            ]
+        );
+        // x, normed, gate and up are live at the same time; the fused
+        // activation, down projection and residual add reuse their slots.
+        assert_eq!(plan.num_buffers(), 4);
+
+        for seed in [5, 6] {
+            let input = values(n_embd, seed);
+            plan.run(&b, &[&input], 0).unwrap();
+
+            let normed = b.rms_norm(&input, norm.data_f32(), 1e-5, n_embd).unwrap();
+            let gate = b.silu(&eager_matvec(&b, &w_gate, &normed)).unwrap();
+            let act = b.mul(&gate, &eager_matvec(&b, &w_up, &normed)).unwrap();
+            let want = b.add(&input, &eager_matvec(&b, &w_down, &act)).unwrap();
+            assert_close(plan.output(0), &want);
+        }
+    }
+
+    #[test]
+    fn test_fuses_single_use_rms_norm_into_matmul() {
+        let b = CpuBackend::new();
+        let norm = Tensor::new(values(32, 1), Shape::new(vec![32]));
+        let w = q8_0_weight(8, 32, 2);
+
+        let mut g = Graph::new();
+        let x = g.input(32);
+        let normed = g.rms_norm(x, &norm, 1e-5).unwrap();
+        let y = g.matmul(&w, normed).unwrap();
+        g.output(y).unwrap();
+        let mut plan = g.compile().unwrap();
+        assert_eq!(plan.step_names(), ["input", "rms_norm_matmul"]);
+
+        let input = values(32, 3);
+        plan.run(&b, &[&input], 0).unwrap();
+        let normed = b.rms_norm(&input, norm.data_f32(), 1e-5, 32).unwrap();
+        assert_close(plan.output(0), &eager_matvec(&b, &w, &normed));
+    }
+
+    #[test]
+    fn test_f16_weight_matches_f32_reference() {
+        let b = CpuBackend::new();
+        let w = f16_weight(8, 32, 2);
+
+        let mut g = Graph::new();
+        let x = g.input(32);
+        let y = g.matmul(&w, x).unwrap();
+        g.output(y).unwrap();
+        let mut plan = g.compile().unwrap();
+
+        let input = values(32, 3);
+        plan.run(&b, &[&input], 0).unwrap();
+        let w = quant::dequantize(DType::F16, w.raw_bytes().unwrap(), 8 * 32).unwrap();
+        let want = b.matmul(&w, &input, 8, 32, 1).unwrap();
+        assert_close(plan.output(0), &want);
+    }
+
+    #[test]
+    fn test_removes_dead_nodes() {
+        let mut g = Graph::new();
+        let x = g.input(4);
+        let unused = g.gelu(x).unwrap();
+        g.scale(unused, 2.0).unwrap();
+        let y = g.silu(x).unwrap();
+        g.output(y).unwrap();
+        let plan = g.compile().unwrap();
+        assert_eq!(plan.step_names(), ["input", "silu"]);
+    }
+
+    #[test]
+    fn test_elementwise_chain_runs_in_place() {
+        let b = CpuBackend::new();
+        let mut g = Graph::new();
+        let x = g.input(6);
+        let s = g.scale(x, 0.5).unwrap();
+        let a = g.gelu(s).unwrap();
+        let y = g.silu(a).unwrap();
+        g.output(y).unwrap();
+        let mut plan = g.compile().unwrap();
+        assert_eq!(plan.num_buffers(), 1);
+
+        let input = values(6, 1);
+        plan.run(&b, &[&input], 0).unwrap();
+        let mut want = b.scale(&input, 0.5).unwrap();
+        b.gelu_inplace(&mut want).unwrap();
+        b.silu_inplace(&mut want).unwrap();
+        assert_close(plan.output(0), &want);
+        // The input slice itself is never written.
+        assert_eq!(input, values(6, 1));
+    }
+
+    #[test]
+    fn test_outputs_are_not_overwritten() {
+        let b = CpuBackend::new();
+        let mut g = Graph::new();
+        let x = g.input(4);
+        let y = g.input(4);
+        let s = g.silu(x).unwrap();
+        let p = g.mul(s, y).unwrap();
+        let q = g.add(p, p).unwrap();
+        g.output(s).unwrap();
+        g.output(q).unwrap();
+        let mut plan = g.compile().unwrap();
+        // `s` is an output, so silu * y is not fused.
+        assert_eq!(plan.step_names(), ["input", "input", "silu", "mul", "add"]);
+        assert_eq!(plan.num_outputs(), 2);
+
+        let (xs, ys) = (values(4, 1), values(4, 2));
+        plan.run(&b, &[&xs, &ys], 0).unwrap();
+        let s = b.silu(&xs).unwrap();
+        let p = b.mul(&s, &ys).unwrap();
+        assert_close(plan.output(0), &s);
+        assert_close(plan.output(1), &b.add(&p, &p).unwrap());
+    }
+
+    #[test]
+    fn test_rope_uses_run_position() {
+        let b = CpuBackend::new();
+        let mut g = Graph::new();
+        let x = g.input(8);
+        let table = RopeTable::new(RopeConfig::new(4), 8).unwrap();
+        let r = g.rope(x, &table, 2).unwrap();
+        let p = g.softmax(r, 4).unwrap();
+        g.output(p).unwrap();
+        let mut plan = g.compile().unwrap();
+
+        let input = values(8, 1);
+        for pos in [0, 7] {
+            plan.run(&b, &[&input], pos).unwrap();
+            let (rotated, _) = b.rope(&input, &[], &table, pos, 2, 0).unwrap();
+            assert_close(plan.output(0), &b.softmax(&rotated, 4).unwrap());
+        }
+    }
+
+    /// An f32 cache of `n_layers` layers that grows with each append.
+    #[derive(Default)]
+    struct VecKv {
+        layers: Vec<(Vec<f32>, Vec<f32>)>,
+    }
+
+    impl KvStore for VecKv {
+        fn append(&mut self, layer: usize, k: &[f32], v: &[f32], pos: usize) -> Result<()> {
+            if self.layers.len() <= layer {
+                self.layers.resize(layer + 1, Default::default());
+            }
+            let (keys, vals) = &mut self.layers[layer];
+            keys.truncate(pos * k.len());
+            vals.truncate(pos * v.len());
+            keys.extend_from_slice(k);
+            vals.extend_from_slice(v);
+            Ok(())
+        }
+
+        fn attention_into(
+            &self,
+            backend: &dyn ComputeBackend,
+            layer: usize,
+            q: &[f32],
+            pos: usize,
+            params: &AttentionParams,
+            out: &mut [f32],
+        ) -> Result<()> {
+            let (k, v) = &self.layers[layer];
+            let kv = KvView {
+                k,
+                v,
+                n_positions: pos + 1,
+            };
+            backend.attention_into(q, kv, pos, params, out)
+        }
+    }
+
+    #[test]
+    fn test_embedding_looks_up_run_token() {
+        let b = CpuBackend::new();
+        for table in [f32_weight(5, 32, 1), q8_0_weight(5, 32, 1)] {
+            let mut g = Graph::new();
+            let e = g.embedding(&table).unwrap();
+            g.output(e).unwrap();
+            let mut plan = g.compile().unwrap();
+
+            let raw = match table.dtype() {
+                DType::F32 => table.data_f32().to_vec(),
+                dtype => quant::dequantize(dtype, table.raw_bytes().unwrap(), 5 * 32).unwrap(),
+            };
+            for token in [0, 3] {
+                let state = RunState {
+                    token: Some(token),
+                    ..RunState::default()
+                };
+                plan.run_with(&b, &[], state).unwrap();
+                let t = token as usize;
+                assert_eq!(plan.output(0), &raw[t * 32..(t + 1) * 32]);
+            }
+            assert!(plan.run(&b, &[], 0).is_err());
+            let state = RunState {
+                token: Some(5),
+                ..RunState::default()
+            };
+            assert!(plan.run_with(&b, &[], state).is_err());
+        }
+    }
+
+    #[test]
+    fn test_attention_reads_appended_kv() {
+        let b = CpuBackend::new();
+        let params = AttentionParams::new(4, 2, 8);
+        let (w_q, w_k, w_v) = (
+            f32_weight(32, 16, 1),
+            f32_weight(16, 16, 2),
+            f32_weight(16, 16, 3),
+        );
+
+        let mut g = Graph::new();
+        let x = g.input(16);
+        let q = g.matmul(&w_q, x).unwrap();
+        let k = g.matmul(&w_k, x).unwrap();
+        let v = g.matmul(&w_v, x).unwrap();
+        let append = g.kv_append(k, v, 1).unwrap();
+        let att = g.attention(q, append, params).unwrap();
+        g.output(att).unwrap();
+        let mut plan = g.compile().unwrap();
+
+        let (mut kv, mut keys, mut vals) = (VecKv::default(), Vec::new(), Vec::new());
+        for pos in 0..3 {
+            let input = values(16, pos as u32 + 10);
+            let state = RunState {
+                pos,
+                kv: Some(&mut kv),
+                ..RunState::default()
+            };
+            plan.run_with(&b, &[&input], state).unwrap();
+
+            keys.extend(eager_matvec(&b, &w_k, &input));
+            vals.extend(eager_matvec(&b, &w_v, &input));
+            let view = KvView {
+                k: &keys,
+                v: &vals,
+                n_positions: pos + 1,
+            };
+            let mut want = vec![0.0; 32];
+            b.attention_into(
+                &eager_matvec(&b, &w_q, &input),
+                view,
+                pos,
+                &params,
+                &mut want,
+            )
+            .unwrap();
+            assert_close(plan.output(0), &want);
+        }
+        assert_eq!(kv.layers[1].0, keys);
+        assert!(plan.run(&b, &[&values(16, 0)], 0).is_err());
+    }
+
+    #[test]
+    fn test_kv_append_only_graph_keeps_the_append() {
+        let b = CpuBackend::new();
+        let mut g = Graph::new();
+        let x = g.input(8);
+        let k = g.scale(x, 2.0).unwrap();
+        let append = g.kv_append(k, x, 0).unwrap();
+        g.output(append).unwrap();
+        let mut plan = g.compile().unwrap();
+
+        let mut kv = VecKv::default();
+        let input = values(8, 1);
+        let state = RunState {
+            kv: Some(&mut kv),
+            ..RunState::default()
+        };
+        plan.run_with(&b, &[&input], state).unwrap();
+        assert!(plan.output(0).is_empty());
+        assert_eq!(kv.layers[0].1, input);
+        assert_close(
+            &kv.layers[0].0,
+            &input.iter().map(|v| v * 2.0).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_graphs_and_inputs() {
+        let mut g = Graph::new();
+        let x = g.input(4);
+        let y = g.input(3);
+        assert!(g.add(x, y).is_err());
+        assert!(g.matmul(&f32_weight(2, 3, 0), x).is_err());
+        assert!(g.softmax(x, 3).is_err());
+        let table = RopeTable::new(RopeConfig::new(2), 1).unwrap();
+        assert!(g.rope(x, &table, 1).is_err());
+        assert!(g.silu(NodeId(99)).is_err());
+        assert!(g.rms_norm(x, &q8_0_weight(1, 32, 0), 1e-5).is_err());
+        let vector = Tensor::new(values(4, 0), Shape::new(vec![4]));
+        assert!(g.embedding(&vector).is_err());
+        let (mismatched, matching) = (AttentionParams::new(1, 1, 2), AttentionParams::new(2, 2, 2));
+        assert!(g.attention(x, x, matching).is_err());
+        let append = g.kv_append(x, x, 0).unwrap();
+        assert!(g.attention(x, append, mismatched).is_err());
+        assert!(g.attention(x, append, matching).is_ok());
+        let norm = Tensor::new(values(2, 0), Shape::new(vec![2]));
+        assert!(g.rms_norm(x, &norm, 1e-5).is_ok());
+        assert!(g.clone().compile().is_err());
+
+        g.output(x).unwrap();
+        let mut plan = g.compile().unwrap();
+        let b = CpuBackend::new();
+        assert!(plan.run(&b, &[&[0.0; 4]], 0).is_err());
+        assert!(plan.run(&b, &[&[0.0; 4], &[0.0; 4]], 0).is_err());
+        assert!(plan.run(&b, &[&[0.0; 4], &[0.0; 3]], 0).is_ok());
+    }
+}
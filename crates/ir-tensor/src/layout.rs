@@ -0,0 +1,408 @@
+use crate::error::{Result, TensorError};
+use crate::shape::Shape;
+
+/// How a tensor's logical elements map onto its storage.
+///
+/// Element `[i0, i1, ..]` lives at storage index
+/// `offset + i0 * strides[0] + i1 * strides[1] + ..`. A freshly created
+/// tensor is contiguous (row-major strides, zero offset); view operations
+/// such as `transpose` and `narrow` only rewrite the layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    shape: Shape,
+    strides: Vec<usize>,
+    offset: usize,
+}
+
+impl Layout {
+    /// A contiguous row-major layout for `shape` starting at index 0.
+    pub fn contiguous(shape: Shape) -> Self {
+        let strides = shape.strides();
+        Layout {
+            shape,
+            strides,
+            offset: 0,
+        }
+    }
+
+    /// The same layout starting at storage index `offset`.
+    pub(crate) fn with_offset(mut self, offset: usize) -> Layout {
+        self.offset = offset;
+        self
+    }
+
+    /// The logical shape.
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    /// Storage step per dimension, in elements.
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    /// Storage index of the first element.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns true if the elements are laid out row-major without gaps.
+    ///
+    /// Dimensions of size 1 may have any stride, since they are never
+    /// stepped over.
+    pub fn is_contiguous(&self) -> bool {
+        let mut expected = 1;
+        for (&dim, &stride) in self.shape.dims().iter().zip(&self.strides).rev() {
+            if dim != 1 && stride != expected {
+                return false;
+            }
+            expected *= dim;
+        }
+        true
+    }
+
+    /// Swap dimensions `dim0` and `dim1`.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Layout> {
+        self.check_dim(dim0)?;
+        self.check_dim(dim1)?;
+        let mut perm: Vec<usize> = (0..self.shape.ndim()).collect();
+        perm.swap(dim0, dim1);
+        self.permute(&perm)
+    }
+
+    /// Reorder dimensions so that output dimension `i` is input dimension
+    /// `perm[i]`.
+    pub fn permute(&self, perm: &[usize]) -> Result<Layout> {
+        let ndim = self.shape.ndim();
+        let mut seen = vec![false; ndim];
+        if perm.len() != ndim {
+            return Err(TensorError::Other(format!(
+                "permute: {} dims given for a tensor with {} dimensions",
+                perm.len(),
+                ndim
+            )));
+        }
+        for &p in perm {
+            self.check_dim(p)?;
+            if std::mem::replace(&mut seen[p], true) {
+                return Err(TensorError::Other(format!(
+                    "permute: dimension {} appears more than once in {:?}",
+                    p, perm
+                )));
+            }
+        }
+        let dims = self.shape.dims();
+        Ok(Layout {
+            shape: Shape::new(perm.iter().map(|&p| dims[p]).collect()),
+            strides: perm.iter().map(|&p| self.strides[p]).collect(),
+            offset: self.offset,
+        })
+    }
+
+    /// Keep `len` elements of dimension `dim`, starting at `start`.
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Layout> {
+        self.check_dim(dim)?;
+        let size = self.shape.dim(dim);
+        if start.checked_add(len).is_none_or(|end| end > size) {
+            return Err(TensorError::Other(format!(
+                "narrow: range {}..{} out of bounds for dimension {} of size {}",
+                start,
+                start.saturating_add(len),
+                dim,
+                size
+            )));
+        }
+        let mut dims = self.shape.dims().to_vec();
+        dims[dim] = len;
+        Ok(Layout {
+            shape: Shape::new(dims),
+            strides: self.strides.clone(),
+            offset: self.offset + start * self.strides[dim],
+        })
+    }
+
+    /// Remove dimension `dim`, which must have size 1.
+    pub fn squeeze(&self, dim: usize) -> Result<Layout> {
+        self.check_dim(dim)?;
+        if self.shape.dim(dim) != 1 {
+            return Err(TensorError::Other(format!(
+                "squeeze: dimension {} has size {}, not 1",
+                dim,
+                self.shape.dim(dim)
+            )));
+        }
+        let mut dims = self.shape.dims().to_vec();
+        let mut strides = self.strides.clone();
+        dims.remove(dim);
+        strides.remove(dim);
+        Ok(Layout {
+            shape: Shape::new(dims),
+            strides,
+            offset: self.offset,
+        })
+    }
+
+    /// Insert a dimension of size 1 at position `dim` (0..=ndim).
+    pub fn unsqueeze(&self, dim: usize) -> Result<Layout> {
+        let ndim = self.shape.ndim();
+        if dim > ndim {
+            return Err(TensorError::InvalidAxis {
+                axis: dim,
+                ndim: ndim + 1,
+            });
+        }
+        // The stride of a size-1 dimension is never used; pick the one that
+        // keeps a contiguous layout contiguous.
+        let stride = if dim < ndim {
+            self.strides[dim] * self.shape.dim(dim)
+        } else {
+            1
+        };
+        let mut dims = self.shape.dims().to_vec();
+        let mut strides = self.strides.clone();
+        dims.insert(dim, 1);
+        strides.insert(dim, stride);
+        Ok(Layout {
+            shape: Shape::new(dims),
+            strides,
+            offset: self.offset,
+        })
+    }
+
+    /// View this layout as `shape` under numpy-style broadcasting.
+    ///
+    /// Missing leading dimensions and dimensions of size 1 that `shape`
+    /// expands get stride 0, so every position along them reads the same
+    /// element.
+    pub fn broadcast_as(&self, shape: &Shape) -> Result<Layout> {
+        let dims = self.shape.dims();
+        let target = shape.dims();
+        let error = || TensorError::BroadcastError {
+            a: dims.to_vec(),
+            b: target.to_vec(),
+        };
+        if dims.len() > target.len() {
+            return Err(error());
+        }
+        let pad = target.len() - dims.len();
+        let mut strides = vec![0; target.len()];
+        for (i, (&d, &stride)) in dims.iter().zip(&self.strides).enumerate() {
+            if d == target[pad + i] {
+                strides[pad + i] = stride;
+            } else if d != 1 {
+                return Err(error());
+            }
+        }
+        Ok(Layout {
+            shape: shape.clone(),
+            strides,
+            offset: self.offset,
+        })
+    }
+
+    /// Smallest storage length that holds every element of this layout.
+    pub fn min_storage_len(&self) -> usize {
+        if self.shape.numel() == 0 {
+            return 0;
+        }
+        let last: usize = self
+            .shape
+            .dims()
+            .iter()
+            .zip(&self.strides)
+            .map(|(&d, &s)| (d - 1) * s)
+            .sum();
+        self.offset + last + 1
+    }
+
+    /// Storage index of the first element of row `row`, where rows are the
+    /// innermost dimension and are counted in row-major order.
+    pub fn row_start(&self, row: usize) -> usize {
+        let dims = self.shape.dims();
+        let outer = dims.len().saturating_sub(1);
+        let mut rest = row;
+        let mut start = self.offset;
+        for (&d, &stride) in dims[..outer].iter().zip(&self.strides).rev() {
+            start += rest % d * stride;
+            rest /= d;
+        }
+        start
+    }
+
+    /// Storage indices of every element, in row-major logical order.
+    pub fn strided_index(&self) -> StridedIndex<'_> {
+        StridedIndex::new(self)
+    }
+
+    fn check_dim(&self, dim: usize) -> Result<()> {
+        if dim >= self.shape.ndim() {
+            return Err(TensorError::InvalidAxis {
+                axis: dim,
+                ndim: self.shape.ndim(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the storage indices of a [`Layout`], in row-major logical
+/// order.
+#[derive(Debug)]
+pub struct StridedIndex<'a> {
+    dims: &'a [usize],
+    strides: &'a [usize],
+    /// Current multi-dimensional index, or `None` once exhausted.
+    index: Option<Vec<usize>>,
+    next: usize,
+}
+
+impl<'a> StridedIndex<'a> {
+    fn new(layout: &'a Layout) -> Self {
+        let dims = layout.shape.dims();
+        let empty = dims.contains(&0);
+        StridedIndex {
+            dims,
+            strides: &layout.strides,
+            index: (!empty).then(|| vec![0; dims.len()]),
+            next: layout.offset,
+        }
+    }
+}
+
+impl Iterator for StridedIndex<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let index = self.index.as_mut()?;
+        let current = self.next;
+        // Advance like an odometer, innermost dimension first.
+        let mut d = index.len();
+        loop {
+            if d == 0 {
+                self.index = None;
+                break;
+            }
+            d -= 1;
+            index[d] += 1;
+            self.next += self.strides[d];
+            if index[d] < self.dims[d] {
+                break;
+            }
+            self.next -= self.strides[d] * index[d];
+            index[d] = 0;
+        }
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(dims: &[usize]) -> Layout {
+        Layout::contiguous(Shape::from_slice(dims))
+    }
+
+    #[test]
+    fn test_contiguous() {
+        let l = layout(&[2, 3, 4]);
+        assert_eq!(l.strides(), &[12, 4, 1]);
+        assert!(l.is_contiguous());
+        assert_eq!(
+            l.strided_index().collect::<Vec<_>>(),
+            (0..24).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_transpose() {
+        let t = layout(&[2, 3]).transpose(0, 1).unwrap();
+        assert_eq!(t.shape().dims(), &[3, 2]);
+        assert_eq!(t.strides(), &[1, 3]);
+        assert!(!t.is_contiguous());
+        assert_eq!(
+            t.strided_index().collect::<Vec<_>>(),
+            vec![0, 3, 1, 4, 2, 5]
+        );
+        assert!(layout(&[2, 3]).transpose(0, 2).is_err());
+    }
+
+    #[test]
+    fn test_permute() {
+        let p = layout(&[2, 3, 4]).permute(&[2, 0, 1]).unwrap();
+        assert_eq!(p.shape().dims(), &[4, 2, 3]);
+        assert_eq!(p.strides(), &[1, 12, 4]);
+        assert!(layout(&[2, 3]).permute(&[0, 0]).is_err());
+        assert!(layout(&[2, 3]).permute(&[0]).is_err());
+    }
+
+    #[test]
+    fn test_narrow() {
+        let n = layout(&[4, 5]).narrow(1, 1, 3).unwrap();
+        assert_eq!(n.shape().dims(), &[4, 3]);
+        assert_eq!(n.offset(), 1);
+        assert!(!n.is_contiguous());
+        assert_eq!(
+            n.strided_index().take(4).collect::<Vec<_>>(),
+            vec![1, 2, 3, 6]
+        );
+
+        // Narrowing the outer dimension keeps the view contiguous.
+        let rows = layout(&[4, 5]).narrow(0, 2, 2).unwrap();
+        assert!(rows.is_contiguous());
+        assert_eq!(rows.offset(), 10);
+
+        assert!(layout(&[4, 5]).narrow(1, 3, 3).is_err());
+        assert!(layout(&[4, 5]).narrow(2, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_squeeze_unsqueeze() {
+        let l = layout(&[3, 4]);
+        let u = l.unsqueeze(0).unwrap();
+        assert_eq!(u.shape().dims(), &[1, 3, 4]);
+        assert!(u.is_contiguous());
+        let u2 = l.unsqueeze(2).unwrap();
+        assert_eq!(u2.shape().dims(), &[3, 4, 1]);
+        assert!(u2.is_contiguous());
+        assert_eq!(u.squeeze(0).unwrap(), l);
+        assert!(l.squeeze(0).is_err());
+        assert!(l.unsqueeze(3).is_err());
+    }
+
+    #[test]
+    fn test_strided_index_edge_cases() {
+        // A scalar has exactly one element.
+        assert_eq!(layout(&[]).strided_index().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(layout(&[2, 0, 3]).strided_index().count(), 0);
+    }
+
+    #[test]
+    fn test_broadcast_as() {
+        let bias = layout(&[3]).broadcast_as(&Shape::new(vec![2, 3])).unwrap();
+        assert_eq!(bias.strides(), &[0, 1]);
+        assert_eq!(
+            bias.strided_index().collect::<Vec<_>>(),
+            vec![0, 1, 2, 0, 1, 2]
+        );
+
+        let col = layout(&[2, 1])
+            .broadcast_as(&Shape::new(vec![2, 3]))
+            .unwrap();
+        assert_eq!(col.strides(), &[1, 0]);
+        assert_eq!(col.min_storage_len(), 2);
+
+        assert!(layout(&[2, 3]).broadcast_as(&Shape::new(vec![3])).is_err());
+        assert!(layout(&[2]).broadcast_as(&Shape::new(vec![2, 3])).is_err());
+    }
+
+    #[test]
+    fn test_row_start() {
+        let t = layout(&[2, 3, 4]).transpose(0, 1).unwrap();
+        // Rows of the [3, 2, 4] view start where t[i][j][0] lives.
+        let starts: Vec<usize> = (0..6).map(|r| t.row_start(r)).collect();
+        assert_eq!(starts, vec![0, 12, 4, 16, 8, 20]);
+        assert_eq!(t.min_storage_len(), 24);
+        assert_eq!(layout(&[]).row_start(0), 0);
+    }
+}
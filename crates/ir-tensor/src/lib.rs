@@ -1,27 +1,55 @@
 //! `ir-tensor` - Tensor library with pluggable compute backends for inference-runtime.
 //!
 //! This crate provides:
-//! - A `Tensor` type backed by CPU storage
+//! - A `Tensor` type backed by CPU storage (f32, f16, or quantized blocks)
 //! - A `ComputeBackend` trait for pluggable compute (CPU, Metal, etc.)
 //! - A reference `CpuBackend` implementation
 //! - Shape utilities and broadcasting
+//! - Strided layouts, so views such as transposes and slices share storage
+//! - An optional lazy compute graph with operator fusion and buffer reuse
+//! - A `ValidatingBackend` that cross-checks one backend against another
+//! - A `ProfilingBackend` that times ops and exports Chrome traces
+//! - Capability queries, and a `FallbackBackend` that routes each op to the
+//!   first backend supporting it
 //! - Data type definitions (F32, F16, quantized formats)
+//! - Quantize and dequantize routines for every data type
 
 pub mod backend;
 pub mod cpu;
 pub mod dtype;
 pub mod error;
+pub mod fallback;
+pub mod graph;
+pub mod layout;
 #[cfg(feature = "metal")]
 pub mod metal;
+mod ops;
+pub mod profile;
+pub mod quant;
+pub mod rope;
 pub mod shape;
 pub mod storage;
 pub mod tensor;
+#[cfg(test)]
+mod test_util;
+pub mod validate;
 
 // Re-export primary types at the crate root for convenience.
-pub use backend::ComputeBackend;
+pub use backend::{
+    AttentionMask, AttentionParams, BackendOp, BinaryOp, Capabilities, ComputeBackend, KvView,
+    KvViewF16, MatmulParams, ReduceOp,
+};
 pub use cpu::CpuBackend;
+pub use cpu::reduce::Reduction;
+pub use cpu::simd::{KernelVariant, SimdLevel};
 pub use dtype::DType;
 pub use error::{Result, TensorError};
+pub use fallback::FallbackBackend;
+pub use graph::{CompiledGraph, Graph, KvStore, NodeId, RunState};
+pub use layout::{Layout, StridedIndex};
+pub use profile::{OpProfile, ProfilingBackend};
+pub use rope::{RopeConfig, RopeScaling, RopeStyle, RopeTable};
 pub use shape::Shape;
-pub use storage::CpuStorage;
+pub use storage::{ByteBuffer, CpuStorage, SharedBytes};
 pub use tensor::Tensor;
+pub use validate::{Divergence, OnDivergence, Tolerance, ValidatingBackend};
@@ -0,0 +1,537 @@
+// Transformer operators on `Tensor`.
+//
+// Each method validates dtypes and shapes, then hands flat slices to the
+// backend. Inputs may be views; they are read by stride or materialized as
+// needed, and results are always new contiguous tensors.
+
+use crate::backend::{ComputeBackend, ReduceOp};
+use crate::dtype::DType;
+use crate::error::{Result, TensorError};
+use crate::rope::RopeTable;
+use crate::shape::Shape;
+use crate::tensor::Tensor;
+
+impl Tensor {
+    /// RMS normalization over the last dimension, scaled by `weight`.
+    ///
+    /// `weight` must be a 1-D tensor as long as the last dimension.
+    pub fn rms_norm(
+        &self,
+        weight: &Tensor,
+        eps: f32,
+        backend: &dyn ComputeBackend,
+    ) -> Result<Tensor> {
+        let hidden = self.last_dim("rms_norm")?;
+        require_f32(weight)?;
+        require_vector(weight, hidden, "rms_norm weight")?;
+
+        let x = self.contiguous()?;
+        let w = weight.contiguous()?;
+        let out = backend.rms_norm(x.data_f32(), w.data_f32(), eps, hidden)?;
+        Ok(Tensor::new(out, self.shape().clone()))
+    }
+
+    /// Layer normalization over the last dimension:
+    /// `(x - mean) / sqrt(var + eps) * weight + bias`.
+    ///
+    /// `weight` and `bias` must be 1-D tensors as long as the last dimension.
+    pub fn layer_norm(
+        &self,
+        weight: &Tensor,
+        bias: Option<&Tensor>,
+        eps: f32,
+        backend: &dyn ComputeBackend,
+    ) -> Result<Tensor> {
+        let hidden = self.last_dim("layer_norm")?;
+        require_f32(weight)?;
+        require_vector(weight, hidden, "layer_norm weight")?;
+        let bias = match bias {
+            Some(bias) => {
+                require_f32(bias)?;
+                require_vector(bias, hidden, "layer_norm bias")?;
+                Some(bias.contiguous()?)
+            }
+            None => None,
+        };
+
+        let x = self.contiguous()?;
+        let w = weight.contiguous()?;
+        let out = backend.layer_norm(
+            x.data_f32(),
+            w.data_f32(),
+            bias.as_ref().map(|b| b.data_f32()),
+            eps,
+            hidden,
+        )?;
+        Ok(Tensor::new(out, self.shape().clone()))
+    }
+
+    /// Softmax along dimension `dim`.
+    pub fn softmax(&self, dim: usize, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        require_f32(self)?;
+        let ndim = self.shape().ndim();
+        if dim >= ndim {
+            return Err(TensorError::InvalidAxis { axis: dim, ndim });
+        }
+        let n = self.shape().dim(dim);
+        if n == 0 {
+            return self.contiguous();
+        }
+
+        // The backend normalizes contiguous rows, so bring `dim` last.
+        let last = ndim - 1;
+        let x = self.transpose(dim, last)?.contiguous()?;
+        let out = backend.softmax(x.data_f32(), n)?;
+        Tensor::new(out, x.shape().clone())
+            .transpose(dim, last)?
+            .contiguous()
+    }
+
+    /// Rotary position embedding for `[n_heads, head_dim]` (one token) or
+    /// `[n_tokens, n_heads, head_dim]`, where token `t` sits at position
+    /// `pos + t`. `head_dim` must match the table.
+    pub fn rope(
+        &self,
+        table: &RopeTable,
+        pos: usize,
+        backend: &dyn ComputeBackend,
+    ) -> Result<Tensor> {
+        require_f32(self)?;
+        let dims = self.shape().dims();
+        let (n_heads, head_dim) = match *dims {
+            [h, d] | [_, h, d] => (h, d),
+            _ => {
+                return Err(TensorError::Other(format!(
+                    "rope: expected [n_heads, head_dim] or [n_tokens, n_heads, head_dim], got {}",
+                    self.shape()
+                )));
+            }
+        };
+        if head_dim != table.config().head_dim {
+            return Err(TensorError::Other(format!(
+                "rope: head_dim={} but the table is for head_dim={}",
+                head_dim,
+                table.config().head_dim
+            )));
+        }
+
+        let mut data = self.to_vec_f32()?;
+        // With no heads there is nothing to rotate, whatever the token count.
+        if n_heads * head_dim > 0 {
+            for (t, token) in data.chunks_exact_mut(n_heads * head_dim).enumerate() {
+                backend.rope_inplace(token, &mut [], table, pos + t, n_heads, 0)?;
+            }
+        }
+        Ok(Tensor::new(data, self.shape().clone()))
+    }
+
+    /// Element-wise SiLU activation.
+    pub fn silu(&self, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.map_f32(|x| backend.silu_inplace(x))
+    }
+
+    /// Element-wise GELU activation (tanh approximation).
+    pub fn gelu(&self, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.map_f32(|x| backend.gelu_inplace(x))
+    }
+
+    /// Element-wise exact (erf) GELU activation.
+    pub fn gelu_erf(&self, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.map_f32(|x| backend.gelu_erf_inplace(x))
+    }
+
+    /// GeGLU gating, `gelu(self) * up`, for tensors of the same shape.
+    pub fn geglu(&self, up: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        require_f32(up)?;
+        if up.shape() != self.shape() {
+            return Err(TensorError::ShapeMismatch {
+                expected: self.shape().dims().to_vec(),
+                got: up.shape().dims().to_vec(),
+            });
+        }
+        let up = up.contiguous()?;
+        self.map_f32(|x| backend.geglu_inplace(x, up.data_f32()))
+    }
+
+    /// Element-wise squared ReLU.
+    pub fn relu2(&self, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.map_f32(|x| backend.relu2_inplace(x))
+    }
+
+    /// Element-wise tanh soft-cap, `cap * tanh(x / cap)`.
+    pub fn softcap(&self, cap: f32, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.map_f32(|x| backend.softcap_inplace(x, cap))
+    }
+
+    /// Join `tensors` along dimension `dim`.
+    ///
+    /// All tensors must be F32 with the same rank and the same size in every
+    /// other dimension.
+    pub fn concat(tensors: &[&Tensor], dim: usize) -> Result<Tensor> {
+        let first = tensors
+            .first()
+            .ok_or_else(|| TensorError::Other("concat: no tensors given".to_string()))?;
+        let dims = first.shape().dims();
+        if dim >= dims.len() {
+            return Err(TensorError::InvalidAxis {
+                axis: dim,
+                ndim: dims.len(),
+            });
+        }
+        for t in tensors {
+            require_f32(t)?;
+            let other = t.shape().dims();
+            let compatible = other.len() == dims.len()
+                && other
+                    .iter()
+                    .zip(dims)
+                    .enumerate()
+                    .all(|(d, (a, b))| d == dim || a == b);
+            if !compatible {
+                return Err(TensorError::ShapeMismatch {
+                    expected: dims.to_vec(),
+                    got: other.to_vec(),
+                });
+            }
+        }
+
+        let outer: usize = dims[..dim].iter().product();
+        let inner: usize = dims[dim + 1..].iter().product();
+        let parts = tensors
+            .iter()
+            .map(|t| t.contiguous())
+            .collect::<Result<Vec<_>>>()?;
+        let mut out_dims = dims.to_vec();
+        out_dims[dim] = parts.iter().map(|t| t.shape().dim(dim)).sum();
+        let out_shape = Shape::new(out_dims);
+
+        let mut out = Vec::with_capacity(out_shape.numel());
+        for o in 0..outer {
+            for part in &parts {
+                let run = part.shape().dim(dim) * inner;
+                out.extend_from_slice(&part.data_f32()[o * run..(o + 1) * run]);
+            }
+        }
+        Ok(Tensor::new(out, out_shape))
+    }
+
+    /// Split dimension `dim` into consecutive views of the given sizes,
+    /// which must add up to the size of `dim`.
+    pub fn split(&self, sizes: &[usize], dim: usize) -> Result<Vec<Tensor>> {
+        let ndim = self.shape().ndim();
+        if dim >= ndim {
+            return Err(TensorError::InvalidAxis { axis: dim, ndim });
+        }
+        let total: usize = sizes.iter().sum();
+        if total != self.shape().dim(dim) {
+            return Err(TensorError::Other(format!(
+                "split: sizes {:?} add up to {}, but dimension {} has size {}",
+                sizes,
+                total,
+                dim,
+                self.shape().dim(dim)
+            )));
+        }
+        let mut start = 0;
+        sizes
+            .iter()
+            .map(|&len| {
+                let part = self.narrow(dim, start, len);
+                start += len;
+                part
+            })
+            .collect()
+    }
+
+    /// Look up rows of a `[n_vocab, n_embd]` embedding table.
+    ///
+    /// Returns `[ids.len(), n_embd]` in the table's dtype, so rows of a
+    /// quantized table stay quantized.
+    pub fn embedding(&self, ids: &[u32]) -> Result<Tensor> {
+        if self.shape().ndim() != 2 {
+            return Err(TensorError::Other(format!(
+                "embedding: table must be 2-D, got {}",
+                self.shape()
+            )));
+        }
+        let rows: Vec<usize> = ids.iter().map(|&id| id as usize).collect();
+        self.index_select(0, &rows)
+    }
+
+    /// Sum along dimension `dim`, which is removed from the result.
+    pub fn sum(&self, dim: usize, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.reduce(ReduceOp::Sum, dim, backend)
+    }
+
+    /// Mean along dimension `dim`, which is removed from the result.
+    pub fn mean(&self, dim: usize, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.reduce(ReduceOp::Mean, dim, backend)
+    }
+
+    /// Maximum along dimension `dim`, which is removed from the result.
+    pub fn max(&self, dim: usize, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.reduce(ReduceOp::Max, dim, backend)
+    }
+
+    /// Index of the maximum along dimension `dim` for every position of the
+    /// other dimensions, in row-major order. Ties resolve to the first index.
+    pub fn argmax(&self, dim: usize, backend: &dyn ComputeBackend) -> Result<Vec<usize>> {
+        let idx = self.reduce(ReduceOp::ArgMax, dim, backend)?;
+        Ok(idx.data_f32().iter().map(|&i| i as usize).collect())
+    }
+
+    fn reduce(&self, op: ReduceOp, dim: usize, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        require_f32(self)?;
+        let ndim = self.shape().ndim();
+        if dim >= ndim {
+            return Err(TensorError::InvalidAxis { axis: dim, ndim });
+        }
+        let mut out_dims = self.shape().dims().to_vec();
+        out_dims.remove(dim);
+        let out_shape = Shape::new(out_dims);
+        let mut out = vec![0.0f32; out_shape.numel()];
+        backend.reduce_into(
+            op,
+            self.storage().as_f32_slice()?,
+            self.layout(),
+            dim,
+            &mut out,
+        )?;
+        Ok(Tensor::new(out, out_shape))
+    }
+
+    /// Copy the elements out and apply an in-place backend op to them.
+    fn map_f32(&self, f: impl FnOnce(&mut [f32]) -> Result<()>) -> Result<Tensor> {
+        require_f32(self)?;
+        let mut data = self.to_vec_f32()?;
+        f(&mut data)?;
+        Ok(Tensor::new(data, self.shape().clone()))
+    }
+
+    /// Size of the last dimension of an F32 tensor with at least one
+    /// dimension.
+    fn last_dim(&self, op: &str) -> Result<usize> {
+        require_f32(self)?;
+        self.shape()
+            .dims()
+            .last()
+            .copied()
+            .ok_or_else(|| TensorError::Other(format!("{}: input must have a dimension", op)))
+    }
+}
+
+fn require_f32(t: &Tensor) -> Result<()> {
+    if t.dtype() != DType::F32 {
+        return Err(TensorError::DTypeMismatch {
+            expected: DType::F32.to_string(),
+            got: t.dtype().to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn require_vector(t: &Tensor, len: usize, what: &str) -> Result<()> {
+    if t.shape().dims() != [len] {
+        return Err(TensorError::Other(format!(
+            "{}: expected shape [{}], got {}",
+            what,
+            len,
+            t.shape()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CpuBackend;
+    use crate::rope::RopeConfig;
+    use crate::storage::CpuStorage;
+    use crate::test_util::assert_close;
+
+    fn t(data: &[f32], dims: &[usize]) -> Tensor {
+        Tensor::new(data.to_vec(), Shape::from_slice(dims))
+    }
+
+    fn arange(dims: &[usize]) -> Tensor {
+        let shape = Shape::from_slice(dims);
+        Tensor::new((0..shape.numel()).map(|v| v as f32).collect(), shape)
+    }
+
+    #[test]
+    fn test_rms_norm() {
+        let b = CpuBackend::new();
+        let x = t(&[1.0, 2.0, 3.0, 4.0, -1.0, -1.0], &[2, 3])
+            .narrow(1, 0, 2)
+            .unwrap();
+        let w = t(&[1.0, 2.0], &[2]);
+        let r = x.rms_norm(&w, 0.0, &b).unwrap();
+        let (r0, r1) = ((2.5f32).sqrt(), (8.5f32).sqrt());
+        assert_close(r.data_f32(), &[1.0 / r0, 4.0 / r0, 4.0 / r1, -2.0 / r1]);
+        assert!(x.rms_norm(&t(&[1.0; 3], &[3]), 0.0, &b).is_err());
+    }
+
+    #[test]
+    fn test_layer_norm() {
+        let b = CpuBackend::new();
+        let x = t(&[1.0, 2.0, 3.0, 10.0, 10.0, 10.0], &[2, 3]);
+        let w = t(&[1.0, 1.0, 2.0], &[3]);
+        let bias = t(&[0.0, 1.0, 0.0], &[3]);
+        let r = x.layer_norm(&w, Some(&bias), 0.0, &b).unwrap();
+        let s = (2.0f32 / 3.0).sqrt();
+        assert_close(&r.data_f32()[..3], &[-1.0 / s, 1.0, 2.0 / s]);
+        // A constant row normalizes to zero (plus bias) with eps > 0.
+        let r = x.layer_norm(&w, Some(&bias), 1e-5, &b).unwrap();
+        assert_close(&r.data_f32()[3..], &[0.0, 1.0, 0.0]);
+        assert!(x.layer_norm(&w, Some(&t(&[0.0], &[1])), 0.0, &b).is_err());
+    }
+
+    #[test]
+    fn test_softmax_dims() {
+        let b = CpuBackend::new();
+        let x = arange(&[2, 3]);
+        let last = x.softmax(1, &b).unwrap();
+        let first = x.softmax(0, &b).unwrap();
+        let e = [1.0f32, 1f32.exp(), 2f32.exp()];
+        let sum: f32 = e.iter().sum();
+        assert_close(&last.data_f32()[..3], &[e[0] / sum, e[1] / sum, e[2] / sum]);
+        // Down each column the values differ by 3.
+        let p = 1.0 / (1.0 + 3f32.exp());
+        assert_close(first.data_f32(), &[p, p, p, 1.0 - p, 1.0 - p, 1.0 - p]);
+        assert!(x.softmax(2, &b).is_err());
+    }
+
+    #[test]
+    fn test_rope_matches_backend() {
+        let b = CpuBackend::new();
+        let x = arange(&[2, 2, 4]);
+        let table = RopeTable::new(RopeConfig::new(4), 8).unwrap();
+        let r = x.rope(&table, 5, &b).unwrap();
+        for tok in 0..2 {
+            let token = x.narrow(0, tok, 1).unwrap().squeeze(0).unwrap();
+            let (want, _) = b
+                .rope(token.data_f32(), &[], &table, 5 + tok, 2, 0)
+                .unwrap();
+            assert_eq!(&r.data_f32()[tok * 8..(tok + 1) * 8], want.as_slice());
+        }
+        assert!(arange(&[2, 3]).rope(&table, 0, &b).is_err());
+        assert!(arange(&[8]).rope(&table, 0, &b).is_err());
+        // Position 8 is past the end of the table.
+        assert!(x.rope(&table, 7, &b).is_err());
+    }
+
+    #[test]
+    fn test_rope_empty() {
+        let b = CpuBackend::new();
+        let table = RopeTable::new(RopeConfig::new(4), 8).unwrap();
+        for dims in [&[0, 4][..], &[0, 2, 4], &[3, 0, 4]] {
+            let x = Tensor::zeros(Shape::from_slice(dims));
+            let r = x.rope(&table, 0, &b).unwrap();
+            assert_eq!(r.shape().dims(), dims);
+        }
+    }
+
+    #[test]
+    fn test_activations() {
+        let b = CpuBackend::new();
+        let x = t(&[-1.0, 0.0, 1.0], &[3]);
+        let s = x.silu(&b).unwrap();
+        assert_close(s.data_f32(), &[-0.268_941_4, 0.0, 0.731_058_6]);
+        let g = x.gelu(&b).unwrap();
+        assert_close(g.data_f32(), &[-0.158_808, 0.0, 0.841_192]);
+    }
+
+    #[test]
+    fn test_extra_activations() {
+        let b = CpuBackend::new();
+        let x = t(&[-2.0, -0.5, 0.0, 1.5], &[2, 2]);
+        let up = t(&[1.0, 2.0, 3.0, 4.0], &[2, 2]);
+
+        let g = x.geglu(&up, &b).unwrap();
+        let gelu = x.gelu(&b).unwrap();
+        let want: Vec<f32> = gelu
+            .data_f32()
+            .iter()
+            .zip(up.data_f32())
+            .map(|(g, u)| g * u)
+            .collect();
+        assert_close(g.data_f32(), &want);
+        assert!(x.geglu(&t(&[1.0; 4], &[4]), &b).is_err());
+
+        assert_eq!(x.relu2(&b).unwrap().data_f32(), &[0.0, 0.0, 0.0, 2.25]);
+        let capped = x.softcap(1.0, &b).unwrap();
+        assert_close(
+            capped.data_f32(),
+            &x.data_f32().iter().map(|v| v.tanh()).collect::<Vec<_>>(),
+        );
+        assert!(x.softcap(0.0, &b).is_err());
+        assert_close(&x.gelu_erf(&b).unwrap().data_f32()[3..], &[1.399_789_2]);
+    }
+
+    #[test]
+    fn test_concat_split() {
+        let a = arange(&[2, 2]);
+        let c = t(&[9.0, 8.0], &[2, 1]);
+        let j = Tensor::concat(&[&a, &c], 1).unwrap();
+        assert_eq!(j.shape().dims(), &[2, 3]);
+        assert_eq!(j.data_f32(), &[0.0, 1.0, 9.0, 2.0, 3.0, 8.0]);
+
+        let parts = j.split(&[2, 1], 1).unwrap();
+        assert_eq!(parts[0].to_vec_f32().unwrap(), a.data_f32());
+        assert_eq!(parts[1].to_vec_f32().unwrap(), c.data_f32());
+
+        let rows = Tensor::concat(&[&a, &a.transpose(0, 1).unwrap()], 0).unwrap();
+        assert_eq!(rows.data_f32(), &[0.0, 1.0, 2.0, 3.0, 0.0, 2.0, 1.0, 3.0]);
+
+        assert!(Tensor::concat(&[&a, &c], 0).is_err());
+        assert!(Tensor::concat(&[], 0).is_err());
+        assert!(j.split(&[1, 1], 1).is_err());
+    }
+
+    #[test]
+    fn test_embedding() {
+        let table = arange(&[4, 2]);
+        let e = table.embedding(&[2, 0]).unwrap();
+        assert_eq!(e.data_f32(), &[4.0, 5.0, 0.0, 1.0]);
+        assert!(table.embedding(&[4]).is_err());
+        assert!(arange(&[4]).embedding(&[0]).is_err());
+    }
+
+    #[test]
+    fn test_reductions() {
+        let b = CpuBackend::new();
+        let x = t(&[1.0, 5.0, 3.0, 4.0, 2.0, 6.0], &[2, 3]);
+        assert_eq!(x.sum(1, &b).unwrap().data_f32(), &[9.0, 12.0]);
+        assert_eq!(x.sum(0, &b).unwrap().data_f32(), &[5.0, 7.0, 9.0]);
+        assert_eq!(x.mean(1, &b).unwrap().data_f32(), &[3.0, 4.0]);
+        assert_eq!(x.max(0, &b).unwrap().data_f32(), &[4.0, 5.0, 6.0]);
+        assert_eq!(x.argmax(1, &b).unwrap(), vec![1, 2]);
+        assert_eq!(x.argmax(0, &b).unwrap(), vec![1, 0, 1]);
+
+        // Views are reduced by stride.
+        let xt = x.transpose(0, 1).unwrap();
+        assert_eq!(xt.sum(1, &b).unwrap().data_f32(), &[5.0, 7.0, 9.0]);
+
+        assert!(x.sum(2, &b).is_err());
+        let empty = Tensor::zeros(Shape::new(vec![2, 0]));
+        assert_eq!(empty.sum(1, &b).unwrap().data_f32(), &[0.0, 0.0]);
+        assert!(empty.max(1, &b).is_err());
+    }
+
+    #[test]
+    fn test_ops_reject_quantized() {
+        let b = CpuBackend::new();
+        let q = Tensor::from_storage(
+            CpuStorage::zeros(DType::Q8_0, 32).unwrap(),
+            Shape::new(vec![1, 32]),
+        )
+        .unwrap();
+        let w = Tensor::ones(Shape::new(vec![32]));
+        assert!(q.rms_norm(&w, 1e-5, &b).is_err());
+        assert!(q.softmax(1, &b).is_err());
+        assert!(q.silu(&b).is_err());
+        assert!(q.sum(1, &b).is_err());
+        assert!(Tensor::concat(&[&q], 0).is_err());
+    }
+}
@@ -0,0 +1,741 @@
+//! Per-op timing of a backend.
+//!
+//! [`ProfilingBackend`] is a [`ComputeBackend`] that forwards every op to an
+//! inner backend and records how long it took, together with an estimate of
+//! the floating-point operations it did and the bytes it read and wrote.
+//! Calls are grouped by op name, weight dtype and shape into [`OpProfile`]s,
+//! which [`ProfilingBackend::summary`] prints as a table. With
+//! [`ProfilingBackend::with_trace`], every call is also kept as an event for
+//! [`ProfilingBackend::chrome_trace`], which writes the JSON read by
+//! `chrome://tracing` and Perfetto.
+//!
+//! FLOP counts are the textbook ones (`2mkn` for a matmul); transcendental
+//! functions such as `exp` and `tanh` count as one. Byte counts assume every
+//! input is read once and every output written once, so they are a lower
+//! bound on memory traffic.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::backend::{
+    AttentionParams, BinaryOp, Capabilities, ComputeBackend, KvView, KvViewF16, MatmulParams,
+    ReduceOp,
+};
+use crate::dtype::DType;
+use crate::error::Result;
+use crate::layout::Layout;
+use crate::rope::RopeTable;
+
+/// Aggregated statistics for one op at one shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpProfile {
+    /// Name of the backend op, e.g. `"matmul"`.
+    pub op: &'static str,
+    /// Weight dtype, for ops such as `matmul_quantized` that accept several.
+    pub dtype: Option<DType>,
+    /// The op's problem size; what each entry means depends on the op, e.g.
+    /// `[m, k, n]` for `matmul` and `[rows, hidden_size]` for `rms_norm`.
+    pub shape: Vec<usize>,
+    /// Number of calls.
+    pub calls: u64,
+    /// Wall time summed over all calls.
+    pub total: Duration,
+    /// Fastest call.
+    pub min: Duration,
+    /// Slowest call.
+    pub max: Duration,
+    /// Estimated floating-point operations, summed over all calls.
+    pub flops: u64,
+    /// Estimated bytes read and written, summed over all calls.
+    pub bytes: u64,
+}
+
+impl OpProfile {
+    /// Mean wall time per call.
+    pub fn mean(&self) -> Duration {
+        self.total / self.calls.max(1) as u32
+    }
+
+    /// Achieved GFLOP/s over all calls.
+    pub fn gflops(&self) -> f64 {
+        per_second(self.flops, self.total) / 1e9
+    }
+
+    /// Achieved memory bandwidth in GB/s over all calls.
+    pub fn gbytes_per_sec(&self) -> f64 {
+        per_second(self.bytes, self.total) / 1e9
+    }
+}
+
+fn per_second(count: u64, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs > 0.0 { count as f64 / secs } else { 0.0 }
+}
+
+/// One call, as recorded for the trace.
+#[derive(Debug, Clone)]
+struct Event {
+    op: &'static str,
+    dtype: Option<DType>,
+    shape: Vec<usize>,
+    start: Duration,
+    duration: Duration,
+    tid: u64,
+    flops: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    ops: HashMap<(&'static str, Option<DType>, Vec<usize>), OpProfile>,
+    events: Vec<Event>,
+}
+
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A small, stable id for the current thread, for the trace's `tid` field.
+fn thread_id() -> u64 {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT_TID.fetch_add(1, Ordering::Relaxed));
+        }
+        tid.get()
+    })
+}
+
+/// A backend that times every op it forwards to `B`.
+///
+/// `B` can be any backend, including `&dyn ComputeBackend` and
+/// `Box<dyn ComputeBackend>`.
+///
+/// ```
+/// use ir_tensor::{ComputeBackend, CpuBackend, ProfilingBackend};
+///
+/// let backend = ProfilingBackend::new(CpuBackend::new());
+/// backend.matmul(&[1.0; 6], &[1.0; 6], 2, 3, 2).unwrap();
+/// let profile = backend.profile();
+/// assert_eq!(profile[0].op, "matmul");
+/// assert_eq!(profile[0].shape, vec![2, 3, 2]);
+/// assert_eq!(profile[0].flops, 2 * 2 * 3 * 2);
+/// assert!(backend.summary().contains("[2, 3, 2]"));
+/// ```
+#[derive(Debug)]
+pub struct ProfilingBackend<B> {
+    inner: B,
+    name: String,
+    epoch: Instant,
+    trace: bool,
+    records: Mutex<Records>,
+}
+
+impl<B: ComputeBackend> ProfilingBackend<B> {
+    /// Profile `inner`, keeping only the aggregated [`OpProfile`]s.
+    pub fn new(inner: B) -> Self {
+        let name = format!("profiling({})", inner.name());
+        ProfilingBackend {
+            inner,
+            name,
+            epoch: Instant::now(),
+            trace: false,
+            records: Mutex::new(Records::default()),
+        }
+    }
+
+    /// Whether to keep an event per call for [`Self::chrome_trace`]. Off by
+    /// default: the events grow with every call, while the aggregated
+    /// [`OpProfile`]s use constant memory however long the run.
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// The profiled backend.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Discard everything recorded so far.
+    pub fn reset(&self) {
+        let mut records = self.records();
+        records.ops.clear();
+        records.events.clear();
+    }
+
+    /// Statistics per op and shape, slowest (by total time) first.
+    pub fn profile(&self) -> Vec<OpProfile> {
+        let mut ops: Vec<OpProfile> = self.records().ops.values().cloned().collect();
+        ops.sort_by(|a, b| {
+            b.total
+                .cmp(&a.total)
+                .then_with(|| op_label(a.op, a.dtype).cmp(&op_label(b.op, b.dtype)))
+                .then_with(|| a.shape.cmp(&b.shape))
+        });
+        ops
+    }
+
+    /// A table of [`Self::profile`] with each row's share of the total time
+    /// and its achieved throughput.
+    pub fn summary(&self) -> String {
+        let ops = self.profile();
+        let total: Duration = ops.iter().map(|p| p.total).sum();
+        let calls: u64 = ops.iter().map(|p| p.calls).sum();
+
+        let mut s = String::new();
+        let _ = writeln!(
+            s,
+            "{:<24} {:<20} {:>8} {:>11} {:>10} {:>6} {:>9} {:>8}",
+            "op", "shape", "calls", "total ms", "mean us", "%", "GFLOP/s", "GB/s"
+        );
+        for p in &ops {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                100.0 * p.total.as_secs_f64() / total.as_secs_f64()
+            };
+            let _ = writeln!(
+                s,
+                "{:<24} {:<20} {:>8} {:>11.3} {:>10.2} {:>6.1} {:>9.2} {:>8.2}",
+                op_label(p.op, p.dtype),
+                format!("{:?}", p.shape),
+                p.calls,
+                p.total.as_secs_f64() * 1e3,
+                p.mean().as_secs_f64() * 1e6,
+                share,
+                p.gflops(),
+                p.gbytes_per_sec()
+            );
+        }
+        let _ = writeln!(
+            s,
+            "{:<24} {:<20} {:>8} {:>11.3}",
+            "total",
+            "",
+            calls,
+            total.as_secs_f64() * 1e3
+        );
+        s
+    }
+
+    /// The recorded calls in Chrome's trace event format, one complete
+    /// (`"ph": "X"`) event per call with its shape, FLOPs and bytes as args.
+    pub fn chrome_trace(&self) -> String {
+        let records = self.records();
+        let mut s = String::from("{\"traceEvents\":[");
+        for (i, e) in records.events.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            let _ = write!(
+                s,
+                "\n{{\"name\":\"{}\",\"cat\":\"backend\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\
+                 \"pid\":1,\"tid\":{},\"args\":{{\"shape\":{:?},\"flops\":{},\"bytes\":{}}}}}",
+                op_label(e.op, e.dtype),
+                e.start.as_secs_f64() * 1e6,
+                e.duration.as_secs_f64() * 1e6,
+                e.tid,
+                e.shape,
+                e.flops,
+                e.bytes
+            );
+        }
+        s.push_str("\n],\"displayTimeUnit\":\"ns\"}\n");
+        s
+    }
+
+    /// Write [`Self::chrome_trace`] to `w`.
+    pub fn write_chrome_trace<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(self.chrome_trace().as_bytes())
+    }
+
+    fn records(&self) -> std::sync::MutexGuard<'_, Records> {
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f`, then record its wall time under `op` and `shape`.
+    fn time<T>(
+        &self,
+        op: &'static str,
+        shape: Vec<usize>,
+        flops: u64,
+        bytes: u64,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        self.time_dtype(op, None, shape, flops, bytes, f)
+    }
+
+    /// Like [`Self::time`], keeping calls with different weight dtypes apart.
+    fn time_dtype<T>(
+        &self,
+        op: &'static str,
+        dtype: Option<DType>,
+        shape: Vec<usize>,
+        flops: u64,
+        bytes: u64,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let start = Instant::now();
+        let result = f();
+        let duration = start.elapsed();
+
+        let mut records = self.records();
+        if self.trace {
+            records.events.push(Event {
+                op,
+                dtype,
+                shape: shape.clone(),
+                start: start.duration_since(self.epoch),
+                duration,
+                tid: thread_id(),
+                flops,
+                bytes,
+            });
+        }
+        let entry = records
+            .ops
+            .entry((op, dtype, shape.clone()))
+            .or_insert_with(|| OpProfile {
+                op,
+                dtype,
+                shape,
+                calls: 0,
+                total: Duration::ZERO,
+                min: Duration::MAX,
+                max: Duration::ZERO,
+                flops: 0,
+                bytes: 0,
+            });
+        entry.calls += 1;
+        entry.total += duration;
+        entry.min = entry.min.min(duration);
+        entry.max = entry.max.max(duration);
+        entry.flops += flops;
+        entry.bytes += bytes;
+        result
+    }
+}
+
+/// `op`, followed by the weight dtype in parentheses when there is one.
+fn op_label(op: &str, dtype: Option<DType>) -> String {
+    match dtype {
+        Some(dtype) => format!("{}({})", op, dtype),
+        None => op.to_string(),
+    }
+}
+
+/// Bytes taken by `n` f32 values.
+fn f32_bytes(n: usize) -> u64 {
+    4 * n as u64
+}
+
+/// Shape `[n_tokens, n_heads, head_dim, n_positions]` and FLOPs of an
+/// attention call. Per visible key and head that is a q·k dot product and a
+/// weighted add of the value row, plus the softmax.
+fn attention_work(
+    q: &[f32],
+    n_positions: usize,
+    q_pos: usize,
+    params: &AttentionParams,
+) -> (Vec<usize>, u64) {
+    let q_dim = (params.n_heads * params.head_dim).max(1);
+    let n_tokens = q.len() / q_dim;
+    let n_keys: usize = (0..n_tokens)
+        .map(|t| params.mask.key_range(q_pos + t, n_positions).len())
+        .sum();
+    let flops = (n_keys * params.n_heads * (4 * params.head_dim + 5)) as u64;
+    let shape = vec![n_tokens, params.n_heads, params.head_dim, n_positions];
+    (shape, flops)
+}
+
+/// `[len / row_len, row_len]`, the shape of a batch of rows.
+fn rows(len: usize, row_len: usize) -> Vec<usize> {
+    vec![len / row_len.max(1), row_len]
+}
+
+impl<B: ComputeBackend> ComputeBackend for ProfilingBackend<B> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        let flops = 2 * (m * k * n) as u64;
+        let bytes = f32_bytes(m * k + k * n + m * n);
+        self.time("matmul", vec![m, k, n], flops, bytes, || {
+            self.inner.matmul_into(a, b, out, m, k, n)
+        })
+    }
+
+    fn batched_matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        params: &MatmulParams,
+    ) -> Result<()> {
+        let (batch, m, k, n) = (params.batch, params.m, params.k, params.n);
+        let flops = 2 * (batch * m * k * n) as u64;
+        let bytes = f32_bytes(a.len() + b.len() + out.len());
+        let shape = vec![batch, m, k, n];
+        self.time("batched_matmul", shape, flops, bytes, || {
+            self.inner.batched_matmul_into(a, b, out, params)
+        })
+    }
+
+    fn matmul_quantized_into(
+        &self,
+        w: &[u8],
+        dtype: DType,
+        x: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+    ) -> Result<()> {
+        let flops = 2 * (m * k) as u64;
+        let bytes = w.len() as u64 + f32_bytes(k + m);
+        let shape = vec![m, k];
+        self.time_dtype("matmul_quantized", Some(dtype), shape, flops, bytes, || {
+            self.inner.matmul_quantized_into(w, dtype, x, out, m, k)
+        })
+    }
+
+    fn matmul_f16_into(
+        &self,
+        a: &[u8],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        let flops = 2 * (m * k * n) as u64;
+        let bytes = a.len() as u64 + f32_bytes(k * n + m * n);
+        self.time("matmul_f16", vec![m, k, n], flops, bytes, || {
+            self.inner.matmul_f16_into(a, b, out, m, k, n)
+        })
+    }
+
+    fn add_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        let n = a.len();
+        self.time("add", vec![n], n as u64, f32_bytes(3 * n), || {
+            self.inner.add_inplace(a, b)
+        })
+    }
+
+    fn mul_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        let n = a.len();
+        self.time("mul", vec![n], n as u64, f32_bytes(3 * n), || {
+            self.inner.mul_inplace(a, b)
+        })
+    }
+
+    fn scale_inplace(&self, a: &mut [f32], s: f32) -> Result<()> {
+        let n = a.len();
+        self.time("scale", vec![n], n as u64, f32_bytes(2 * n), || {
+            self.inner.scale_inplace(a, s)
+        })
+    }
+
+    fn binary_into(
+        &self,
+        op: BinaryOp,
+        a: &[f32],
+        a_layout: &Layout,
+        b: &[f32],
+        b_layout: &Layout,
+        out: &mut [f32],
+    ) -> Result<()> {
+        // Broadcast inputs are read once per output element.
+        let n = out.len();
+        self.time(
+            "binary",
+            a_layout.shape().dims().to_vec(),
+            n as u64,
+            f32_bytes(3 * n),
+            || self.inner.binary_into(op, a, a_layout, b, b_layout, out),
+        )
+    }
+
+    fn reduce_into(
+        &self,
+        op: ReduceOp,
+        x: &[f32],
+        layout: &Layout,
+        dim: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let n = layout.shape().numel();
+        let mut shape = layout.shape().dims().to_vec();
+        shape.push(dim);
+        self.time("reduce", shape, n as u64, f32_bytes(n + out.len()), || {
+            self.inner.reduce_into(op, x, layout, dim, out)
+        })
+    }
+
+    fn rms_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        // Square and sum, then scale by 1/rms and by the weight.
+        let n = x.len();
+        let bytes = f32_bytes(2 * n + weight.len());
+        self.time(
+            "rms_norm",
+            rows(n, hidden_size),
+            4 * n as u64,
+            bytes,
+            || self.inner.rms_norm_into(x, weight, eps, hidden_size, out),
+        )
+    }
+
+    fn softmax_into(&self, x: &[f32], n_vocab: usize, out: &mut [f32]) -> Result<()> {
+        // Max, subtract, exp, sum and divide.
+        let n = x.len();
+        self.time(
+            "softmax",
+            rows(n, n_vocab),
+            5 * n as u64,
+            f32_bytes(2 * n),
+            || self.inner.softmax_into(x, n_vocab, out),
+        )
+    }
+
+    fn rope_inplace(
+        &self,
+        q: &mut [f32],
+        k: &mut [f32],
+        rope: &RopeTable,
+        pos: usize,
+        n_heads_q: usize,
+        n_heads_k: usize,
+    ) -> Result<()> {
+        // Each rotated pair takes four multiplies and two adds.
+        let n_dims = rope.config().n_dims;
+        let pairs = (n_heads_q + n_heads_k) * n_dims / 2;
+        let bytes = f32_bytes(2 * (q.len() + k.len()) + n_dims);
+        let shape = vec![n_heads_q, n_heads_k, rope.config().head_dim];
+        self.time("rope", shape, 6 * pairs as u64, bytes, || {
+            self.inner
+                .rope_inplace(q, k, rope, pos, n_heads_q, n_heads_k)
+        })
+    }
+
+    fn attention_into(
+        &self,
+        q: &[f32],
+        kv: KvView<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let (shape, flops) = attention_work(q, kv.n_positions, q_pos, params);
+        let bytes = f32_bytes(2 * q.len() + 2 * kv.n_positions * params.kv_dim());
+        self.time("attention", shape, flops, bytes, || {
+            self.inner.attention_into(q, kv, q_pos, params, out)
+        })
+    }
+
+    fn attention_f16_into(
+        &self,
+        q: &[f32],
+        kv: KvViewF16<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let (shape, flops) = attention_work(q, kv.n_positions, q_pos, params);
+        let bytes = f32_bytes(2 * q.len()) + 4 * (kv.n_positions * params.kv_dim()) as u64;
+        self.time("attention_f16", shape, flops, bytes, || {
+            self.inner.attention_f16_into(q, kv, q_pos, params, out)
+        })
+    }
+
+    fn silu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let n = x.len();
+        self.time("silu", vec![n], 4 * n as u64, f32_bytes(2 * n), || {
+            self.inner.silu_inplace(x)
+        })
+    }
+
+    fn gelu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let n = x.len();
+        self.time("gelu", vec![n], 8 * n as u64, f32_bytes(2 * n), || {
+            self.inner.gelu_inplace(x)
+        })
+    }
+
+    fn gelu_erf_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let n = x.len();
+        self.time("gelu_erf", vec![n], 5 * n as u64, f32_bytes(2 * n), || {
+            self.inner.gelu_erf_inplace(x)
+        })
+    }
+
+    fn geglu_inplace(&self, gate: &mut [f32], up: &[f32]) -> Result<()> {
+        let n = gate.len();
+        self.time("geglu", vec![n], 9 * n as u64, f32_bytes(3 * n), || {
+            self.inner.geglu_inplace(gate, up)
+        })
+    }
+
+    fn relu2_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let n = x.len();
+        self.time("relu2", vec![n], 2 * n as u64, f32_bytes(2 * n), || {
+            self.inner.relu2_inplace(x)
+        })
+    }
+
+    fn softcap_inplace(&self, x: &mut [f32], cap: f32) -> Result<()> {
+        let n = x.len();
+        self.time("softcap", vec![n], 3 * n as u64, f32_bytes(2 * n), || {
+            self.inner.softcap_inplace(x, cap)
+        })
+    }
+
+    fn layer_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        // Mean, centred sum of squares, normalize, scale and shift.
+        let n = x.len();
+        let params = weight.len() + bias.map_or(0, |b| b.len());
+        self.time(
+            "layer_norm",
+            rows(n, hidden_size),
+            7 * n as u64,
+            f32_bytes(2 * n + params),
+            || {
+                self.inner
+                    .layer_norm_into(x, weight, bias, eps, hidden_size, out)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CpuBackend;
+
+    #[test]
+    fn test_aggregates_by_op_and_shape() {
+        let backend = ProfilingBackend::new(CpuBackend::with_threads(1).unwrap());
+        let a = vec![1.0; 12];
+        backend.matmul(&a, &a, 3, 4, 3).unwrap();
+        backend.matmul(&a, &a, 3, 4, 3).unwrap();
+        backend.matmul(&a, &a, 4, 3, 4).unwrap();
+        backend.silu(&a).unwrap();
+
+        let profile = backend.profile();
+        assert_eq!(profile.len(), 3);
+        let mm = profile
+            .iter()
+            .find(|p| p.op == "matmul" && p.shape == [3, 4, 3])
+            .unwrap();
+        assert_eq!(mm.calls, 2);
+        assert_eq!(mm.flops, 2 * 2 * 3 * 4 * 3);
+        assert_eq!(mm.bytes, 2 * 4 * (12 + 12 + 9));
+        assert!(mm.min <= mm.max && mm.max <= mm.total);
+        assert!(profile.windows(2).all(|w| w[0].total >= w[1].total));
+
+        let summary = backend.summary();
+        assert!(summary.starts_with("op "));
+        assert!(summary.contains("[3, 4, 3]"));
+        assert!(summary.lines().last().unwrap().starts_with("total"));
+
+        backend.reset();
+        assert!(backend.profile().is_empty());
+    }
+
+    #[test]
+    fn test_errors_are_recorded_and_passed_through() {
+        let backend = ProfilingBackend::new(CpuBackend::with_threads(1).unwrap());
+        assert!(backend.add(&[1.0, 2.0], &[1.0]).is_err());
+        assert_eq!(backend.profile()[0].op, "add");
+    }
+
+    #[test]
+    fn test_quantized_matmul_is_keyed_by_dtype() {
+        let backend = ProfilingBackend::new(CpuBackend::with_threads(1).unwrap()).with_trace(true);
+        let x = vec![1.0; 32];
+        let q4 = vec![0u8; DType::Q4_0.storage_size(2 * 32)];
+        let q8 = vec![0u8; DType::Q8_0.storage_size(2 * 32)];
+        backend
+            .matmul_quantized(&q4, DType::Q4_0, &x, 2, 32)
+            .unwrap();
+        backend
+            .matmul_quantized(&q8, DType::Q8_0, &x, 2, 32)
+            .unwrap();
+        backend
+            .matmul_quantized(&q8, DType::Q8_0, &x, 2, 32)
+            .unwrap();
+
+        let profile = backend.profile();
+        assert_eq!(profile.len(), 2);
+        let calls = |dtype| {
+            profile
+                .iter()
+                .find(|p| p.dtype == Some(dtype))
+                .unwrap()
+                .calls
+        };
+        assert_eq!(calls(DType::Q4_0), 1);
+        assert_eq!(calls(DType::Q8_0), 2);
+        assert!(backend.summary().contains("matmul_quantized(q4_0)"));
+        assert!(
+            backend
+                .chrome_trace()
+                .contains("\"name\":\"matmul_quantized(q8_0)\"")
+        );
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let cpu = CpuBackend::with_threads(1).unwrap();
+        let inner: &dyn ComputeBackend = &cpu;
+        let backend = ProfilingBackend::new(inner).with_trace(true);
+        assert_eq!(backend.name(), "profiling(cpu)");
+        backend.softmax(&[1.0, 2.0, 3.0, 4.0], 2).unwrap();
+        backend.scale(&[1.0, 2.0], 3.0).unwrap();
+
+        let trace = backend.chrome_trace();
+        assert!(trace.starts_with("{\"traceEvents\":["));
+        assert!(trace.trim_end().ends_with('}'));
+        assert_eq!(trace.matches("\"ph\":\"X\"").count(), 2);
+        assert!(trace.contains("\"name\":\"softmax\""));
+        assert!(trace.contains("\"shape\":[2, 2],\"flops\":20,\"bytes\":32"));
+
+        let untraced = ProfilingBackend::new(inner);
+        untraced.scale(&[1.0], 2.0).unwrap();
+        assert_eq!(untraced.profile().len(), 1);
+        assert!(!untraced.chrome_trace().contains("\"name\""));
+    }
+}
@@ -0,0 +1,581 @@
+// The k-quant formats: 256-element super-blocks split into 16- or
+// 32-element groups, each with its own scale quantized against a shared
+// super-block scale.
+//
+// The quantizers fit each group's scale (and min) to its range directly.
+// ggml searches a few candidate scales per group to minimize the squared
+// error, so its encodings are somewhat more accurate, but both decode the
+// same way.
+
+use crate::dtype::DType;
+
+use super::{put_f16, read_f16, signed_absmax};
+
+/// Number of elements in a k-quant super-block.
+pub(crate) const QK_K: usize = 256;
+
+/// Dequantize k-quant super-blocks to f32, appending to `out`.
+///
+/// All k-quants share a 256-element super-block; `decode` unpacks one
+/// `dtype.size_in_bytes()`-byte block into its 256 values.
+fn dequantize_k_quant(
+    data: &[u8],
+    numel: usize,
+    dtype: DType,
+    decode: fn(&[u8], &mut [f32; QK_K]),
+    out: &mut Vec<f32>,
+) {
+    let block_bytes = dtype.size_in_bytes();
+    let n_blocks = numel.div_ceil(QK_K);
+    assert!(
+        data.len() >= n_blocks * block_bytes,
+        "{} elements of {} take {} bytes, got {}",
+        numel,
+        dtype,
+        n_blocks * block_bytes,
+        data.len()
+    );
+    let start_len = out.len();
+    out.reserve(n_blocks * QK_K);
+
+    let mut values = [0.0f32; QK_K];
+    for block in data.chunks_exact(block_bytes).take(n_blocks) {
+        decode(block, &mut values);
+        out.extend_from_slice(&values);
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Dequantize Q2_K super-blocks to f32, appending to `out`.
+pub fn dequantize_q2_k(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    dequantize_k_quant(data, numel, DType::Q2K, dequantize_block_q2_k, out);
+}
+
+/// Dequantize Q3_K super-blocks to f32, appending to `out`.
+pub fn dequantize_q3_k(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    dequantize_k_quant(data, numel, DType::Q3K, dequantize_block_q3_k, out);
+}
+
+/// Dequantize Q4_K super-blocks to f32, appending to `out`.
+pub fn dequantize_q4_k(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    dequantize_k_quant(data, numel, DType::Q4K, dequantize_block_q4_k, out);
+}
+
+/// Dequantize Q5_K super-blocks to f32, appending to `out`.
+pub fn dequantize_q5_k(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    dequantize_k_quant(data, numel, DType::Q5K, dequantize_block_q5_k, out);
+}
+
+/// Dequantize Q6_K super-blocks to f32, appending to `out`.
+pub fn dequantize_q6_k(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    dequantize_k_quant(data, numel, DType::Q6K, dequantize_block_q6_k, out);
+}
+
+/// Dequantize Q8_K super-blocks to f32, appending to `out`.
+pub fn dequantize_q8_k(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    dequantize_k_quant(data, numel, DType::Q8K, dequantize_block_q8_k, out);
+}
+
+/// Unpack the `j`-th 6-bit (scale, min) pair from the 12-byte scales array
+/// shared by Q4_K and Q5_K.
+///
+/// The first four pairs live in the low 6 bits of bytes 0..4 (scales) and
+/// 4..8 (mins). The last four store their low nibbles in bytes 8..12 and
+/// borrow the top two bits of bytes 0..8 for their high bits.
+fn scale_min_k4(j: usize, q: &[u8]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        let sc = (q[j + 4] & 0x0F) | ((q[j - 4] >> 6) << 4);
+        let m = (q[j + 4] >> 4) | ((q[j] >> 6) << 4);
+        (sc, m)
+    }
+}
+
+/// Dequantize one Q2_K super-block.
+///
+/// Q2_K block layout (84 bytes total, 256 elements per block):
+///   - 16 bytes: per-16-element scale (low nibble) and min (high nibble)
+///   - 64 bytes: 2-bit quants; each 32-byte half covers 128 elements, with
+///     bit pair `2 * k` of byte `l` holding element `32 * k + l`
+///   - 2 bytes: f16 super-block scale `d`
+///   - 2 bytes: f16 super-block min scale `dmin`
+///
+/// Dequantized as: d * scale * q - dmin * min.
+fn dequantize_block_q2_k(block: &[u8], y: &mut [f32; QK_K]) {
+    let scales = &block[..16];
+    let qs = &block[16..80];
+    let d = read_f16(block, 80);
+    let dmin = read_f16(block, 82);
+
+    let mut is = 0;
+    for (half, q) in qs.chunks_exact(32).enumerate() {
+        for k in 0..4 {
+            let shift = 2 * k;
+            for sub in 0..2 {
+                let sc = scales[is];
+                is += 1;
+                let dl = d * (sc & 0x0F) as f32;
+                let ml = dmin * (sc >> 4) as f32;
+                let base = half * 128 + k * 32 + sub * 16;
+                for l in 0..16 {
+                    let v = (q[sub * 16 + l] >> shift) & 3;
+                    y[base + l] = dl * v as f32 - ml;
+                }
+            }
+        }
+    }
+}
+
+/// Dequantize one Q3_K super-block.
+///
+/// Q3_K block layout (110 bytes total, 256 elements per block):
+///   - 32 bytes: high-bit mask; bit `k` of byte `l` is the third bit of
+///     element `32 * k + l`
+///   - 64 bytes: low 2 bits of each quant, laid out as in Q2_K
+///   - 12 bytes: sixteen 6-bit scales, stored with an offset of 32
+///   - 2 bytes: f16 super-block scale `d`
+///
+/// Quants are signed (-4..3): the low two bits, minus 4 when the high bit
+/// is clear. Dequantized as: d * (scale - 32) * q.
+fn dequantize_block_q3_k(block: &[u8], y: &mut [f32; QK_K]) {
+    const KMASK1: u32 = 0x0303_0303;
+    const KMASK2: u32 = 0x0f0f_0f0f;
+
+    let hmask = &block[..32];
+    let qs = &block[32..96];
+    let raw = &block[96..108];
+    let d = read_f16(block, 108);
+
+    // Unpack the 12 scale bytes into 16 6-bit scales: the low nibbles come
+    // from bytes 0..8, the top two bits from bytes 8..12.
+    let word =
+        |i: usize| u32::from_le_bytes([raw[4 * i], raw[4 * i + 1], raw[4 * i + 2], raw[4 * i + 3]]);
+    let (a0, a1, tmp) = (word(0), word(1), word(2));
+    let aux = [
+        (a0 & KMASK2) | ((tmp & KMASK1) << 4),
+        (a1 & KMASK2) | (((tmp >> 2) & KMASK1) << 4),
+        ((a0 >> 4) & KMASK2) | (((tmp >> 4) & KMASK1) << 4),
+        ((a1 >> 4) & KMASK2) | (((tmp >> 6) & KMASK1) << 4),
+    ];
+    let mut scales = [0i32; 16];
+    for (i, sc) in scales.iter_mut().enumerate() {
+        *sc = ((aux[i / 4] >> (8 * (i % 4))) & 0xFF) as i32 - 32;
+    }
+
+    let mut is = 0;
+    for (half, q) in qs.chunks_exact(32).enumerate() {
+        for k in 0..4 {
+            let shift = 2 * k;
+            let m = 1u8 << (half * 4 + k);
+            for sub in 0..2 {
+                let dl = d * scales[is] as f32;
+                is += 1;
+                let base = half * 128 + k * 32 + sub * 16;
+                for l in 0..16 {
+                    let idx = sub * 16 + l;
+                    let lo = ((q[idx] >> shift) & 3) as i32;
+                    let v = if hmask[idx] & m != 0 { lo } else { lo - 4 };
+                    y[base + l] = dl * v as f32;
+                }
+            }
+        }
+    }
+}
+
+/// Dequantize one Q4_K super-block.
+///
+/// Q4_K block layout (144 bytes total, 256 elements per block):
+///   - 2 bytes: f16 super-block scale `d`
+///   - 2 bytes: f16 super-block min scale `dmin`
+///   - 12 bytes: eight 6-bit (scale, min) pairs, one per 32 elements
+///   - 128 bytes: 4-bit quants; each 32-byte chunk covers 64 elements, the
+///     lower nibbles first and the upper nibbles second
+///
+/// Dequantized as: d * scale * q - dmin * min.
+fn dequantize_block_q4_k(block: &[u8], y: &mut [f32; QK_K]) {
+    let d = read_f16(block, 0);
+    let dmin = read_f16(block, 2);
+    let scales = &block[4..16];
+    let qs = &block[16..144];
+
+    for (chunk, q) in qs.chunks_exact(32).enumerate() {
+        let (sc1, m1) = scale_min_k4(2 * chunk, scales);
+        let (sc2, m2) = scale_min_k4(2 * chunk + 1, scales);
+        let (d1, m1) = (d * sc1 as f32, dmin * m1 as f32);
+        let (d2, m2) = (d * sc2 as f32, dmin * m2 as f32);
+        let base = chunk * 64;
+        for (l, &byte) in q.iter().enumerate() {
+            y[base + l] = d1 * (byte & 0x0F) as f32 - m1;
+            y[base + 32 + l] = d2 * (byte >> 4) as f32 - m2;
+        }
+    }
+}
+
+/// Dequantize one Q5_K super-block.
+///
+/// Q5_K block layout (176 bytes total, 256 elements per block):
+///   - 2 bytes: f16 super-block scale `d`
+///   - 2 bytes: f16 super-block min scale `dmin`
+///   - 12 bytes: eight 6-bit (scale, min) pairs, as in Q4_K
+///   - 32 bytes: fifth bits; bit `k` of byte `l` belongs to element `32 * k + l`
+///   - 128 bytes: low 4 bits, laid out as in Q4_K
+///
+/// Dequantized as: d * scale * q - dmin * min.
+fn dequantize_block_q5_k(block: &[u8], y: &mut [f32; QK_K]) {
+    let d = read_f16(block, 0);
+    let dmin = read_f16(block, 2);
+    let scales = &block[4..16];
+    let qh = &block[16..48];
+    let qs = &block[48..176];
+
+    for (chunk, q) in qs.chunks_exact(32).enumerate() {
+        let (sc1, m1) = scale_min_k4(2 * chunk, scales);
+        let (sc2, m2) = scale_min_k4(2 * chunk + 1, scales);
+        let (d1, m1) = (d * sc1 as f32, dmin * m1 as f32);
+        let (d2, m2) = (d * sc2 as f32, dmin * m2 as f32);
+        let u1 = 1u8 << (2 * chunk);
+        let u2 = 2u8 << (2 * chunk);
+        let base = chunk * 64;
+        for (l, &byte) in q.iter().enumerate() {
+            let hi1 = if qh[l] & u1 != 0 { 16 } else { 0 };
+            let hi2 = if qh[l] & u2 != 0 { 16 } else { 0 };
+            y[base + l] = d1 * ((byte & 0x0F) + hi1) as f32 - m1;
+            y[base + 32 + l] = d2 * ((byte >> 4) + hi2) as f32 - m2;
+        }
+    }
+}
+
+/// Dequantize one Q6_K super-block.
+///
+/// Q6_K block layout (210 bytes total, 256 elements per block):
+///   - 128 bytes: low 4 bits of each quant
+///   - 64 bytes: high 2 bits of each quant
+///   - 16 bytes: signed 8-bit scales, one per 16 elements
+///   - 2 bytes: f16 super-block scale `d`
+///
+/// Each 128-element half uses 64 low-nibble bytes and 32 high-bit bytes:
+/// element `l + 32 * k` takes nibble `k / 2` of low byte `l + 32 * (k % 2)`
+/// and bit pair `k` of high byte `l`. Quants are offset by 32.
+/// Dequantized as: d * scale * (q - 32).
+fn dequantize_block_q6_k(block: &[u8], y: &mut [f32; QK_K]) {
+    let ql = &block[..128];
+    let qh = &block[128..192];
+    let scales = &block[192..208];
+    let d = read_f16(block, 208);
+
+    for half in 0..2 {
+        let ql = &ql[half * 64..];
+        let qh = &qh[half * 32..];
+        let sc = &scales[half * 8..];
+        let base = half * 128;
+        for l in 0..32 {
+            let is = l / 16;
+            let q1 = ((ql[l] & 0x0F) | ((qh[l] & 3) << 4)) as i32 - 32;
+            let q2 = ((ql[l + 32] & 0x0F) | (((qh[l] >> 2) & 3) << 4)) as i32 - 32;
+            let q3 = ((ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4)) as i32 - 32;
+            let q4 = ((ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4)) as i32 - 32;
+            y[base + l] = d * (sc[is] as i8) as f32 * q1 as f32;
+            y[base + l + 32] = d * (sc[is + 2] as i8) as f32 * q2 as f32;
+            y[base + l + 64] = d * (sc[is + 4] as i8) as f32 * q3 as f32;
+            y[base + l + 96] = d * (sc[is + 6] as i8) as f32 * q4 as f32;
+        }
+    }
+}
+
+/// Dequantize one Q8_K super-block.
+///
+/// Q8_K block layout (292 bytes total, 256 elements per block):
+///   - 4 bytes: f32 scale `d`
+///   - 256 bytes: signed 8-bit quants
+///   - 32 bytes: sixteen i16 sums of each 16-element group (unused here)
+///
+/// Dequantized as: d * q.
+fn dequantize_block_q8_k(block: &[u8], y: &mut [f32; QK_K]) {
+    let d = f32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+    for (v, &q) in y.iter_mut().zip(&block[4..4 + QK_K]) {
+        *v = d * (q as i8) as f32;
+    }
+}
+
+/// Quantize f32 values into Q2_K super-blocks.
+///
+/// `x.len()` must be a multiple of 256 and `out` must hold 84 bytes per
+/// super-block. Each 16-element group gets a 4-bit scale and min fitted to
+/// its range, relative to the super-block's `d` and `dmin`.
+pub fn quantize_q2_k(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in super_blocks(x, out, DType::Q2K) {
+        let (scales, mins) = fit_min_scale::<16>(src, 3.0);
+        let (d, sc) = quantize_scales(&scales, 15.0);
+        let (dmin, m) = quantize_scales(&mins, 15.0);
+        put_f16(dst, 80, d);
+        put_f16(dst, 82, dmin);
+        let (d, dmin) = (read_f16(dst, 80), read_f16(dst, 82));
+
+        let mut q = [0u8; QK_K];
+        for (i, group) in src.chunks_exact(16).enumerate() {
+            dst[i] = sc[i] | (m[i] << 4);
+            let (dl, ml) = (d * sc[i] as f32, dmin * m[i] as f32);
+            for (q, &v) in q[16 * i..].iter_mut().zip(group) {
+                *q = quantize_level(v + ml, dl, 0, 3) as u8;
+            }
+        }
+        pack_2bit(&q, &mut dst[16..80]);
+    }
+}
+
+/// Quantize f32 values into Q3_K super-blocks.
+///
+/// `x.len()` must be a multiple of 256 and `out` must hold 110 bytes per
+/// super-block. Each 16-element group gets a signed 6-bit scale; quants run
+/// from -4 to 3 with their third bit in the high-bit mask.
+pub fn quantize_q3_k(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in super_blocks(x, out, DType::Q3K) {
+        let mut scales = [0.0f32; 16];
+        for (s, group) in scales.iter_mut().zip(src.chunks_exact(16)) {
+            *s = signed_absmax(group) / -4.0;
+        }
+        let max = signed_absmax(&scales);
+        let iscale = if max != 0.0 { -32.0 / max } else { 0.0 };
+        let mut sc = [0i32; 16];
+        for (l, &s) in sc.iter_mut().zip(&scales) {
+            *l = ((iscale * s).round() as i32).clamp(-32, 31);
+        }
+        put_f16(dst, 108, if iscale != 0.0 { 1.0 / iscale } else { 0.0 });
+        let d = read_f16(dst, 108);
+
+        // Sixteen 6-bit scales, offset by 32: low nibbles in bytes 0..8,
+        // top two bits in bytes 8..12.
+        let packed = &mut dst[96..108];
+        packed.fill(0);
+        for (j, &l) in sc.iter().enumerate() {
+            let l = (l + 32) as u8;
+            if j < 8 {
+                packed[j] = l & 0x0F;
+            } else {
+                packed[j - 8] |= (l & 0x0F) << 4;
+            }
+            packed[j % 4 + 8] |= (l >> 4) << (2 * (j / 4));
+        }
+
+        let mut q = [0u8; QK_K];
+        for (i, group) in src.chunks_exact(16).enumerate() {
+            let dl = d * sc[i] as f32;
+            for (q, &v) in q[16 * i..].iter_mut().zip(group) {
+                *q = (quantize_level(v, dl, -4, 3) + 4) as u8;
+            }
+        }
+        let hmask = &mut dst[..32];
+        hmask.fill(0);
+        for (j, q) in q.iter_mut().enumerate() {
+            if *q > 3 {
+                hmask[j % 32] |= 1 << (j / 32);
+                *q -= 4;
+            }
+        }
+        pack_2bit(&q, &mut dst[32..96]);
+    }
+}
+
+/// Quantize f32 values into Q4_K super-blocks.
+///
+/// `x.len()` must be a multiple of 256 and `out` must hold 144 bytes per
+/// super-block. Each 32-element group gets a 6-bit scale and min fitted to
+/// its range, relative to the super-block's `d` and `dmin`.
+pub fn quantize_q4_k(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in super_blocks(x, out, DType::Q4K) {
+        let q = quantize_k4_groups(src, 15.0, dst);
+        for (chunk, qs) in dst[16..144].chunks_exact_mut(32).enumerate() {
+            let q = &q[chunk * 64..];
+            for (l, byte) in qs.iter_mut().enumerate() {
+                *byte = q[l] | (q[l + 32] << 4);
+            }
+        }
+    }
+}
+
+/// Quantize f32 values into Q5_K super-blocks.
+///
+/// `x.len()` must be a multiple of 256 and `out` must hold 176 bytes per
+/// super-block. As Q4_K with 5-bit quants, whose fifth bits go in a
+/// separate 32-byte array.
+pub fn quantize_q5_k(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in super_blocks(x, out, DType::Q5K) {
+        let q = quantize_k4_groups(src, 31.0, dst);
+        let (qh, qs) = dst[16..176].split_at_mut(32);
+        qh.fill(0);
+        for (chunk, qs) in qs.chunks_exact_mut(32).enumerate() {
+            let q = &q[chunk * 64..];
+            for (l, byte) in qs.iter_mut().enumerate() {
+                let (a, b) = (q[l], q[l + 32]);
+                *byte = (a & 0x0F) | ((b & 0x0F) << 4);
+                qh[l] |= (a >> 4) << (2 * chunk);
+                qh[l] |= (b >> 4) << (2 * chunk + 1);
+            }
+        }
+    }
+}
+
+/// Quantize f32 values into Q6_K super-blocks.
+///
+/// `x.len()` must be a multiple of 256 and `out` must hold 210 bytes per
+/// super-block. Each 16-element group gets a signed 8-bit scale; quants run
+/// from -32 to 31 and are stored offset by 32.
+pub fn quantize_q6_k(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in super_blocks(x, out, DType::Q6K) {
+        let mut scales = [0.0f32; 16];
+        for (s, group) in scales.iter_mut().zip(src.chunks_exact(16)) {
+            *s = signed_absmax(group) / -32.0;
+        }
+        let max = signed_absmax(&scales);
+        let iscale = if max != 0.0 { -128.0 / max } else { 0.0 };
+        put_f16(dst, 208, if iscale != 0.0 { 1.0 / iscale } else { 0.0 });
+        let d = read_f16(dst, 208);
+
+        let mut q = [0u8; QK_K];
+        for (i, group) in src.chunks_exact(16).enumerate() {
+            let sc = ((iscale * scales[i]).round() as i32).clamp(-128, 127) as i8;
+            dst[192 + i] = sc as u8;
+            let dl = d * sc as f32;
+            for (q, &v) in q[16 * i..].iter_mut().zip(group) {
+                *q = (quantize_level(v, dl, -32, 31) + 32) as u8;
+            }
+        }
+
+        let (ql, rest) = dst.split_at_mut(128);
+        let qh = &mut rest[..64];
+        for half in 0..2 {
+            let q = &q[half * 128..];
+            let ql = &mut ql[half * 64..];
+            let qh = &mut qh[half * 32..];
+            for l in 0..32 {
+                let (q1, q2, q3, q4) = (q[l], q[l + 32], q[l + 64], q[l + 96]);
+                ql[l] = (q1 & 0x0F) | ((q3 & 0x0F) << 4);
+                ql[l + 32] = (q2 & 0x0F) | ((q4 & 0x0F) << 4);
+                qh[l] = (q1 >> 4) | ((q2 >> 4) << 2) | ((q3 >> 4) << 4) | ((q4 >> 4) << 6);
+            }
+        }
+    }
+}
+
+/// Quantize f32 values into Q8_K super-blocks.
+///
+/// `x.len()` must be a multiple of 256 and `out` must hold 292 bytes per
+/// super-block: an f32 scale, 256 signed quants and the sum of each
+/// 16-element group.
+pub fn quantize_q8_k(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in super_blocks(x, out, DType::Q8K) {
+        let max = signed_absmax(src);
+        let iscale = if max != 0.0 { -127.0 / max } else { 0.0 };
+        let d = if iscale != 0.0 { 1.0 / iscale } else { 0.0 };
+        dst[..4].copy_from_slice(&d.to_le_bytes());
+
+        let (qs, sums) = dst[4..].split_at_mut(QK_K);
+        for ((group, qs), sum) in src
+            .chunks_exact(16)
+            .zip(qs.chunks_exact_mut(16))
+            .zip(sums.chunks_exact_mut(2))
+        {
+            let mut total = 0i16;
+            for (q, &v) in qs.iter_mut().zip(group) {
+                let v = ((iscale * v).round() as i32).clamp(-127, 127) as i8;
+                total += v as i16;
+                *q = v as u8;
+            }
+            sum.copy_from_slice(&total.to_le_bytes());
+        }
+    }
+}
+
+/// Pairs of 256-element super-blocks of `x` and their encoded bytes in `out`.
+fn super_blocks<'a>(
+    x: &'a [f32],
+    out: &'a mut [u8],
+    dtype: DType,
+) -> impl Iterator<Item = (&'a [f32], &'a mut [u8])> {
+    debug_assert_eq!(x.len() % QK_K, 0);
+    debug_assert_eq!(out.len(), dtype.storage_size(x.len()));
+    x.chunks_exact(QK_K)
+        .zip(out.chunks_exact_mut(dtype.size_in_bytes()))
+}
+
+/// `round(v / step)` clamped to `lo..=hi`, or 0 for a zero step.
+fn quantize_level(v: f32, step: f32, lo: i32, hi: i32) -> i32 {
+    if step == 0.0 {
+        return 0.clamp(lo, hi);
+    }
+    ((v / step).round() as i32).clamp(lo, hi)
+}
+
+/// Fit `x ≈ scale * q - min` with `q` in `0..=levels` to each `GROUP`-element
+/// group of a super-block. The min is never negative, so groups whose values
+/// are all positive still reach zero.
+fn fit_min_scale<const GROUP: usize>(x: &[f32], levels: f32) -> (Vec<f32>, Vec<f32>) {
+    x.chunks_exact(GROUP)
+        .map(|group| {
+            let lo = group.iter().fold(0.0f32, |m, &v| m.min(v));
+            let hi = group.iter().fold(lo, |m, &v| m.max(v));
+            ((hi - lo) / levels, -lo)
+        })
+        .unzip()
+}
+
+/// Quantize non-negative per-group values to integers in `0..=max_level`
+/// relative to a shared f16-representable super-block scale.
+fn quantize_scales(values: &[f32], max_level: f32) -> (f32, Vec<u8>) {
+    let max = values.iter().fold(0.0f32, |m, &v| m.max(v));
+    let d = half::f16::from_f32(max / max_level).to_f32();
+    let levels = values
+        .iter()
+        .map(|&v| quantize_level(v, d, 0, max_level as i32) as u8)
+        .collect();
+    (d, levels)
+}
+
+/// Shared part of Q4_K and Q5_K: fit a scale and min to each 32-element
+/// group, write `d`, `dmin` and the packed 6-bit pairs to the first 16
+/// bytes of `dst`, and return the quants in element order.
+fn quantize_k4_groups(x: &[f32], levels: f32, dst: &mut [u8]) -> [u8; QK_K] {
+    let (scales, mins) = fit_min_scale::<32>(x, levels);
+    let (d, sc) = quantize_scales(&scales, 63.0);
+    let (dmin, m) = quantize_scales(&mins, 63.0);
+    put_f16(dst, 0, d);
+    put_f16(dst, 2, dmin);
+
+    // Inverse of `scale_min_k4`.
+    let packed = &mut dst[4..16];
+    packed.fill(0);
+    for j in 0..8 {
+        let (ls, lm) = (sc[j], m[j]);
+        if j < 4 {
+            packed[j] = ls;
+            packed[j + 4] = lm;
+        } else {
+            packed[j + 4] = (ls & 0x0F) | ((lm & 0x0F) << 4);
+            packed[j - 4] |= (ls >> 4) << 6;
+            packed[j] |= (lm >> 4) << 6;
+        }
+    }
+
+    let mut q = [0u8; QK_K];
+    for (i, group) in x.chunks_exact(32).enumerate() {
+        let (dl, ml) = (d * sc[i] as f32, dmin * m[i] as f32);
+        for (q, &v) in q[32 * i..].iter_mut().zip(group) {
+            *q = quantize_level(v + ml, dl, 0, levels as i32) as u8;
+        }
+    }
+    q
+}
+
+/// Pack 2-bit quants as Q2_K and Q3_K store them: each 128-element half
+/// uses 32 bytes, with bit pair `k` of byte `l` holding element `32 * k + l`.
+fn pack_2bit(q: &[u8; QK_K], qs: &mut [u8]) {
+    for (half, qs) in qs.chunks_exact_mut(32).enumerate() {
+        let q = &q[half * 128..];
+        for (l, byte) in qs.iter_mut().enumerate() {
+            *byte = q[l] | (q[l + 32] << 2) | (q[l + 64] << 4) | (q[l + 96] << 6);
+        }
+    }
+}
@@ -0,0 +1,418 @@
+// The original ggml block formats: 32 elements per block with one f16
+// scale (and, for the `_1` variants, an f16 offset).
+
+use crate::cpu::quant::{self, QK};
+use crate::dtype::DType;
+
+use super::{put_f16, read_f16, read_u32, signed_absmax};
+
+/// Reinterpret raw bytes as f32 values (little-endian), appending to `out`.
+pub fn dequantize_f32(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    out.reserve(numel);
+    for i in 0..numel {
+        let offset = i * 4;
+        let bytes: [u8; 4] = [
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ];
+        out.push(f32::from_le_bytes(bytes));
+    }
+}
+
+/// Convert f16 values to f32, appending to `out`.
+pub fn dequantize_f16(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    out.reserve(numel);
+    for i in 0..numel {
+        let offset = i * 2;
+        let bytes: [u8; 2] = [data[offset], data[offset + 1]];
+        let h = half::f16::from_le_bytes(bytes);
+        out.push(h.to_f32());
+    }
+}
+
+/// Convert bf16 values to f32, appending to `out`.
+///
+/// bf16 is the upper half of an f32, so the conversion is exact.
+pub fn dequantize_bf16(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    out.reserve(numel);
+    for i in 0..numel {
+        let offset = i * 2;
+        let bytes: [u8; 2] = [data[offset], data[offset + 1]];
+        let h = half::bf16::from_le_bytes(bytes);
+        out.push(h.to_f32());
+    }
+}
+
+/// Dequantize Q4_0 blocks to f32, appending to `out`.
+///
+/// Q4_0 block layout (18 bytes total, 32 elements per block):
+///   - 2 bytes: f16 scale factor
+///   - 16 bytes: 32 packed 4-bit values; byte `j` holds element `j` in its
+///     lower nibble and element `j + 16` in its upper nibble
+///
+/// Each 4-bit value is unsigned (0..15); dequantized as: (nibble - 8) * scale.
+pub fn dequantize_q4_0(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    const BLOCK_SIZE: usize = 32;
+    const BLOCK_BYTES: usize = 18; // 2 (scale) + 16 (nibbles)
+
+    let n_blocks = numel.div_ceil(BLOCK_SIZE);
+    let start_len = out.len();
+    out.reserve(n_blocks * BLOCK_SIZE);
+
+    for block_idx in 0..n_blocks {
+        let block_start = block_idx * BLOCK_BYTES;
+
+        // Read f16 scale.
+        let scale_bytes: [u8; 2] = [data[block_start], data[block_start + 1]];
+        let scale = half::f16::from_le_bytes(scale_bytes).to_f32();
+
+        // 16 bytes of packed nibbles (32 values).
+        let qs = &data[block_start + 2..block_start + BLOCK_BYTES];
+
+        // Lower nibbles are the first half of the block.
+        for &byte in qs {
+            let lo = (byte & 0x0F) as i32 - 8;
+            out.push(lo as f32 * scale);
+        }
+
+        // Upper nibbles are the second half.
+        for &byte in qs {
+            let hi = ((byte >> 4) & 0x0F) as i32 - 8;
+            out.push(hi as f32 * scale);
+        }
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Dequantize Q4_1 blocks to f32, appending to `out`.
+///
+/// Q4_1 block layout (20 bytes total, 32 elements per block):
+///   - 2 bytes: f16 scale factor
+///   - 2 bytes: f16 block minimum
+///   - 16 bytes: 32 packed 4-bit values, laid out as in Q4_0
+///
+/// Each 4-bit value is unsigned (0..15); dequantized as: nibble * scale + min.
+pub fn dequantize_q4_1(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    const BLOCK_SIZE: usize = 32;
+    const BLOCK_BYTES: usize = 20; // 2 (scale) + 2 (min) + 16 (nibbles)
+
+    let n_blocks = numel.div_ceil(BLOCK_SIZE);
+    let start_len = out.len();
+    out.reserve(n_blocks * BLOCK_SIZE);
+
+    for block_idx in 0..n_blocks {
+        let block_start = block_idx * BLOCK_BYTES;
+        let scale = read_f16(data, block_start);
+        let min = read_f16(data, block_start + 2);
+        let qs = &data[block_start + 4..block_start + BLOCK_BYTES];
+
+        for &byte in qs {
+            out.push((byte & 0x0F) as f32 * scale + min);
+        }
+        for &byte in qs {
+            out.push((byte >> 4) as f32 * scale + min);
+        }
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Dequantize Q5_0 blocks to f32, appending to `out`.
+///
+/// Q5_0 block layout (22 bytes total, 32 elements per block):
+///   - 2 bytes: f16 scale factor
+///   - 4 bytes: little-endian u32 of fifth bits; bit `i` belongs to element `i`
+///   - 16 bytes: low 4 bits, laid out as in Q4_0
+///
+/// Each 5-bit value is unsigned (0..31); dequantized as: (q - 16) * scale.
+pub fn dequantize_q5_0(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    const BLOCK_SIZE: usize = 32;
+    const BLOCK_BYTES: usize = 22; // 2 (scale) + 4 (high bits) + 16 (nibbles)
+
+    let n_blocks = numel.div_ceil(BLOCK_SIZE);
+    let start_len = out.len();
+    out.reserve(n_blocks * BLOCK_SIZE);
+
+    for block_idx in 0..n_blocks {
+        let block_start = block_idx * BLOCK_BYTES;
+        let scale = read_f16(data, block_start);
+        let qh = read_u32(data, block_start + 2);
+        let qs = &data[block_start + 6..block_start + BLOCK_BYTES];
+
+        push_q5(qs, qh, |q| (q as i32 - 16) as f32 * scale, out);
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Dequantize Q5_1 blocks to f32, appending to `out`.
+///
+/// Q5_1 block layout (24 bytes total, 32 elements per block):
+///   - 2 bytes: f16 scale factor
+///   - 2 bytes: f16 block minimum
+///   - 4 bytes: little-endian u32 of fifth bits, as in Q5_0
+///   - 16 bytes: low 4 bits, laid out as in Q4_0
+///
+/// Each 5-bit value is unsigned (0..31); dequantized as: q * scale + min.
+pub fn dequantize_q5_1(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    const BLOCK_SIZE: usize = 32;
+    const BLOCK_BYTES: usize = 24; // 2 (scale) + 2 (min) + 4 (high bits) + 16 (nibbles)
+
+    let n_blocks = numel.div_ceil(BLOCK_SIZE);
+    let start_len = out.len();
+    out.reserve(n_blocks * BLOCK_SIZE);
+
+    for block_idx in 0..n_blocks {
+        let block_start = block_idx * BLOCK_BYTES;
+        let scale = read_f16(data, block_start);
+        let min = read_f16(data, block_start + 2);
+        let qh = read_u32(data, block_start + 4);
+        let qs = &data[block_start + 8..block_start + BLOCK_BYTES];
+
+        push_q5(qs, qh, |q| q as f32 * scale + min, out);
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Recombine the nibbles and fifth bits of one Q5_0/Q5_1 block into 5-bit
+/// values and push `decode(q)` for each, in element order.
+fn push_q5(qs: &[u8], qh: u32, decode: impl Fn(u8) -> f32, out: &mut Vec<f32>) {
+    for (j, &byte) in qs.iter().enumerate() {
+        let hi = ((qh >> j) & 1) as u8;
+        out.push(decode((byte & 0x0F) | (hi << 4)));
+    }
+    for (j, &byte) in qs.iter().enumerate() {
+        let hi = ((qh >> (j + 16)) & 1) as u8;
+        out.push(decode((byte >> 4) | (hi << 4)));
+    }
+}
+
+/// Dequantize Q8_0 blocks to f32, appending to `out`.
+///
+/// Q8_0 block layout (34 bytes total, 32 elements per block):
+///   - 2 bytes: f16 scale factor
+///   - 32 bytes: 32 signed 8-bit values
+///
+/// Dequantized as: value * scale.
+pub fn dequantize_q8_0(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    const BLOCK_SIZE: usize = 32;
+    const BLOCK_BYTES: usize = 34; // 2 (scale) + 32 (quants)
+
+    let n_blocks = numel.div_ceil(BLOCK_SIZE);
+    let start_len = out.len();
+    out.reserve(n_blocks * BLOCK_SIZE);
+
+    for block_idx in 0..n_blocks {
+        let block_start = block_idx * BLOCK_BYTES;
+
+        // Read f16 scale.
+        let scale_bytes: [u8; 2] = [data[block_start], data[block_start + 1]];
+        let scale = half::f16::from_le_bytes(scale_bytes).to_f32();
+
+        // Read 32 signed 8-bit values.
+        for i in 0..BLOCK_SIZE {
+            let val = data[block_start + 2 + i] as i8;
+            out.push(val as f32 * scale);
+        }
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Dequantize Q8_1 blocks to f32, appending to `out`.
+///
+/// Q8_1 block layout (36 bytes total, 32 elements per block):
+///   - 2 bytes: f16 scale factor
+///   - 2 bytes: f16 `scale * sum(quants)`, used by dot kernels (unused here)
+///   - 32 bytes: 32 signed 8-bit values
+///
+/// Dequantized as: value * scale.
+pub fn dequantize_q8_1(data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    const BLOCK_SIZE: usize = 32;
+    const BLOCK_BYTES: usize = 36; // 2 (scale) + 2 (sum) + 32 (quants)
+
+    let n_blocks = numel.div_ceil(BLOCK_SIZE);
+    let start_len = out.len();
+    out.reserve(n_blocks * BLOCK_SIZE);
+
+    for block_idx in 0..n_blocks {
+        let block_start = block_idx * BLOCK_BYTES;
+        let scale = read_f16(data, block_start);
+        let qs = &data[block_start + 4..block_start + BLOCK_BYTES];
+        out.extend(qs.iter().map(|&q| q as i8 as f32 * scale));
+    }
+
+    // Trim to exact element count (last block may have padding).
+    out.truncate(start_len + numel);
+}
+
+/// Encode f32 values as little-endian bytes; `out` holds `4 * x.len()` bytes.
+pub fn quantize_f32(x: &[f32], out: &mut [u8]) {
+    for (dst, &v) in out.chunks_exact_mut(4).zip(x) {
+        dst.copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+/// Round f32 values to f16; `out` holds `2 * x.len()` bytes.
+pub fn quantize_f16(x: &[f32], out: &mut [u8]) {
+    for (dst, &v) in out.chunks_exact_mut(2).zip(x) {
+        dst.copy_from_slice(&half::f16::from_f32(v).to_le_bytes());
+    }
+}
+
+/// Round f32 values to bf16; `out` holds `2 * x.len()` bytes.
+pub fn quantize_bf16(x: &[f32], out: &mut [u8]) {
+    for (dst, &v) in out.chunks_exact_mut(2).zip(x) {
+        dst.copy_from_slice(&half::bf16::from_f32(v).to_le_bytes());
+    }
+}
+
+/// Quantize f32 values into Q4_0 blocks.
+///
+/// `x.len()` must be a multiple of 32 and `out` must hold 18 bytes per
+/// block. The element with the largest magnitude maps to -8, so the scale is
+/// `d = max / -8` and each value is stored as `round(x / d) + 8`.
+pub fn quantize_q4_0(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in blocks(x, out, DType::Q4_0) {
+        let max = signed_absmax(src);
+        let d = max / -8.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        put_f16(dst, 0, d);
+        let (lo, hi) = src.split_at(QK / 2);
+        for (q, (&a, &b)) in dst[2..].iter_mut().zip(lo.iter().zip(hi)) {
+            *q = nibble(a * id + 8.5, 15) | (nibble(b * id + 8.5, 15) << 4);
+        }
+    }
+}
+
+/// Quantize f32 values into Q4_1 blocks.
+///
+/// `x.len()` must be a multiple of 32 and `out` must hold 20 bytes per
+/// block. The block's range is split into 15 steps: `d = (max - min) / 15`,
+/// `m = min`, and each value is stored as `round((x - m) / d)`.
+pub fn quantize_q4_1(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in blocks(x, out, DType::Q4_1) {
+        let (min, max) = min_max(src);
+        let d = (max - min) / 15.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        put_f16(dst, 0, d);
+        put_f16(dst, 2, min);
+        let (lo, hi) = src.split_at(QK / 2);
+        for (q, (&a, &b)) in dst[4..].iter_mut().zip(lo.iter().zip(hi)) {
+            *q = nibble((a - min) * id + 0.5, 15) | (nibble((b - min) * id + 0.5, 15) << 4);
+        }
+    }
+}
+
+/// Quantize f32 values into Q5_0 blocks.
+///
+/// `x.len()` must be a multiple of 32 and `out` must hold 22 bytes per
+/// block. As Q4_0 with 5-bit values: `d = max / -16` and each value is
+/// stored as `round(x / d) + 16`.
+pub fn quantize_q5_0(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in blocks(x, out, DType::Q5_0) {
+        let max = signed_absmax(src);
+        let d = max / -16.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        put_f16(dst, 0, d);
+        let qh = pack_q5(src, |v| nibble(v * id + 16.5, 31), &mut dst[6..]);
+        dst[2..6].copy_from_slice(&qh.to_le_bytes());
+    }
+}
+
+/// Quantize f32 values into Q5_1 blocks.
+///
+/// `x.len()` must be a multiple of 32 and `out` must hold 24 bytes per
+/// block. As Q4_1 with 5-bit values: `d = (max - min) / 31`, `m = min`.
+pub fn quantize_q5_1(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in blocks(x, out, DType::Q5_1) {
+        let (min, max) = min_max(src);
+        let d = (max - min) / 31.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        put_f16(dst, 0, d);
+        put_f16(dst, 2, min);
+        let qh = pack_q5(src, |v| nibble((v - min) * id + 0.5, 31), &mut dst[8..]);
+        dst[4..8].copy_from_slice(&qh.to_le_bytes());
+    }
+}
+
+/// Quantize f32 values into Q8_0 blocks; see
+/// [`quantize_row_q8_0`](crate::cpu::quant::quantize_row_q8_0).
+pub fn quantize_q8_0(x: &[f32], out: &mut [u8]) {
+    quant::quantize_row_q8_0(x, out);
+}
+
+/// Quantize f32 values into Q8_1 blocks.
+///
+/// `x.len()` must be a multiple of 32 and `out` must hold 36 bytes per
+/// block. The quants are those of Q8_0, followed by `d * sum(q)` in the
+/// block header for dot kernels that need the block sum.
+pub fn quantize_q8_1(x: &[f32], out: &mut [u8]) {
+    for (src, dst) in blocks(x, out, DType::Q8_1) {
+        let amax = src.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        let d = amax / 127.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        let mut sum = 0i32;
+        for (q, &v) in dst[4..].iter_mut().zip(src) {
+            let v = (v * id).round() as i8;
+            sum += v as i32;
+            *q = v as u8;
+        }
+        put_f16(dst, 0, d);
+        put_f16(dst, 2, d * sum as f32);
+    }
+}
+
+/// Pairs of 32-element blocks of `x` and their encoded bytes in `out`.
+fn blocks<'a>(
+    x: &'a [f32],
+    out: &'a mut [u8],
+    dtype: DType,
+) -> impl Iterator<Item = (&'a [f32], &'a mut [u8])> {
+    debug_assert_eq!(x.len() % QK, 0);
+    debug_assert_eq!(out.len(), dtype.storage_size(x.len()));
+    x.chunks_exact(QK)
+        .zip(out.chunks_exact_mut(dtype.size_in_bytes()))
+}
+
+/// Smallest and largest value of `x`.
+fn min_max(x: &[f32]) -> (f32, f32) {
+    x.iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        })
+}
+
+/// Truncate `v` (already offset by +0.5 for rounding) to an integer in
+/// `0..=max`.
+fn nibble(v: f32, max: u8) -> u8 {
+    (v.max(0.0) as u8).min(max)
+}
+
+/// Store the low four bits of 5-bit quants in `qs`, laid out as in Q4_0,
+/// and return the fifth bits as the `qh` word.
+fn pack_q5(x: &[f32], quantize: impl Fn(f32) -> u8, qs: &mut [u8]) -> u32 {
+    let mut qh = 0u32;
+    let (lo, hi) = x.split_at(QK / 2);
+    for (j, (&a, &b)) in lo.iter().zip(hi).enumerate() {
+        let (a, b) = (quantize(a), quantize(b));
+        qs[j] = (a & 0x0F) | ((b & 0x0F) << 4);
+        qh |= ((a >> 4) as u32) << j;
+        qh |= ((b >> 4) as u32) << (j + QK / 2);
+    }
+    qh
+}
@@ -0,0 +1,589 @@
+//! Conversion between f32 and every storage [`DType`].
+//!
+//! Each dtype has a `quantize_*` function that encodes whole blocks of f32
+//! values into the GGUF byte layout, and a `dequantize_*` function that
+//! decodes them again, appending to a `Vec<f32>`. [`quantize`] and
+//! [`dequantize`] dispatch on the dtype and accept any element count,
+//! padding the last block with zeros. [`ErrorMetrics`] measures how much a
+//! round trip loses.
+//!
+//! The encodings use ggml's block layouts, so tensors quantized here load in
+//! llama.cpp and GGUF tensors from elsewhere decode here.
+
+mod k_quants;
+mod legacy;
+
+pub use k_quants::{
+    dequantize_q2_k, dequantize_q3_k, dequantize_q4_k, dequantize_q5_k, dequantize_q6_k,
+    dequantize_q8_k, quantize_q2_k, quantize_q3_k, quantize_q4_k, quantize_q5_k, quantize_q6_k,
+    quantize_q8_k,
+};
+pub use legacy::{
+    dequantize_bf16, dequantize_f16, dequantize_f32, dequantize_q4_0, dequantize_q4_1,
+    dequantize_q5_0, dequantize_q5_1, dequantize_q8_0, dequantize_q8_1, quantize_bf16,
+    quantize_f16, quantize_f32, quantize_q4_0, quantize_q4_1, quantize_q5_0, quantize_q5_1,
+    quantize_q8_0, quantize_q8_1,
+};
+
+use crate::dtype::DType;
+use crate::error::{Result, TensorError};
+
+/// Decode `numel` elements of `dtype`-encoded data to f32, appending them
+/// to `out`.
+///
+/// # Panics
+/// Panics if `data` is shorter than `dtype.storage_size(numel)`; use
+/// [`dequantize`] for untrusted input.
+pub fn dequantize_into(dtype: DType, data: &[u8], numel: usize, out: &mut Vec<f32>) {
+    let needed = dtype.storage_size(numel);
+    assert!(
+        data.len() >= needed,
+        "{} elements of {} take {} bytes, got {}",
+        numel,
+        dtype,
+        needed,
+        data.len()
+    );
+    match dtype {
+        DType::F32 => dequantize_f32(data, numel, out),
+        DType::F16 => dequantize_f16(data, numel, out),
+        DType::BF16 => dequantize_bf16(data, numel, out),
+        DType::Q4_0 => dequantize_q4_0(data, numel, out),
+        DType::Q4_1 => dequantize_q4_1(data, numel, out),
+        DType::Q5_0 => dequantize_q5_0(data, numel, out),
+        DType::Q5_1 => dequantize_q5_1(data, numel, out),
+        DType::Q8_0 => dequantize_q8_0(data, numel, out),
+        DType::Q8_1 => dequantize_q8_1(data, numel, out),
+        DType::Q2K => dequantize_q2_k(data, numel, out),
+        DType::Q3K => dequantize_q3_k(data, numel, out),
+        DType::Q4K => dequantize_q4_k(data, numel, out),
+        DType::Q5K => dequantize_q5_k(data, numel, out),
+        DType::Q6K => dequantize_q6_k(data, numel, out),
+        DType::Q8K => dequantize_q8_k(data, numel, out),
+    }
+}
+
+/// Decode `numel` elements of `dtype`-encoded data to f32.
+///
+/// # Errors
+/// Returns an error if `data` is not `dtype.storage_size(numel)` bytes.
+pub fn dequantize(dtype: DType, data: &[u8], numel: usize) -> Result<Vec<f32>> {
+    check_len(dtype, data.len(), numel)?;
+    let mut out = Vec::with_capacity(numel);
+    dequantize_into(dtype, data, numel, &mut out);
+    Ok(out)
+}
+
+/// Encode `x` as `dtype` into `out`, padding a partial last block with
+/// zeros.
+///
+/// # Errors
+/// Returns an error if `out` is not `dtype.storage_size(x.len())` bytes.
+pub fn quantize_into(dtype: DType, x: &[f32], out: &mut [u8]) -> Result<()> {
+    check_len(dtype, out.len(), x.len())?;
+    let block = dtype.block_size();
+    let whole = x.len() / block * block;
+    let (head, tail) = out.split_at_mut(dtype.storage_size(whole));
+    encode(dtype, &x[..whole], head);
+    if whole < x.len() {
+        let mut last = vec![0.0f32; block];
+        last[..x.len() - whole].copy_from_slice(&x[whole..]);
+        encode(dtype, &last, tail);
+    }
+    Ok(())
+}
+
+/// Encode `x` as `dtype`, padding a partial last block with zeros.
+pub fn quantize(dtype: DType, x: &[f32]) -> Vec<u8> {
+    let mut out = vec![0u8; dtype.storage_size(x.len())];
+    quantize_into(dtype, x, &mut out).expect("buffer sized by storage_size");
+    out
+}
+
+/// Encode whole blocks of `x` into `out`.
+fn encode(dtype: DType, x: &[f32], out: &mut [u8]) {
+    match dtype {
+        DType::F32 => quantize_f32(x, out),
+        DType::F16 => quantize_f16(x, out),
+        DType::BF16 => quantize_bf16(x, out),
+        DType::Q4_0 => quantize_q4_0(x, out),
+        DType::Q4_1 => quantize_q4_1(x, out),
+        DType::Q5_0 => quantize_q5_0(x, out),
+        DType::Q5_1 => quantize_q5_1(x, out),
+        DType::Q8_0 => quantize_q8_0(x, out),
+        DType::Q8_1 => quantize_q8_1(x, out),
+        DType::Q2K => quantize_q2_k(x, out),
+        DType::Q3K => quantize_q3_k(x, out),
+        DType::Q4K => quantize_q4_k(x, out),
+        DType::Q5K => quantize_q5_k(x, out),
+        DType::Q6K => quantize_q6_k(x, out),
+        DType::Q8K => quantize_q8_k(x, out),
+    }
+}
+
+fn check_len(dtype: DType, bytes: usize, numel: usize) -> Result<()> {
+    let expected = dtype.storage_size(numel);
+    if bytes != expected {
+        return Err(TensorError::Other(format!(
+            "{} elements of {} take {} bytes, got {}",
+            numel, dtype, expected, bytes
+        )));
+    }
+    Ok(())
+}
+
+/// How far a dequantized tensor is from the original.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ErrorMetrics {
+    /// Root mean square of the element-wise differences.
+    pub rmse: f32,
+    /// Largest absolute element-wise difference.
+    pub max_abs: f32,
+}
+
+impl ErrorMetrics {
+    /// Compare `restored` against `original` element by element.
+    ///
+    /// # Panics
+    /// Panics if the slices differ in length.
+    pub fn compare(original: &[f32], restored: &[f32]) -> Self {
+        assert_eq!(original.len(), restored.len(), "length mismatch");
+        if original.is_empty() {
+            return Self::default();
+        }
+        let mut sum_sq = 0.0f64;
+        let mut max_abs = 0.0f32;
+        for (&a, &b) in original.iter().zip(restored) {
+            let diff = (a - b).abs();
+            sum_sq += diff as f64 * diff as f64;
+            max_abs = max_abs.max(diff);
+        }
+        ErrorMetrics {
+            rmse: (sum_sq / original.len() as f64).sqrt() as f32,
+            max_abs,
+        }
+    }
+}
+
+/// Quantize `x` as `dtype`, decode it again and measure the error.
+pub fn round_trip_error(dtype: DType, x: &[f32]) -> ErrorMetrics {
+    let data = quantize(dtype, x);
+    let mut restored = Vec::with_capacity(x.len());
+    dequantize_into(dtype, &data, x.len(), &mut restored);
+    ErrorMetrics::compare(x, &restored)
+}
+
+/// Read a little-endian f16 at `offset` as f32.
+fn read_f16(data: &[u8], offset: usize) -> f32 {
+    half::f16::from_le_bytes([data[offset], data[offset + 1]]).to_f32()
+}
+
+/// Read a little-endian u32 at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Write `v` as a little-endian f16 at `offset`.
+fn put_f16(data: &mut [u8], offset: usize, v: f32) {
+    data[offset..offset + 2].copy_from_slice(&half::f16::from_f32(v).to_le_bytes());
+}
+
+/// The value with the largest magnitude, keeping its sign.
+fn signed_absmax(x: &[f32]) -> f32 {
+    x.iter()
+        .fold(0.0f32, |m, &v| if v.abs() > m.abs() { v } else { m })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [DType; 15] = [
+        DType::F32,
+        DType::F16,
+        DType::BF16,
+        DType::Q4_0,
+        DType::Q4_1,
+        DType::Q5_0,
+        DType::Q5_1,
+        DType::Q8_0,
+        DType::Q8_1,
+        DType::Q2K,
+        DType::Q3K,
+        DType::Q4K,
+        DType::Q5K,
+        DType::Q6K,
+        DType::Q8K,
+    ];
+
+    /// Deterministic values in roughly -2..2 with some larger outliers.
+    fn sample(n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| {
+                let x = (i as f32 * 0.731).sin() * 2.0;
+                if i % 97 == 0 { x * 4.0 } else { x }
+            })
+            .collect()
+    }
+
+    fn decode_block(dtype: DType, block: &[u8]) -> Vec<f32> {
+        let mut out = Vec::new();
+        dequantize_into(dtype, block, dtype.block_size(), &mut out);
+        out
+    }
+
+    #[test]
+    fn test_bf16_is_exact() {
+        let values = [1.0f32, -2.5, 3.140625, 65504.0, 1.0e30];
+        let data: Vec<u8> = values
+            .iter()
+            .flat_map(|v| half::bf16::from_f32(*v).to_le_bytes())
+            .collect();
+        let mut out = Vec::new();
+        dequantize_into(DType::BF16, &data, values.len(), &mut out);
+        for (got, v) in out.iter().zip(values) {
+            assert_eq!(*got, half::bf16::from_f32(v).to_f32());
+        }
+        // Values with at most 8 significant bits survive unchanged.
+        assert_eq!(&out[..3], &[1.0, -2.5, 3.140625]);
+    }
+
+    #[test]
+    fn test_q4_0_nibble_layout() {
+        let mut block = vec![0u8; 18];
+        put_f16(&mut block, 0, 1.0);
+        // Element 0 in the low nibble, element 16 in the high nibble.
+        block[2] = 0xA3;
+        let y = decode_block(DType::Q4_0, &block);
+        assert_eq!(y[0], 3.0 - 8.0);
+        assert_eq!(y[16], 10.0 - 8.0);
+        assert_eq!(y[1], -8.0);
+    }
+
+    #[test]
+    fn test_q4_1_block() {
+        let mut block = vec![0u8; 20];
+        put_f16(&mut block, 0, 0.5);
+        put_f16(&mut block, 2, -3.0);
+        block[4] = 0xF2;
+        let y = decode_block(DType::Q4_1, &block);
+        assert_eq!(y[0], 2.0 * 0.5 - 3.0);
+        assert_eq!(y[16], 15.0 * 0.5 - 3.0);
+        assert_eq!(y[1], -3.0);
+    }
+
+    #[test]
+    fn test_q5_0_block() {
+        let mut block = vec![0u8; 22];
+        put_f16(&mut block, 0, 2.0);
+        // Fifth bits for element 0 and element 17.
+        block[2..6].copy_from_slice(&(1u32 | (1 << 17)).to_le_bytes());
+        block[6] = 0x31;
+        block[7] = 0x40;
+        let y = decode_block(DType::Q5_0, &block);
+        assert_eq!(y[0], (17.0 - 16.0) * 2.0);
+        assert_eq!(y[16], (3.0 - 16.0) * 2.0);
+        assert_eq!(y[17], (20.0 - 16.0) * 2.0);
+        assert_eq!(y[1], -32.0);
+    }
+
+    #[test]
+    fn test_q5_1_block() {
+        let mut block = vec![0u8; 24];
+        put_f16(&mut block, 0, 0.25);
+        put_f16(&mut block, 2, 1.0);
+        block[4..8].copy_from_slice(&(1u32 << 31).to_le_bytes());
+        block[8 + 15] = 0xF0;
+        let y = decode_block(DType::Q5_1, &block);
+        assert_eq!(y[31], 31.0 * 0.25 + 1.0);
+        assert_eq!(y[15], 1.0);
+    }
+
+    #[test]
+    fn test_q8_1_block() {
+        let mut block = vec![0u8; 36];
+        put_f16(&mut block, 0, 0.5);
+        put_f16(&mut block, 2, 100.0);
+        block[4] = (-6i8) as u8;
+        block[35] = 9;
+        let y = decode_block(DType::Q8_1, &block);
+        assert_eq!(y[0], -3.0);
+        assert_eq!(y[31], 4.5);
+    }
+
+    #[test]
+    fn test_q2_k_block() {
+        let mut block = vec![0u8; 84];
+        // Sub-block 0: scale 3, min 1. Sub-block 1: scale 2, min 0.
+        block[0] = 0x13;
+        block[1] = 0x02;
+        // Sub-block 2 (elements 32..48) uses bit pair 1 of qs[0..16].
+        block[2] = 0x25;
+        // qs[0] = 0b_01_11 -> element 0 = 3, element 32 = 1.
+        block[16] = 0b0000_0111;
+        // qs[16] = 2 -> element 16 (sub-block 1) = 2.
+        block[32] = 0b10;
+        put_f16(&mut block, 80, 0.5);
+        put_f16(&mut block, 82, 2.0);
+
+        let y = decode_block(DType::Q2K, &block);
+        assert_eq!(y.len(), 256);
+        assert_eq!(y[0], 0.5 * 3.0 * 3.0 - 2.0 * 1.0);
+        assert_eq!(y[1], -2.0);
+        assert_eq!(y[16], 0.5 * 2.0 * 2.0);
+        assert_eq!(y[32], 0.5 * 5.0 * 1.0 - 2.0 * 2.0);
+    }
+
+    #[test]
+    fn test_q3_k_block() {
+        let mut block = vec![0u8; 110];
+        // High bit set for element 0 (bit 0 of hmask[0]) and element 160
+        // (bit 5 of hmask[0]: second half, bit pair 1).
+        block[0] = 0b0010_0001;
+        // Element 0: low bits 3. Element 1: low bits 1, high bit clear.
+        block[32] = 3;
+        block[33] = 1;
+        // Element 160: qs[32] bit pair 1 = 2.
+        block[64] = 0b1000;
+        // Scale 0: low nibble 4 in byte 0, high bits 2 in byte 8 -> 36 - 32 = 4.
+        // Scale 10 (elements 160..176): low nibble 15 in the high nibble of
+        // byte 2, high bits 1 in bits 4..6 of byte 10 -> 31 - 32 = -1.
+        block[96] = 0x04;
+        block[98] = 0xF0;
+        block[104] = 0x02;
+        block[106] = 0x10;
+        put_f16(&mut block, 108, 0.25);
+
+        let y = decode_block(DType::Q3K, &block);
+        assert_eq!(y[0], 0.25 * 4.0 * 3.0);
+        assert_eq!(y[1], 0.25 * 4.0 * (1.0 - 4.0));
+        assert_eq!(y[160], -0.25 * 2.0);
+        // Scale 1 decodes to 0 - 32.
+        assert_eq!(y[16], 0.25 * -32.0 * -4.0);
+    }
+
+    #[test]
+    fn test_q4_k_block() {
+        let mut block = vec![0u8; 144];
+        put_f16(&mut block, 0, 2.0);
+        put_f16(&mut block, 2, 1.0);
+        // Pair 0: scale 3, min 1. Pair 1: scale 5, min 2.
+        block[4] = 3;
+        block[5] = 5;
+        block[8] = 1;
+        block[9] = 2;
+        // Pair 4 (elements 128..160): scale 6 | (1 << 4) = 22, min 7 | (2 << 4) = 39.
+        block[4 + 8] = 0x76;
+        block[4] |= 1 << 6;
+        block[8] |= 2 << 6;
+        // qs[0]: element 0 = 0xA, element 32 = 0x5.
+        block[16] = 0x5A;
+        // qs[64]: element 128 = 1.
+        block[16 + 64] = 0x01;
+
+        let y = decode_block(DType::Q4K, &block);
+        assert_eq!(y[0], 2.0 * 3.0 * 10.0 - 1.0);
+        assert_eq!(y[32], 2.0 * 5.0 * 5.0 - 2.0);
+        assert_eq!(y[1], -1.0);
+        assert_eq!(y[128], 2.0 * 22.0 * 1.0 - 39.0);
+    }
+
+    #[test]
+    fn test_q5_k_block() {
+        let mut block = vec![0u8; 176];
+        put_f16(&mut block, 0, 1.0);
+        put_f16(&mut block, 2, 0.5);
+        // Pairs 0..4: scale 1, min 2.
+        for j in 0..4 {
+            block[4 + j] = 1;
+            block[8 + j] = 2;
+        }
+        // Fifth bits: element 0 (bit 0) and element 96 (bit 3) of qh[0].
+        block[16] = 0b1001;
+        // qs[0]: element 0 low = 3, element 32 low = 4.
+        block[48] = 0x43;
+        // qs[32]: element 96 low (upper nibble of chunk 1) = 7.
+        block[48 + 32] = 0x70;
+
+        let y = decode_block(DType::Q5K, &block);
+        assert_eq!(y[0], (3.0 + 16.0) - 1.0);
+        assert_eq!(y[32], 4.0 - 1.0);
+        assert_eq!(y[96], (7.0 + 16.0) - 1.0);
+    }
+
+    #[test]
+    fn test_q6_k_block() {
+        let mut block = vec![0u8; 210];
+        // ql[0]: element 0 low = 1, element 64 low = 2.
+        block[0] = 0x21;
+        // ql[32]: element 32 low = 15.
+        block[32] = 0x0F;
+        // qh[0]: element 0 high = 2, element 32 high = 1, element 96 high = 3.
+        block[128] = 0b11_00_01_10;
+        // Scales for elements 0..16, 32..48, 64..80, 96..112.
+        block[192] = 2;
+        block[194] = (-1i8) as u8;
+        block[196] = 4;
+        block[198] = 1;
+        put_f16(&mut block, 208, 0.5);
+
+        let y = decode_block(DType::Q6K, &block);
+        assert_eq!(y[0], 0.5 * 2.0 * ((1 | (2 << 4)) - 32) as f32);
+        assert_eq!(y[32], -0.5 * ((15 | (1 << 4)) - 32) as f32);
+        assert_eq!(y[64], 0.5 * 4.0 * (2 - 32) as f32);
+        assert_eq!(y[96], 0.5 * 1.0 * ((3 << 4) - 32) as f32);
+    }
+
+    #[test]
+    fn test_q8_k_block() {
+        let mut block = vec![0u8; 292];
+        block[..4].copy_from_slice(&0.125f32.to_le_bytes());
+        block[4] = 8;
+        block[4 + 255] = (-16i8) as u8;
+
+        let y = decode_block(DType::Q8K, &block);
+        assert_eq!(y[0], 1.0);
+        assert_eq!(y[255], -2.0);
+        assert_eq!(y[1], 0.0);
+    }
+
+    #[test]
+    fn test_k_quant_partial_numel() {
+        let block = vec![0u8; 2 * 144];
+        let mut out = vec![1.0];
+        dequantize_into(DType::Q4K, &block, 300, &mut out);
+        assert_eq!(out.len(), 301);
+    }
+
+    #[test]
+    fn test_round_trip_error_per_dtype() {
+        // Bounds sit about 1.5x above the measured errors on `sample`, so
+        // they catch layout mistakes without pinning the exact rounding.
+        let bounds: [(DType, f32, f32); 15] = [
+            (DType::F32, 0.0, 0.0),
+            (DType::F16, 4e-4, 3e-3),
+            (DType::BF16, 3e-3, 2e-2),
+            (DType::Q4_0, 0.22, 0.73),
+            (DType::Q4_1, 0.15, 0.49),
+            (DType::Q5_0, 0.1, 0.36),
+            (DType::Q5_1, 0.075, 0.24),
+            (DType::Q8_0, 0.013, 0.046),
+            (DType::Q8_1, 0.013, 0.046),
+            (DType::Q2K, 0.6, 2.3),
+            (DType::Q3K, 0.38, 1.44),
+            (DType::Q4K, 0.15, 0.49),
+            (DType::Q5K, 0.075, 0.24),
+            (DType::Q6K, 0.04, 0.17),
+            (DType::Q8K, 0.026, 0.046),
+        ];
+        let x = sample(4 * 256);
+        for (dtype, rmse, max_abs) in bounds {
+            let e = round_trip_error(dtype, &x);
+            assert!(e.rmse <= rmse, "{}: rmse {}", dtype, e.rmse);
+            assert!(e.max_abs <= max_abs, "{}: max_abs {}", dtype, e.max_abs);
+        }
+    }
+
+    #[test]
+    fn test_more_bits_means_less_error() {
+        let x = sample(4 * 256);
+        let rmse = |dtype| round_trip_error(dtype, &x).rmse;
+        for pair in [
+            DType::Q4_0,
+            DType::Q5_0,
+            DType::Q8_0,
+            DType::BF16,
+            DType::F16,
+            DType::F32,
+        ]
+        .windows(2)
+        {
+            assert!(rmse(pair[1]) < rmse(pair[0]), "{} vs {}", pair[0], pair[1]);
+        }
+        for pair in [DType::Q2K, DType::Q3K, DType::Q4K, DType::Q5K, DType::Q6K].windows(2) {
+            assert!(rmse(pair[1]) < rmse(pair[0]), "{} vs {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_zeros_round_trip_exactly() {
+        let x = vec![0.0f32; 256];
+        for dtype in ALL {
+            assert_eq!(
+                round_trip_error(dtype, &x),
+                ErrorMetrics::default(),
+                "{}",
+                dtype
+            );
+        }
+    }
+
+    #[test]
+    fn test_partial_block_is_padded() {
+        let x = sample(300);
+        for dtype in ALL {
+            let data = quantize(dtype, &x);
+            assert_eq!(data.len(), dtype.storage_size(300), "{}", dtype);
+            let y = dequantize(dtype, &data, x.len()).unwrap();
+            assert_eq!(y.len(), 300);
+            let e = ErrorMetrics::compare(&x, &y);
+            assert!(e.max_abs < 2.0, "{}: max_abs {}", dtype, e.max_abs);
+        }
+    }
+
+    #[test]
+    fn test_q8_0_matches_activation_quantizer() {
+        let x = sample(64);
+        let mut want = vec![0u8; DType::Q8_0.storage_size(64)];
+        crate::cpu::quant::quantize_row_q8_0(&x, &mut want);
+        assert_eq!(quantize(DType::Q8_0, &x), want);
+    }
+
+    #[test]
+    fn test_q8_k_block_sums() {
+        let x = sample(256);
+        let data = quantize(DType::Q8K, &x);
+        for g in 0..16 {
+            let sum: i16 = data[4 + 16 * g..4 + 16 * (g + 1)]
+                .iter()
+                .map(|&q| q as i8 as i16)
+                .sum();
+            let stored = i16::from_le_bytes([data[260 + 2 * g], data[261 + 2 * g]]);
+            assert_eq!(stored, sum);
+        }
+    }
+
+    #[test]
+    fn test_length_checks() {
+        assert!(dequantize(DType::Q4_0, &[0u8; 17], 32).is_err());
+        assert!(dequantize(DType::F16, &[0u8; 4], 3).is_err());
+        let mut out = [0u8; 18];
+        assert!(quantize_into(DType::Q4_0, &[0.0; 33], &mut out).is_err());
+        assert!(quantize_into(DType::Q4_0, &[0.0; 32], &mut out).is_ok());
+    }
+
+    #[test]
+    fn test_dequantize_into_panics_on_short_data() {
+        for dtype in ALL {
+            let numel = 2 * dtype.block_size();
+            let data = vec![0u8; dtype.storage_size(numel) - 1];
+            let result = std::panic::catch_unwind(|| {
+                dequantize_into(dtype, &data, numel, &mut Vec::new());
+            });
+            assert!(result.is_err(), "{}", dtype);
+        }
+    }
+
+    #[test]
+    fn test_error_metrics() {
+        let e = ErrorMetrics::compare(&[1.0, 2.0, 3.0, 4.0], &[1.0, 2.5, 3.0, 3.5]);
+        assert_eq!(e.max_abs, 0.5);
+        assert!((e.rmse - (0.125f32).sqrt()).abs() < 1e-7);
+        assert_eq!(ErrorMetrics::compare(&[], &[]), ErrorMetrics::default());
+    }
+}
@@ -0,0 +1,530 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::error::{Result, TensorError};
+
+/// How rotary embeddings pair up the elements of a head.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RopeStyle {
+    /// Rotate adjacent pairs `(x[2i], x[2i + 1])`, as in the original LLaMA
+    /// checkpoints converted to GGUF.
+    #[default]
+    Interleaved,
+    /// Rotate `(x[i], x[i + n_dims / 2])`, the GPT-NeoX half-split layout
+    /// used by Qwen, Phi, Falcon and others.
+    NeoX,
+}
+
+/// Frequency adjustment that stretches RoPE past the context length a
+/// model was trained with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RopeScaling {
+    /// Unscaled frequencies.
+    #[default]
+    None,
+    /// Position interpolation: every frequency is divided by `factor`.
+    Linear { factor: f32 },
+    /// NTK-aware scaling: the base grows to
+    /// `freq_base * factor^(n_dims / (n_dims - 2))`, which stretches low
+    /// frequencies by about `factor` and leaves high ones nearly unchanged.
+    Ntk { factor: f32 },
+    /// YaRN: frequencies whose wavelength fits in the original context many
+    /// times (more than `beta_fast` rotations) are kept, those with fewer
+    /// than `beta_slow` rotations are interpolated by `factor`, with a
+    /// linear ramp in between. cos/sin are also scaled by
+    /// `attn_factor * (1 + 0.1 * ln(factor))` to keep attention entropy
+    /// stable.
+    Yarn {
+        factor: f32,
+        original_context: usize,
+        beta_fast: f32,
+        beta_slow: f32,
+        attn_factor: f32,
+    },
+    /// Llama 3.1: wavelengths shorter than `original_context /
+    /// high_freq_factor` are kept, those longer than `original_context /
+    /// low_freq_factor` are divided by `factor`, and the band in between is
+    /// smoothly interpolated.
+    Llama3 {
+        factor: f32,
+        low_freq_factor: f32,
+        high_freq_factor: f32,
+        original_context: usize,
+    },
+}
+
+impl RopeScaling {
+    /// YaRN with the reference defaults: `beta_fast = 32`, `beta_slow = 1`
+    /// and no extra attention factor.
+    pub fn yarn(factor: f32, original_context: usize) -> Self {
+        RopeScaling::Yarn {
+            factor,
+            original_context,
+            beta_fast: 32.0,
+            beta_slow: 1.0,
+            attn_factor: 1.0,
+        }
+    }
+
+    /// Llama 3.1 scaling with the parameters Meta ships: `factor = 8`,
+    /// `low_freq_factor = 1`, `high_freq_factor = 4` over an original
+    /// context of 8192.
+    pub fn llama3() -> Self {
+        RopeScaling::Llama3 {
+            factor: 8.0,
+            low_freq_factor: 1.0,
+            high_freq_factor: 4.0,
+            original_context: 8192,
+        }
+    }
+
+    fn factor(&self) -> f32 {
+        match *self {
+            RopeScaling::None => 1.0,
+            RopeScaling::Linear { factor }
+            | RopeScaling::Ntk { factor }
+            | RopeScaling::Yarn { factor, .. }
+            | RopeScaling::Llama3 { factor, .. } => factor,
+        }
+    }
+}
+
+/// Rotary position embedding parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RopeConfig {
+    /// Dimension of each attention head.
+    pub head_dim: usize,
+    /// Number of leading dimensions of each head that are rotated. The
+    /// remaining `head_dim - n_dims` dimensions pass through unchanged.
+    pub n_dims: usize,
+    /// Frequency base (theta); pair `i` rotates at `freq_base^(-2i / n_dims)`
+    /// radians per position.
+    pub freq_base: f32,
+    /// Element pairing.
+    pub style: RopeStyle,
+    /// Context-extension scaling.
+    pub scaling: RopeScaling,
+    /// Per-pair divisors applied on top of `scaling`, one per rotated pair
+    /// (GGUF `rope_freqs.weight`).
+    pub freq_factors: Option<Arc<[f32]>>,
+}
+
+impl RopeConfig {
+    /// Rotate all of `head_dim` with the original LLaMA base of 10000 and
+    /// interleaved pairs.
+    pub fn new(head_dim: usize) -> Self {
+        RopeConfig {
+            head_dim,
+            n_dims: head_dim,
+            freq_base: 10000.0,
+            style: RopeStyle::Interleaved,
+            scaling: RopeScaling::None,
+            freq_factors: None,
+        }
+    }
+
+    /// Set the frequency base.
+    pub fn with_freq_base(mut self, freq_base: f32) -> Self {
+        self.freq_base = freq_base;
+        self
+    }
+
+    /// Set the number of rotated dimensions.
+    pub fn with_dims(mut self, n_dims: usize) -> Self {
+        self.n_dims = n_dims;
+        self
+    }
+
+    /// Set the element pairing.
+    pub fn with_style(mut self, style: RopeStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Set the context-extension scaling.
+    pub fn with_scaling(mut self, scaling: RopeScaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    /// Set per-pair frequency divisors.
+    pub fn with_freq_factors(mut self, factors: impl Into<Arc<[f32]>>) -> Self {
+        self.freq_factors = Some(factors.into());
+        self
+    }
+
+    /// Check that the dimensions and base describe a valid rotation.
+    pub fn validate(&self) -> Result<()> {
+        if !self.n_dims.is_multiple_of(2) || self.n_dims > self.head_dim {
+            return Err(TensorError::Other(format!(
+                "rope: n_dims={} must be even and at most head_dim={}",
+                self.n_dims, self.head_dim
+            )));
+        }
+        if !(self.freq_base > 0.0 && self.freq_base.is_finite()) {
+            return Err(TensorError::Other(format!(
+                "rope: invalid freq_base {}",
+                self.freq_base
+            )));
+        }
+        let factor = self.scaling.factor();
+        if !(factor > 0.0 && factor.is_finite()) {
+            return Err(TensorError::Other(format!(
+                "rope: invalid scaling factor {}",
+                factor
+            )));
+        }
+        match self.scaling {
+            RopeScaling::Ntk { .. } if self.n_dims <= 2 => {
+                return Err(TensorError::Other(
+                    "rope: NTK scaling needs more than 2 rotated dimensions".to_string(),
+                ));
+            }
+            RopeScaling::Yarn {
+                original_context: 0,
+                ..
+            }
+            | RopeScaling::Llama3 {
+                original_context: 0,
+                ..
+            } => {
+                return Err(TensorError::Other(
+                    "rope: original_context must be non-zero".to_string(),
+                ));
+            }
+            RopeScaling::Llama3 {
+                low_freq_factor,
+                high_freq_factor,
+                ..
+            } if !(0.0 < low_freq_factor && low_freq_factor < high_freq_factor) => {
+                return Err(TensorError::Other(format!(
+                    "rope: need 0 < low_freq_factor ({}) < high_freq_factor ({})",
+                    low_freq_factor, high_freq_factor
+                )));
+            }
+            _ => {}
+        }
+        if let Some(factors) = &self.freq_factors
+            && factors.len() != self.n_dims / 2
+        {
+            return Err(TensorError::Other(format!(
+                "rope: {} freq_factors for {} rotated pairs",
+                factors.len(),
+                self.n_dims / 2
+            )));
+        }
+        Ok(())
+    }
+
+    /// Rotation speed of pair `i` in radians per position, after scaling
+    /// and frequency factors.
+    pub fn inv_freq(&self, i: usize) -> f64 {
+        let d = self.n_dims as f64;
+        let exponent = -2.0 * i as f64 / d;
+        let base = self.freq_base as f64;
+        let unscaled = base.powf(exponent);
+
+        let scaled = match self.scaling {
+            RopeScaling::None => unscaled,
+            RopeScaling::Linear { factor } => unscaled / factor as f64,
+            RopeScaling::Ntk { factor } => {
+                (base * (factor as f64).powf(d / (d - 2.0))).powf(exponent)
+            }
+            RopeScaling::Yarn {
+                factor,
+                original_context,
+                beta_fast,
+                beta_slow,
+                ..
+            } => {
+                // Pair index at which a frequency completes `rotations`
+                // turns over the original context.
+                let pair_for = |rotations: f32| {
+                    d * (original_context as f64 / (rotations as f64 * 2.0 * PI)).ln()
+                        / (2.0 * base.ln())
+                };
+                let low = pair_for(beta_fast).floor().max(0.0);
+                let mut high = pair_for(beta_slow).ceil().min(d - 1.0);
+                if low == high {
+                    high += 0.001;
+                }
+                // 0 keeps the original frequency, 1 fully interpolates.
+                let ramp = ((i as f64 - low) / (high - low)).clamp(0.0, 1.0);
+                let interpolated = unscaled / factor as f64;
+                interpolated * ramp + unscaled * (1.0 - ramp)
+            }
+            RopeScaling::Llama3 {
+                factor,
+                low_freq_factor,
+                high_freq_factor,
+                original_context,
+            } => {
+                let (factor, low, high) = (
+                    factor as f64,
+                    low_freq_factor as f64,
+                    high_freq_factor as f64,
+                );
+                let context = original_context as f64;
+                let wavelen = 2.0 * PI / unscaled;
+                if wavelen < context / high {
+                    unscaled
+                } else if wavelen > context / low {
+                    unscaled / factor
+                } else {
+                    let smooth = (context / wavelen - low) / (high - low);
+                    (1.0 - smooth) * unscaled / factor + smooth * unscaled
+                }
+            }
+        };
+
+        match &self.freq_factors {
+            Some(factors) => scaled / factors[i] as f64,
+            None => scaled,
+        }
+    }
+
+    /// Multiplier applied to every cos/sin value; only YaRN changes it.
+    pub fn attn_scale(&self) -> f32 {
+        match self.scaling {
+            RopeScaling::Yarn {
+                factor,
+                attn_factor,
+                ..
+            } => {
+                let mscale = if factor > 1.0 {
+                    1.0 + 0.1 * factor.ln()
+                } else {
+                    1.0
+                };
+                attn_factor * mscale
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Indices of the two elements of a head that pair `i` rotates.
+    #[inline]
+    pub fn pair(&self, i: usize) -> (usize, usize) {
+        match self.style {
+            RopeStyle::Interleaved => (2 * i, 2 * i + 1),
+            RopeStyle::NeoX => (i, i + self.n_dims / 2),
+        }
+    }
+}
+
+/// Cosines and sines of every rotation angle for positions `0..n_positions`.
+///
+/// Built once per model, so applying RoPE is a table lookup plus one
+/// multiply-add per element. Cloning is cheap; the tables are shared.
+#[derive(Debug, Clone)]
+pub struct RopeTable {
+    config: RopeConfig,
+    n_positions: usize,
+    cos: Arc<[f32]>,
+    sin: Arc<[f32]>,
+}
+
+impl RopeTable {
+    /// Precompute the tables for `config` up to (excluding) `n_positions`.
+    pub fn new(config: RopeConfig, n_positions: usize) -> Result<Self> {
+        config.validate()?;
+        let half = config.n_dims / 2;
+        let inv_freq: Vec<f64> = (0..half).map(|i| config.inv_freq(i)).collect();
+        let scale = config.attn_scale() as f64;
+
+        let mut cos = Vec::with_capacity(n_positions * half);
+        let mut sin = Vec::with_capacity(n_positions * half);
+        for pos in 0..n_positions {
+            for &f in &inv_freq {
+                // Angles grow with the position, so compute them in f64.
+                let theta = pos as f64 * f;
+                cos.push((theta.cos() * scale) as f32);
+                sin.push((theta.sin() * scale) as f32);
+            }
+        }
+        Ok(RopeTable {
+            config,
+            n_positions,
+            cos: cos.into(),
+            sin: sin.into(),
+        })
+    }
+
+    /// The parameters the table was built from.
+    pub fn config(&self) -> &RopeConfig {
+        &self.config
+    }
+
+    /// Number of positions covered.
+    pub fn n_positions(&self) -> usize {
+        self.n_positions
+    }
+
+    /// The `n_dims / 2` cosines and sines for position `pos`, one per
+    /// rotated pair.
+    pub fn cos_sin(&self, pos: usize) -> Result<(&[f32], &[f32])> {
+        if pos >= self.n_positions {
+            return Err(TensorError::Other(format!(
+                "rope: position {} is outside the table of {} positions",
+                pos, self.n_positions
+            )));
+        }
+        let half = self.config.n_dims / 2;
+        let range = pos * half..(pos + 1) * half;
+        Ok((&self.cos[range.clone()], &self.sin[range]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_matches_formula() {
+        let config = RopeConfig::new(8).with_freq_base(500000.0);
+        let table = RopeTable::new(config, 40).unwrap();
+        let (cos, sin) = table.cos_sin(37).unwrap();
+        assert_eq!(cos.len(), 4);
+        for i in 0..4 {
+            let theta = 37.0 * 500000f64.powf(-2.0 * i as f64 / 8.0);
+            assert!((cos[i] as f64 - theta.cos()).abs() < 1e-6);
+            assert!((sin[i] as f64 - theta.sin()).abs() < 1e-6);
+        }
+        assert!(table.cos_sin(40).is_err());
+    }
+
+    #[test]
+    fn test_pairs() {
+        let interleaved = RopeConfig::new(8);
+        assert_eq!(interleaved.pair(1), (2, 3));
+        let neox = RopeConfig::new(8).with_style(RopeStyle::NeoX).with_dims(6);
+        assert_eq!(neox.pair(0), (0, 3));
+        assert_eq!(neox.pair(2), (2, 5));
+    }
+
+    /// Compare every pair's frequency with values from the reference
+    /// (Hugging Face transformers) formulas.
+    fn assert_inv_freqs(config: &RopeConfig, want: &[(usize, f64)]) {
+        for &(i, w) in want {
+            let got = config.inv_freq(i);
+            assert!(
+                (got - w).abs() <= 1e-9 * w.max(1.0),
+                "{:?} pair {}: got {} want {}",
+                config.scaling,
+                i,
+                got,
+                w
+            );
+        }
+    }
+
+    #[test]
+    fn test_linear_and_ntk_scaling() {
+        let linear = RopeConfig::new(8).with_scaling(RopeScaling::Linear { factor: 4.0 });
+        assert_inv_freqs(&linear, &[(0, 0.25), (1, 0.025), (2, 0.0025), (3, 0.00025)]);
+
+        let ntk = RopeConfig::new(8).with_scaling(RopeScaling::Ntk { factor: 4.0 });
+        assert_inv_freqs(
+            &ntk,
+            &[
+                (0, 1.0),
+                (1, 0.062_996_052_494_743_66),
+                (2, 0.003_968_502_629_920_499),
+                (3, 0.000_25),
+            ],
+        );
+        assert_eq!(ntk.attn_scale(), 1.0);
+    }
+
+    #[test]
+    fn test_yarn_scaling() {
+        let small = RopeConfig::new(16).with_scaling(RopeScaling::yarn(4.0, 64));
+        assert_inv_freqs(
+            &small,
+            &[
+                (0, 1.0),
+                (1, 0.237_170_824_512_628_47),
+                (2, 0.05),
+                (3, 0.007_905_694_150_420_948),
+                (7, 7.905_694_150_420_948e-5),
+            ],
+        );
+
+        let large = RopeConfig::new(128).with_scaling(RopeScaling::yarn(4.0, 4096));
+        assert_inv_freqs(
+            &large,
+            &[
+                (0, 1.0),
+                (10, 0.237_137_370_566_165_52),
+                (20, 0.056_234_132_519_034_91),
+                (30, 0.009_488_517_882_700_576),
+                (40, 0.001_337_886_702_378_929_7),
+                (63, 2.886_954_961_723_645_5e-5),
+            ],
+        );
+
+        let mscale = 1.138_629_436_111_989;
+        assert!((large.attn_scale() as f64 - mscale).abs() < 1e-6);
+        let table = RopeTable::new(large, 2).unwrap();
+        let (cos, sin) = table.cos_sin(0).unwrap();
+        assert!((cos[5] as f64 - mscale).abs() < 1e-6);
+        assert_eq!(sin[5], 0.0);
+    }
+
+    #[test]
+    fn test_llama3_scaling() {
+        let config = RopeConfig::new(128)
+            .with_freq_base(500000.0)
+            .with_scaling(RopeScaling::llama3());
+        assert_inv_freqs(
+            &config,
+            &[
+                (0, 1.0),
+                (20, 0.016_560_440_080_994_446),
+                (25, 0.005_940_730_375_674_967),
+                (30, 0.001_371_893_567_761_138_1),
+                (35, 9.556_212_353_964_683e-5),
+                (40, 3.428_102_195_952_591e-5),
+                (63, 3.068_925_988_914_511e-7),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_freq_factors() {
+        let config = RopeConfig::new(4).with_freq_factors(vec![1.0, 8.0]);
+        let table = RopeTable::new(config, 11).unwrap();
+        let (cos, _) = table.cos_sin(10).unwrap();
+        let theta = 10.0 * 0.01 / 8.0;
+        assert!((cos[1] as f64 - f64::cos(theta)).abs() < 1e-6);
+        assert!((cos[0] as f64 - f64::cos(10.0)).abs() < 1e-6);
+
+        let wrong_len = RopeConfig::new(4).with_freq_factors(vec![1.0]);
+        assert!(wrong_len.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(RopeConfig::new(8).with_dims(6).validate().is_ok());
+        assert!(RopeConfig::new(8).with_dims(5).validate().is_err());
+        assert!(RopeConfig::new(8).with_dims(10).validate().is_err());
+        assert!(RopeConfig::new(8).with_freq_base(0.0).validate().is_err());
+        assert!(RopeTable::new(RopeConfig::new(7), 4).is_err());
+        let bad_factor = RopeScaling::Linear { factor: 0.0 };
+        assert!(
+            RopeConfig::new(8)
+                .with_scaling(bad_factor)
+                .validate()
+                .is_err()
+        );
+        let no_context = RopeScaling::yarn(4.0, 0);
+        assert!(
+            RopeConfig::new(8)
+                .with_scaling(no_context)
+                .validate()
+                .is_err()
+        );
+        let ntk = RopeScaling::Ntk { factor: 2.0 };
+        assert!(RopeConfig::new(2).with_scaling(ntk).validate().is_err());
+    }
+}
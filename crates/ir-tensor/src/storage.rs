@@ -1,21 +1,130 @@
+use std::fmt;
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
 use crate::dtype::DType;
 use crate::error::{Result, TensorError};
 
+/// A shared, immutable byte buffer that a [`ByteBuffer`] can view into.
+pub type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync + RefUnwindSafe>;
+
+/// Immutable raw bytes backing a non-f32 tensor.
+///
+/// The bytes are either owned or a window into a shared buffer such as a
+/// memory-mapped model file, so quantized weights can be used in place
+/// without copying them out of the mapping.
+#[derive(Clone)]
+pub struct ByteBuffer {
+    owner: SharedBytes,
+    offset: usize,
+    len: usize,
+}
+
+impl ByteBuffer {
+    /// Create a buffer that owns `data`.
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        let len = data.len();
+        ByteBuffer {
+            owner: Arc::new(data),
+            offset: 0,
+            len,
+        }
+    }
+
+    /// Create a buffer viewing `len` bytes of `owner` starting at `offset`.
+    ///
+    /// The owner is kept alive for as long as any buffer refers to it.
+    ///
+    /// # Errors
+    /// Returns an error if the range is out of bounds for `owner`.
+    pub fn from_shared(owner: SharedBytes, offset: usize, len: usize) -> Result<Self> {
+        let available = owner.as_ref().as_ref().len();
+        if offset.checked_add(len).is_none_or(|end| end > available) {
+            return Err(TensorError::Other(format!(
+                "byte range {}..{} out of bounds for buffer of {} bytes",
+                offset,
+                offset.saturating_add(len),
+                available
+            )));
+        }
+        Ok(ByteBuffer { owner, offset, len })
+    }
+
+    /// Returns the viewed bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.owner.as_ref().as_ref()[self.offset..self.offset + self.len]
+    }
+
+    /// Number of bytes in this buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the buffer contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl fmt::Debug for ByteBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ByteBuffer")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
 /// CPU-side tensor storage.
 ///
-/// Phase 1 focuses on F32 storage only. Additional variants (F16, quantized)
-/// will be added in later phases.
+/// F32 data is held as a vector of floats. Every other dtype is kept in its
+/// native GGUF encoding as raw bytes, so quantized weights take roughly the
+/// same amount of memory as they do on disk.
 #[derive(Debug, Clone)]
 pub enum CpuStorage {
     /// 32-bit floating point storage.
     F32(Vec<f32>),
+    /// IEEE 754 half-precision values as little-endian bytes.
+    F16(ByteBuffer),
+    /// bfloat16 values as little-endian bytes.
+    BF16(ByteBuffer),
+    /// GGUF Q4_0 blocks.
+    Q4_0(ByteBuffer),
+    /// GGUF Q4_1 blocks.
+    Q4_1(ByteBuffer),
+    /// GGUF Q5_0 blocks.
+    Q5_0(ByteBuffer),
+    /// GGUF Q5_1 blocks.
+    Q5_1(ByteBuffer),
+    /// GGUF Q8_0 blocks.
+    Q8_0(ByteBuffer),
+    /// GGUF Q8_1 blocks.
+    Q8_1(ByteBuffer),
+    /// GGUF Q2_K super-blocks.
+    Q2K(ByteBuffer),
+    /// GGUF Q3_K super-blocks.
+    Q3K(ByteBuffer),
+    /// GGUF Q4_K super-blocks.
+    Q4K(ByteBuffer),
+    /// GGUF Q5_K super-blocks.
+    Q5K(ByteBuffer),
+    /// GGUF Q6_K super-blocks.
+    Q6K(ByteBuffer),
+    /// GGUF Q8_K super-blocks.
+    Q8K(ByteBuffer),
 }
 
 impl CpuStorage {
     /// Number of elements in this storage.
+    ///
+    /// For block-quantized storage this counts every element of every block,
+    /// including any padding in the last block.
     pub fn len(&self) -> usize {
         match self {
             CpuStorage::F32(v) => v.len(),
+            other => {
+                let dtype = other.dtype();
+                other.size_in_bytes() / dtype.size_in_bytes() * dtype.block_size()
+            }
         }
     }
 
@@ -24,6 +133,14 @@ impl CpuStorage {
         self.len() == 0
     }
 
+    /// Size of the stored data in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        match self.raw() {
+            Some(b) => b.len(),
+            None => self.len() * DType::F32.size_in_bytes(),
+        }
+    }
+
     /// Returns the data as an f32 slice.
     ///
     /// # Errors
@@ -31,6 +148,10 @@ impl CpuStorage {
     pub fn as_f32_slice(&self) -> Result<&[f32]> {
         match self {
             CpuStorage::F32(v) => Ok(v.as_slice()),
+            other => Err(TensorError::DTypeMismatch {
+                expected: DType::F32.to_string(),
+                got: other.dtype().to_string(),
+            }),
         }
     }
 
@@ -41,21 +162,66 @@ impl CpuStorage {
     pub fn as_f32_slice_mut(&mut self) -> Result<&mut [f32]> {
         match self {
             CpuStorage::F32(v) => Ok(v.as_mut_slice()),
+            other => Err(TensorError::DTypeMismatch {
+                expected: DType::F32.to_string(),
+                got: other.dtype().to_string(),
+            }),
+        }
+    }
+
+    /// Returns the raw encoded bytes of non-F32 storage.
+    ///
+    /// # Errors
+    /// Returns an error if the storage is F32, which has no raw byte form.
+    pub fn as_bytes(&self) -> Result<&[u8]> {
+        self.raw().map(ByteBuffer::as_bytes).ok_or_else(|| {
+            TensorError::UnsupportedDType("f32 storage is not held as raw bytes".to_string())
+        })
+    }
+
+    /// The byte buffer behind non-F32 storage.
+    fn raw(&self) -> Option<&ByteBuffer> {
+        match self {
+            CpuStorage::F32(_) => None,
+            CpuStorage::F16(b)
+            | CpuStorage::BF16(b)
+            | CpuStorage::Q4_0(b)
+            | CpuStorage::Q4_1(b)
+            | CpuStorage::Q5_0(b)
+            | CpuStorage::Q5_1(b)
+            | CpuStorage::Q8_0(b)
+            | CpuStorage::Q8_1(b)
+            | CpuStorage::Q2K(b)
+            | CpuStorage::Q3K(b)
+            | CpuStorage::Q4K(b)
+            | CpuStorage::Q5K(b)
+            | CpuStorage::Q6K(b)
+            | CpuStorage::Q8K(b) => Some(b),
         }
     }
 
     /// Create zero-filled storage for the given dtype and element count.
     ///
+    /// An all-zero block decodes to zeros for every supported format.
+    ///
     /// # Errors
-    /// Returns an error for unsupported dtypes (Phase 1: only F32 is supported).
+    /// Returns an error if `n` is not a multiple of the dtype's block size.
     pub fn zeros(dtype: DType, n: usize) -> Result<Self> {
-        match dtype {
-            DType::F32 => Ok(CpuStorage::F32(vec![0.0; n])),
-            other => Err(TensorError::UnsupportedDType(format!(
-                "{} storage not yet implemented",
-                other
-            ))),
+        if dtype == DType::F32 {
+            return Ok(CpuStorage::F32(vec![0.0; n]));
+        }
+        if !n.is_multiple_of(dtype.block_size()) {
+            return Err(TensorError::Other(format!(
+                "{} storage needs a multiple of {} elements, got {}",
+                dtype,
+                dtype.block_size(),
+                n
+            )));
         }
+        CpuStorage::from_raw(
+            dtype,
+            ByteBuffer::from_vec(vec![0u8; dtype.storage_size(n)]),
+        )
     }
 
     /// Create storage from an f32 vector.
@@ -63,10 +229,65 @@ impl CpuStorage {
         CpuStorage::F32(data)
     }
 
+    /// Create storage from bytes encoded as `dtype`.
+    ///
+    /// F32 bytes are decoded (little-endian) into an owned vector; every
+    /// other dtype keeps the buffer as-is.
+    ///
+    /// # Errors
+    /// Returns an error if the byte length is not a whole number of elements
+    /// (or blocks) of `dtype`.
+    pub fn from_raw(dtype: DType, data: ByteBuffer) -> Result<Self> {
+        if !data.len().is_multiple_of(dtype.size_in_bytes()) {
+            return Err(TensorError::Other(format!(
+                "{} bytes is not a whole number of {} blocks of {} bytes",
+                data.len(),
+                dtype,
+                dtype.size_in_bytes()
+            )));
+        }
+        Ok(match dtype {
+            DType::F32 => CpuStorage::F32(
+                data.as_bytes()
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect(),
+            ),
+            DType::F16 => CpuStorage::F16(data),
+            DType::BF16 => CpuStorage::BF16(data),
+            DType::Q4_0 => CpuStorage::Q4_0(data),
+            DType::Q4_1 => CpuStorage::Q4_1(data),
+            DType::Q5_0 => CpuStorage::Q5_0(data),
+            DType::Q5_1 => CpuStorage::Q5_1(data),
+            DType::Q8_0 => CpuStorage::Q8_0(data),
+            DType::Q8_1 => CpuStorage::Q8_1(data),
+            DType::Q2K => CpuStorage::Q2K(data),
+            DType::Q3K => CpuStorage::Q3K(data),
+            DType::Q4K => CpuStorage::Q4K(data),
+            DType::Q5K => CpuStorage::Q5K(data),
+            DType::Q6K => CpuStorage::Q6K(data),
+            DType::Q8K => CpuStorage::Q8K(data),
+        })
+    }
+
     /// Returns the dtype of this storage.
     pub fn dtype(&self) -> DType {
         match self {
             CpuStorage::F32(_) => DType::F32,
+            CpuStorage::F16(_) => DType::F16,
+            CpuStorage::BF16(_) => DType::BF16,
+            CpuStorage::Q4_0(_) => DType::Q4_0,
+            CpuStorage::Q4_1(_) => DType::Q4_1,
+            CpuStorage::Q5_0(_) => DType::Q5_0,
+            CpuStorage::Q5_1(_) => DType::Q5_1,
+            CpuStorage::Q8_0(_) => DType::Q8_0,
+            CpuStorage::Q8_1(_) => DType::Q8_1,
+            CpuStorage::Q2K(_) => DType::Q2K,
+            CpuStorage::Q3K(_) => DType::Q3K,
+            CpuStorage::Q4K(_) => DType::Q4K,
+            CpuStorage::Q5K(_) => DType::Q5K,
+            CpuStorage::Q6K(_) => DType::Q6K,
+            CpuStorage::Q8K(_) => DType::Q8K,
         }
     }
 }
@@ -91,9 +312,20 @@ mod tests {
     }
 
     #[test]
-    fn test_zeros_unsupported() {
-        assert!(CpuStorage::zeros(DType::F16, 5).is_err());
-        assert!(CpuStorage::zeros(DType::Q4_0, 5).is_err());
+    fn test_zeros_quantized() {
+        let s = CpuStorage::zeros(DType::Q4_0, 64).unwrap();
+        assert_eq!(s.dtype(), DType::Q4_0);
+        assert_eq!(s.len(), 64);
+        assert_eq!(s.size_in_bytes(), 36);
+
+        let h = CpuStorage::zeros(DType::F16, 5).unwrap();
+        assert_eq!(h.len(), 5);
+        assert_eq!(h.as_bytes().unwrap(), &[0u8; 10]);
+    }
+
+    #[test]
+    fn test_zeros_partial_block() {
+        assert!(CpuStorage::zeros(DType::Q8_0, 33).is_err());
     }
 
     #[test]
@@ -109,4 +341,52 @@ mod tests {
         slice[0] = 42.0;
         assert_eq!(s.as_f32_slice().unwrap()[0], 42.0);
     }
+
+    #[test]
+    fn test_from_raw_f32_decodes() {
+        let bytes: Vec<u8> = [1.5f32, -2.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let s = CpuStorage::from_raw(DType::F32, ByteBuffer::from_vec(bytes)).unwrap();
+        assert_eq!(s.as_f32_slice().unwrap(), &[1.5, -2.0]);
+    }
+
+    #[test]
+    fn test_from_raw_quantized_keeps_bytes() {
+        let s = CpuStorage::from_raw(DType::Q8_0, ByteBuffer::from_vec(vec![7u8; 68])).unwrap();
+        assert_eq!(s.dtype(), DType::Q8_0);
+        assert_eq!(s.len(), 64);
+        assert_eq!(s.as_bytes().unwrap().len(), 68);
+        assert!(s.as_f32_slice().is_err());
+    }
+
+    #[test]
+    fn test_from_raw_k_quant() {
+        let s = CpuStorage::from_raw(DType::Q4K, ByteBuffer::from_vec(vec![0u8; 288])).unwrap();
+        assert_eq!(s.dtype(), DType::Q4K);
+        assert_eq!(s.len(), 512);
+        assert_eq!(s.size_in_bytes(), 288);
+    }
+
+    #[test]
+    fn test_from_raw_bf16() {
+        let s = CpuStorage::from_raw(DType::BF16, ByteBuffer::from_vec(vec![0u8; 10])).unwrap();
+        assert_eq!(s.dtype(), DType::BF16);
+        assert_eq!(s.len(), 5);
+        assert!(CpuStorage::from_raw(DType::BF16, ByteBuffer::from_vec(vec![0u8; 3])).is_err());
+    }
+
+    #[test]
+    fn test_from_raw_partial_block() {
+        assert!(CpuStorage::from_raw(DType::Q4_0, ByteBuffer::from_vec(vec![0u8; 20])).is_err());
+    }
+
+    #[test]
+    fn test_byte_buffer_shared_window() {
+        let owner: SharedBytes = Arc::new(vec![0u8, 1, 2, 3, 4, 5]);
+        let b = ByteBuffer::from_shared(owner.clone(), 2, 3).unwrap();
+        assert_eq!(b.as_bytes(), &[2, 3, 4]);
+        assert!(ByteBuffer::from_shared(owner, 4, 3).is_err());
+    }
 }
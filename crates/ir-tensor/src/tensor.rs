@@ -1,17 +1,27 @@
-use crate::backend::ComputeBackend;
+use std::sync::Arc;
+
+use crate::backend::{BinaryOp, ComputeBackend, MatmulParams};
 use crate::dtype::DType;
 use crate::error::{Result, TensorError};
+use crate::layout::Layout;
 use crate::shape::Shape;
-use crate::storage::CpuStorage;
+use crate::storage::{ByteBuffer, CpuStorage};
 
 /// A tensor backed by CPU storage.
 ///
-/// Holds contiguous, row-major f32 data with an associated shape and dtype.
-/// Operations that require computation are dispatched to a `ComputeBackend`.
+/// Holds a shared storage buffer plus a [`Layout`] (shape, strides and
+/// offset) describing which of its elements the tensor covers. View
+/// operations (`transpose`, `permute`, `narrow`, `squeeze`, `unsqueeze`,
+/// `reshape` of a contiguous tensor) only build a new layout over the same
+/// storage; `contiguous()` materializes a view into fresh row-major storage.
+///
+/// The dtype always matches the storage, so quantized weights stay in their
+/// native block format. Operations that require computation are dispatched
+/// to a `ComputeBackend`.
 #[derive(Debug, Clone)]
 pub struct Tensor {
-    storage: CpuStorage,
-    shape: Shape,
+    storage: Arc<CpuStorage>,
+    layout: Layout,
     dtype: DType,
 }
 
@@ -30,35 +40,63 @@ impl Tensor {
             shape.numel()
         );
         Tensor {
-            storage: CpuStorage::from_f32_vec(data),
-            shape,
+            storage: Arc::new(CpuStorage::from_f32_vec(data)),
+            layout: Layout::contiguous(shape),
             dtype: DType::F32,
         }
     }
 
+    /// Create a tensor from existing storage of any dtype.
+    ///
+    /// The tensor's dtype is taken from the storage.
+    ///
+    /// # Errors
+    /// Returns an error if the storage size does not match `shape`.
+    pub fn from_storage(storage: CpuStorage, shape: Shape) -> Result<Self> {
+        let dtype = storage.dtype();
+        let expected = dtype.storage_size(shape.numel());
+        if storage.size_in_bytes() != expected {
+            return Err(TensorError::Other(format!(
+                "{} storage of {} bytes does not match shape {} ({} bytes)",
+                dtype,
+                storage.size_in_bytes(),
+                shape,
+                expected
+            )));
+        }
+        Ok(Tensor {
+            storage: Arc::new(storage),
+            layout: Layout::contiguous(shape),
+            dtype,
+        })
+    }
+
     /// Create a zero-filled tensor with the given shape.
     pub fn zeros(shape: Shape) -> Self {
         let n = shape.numel();
-        Tensor {
-            storage: CpuStorage::from_f32_vec(vec![0.0; n]),
-            shape,
-            dtype: DType::F32,
-        }
+        Tensor::new(vec![0.0; n], shape)
     }
 
     /// Create a tensor filled with ones with the given shape.
     pub fn ones(shape: Shape) -> Self {
         let n = shape.numel();
-        Tensor {
-            storage: CpuStorage::from_f32_vec(vec![1.0; n]),
-            shape,
-            dtype: DType::F32,
-        }
+        Tensor::new(vec![1.0; n], shape)
     }
 
     /// Returns a reference to the tensor's shape.
     pub fn shape(&self) -> &Shape {
-        &self.shape
+        self.layout.shape()
+    }
+
+    /// Returns the tensor's layout (shape, strides and storage offset).
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Returns true if the tensor covers a gap-free row-major range of its
+    /// storage.
+    pub fn is_contiguous(&self) -> bool {
+        self.layout.is_contiguous()
     }
 
     /// Returns the tensor's data type.
@@ -66,67 +104,397 @@ impl Tensor {
         self.dtype
     }
 
-    /// Returns the underlying data as an f32 slice.
+    /// Returns the tensor's elements as an f32 slice.
     ///
     /// # Panics
-    /// Panics if the storage is not F32 (should not happen in Phase 1).
+    /// Panics if the storage is not F32 or the tensor is not contiguous
+    /// (call [`Tensor::contiguous`] first).
     pub fn data_f32(&self) -> &[f32] {
-        self.storage
+        assert!(
+            self.is_contiguous(),
+            "tensor is a non-contiguous view; call contiguous() first"
+        );
+        let data = self
+            .storage
             .as_f32_slice()
-            .expect("tensor storage is not F32")
+            .expect("tensor storage is not F32");
+        let start = self.layout.offset();
+        &data[start..start + self.shape().numel()]
+    }
+
+    /// Returns the encoded bytes of a contiguous non-F32 tensor.
+    ///
+    /// # Errors
+    /// Returns an error for F32 tensors, non-contiguous views, and views
+    /// that do not start and end on a block boundary.
+    pub fn raw_bytes(&self) -> Result<&[u8]> {
+        let raw = self.storage.as_bytes()?;
+        let block = self.dtype.block_size();
+        let (start, numel) = (self.layout.offset(), self.shape().numel());
+        if !self.is_contiguous() || !start.is_multiple_of(block) || !numel.is_multiple_of(block) {
+            return Err(TensorError::Other(format!(
+                "{} view of shape {} at element {} is not a whole run of blocks",
+                self.dtype,
+                self.shape(),
+                start
+            )));
+        }
+        let start = self.dtype.storage_size(start);
+        Ok(&raw[start..start + self.dtype.storage_size(numel)])
     }
 
     /// Reshape the tensor, returning a new tensor with the same data but
     /// a different shape.
     ///
-    /// The total number of elements must remain the same.
+    /// The total number of elements must remain the same. A contiguous
+    /// tensor is reshaped in place; a non-contiguous view is materialized
+    /// first.
     pub fn reshape(&self, new_shape: Shape) -> Result<Tensor> {
-        if self.shape.numel() != new_shape.numel() {
+        if self.shape().numel() != new_shape.numel() {
             return Err(TensorError::ShapeMismatch {
-                expected: self.shape.dims().to_vec(),
+                expected: self.shape().dims().to_vec(),
                 got: new_shape.dims().to_vec(),
             });
         }
-        Ok(Tensor {
-            storage: self.storage.clone(),
-            shape: new_shape,
-            dtype: self.dtype,
-        })
+        let src = self.contiguous()?;
+        let offset = src.layout.offset();
+        let layout = Layout::contiguous(new_shape).with_offset(offset);
+        Ok(src.with_layout(layout))
+    }
+
+    /// Swap dimensions `dim0` and `dim1` without copying.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Tensor> {
+        Ok(self.with_layout(self.layout.transpose(dim0, dim1)?))
+    }
+
+    /// Reorder dimensions without copying: output dimension `i` is input
+    /// dimension `perm[i]`.
+    pub fn permute(&self, perm: &[usize]) -> Result<Tensor> {
+        Ok(self.with_layout(self.layout.permute(perm)?))
+    }
+
+    /// View `len` elements of dimension `dim` starting at `start`, without
+    /// copying.
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Tensor> {
+        Ok(self.with_layout(self.layout.narrow(dim, start, len)?))
+    }
+
+    /// Remove dimension `dim`, which must have size 1.
+    pub fn squeeze(&self, dim: usize) -> Result<Tensor> {
+        Ok(self.with_layout(self.layout.squeeze(dim)?))
+    }
+
+    /// Insert a dimension of size 1 at position `dim` (0..=ndim).
+    pub fn unsqueeze(&self, dim: usize) -> Result<Tensor> {
+        Ok(self.with_layout(self.layout.unsqueeze(dim)?))
+    }
+
+    /// Gather the slices at `indices` along dimension `dim` into a new
+    /// contiguous tensor, e.g. embedding rows for a list of token ids.
+    ///
+    /// Only the selected slices are copied. Quantized tensors stay in their
+    /// encoding, which requires each slice to be a whole number of blocks.
+    pub fn index_select(&self, dim: usize, indices: &[usize]) -> Result<Tensor> {
+        let dims = self.shape().dims();
+        if dim >= dims.len() {
+            return Err(TensorError::InvalidAxis {
+                axis: dim,
+                ndim: dims.len(),
+            });
+        }
+        let size = dims[dim];
+        if let Some(&bad) = indices.iter().find(|&&i| i >= size) {
+            return Err(TensorError::Other(format!(
+                "index_select: index {} out of range for dimension {} of size {}",
+                bad, dim, size
+            )));
+        }
+        let outer: usize = dims[..dim].iter().product();
+        let inner: usize = dims[dim + 1..].iter().product();
+        let mut out_dims = dims.to_vec();
+        out_dims[dim] = indices.len();
+        let out_shape = Shape::new(out_dims);
+
+        let src = self.contiguous()?;
+        if self.dtype == DType::F32 {
+            let data = src.data_f32();
+            let mut out = Vec::with_capacity(out_shape.numel());
+            for o in 0..outer {
+                for &i in indices {
+                    let start = (o * size + i) * inner;
+                    out.extend_from_slice(&data[start..start + inner]);
+                }
+            }
+            return Ok(Tensor::new(out, out_shape));
+        }
+
+        if !inner.is_multiple_of(self.dtype.block_size()) {
+            return Err(TensorError::Other(format!(
+                "index_select: {} slices of {} elements are not whole blocks",
+                self.dtype, inner
+            )));
+        }
+        let raw = src.raw_bytes()?;
+        let slice_bytes = self.dtype.storage_size(inner);
+        let mut out = Vec::with_capacity(self.dtype.storage_size(out_shape.numel()));
+        for o in 0..outer {
+            for &i in indices {
+                let start = (o * size + i) * slice_bytes;
+                out.extend_from_slice(&raw[start..start + slice_bytes]);
+            }
+        }
+        let storage = CpuStorage::from_raw(self.dtype, ByteBuffer::from_vec(out))?;
+        Tensor::from_storage(storage, out_shape)
     }
 
-    /// Matrix multiplication of two 2D tensors using the given backend.
+    /// Copy the elements of an F32 tensor out in row-major order.
+    ///
+    /// # Errors
+    /// Returns an error if the tensor is not F32.
+    pub fn to_vec_f32(&self) -> Result<Vec<f32>> {
+        let data = self.storage.as_f32_slice()?;
+        if self.is_contiguous() {
+            return Ok(self.data_f32().to_vec());
+        }
+        Ok(self.layout.strided_index().map(|i| data[i]).collect())
+    }
+
+    /// Return a contiguous tensor with the same elements.
+    ///
+    /// Contiguous tensors are returned as-is (sharing storage); other views
+    /// are copied into fresh row-major storage.
+    ///
+    /// # Errors
+    /// Non-F32 views can only be returned when already contiguous, since
+    /// their elements cannot be rearranged inside encoded blocks.
+    pub fn contiguous(&self) -> Result<Tensor> {
+        if self.is_contiguous() {
+            return Ok(self.clone());
+        }
+        if self.dtype != DType::F32 {
+            return Err(TensorError::UnsupportedDType(format!(
+                "cannot materialize a non-contiguous {} view",
+                self.dtype
+            )));
+        }
+        Ok(Tensor::new(self.to_vec_f32()?, self.shape().clone()))
+    }
+
+    /// Matrix multiplication using the given backend, with optional leading
+    /// batch dimensions.
+    ///
+    /// self is [.., m, k] and other is [.., k, n]; the result is [.., m, n],
+    /// where the batch dimensions broadcast numpy-style. Two 2-D operands
+    /// give a plain [m, n] product.
     ///
-    /// self is [m, k], other is [k, n], result is [m, n].
+    /// An operand whose batch dimensions are broadcast only at the end
+    /// (such as shared KV heads, `[h_kv, 1, d, s]` against
+    /// `[h_kv, group, t, d]`) is shared without copying, and `other` may be
+    /// a transposed view such as `k.transpose(1, 2)`, which is read in place.
+    /// Other non-contiguous operands are materialized first.
     pub fn matmul(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
-        if self.shape.ndim() != 2 || other.shape.ndim() != 2 {
+        if self.shape().ndim() < 2 || other.shape().ndim() < 2 {
             return Err(TensorError::Other(
-                "matmul requires 2D tensors".to_string(),
+                "matmul requires tensors with at least 2 dimensions".to_string(),
             ));
         }
 
-        let m = self.shape.dim(0);
-        let k = self.shape.dim(1);
-        let k2 = other.shape.dim(0);
-        let n = other.shape.dim(1);
+        for t in [self, other] {
+            if t.dtype != DType::F32 {
+                return Err(TensorError::DTypeMismatch {
+                    expected: DType::F32.to_string(),
+                    got: t.dtype.to_string(),
+                });
+            }
+        }
 
+        let (a_dims, b_dims) = (self.shape().dims(), other.shape().dims());
+        let (a_batch, [m, k]) = a_dims.split_at(a_dims.len() - 2) else {
+            unreachable!("ndim checked above")
+        };
+        let (b_batch, [k2, n]) = b_dims.split_at(b_dims.len() - 2) else {
+            unreachable!("ndim checked above")
+        };
+        let (m, k, k2, n) = (*m, *k, *k2, *n);
         if k != k2 {
             return Err(TensorError::MatmulMismatch { m, k, k2, n });
         }
 
-        let result_data = backend.matmul(self.data_f32(), other.data_f32(), m, k, n)?;
-        Ok(Tensor::new(result_data, Shape::new(vec![m, n])))
+        let batch_shape =
+            Shape::broadcast_shape(&Shape::from_slice(a_batch), &Shape::from_slice(b_batch))?;
+        let mut out_dims = batch_shape.dims().to_vec();
+        out_dims.extend([m, n]);
+        let out_shape = Shape::new(out_dims);
+        if out_shape.numel() == 0 {
+            return Ok(Tensor::zeros(out_shape));
+        }
+
+        let (a, a_count, _) = self.batch_operand(&batch_shape, false)?;
+        let (b, b_count, transpose_b) = other.batch_operand(&batch_shape, true)?;
+        let params = MatmulParams {
+            batch: batch_shape.numel(),
+            a_batch: a_count,
+            b_batch: b_count,
+            m,
+            k,
+            n,
+            transpose_b,
+        };
+        let out = backend.batched_matmul(a.data_f32(), b.data_f32(), &params)?;
+        Ok(Tensor::new(out, out_shape))
+    }
+
+    /// This tensor as the stack of matrices one side of a batched matmul
+    /// over `batch` reads: `(tensor, number of matrices, transposed)`.
+    ///
+    /// The matrices can be shared as they are when the batch dimensions this
+    /// tensor broadcasts all come after the ones it has, since consecutive
+    /// batch entries then use the same matrix. Otherwise the broadcast is
+    /// materialized. With `allow_transposed`, a view whose last two
+    /// dimensions are swapped relative to contiguous storage is returned as
+    /// that storage with the flag set.
+    fn batch_operand(
+        &self,
+        batch: &Shape,
+        allow_transposed: bool,
+    ) -> Result<(Tensor, usize, bool)> {
+        let ndim = self.shape().ndim();
+        let dims = self.shape().dims();
+        let target = batch.dims();
+        let own = &dims[..ndim - 2];
+        let pad = target.len() - own.len();
+        let padded = |i: usize| if i < pad { 1 } else { own[i - pad] };
+
+        // Leading dimensions this tensor has, followed only by broadcast ones.
+        let shared = (0..target.len())
+            .position(|i| padded(i) != target[i])
+            .unwrap_or(target.len());
+        let count = if (shared..target.len()).all(|i| padded(i) == 1) {
+            target[..shared].iter().product()
+        } else {
+            let mut full = target.to_vec();
+            full.extend_from_slice(&dims[ndim - 2..]);
+            let view = self.with_layout(self.layout.broadcast_as(&Shape::new(full))?);
+            return Ok((view.contiguous()?, batch.numel(), false));
+        };
+
+        if self.is_contiguous() {
+            return Ok((self.clone(), count, false));
+        }
+        if allow_transposed {
+            let t = self.transpose(ndim - 2, ndim - 1)?;
+            if t.is_contiguous() {
+                return Ok((t, count, true));
+            }
+        }
+        Ok((self.contiguous()?, count, false))
     }
 
-    /// Returns the underlying storage reference.
+    /// Apply `op` element-wise with numpy-style broadcasting.
+    ///
+    /// The result has the broadcast shape of both operands and is always
+    /// contiguous. Either operand may be a view; nothing is materialized
+    /// before the backend walks them by stride.
+    pub fn binary(
+        &self,
+        op: BinaryOp,
+        other: &Tensor,
+        backend: &dyn ComputeBackend,
+    ) -> Result<Tensor> {
+        for t in [self, other] {
+            if t.dtype != DType::F32 {
+                return Err(TensorError::DTypeMismatch {
+                    expected: DType::F32.to_string(),
+                    got: t.dtype.to_string(),
+                });
+            }
+        }
+        let shape = Shape::broadcast_shape(self.shape(), other.shape())?;
+        let a_layout = self.layout.broadcast_as(&shape)?;
+        let b_layout = other.layout.broadcast_as(&shape)?;
+        let mut out = vec![0.0f32; shape.numel()];
+        backend.binary_into(
+            op,
+            self.storage.as_f32_slice()?,
+            &a_layout,
+            other.storage.as_f32_slice()?,
+            &b_layout,
+            &mut out,
+        )?;
+        Ok(Tensor::new(out, shape))
+    }
+
+    /// Broadcasting element-wise `self + other`.
+    pub fn add(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Add, other, backend)
+    }
+
+    /// Broadcasting element-wise `self - other`.
+    pub fn sub(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Sub, other, backend)
+    }
+
+    /// Broadcasting element-wise `self * other`.
+    pub fn mul(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Mul, other, backend)
+    }
+
+    /// Broadcasting element-wise `self / other`.
+    pub fn div(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Div, other, backend)
+    }
+
+    /// Broadcasting `self == other`, as a 1.0/0.0 mask.
+    pub fn eq(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Eq, other, backend)
+    }
+
+    /// Broadcasting `self != other`, as a 1.0/0.0 mask.
+    pub fn ne(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Ne, other, backend)
+    }
+
+    /// Broadcasting `self < other`, as a 1.0/0.0 mask.
+    pub fn lt(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Lt, other, backend)
+    }
+
+    /// Broadcasting `self <= other`, as a 1.0/0.0 mask.
+    pub fn le(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Le, other, backend)
+    }
+
+    /// Broadcasting `self > other`, as a 1.0/0.0 mask.
+    pub fn gt(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Gt, other, backend)
+    }
+
+    /// Broadcasting `self >= other`, as a 1.0/0.0 mask.
+    pub fn ge(&self, other: &Tensor, backend: &dyn ComputeBackend) -> Result<Tensor> {
+        self.binary(BinaryOp::Ge, other, backend)
+    }
+
+    /// Returns the full underlying storage, which a view may only partly
+    /// cover; see [`Tensor::layout`].
     pub fn storage(&self) -> &CpuStorage {
         &self.storage
     }
+
+    /// A tensor sharing this tensor's storage with a different layout.
+    fn with_layout(&self, layout: Layout) -> Tensor {
+        Tensor {
+            storage: Arc::clone(&self.storage),
+            layout,
+            dtype: self.dtype,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cpu::CpuBackend;
+    use crate::storage::ByteBuffer;
 
     #[test]
     fn test_new_tensor() {
@@ -184,4 +552,298 @@ mod tests {
         let b = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], Shape::new(vec![2, 2]));
         assert!(a.matmul(&b, &backend).is_err());
     }
+
+    /// Slice `i` of the leading dimension of a 3-D tensor, as a 2-D tensor.
+    fn slice(t: &Tensor, i: usize) -> Tensor {
+        t.narrow(0, i, 1).unwrap().squeeze(0).unwrap()
+    }
+
+    #[test]
+    fn test_batched_matmul() {
+        let backend = CpuBackend::new();
+        let a = arange(&[3, 2, 4]);
+        let b = arange(&[3, 4, 5]);
+        let c = a.matmul(&b, &backend).unwrap();
+        assert_eq!(c.shape().dims(), &[3, 2, 5]);
+        for i in 0..3 {
+            let want = slice(&a, i).matmul(&slice(&b, i), &backend).unwrap();
+            assert_eq!(slice(&c, i).to_vec_f32().unwrap(), want.data_f32());
+        }
+
+        // A 2-D operand is shared by every batch entry.
+        let w = arange(&[4, 5]);
+        let c = a.matmul(&w, &backend).unwrap();
+        assert_eq!(c.shape().dims(), &[3, 2, 5]);
+        for i in 0..3 {
+            let want = slice(&a, i).matmul(&w, &backend).unwrap();
+            assert_eq!(slice(&c, i).to_vec_f32().unwrap(), want.data_f32());
+        }
+
+        assert!(a.matmul(&arange(&[2, 4, 5]), &backend).is_err());
+        assert!(a.matmul(&arange(&[3, 5, 4]), &backend).is_err());
+    }
+
+    #[test]
+    fn test_batched_matmul_grouped_and_transposed() {
+        let backend = CpuBackend::new();
+        // Four query heads over two shared KV heads: q [2, 2, t, d] against
+        // k [2, 1, s, d], with K^T taken as a view.
+        let (t, s, d) = (3, 5, 4);
+        let q = arange(&[2, 2, t, d]);
+        let k = arange(&[2, 1, s, d]);
+        let kt = k.transpose(2, 3).unwrap();
+        assert!(!kt.is_contiguous());
+        let scores = q.matmul(&kt, &backend).unwrap();
+        assert_eq!(scores.shape().dims(), &[2, 2, t, s]);
+
+        let q3 = q.reshape(Shape::new(vec![4, t, d])).unwrap();
+        let k3 = k.reshape(Shape::new(vec![2, s, d])).unwrap();
+        let scores3 = scores.reshape(Shape::new(vec![4, t, s])).unwrap();
+        for h in 0..4 {
+            let kt = slice(&k3, h / 2).transpose(0, 1).unwrap();
+            let want = slice(&q3, h).matmul(&kt, &backend).unwrap();
+            assert_eq!(slice(&scores3, h).to_vec_f32().unwrap(), want.data_f32());
+        }
+
+        // Broadcasting that cannot share matrices in place is materialized:
+        // [2, 1] against [3] batches gives [2, 3].
+        let a = arange(&[2, 1, 2, 3]);
+        let b = arange(&[3, 3, 2]);
+        let c = a.matmul(&b, &backend).unwrap();
+        assert_eq!(c.shape().dims(), &[2, 3, 2, 2]);
+        let c = c.reshape(Shape::new(vec![6, 2, 2])).unwrap();
+        let a3 = a.reshape(Shape::new(vec![2, 2, 3])).unwrap();
+        for i in 0..6 {
+            let want = slice(&a3, i / 3).matmul(&slice(&b, i % 3), &backend).unwrap();
+            assert_eq!(slice(&c, i).to_vec_f32().unwrap(), want.data_f32());
+        }
+    }
+
+    #[test]
+    fn test_from_storage_keeps_dtype() {
+        let storage =
+            CpuStorage::from_raw(DType::Q4_0, ByteBuffer::from_vec(vec![0u8; 36])).unwrap();
+        let t = Tensor::from_storage(storage, Shape::new(vec![2, 32])).unwrap();
+        assert_eq!(t.dtype(), DType::Q4_0);
+        assert_eq!(t.storage().as_bytes().unwrap().len(), 36);
+
+        let r = t.reshape(Shape::new(vec![64])).unwrap();
+        assert_eq!(r.dtype(), DType::Q4_0);
+    }
+
+    #[test]
+    fn test_from_storage_size_mismatch() {
+        let storage = CpuStorage::zeros(DType::Q8_0, 64).unwrap();
+        assert!(Tensor::from_storage(storage, Shape::new(vec![3, 32])).is_err());
+    }
+
+    #[test]
+    fn test_matmul_rejects_quantized() {
+        let backend = CpuBackend::new();
+        let storage = CpuStorage::zeros(DType::Q8_0, 64).unwrap();
+        let a = Tensor::from_storage(storage, Shape::new(vec![2, 32])).unwrap();
+        let b = Tensor::zeros(Shape::new(vec![32, 1]));
+        assert!(a.matmul(&b, &backend).is_err());
+    }
+
+    fn arange(dims: &[usize]) -> Tensor {
+        let shape = Shape::from_slice(dims);
+        Tensor::new((0..shape.numel()).map(|v| v as f32).collect(), shape)
+    }
+
+    #[test]
+    fn test_views_share_storage() {
+        let t = arange(&[2, 3]);
+        let tt = t.transpose(0, 1).unwrap();
+        assert_eq!(tt.shape().dims(), &[3, 2]);
+        assert!(!tt.is_contiguous());
+        assert!(std::ptr::eq(t.storage(), tt.storage()));
+
+        let c = tt.contiguous().unwrap();
+        assert!(c.is_contiguous());
+        assert_eq!(c.data_f32(), &[0.0, 3.0, 1.0, 4.0, 2.0, 5.0]);
+
+        // Contiguous tensors come back without a copy.
+        assert!(std::ptr::eq(t.contiguous().unwrap().storage(), t.storage()));
+    }
+
+    #[test]
+    fn test_permute_narrow_squeeze() {
+        let t = arange(&[2, 3, 4]);
+        let p = t.permute(&[2, 0, 1]).unwrap().contiguous().unwrap();
+        assert_eq!(p.shape().dims(), &[4, 2, 3]);
+        // p[1][1][2] = t[1][2][1]
+        assert_eq!(p.data_f32()[6 + 3 + 2], (12 + 8 + 1) as f32);
+
+        let rows = t.narrow(0, 1, 1).unwrap();
+        assert!(rows.is_contiguous());
+        assert_eq!(rows.data_f32()[0], 12.0);
+        let sq = rows.squeeze(0).unwrap();
+        assert_eq!(sq.shape().dims(), &[3, 4]);
+        assert_eq!(sq.unsqueeze(0).unwrap().shape().dims(), &[1, 3, 4]);
+
+        let cols = t.narrow(2, 1, 2).unwrap().contiguous().unwrap();
+        assert_eq!(&cols.data_f32()[..4], &[1.0, 2.0, 5.0, 6.0]);
+
+        assert!(t.narrow(1, 2, 2).is_err());
+        assert!(t.squeeze(0).is_err());
+        assert!(t.transpose(0, 3).is_err());
+    }
+
+    #[test]
+    fn test_reshape_view() {
+        let t = arange(&[4, 3]);
+        let r = t
+            .narrow(0, 2, 2)
+            .unwrap()
+            .reshape(Shape::new(vec![6]))
+            .unwrap();
+        assert_eq!(r.data_f32(), &[6.0, 7.0, 8.0, 9.0, 10.0, 11.0]);
+
+        let tr = t
+            .transpose(0, 1)
+            .unwrap()
+            .reshape(Shape::new(vec![12]))
+            .unwrap();
+        assert_eq!(&tr.data_f32()[..4], &[0.0, 3.0, 6.0, 9.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "non-contiguous")]
+    fn test_data_f32_rejects_views() {
+        let _ = arange(&[2, 3]).transpose(0, 1).unwrap().data_f32();
+    }
+
+    #[test]
+    fn test_index_select() {
+        let t = arange(&[4, 2]);
+        let rows = t.index_select(0, &[3, 0, 3]).unwrap();
+        assert_eq!(rows.shape().dims(), &[3, 2]);
+        assert_eq!(rows.data_f32(), &[6.0, 7.0, 0.0, 1.0, 6.0, 7.0]);
+
+        let cols = t.index_select(1, &[1]).unwrap();
+        assert_eq!(cols.data_f32(), &[1.0, 3.0, 5.0, 7.0]);
+
+        // Gathering from a view goes through its logical layout.
+        let tt = t.transpose(0, 1).unwrap().index_select(0, &[1]).unwrap();
+        assert_eq!(tt.data_f32(), &[1.0, 3.0, 5.0, 7.0]);
+
+        assert!(t.index_select(0, &[4]).is_err());
+        assert!(t.index_select(2, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_quantized_views() {
+        // Rows of two Q8_0 blocks with distinct scale bytes.
+        let mut raw = vec![0u8; 3 * 2 * 34];
+        for (b, block) in raw.chunks_exact_mut(34).enumerate() {
+            block[0] = b as u8;
+        }
+        let storage = CpuStorage::from_raw(DType::Q8_0, ByteBuffer::from_vec(raw.clone())).unwrap();
+        let t = Tensor::from_storage(storage, Shape::new(vec![3, 64])).unwrap();
+
+        let row = t.narrow(0, 1, 1).unwrap();
+        assert_eq!(row.raw_bytes().unwrap(), &raw[2 * 34..4 * 34]);
+
+        let gathered = t.index_select(0, &[2, 0]).unwrap();
+        assert_eq!(gathered.dtype(), DType::Q8_0);
+        let bytes = gathered.raw_bytes().unwrap();
+        assert_eq!(&bytes[..2 * 34], &raw[4 * 34..]);
+        assert_eq!(&bytes[2 * 34..], &raw[..2 * 34]);
+
+        // Views that split blocks or reorder elements cannot be encoded.
+        assert!(t.narrow(1, 16, 32).unwrap().raw_bytes().is_err());
+        assert!(t.transpose(0, 1).unwrap().contiguous().is_err());
+        assert!(t.index_select(1, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_broadcast_add_bias() {
+        let backend = CpuBackend::new();
+        let hidden = arange(&[2, 3]);
+        let bias = Tensor::new(vec![10.0, 20.0, 30.0], Shape::new(vec![3]));
+        let r = hidden.add(&bias, &backend).unwrap();
+        assert_eq!(r.shape().dims(), &[2, 3]);
+        assert_eq!(r.data_f32(), &[10.0, 21.0, 32.0, 13.0, 24.0, 35.0]);
+    }
+
+    #[test]
+    fn test_broadcast_both_sides() {
+        let backend = CpuBackend::new();
+        let col = Tensor::new(vec![1.0, 2.0], Shape::new(vec![2, 1]));
+        let row = Tensor::new(vec![10.0, 20.0, 30.0], Shape::new(vec![1, 3]));
+        let r = col.mul(&row, &backend).unwrap();
+        assert_eq!(r.data_f32(), &[10.0, 20.0, 30.0, 20.0, 40.0, 60.0]);
+        let d = row.div(&col, &backend).unwrap();
+        assert_eq!(d.data_f32(), &[10.0, 20.0, 30.0, 5.0, 10.0, 15.0]);
+        let s = row.sub(&col, &backend).unwrap();
+        assert_eq!(s.data_f32(), &[9.0, 19.0, 29.0, 8.0, 18.0, 28.0]);
+    }
+
+    #[test]
+    fn test_binary_on_views() {
+        let backend = CpuBackend::new();
+        // Per-head scaling of a transposed [heads, tokens] view.
+        let t = arange(&[3, 2]).transpose(0, 1).unwrap();
+        let scale = Tensor::new(vec![1.0, 0.5, 2.0], Shape::new(vec![3]));
+        let r = t.mul(&scale, &backend).unwrap();
+        assert_eq!(r.data_f32(), &[0.0, 1.0, 8.0, 1.0, 1.5, 10.0]);
+
+        let narrowed = arange(&[2, 4]).narrow(1, 1, 2).unwrap();
+        let r = narrowed
+            .add(&Tensor::ones(Shape::new(vec![1])), &backend)
+            .unwrap();
+        assert_eq!(r.data_f32(), &[2.0, 3.0, 6.0, 7.0]);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let backend = CpuBackend::new();
+        let a = Tensor::new(vec![1.0, 2.0, 3.0], Shape::new(vec![3]));
+        let two = Tensor::new(vec![2.0], Shape::new(vec![1]));
+        assert_eq!(a.eq(&two, &backend).unwrap().data_f32(), &[0.0, 1.0, 0.0]);
+        assert_eq!(a.ne(&two, &backend).unwrap().data_f32(), &[1.0, 0.0, 1.0]);
+        assert_eq!(a.lt(&two, &backend).unwrap().data_f32(), &[1.0, 0.0, 0.0]);
+        assert_eq!(a.le(&two, &backend).unwrap().data_f32(), &[1.0, 1.0, 0.0]);
+        assert_eq!(a.gt(&two, &backend).unwrap().data_f32(), &[0.0, 0.0, 1.0]);
+        assert_eq!(a.ge(&two, &backend).unwrap().data_f32(), &[0.0, 1.0, 1.0]);
+
+        // Causal mask: key position j is visible from query i when j <= i.
+        let q = arange(&[3]).reshape(Shape::new(vec![3, 1])).unwrap();
+        let k = arange(&[3]);
+        let mask = k.le(&q, &backend).unwrap();
+        assert_eq!(
+            mask.data_f32(),
+            &[1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_binary_errors() {
+        let backend = CpuBackend::new();
+        let a = arange(&[2, 3]);
+        assert!(a.add(&arange(&[2]), &backend).is_err());
+        let q = Tensor::from_storage(
+            CpuStorage::zeros(DType::Q8_0, 32).unwrap(),
+            Shape::new(vec![32]),
+        )
+        .unwrap();
+        assert!(q.add(&q, &backend).is_err());
+    }
+
+    #[test]
+    fn test_binary_threaded_and_empty() {
+        let single = CpuBackend::with_threads(1).unwrap();
+        let multi = CpuBackend::with_threads(3).unwrap();
+        let a = arange(&[5, 7, 3]).permute(&[1, 0, 2]).unwrap();
+        let b = arange(&[5, 1]);
+        let want = a.add(&b, &single).unwrap();
+        assert_eq!(want.data_f32(), a.add(&b, &multi).unwrap().data_f32());
+
+        let e = Tensor::zeros(Shape::new(vec![0, 3]));
+        assert_eq!(
+            e.add(&arange(&[3]), &single).unwrap().shape().dims(),
+            &[0, 3]
+        );
+    }
 }
@@ -0,0 +1,66 @@
+//! Helpers shared by the unit tests in this crate.
+
+use crate::cpu::quant::quantize_row_q8_0;
+use crate::dtype::DType;
+use crate::shape::Shape;
+use crate::storage::{ByteBuffer, CpuStorage};
+use crate::tensor::Tensor;
+
+/// Deterministic pseudo-random values in [-1, 1).
+pub fn values(n: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed;
+    (0..n)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+        })
+        .collect()
+}
+
+/// A `[rows, cols]` F32 weight filled with [`values`].
+pub fn f32_weight(rows: usize, cols: usize, seed: u32) -> Tensor {
+    Tensor::new(values(rows * cols, seed), Shape::new(vec![rows, cols]))
+}
+
+/// A `[rows, cols]` Q8_0 weight quantized from [`values`].
+pub fn q8_0_weight(rows: usize, cols: usize, seed: u32) -> Tensor {
+    let mut raw = vec![0u8; DType::Q8_0.storage_size(rows * cols)];
+    quantize_row_q8_0(&values(rows * cols, seed), &mut raw);
+    let storage = CpuStorage::from_raw(DType::Q8_0, ByteBuffer::from_vec(raw)).unwrap();
+    Tensor::from_storage(storage, Shape::new(vec![rows, cols])).unwrap()
+}
+
+/// A `[rows, cols]` F16 weight rounded from [`values`].
+pub fn f16_weight(rows: usize, cols: usize, seed: u32) -> Tensor {
+    let raw = values(rows * cols, seed)
+        .into_iter()
+        .flat_map(|v| half::f16::from_f32(v).to_le_bytes())
+        .collect();
+    let storage = CpuStorage::from_raw(DType::F16, ByteBuffer::from_vec(raw)).unwrap();
+    Tensor::from_storage(storage, Shape::new(vec![rows, cols])).unwrap()
+}
+
+/// Asserts that `got` matches `want` element-wise to within 1e-5.
+pub fn assert_close(got: &[f32], want: &[f32]) {
+    assert_eq!(got.len(), want.len());
+    for (i, (g, w)) in got.iter().zip(want).enumerate() {
+        assert!((g - w).abs() < 1e-5, "element {}: got {} want {}", i, g, w);
+    }
+}
+
+/// Asserts that `got` matches `want` element-wise to within `tol`, scaled by
+/// the magnitude of `want` above 1. `what` labels the failure message.
+pub fn assert_close_rel(got: &[f32], want: &[f32], tol: f32, what: &str) {
+    assert_eq!(got.len(), want.len());
+    for (i, (g, w)) in got.iter().zip(want).enumerate() {
+        let err = (g - w).abs();
+        assert!(
+            err <= tol * w.abs().max(1.0),
+            "{}: element {} got {} want {}",
+            what,
+            i,
+            g,
+            w
+        );
+    }
+}
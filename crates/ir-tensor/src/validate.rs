@@ -0,0 +1,700 @@
+//! Cross-checking one backend against another.
+//!
+//! [`ValidatingBackend`] is itself a [`ComputeBackend`]: every op runs on a
+//! primary backend and on a reference backend, the reference writing into a
+//! copy of the output. The primary's results are what the caller sees; the
+//! reference's are only compared against them, element by element, within a
+//! [`Tolerance`]. The first op whose outputs differ is recorded as a
+//! [`Divergence`] and handled as the configured [`OnDivergence`] says.
+//!
+//! Validation copies every output, so it is meant for tests and debugging,
+//! not for the decode loop.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::backend::{
+    AttentionParams, BinaryOp, Capabilities, ComputeBackend, KvView, KvViewF16, MatmulParams,
+    ReduceOp,
+};
+use crate::dtype::DType;
+use crate::error::{Result, TensorError};
+use crate::layout::Layout;
+use crate::rope::RopeTable;
+
+/// How far a primary output may be from the reference output.
+///
+/// An element passes if `|got - want| <= abs + rel * |want|`. Two NaNs, or
+/// two infinities of the same sign, compare equal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// Absolute slack.
+    pub abs: f32,
+    /// Slack relative to the magnitude of the reference value.
+    pub rel: f32,
+}
+
+impl Tolerance {
+    /// A tolerance with the given absolute and relative slack.
+    pub const fn new(abs: f32, rel: f32) -> Self {
+        Tolerance { abs, rel }
+    }
+
+    /// Bit-for-bit agreement (up to NaN payloads).
+    pub const fn exact() -> Self {
+        Tolerance::new(0.0, 0.0)
+    }
+
+    /// Whether `got` is close enough to `want`.
+    pub fn allows(&self, got: f32, want: f32) -> bool {
+        if got == want || (got.is_nan() && want.is_nan()) {
+            return true;
+        }
+        (got - want).abs() <= self.abs + self.rel * want.abs()
+    }
+}
+
+impl Default for Tolerance {
+    /// Loose enough for reordered f32 sums and SIMD approximations of
+    /// `exp`, tight enough to catch a wrong index or a missing term.
+    fn default() -> Self {
+        Tolerance::new(1e-4, 1e-3)
+    }
+}
+
+/// What a [`ValidatingBackend`] does when the backends disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OnDivergence {
+    /// Panic with the divergence report.
+    #[default]
+    Panic,
+    /// Print the first divergence to stderr and carry on with the primary's
+    /// results. Later divergences are only counted.
+    Log,
+    /// Return the report as a `TensorError` from the op.
+    Error,
+}
+
+/// One op on which the two backends disagreed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Name of the backend op, e.g. `"matmul"`.
+    pub op: &'static str,
+    /// Names and shapes of the op's inputs.
+    pub inputs: Vec<(&'static str, Vec<usize>)>,
+    /// Largest `|got - want|` over the outputs.
+    pub max_abs_error: f32,
+    /// Largest `|got - want| / |want|` over the outputs.
+    pub max_rel_error: f32,
+    /// Output element with the largest error, counting across all outputs
+    /// of the op in order. `None` if one backend returned an error.
+    pub index: Option<usize>,
+    /// The error one backend returned while the other succeeded.
+    pub failure: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.op)?;
+        for (i, (name, dims)) in self.inputs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}={:?}", name, dims)?;
+        }
+        write!(f, ")")?;
+        match (&self.failure, self.index) {
+            (Some(failure), _) => write!(f, ": {}", failure),
+            (None, Some(index)) => write!(
+                f,
+                ": max abs error {:e}, max rel error {:e}, worst at element {}",
+                self.max_abs_error, self.max_rel_error, index
+            ),
+            (None, None) => write!(f, ": outputs differ"),
+        }
+    }
+}
+
+/// A backend that runs every op on `A` and on the reference `B` and checks
+/// that they agree.
+///
+/// ```
+/// use ir_tensor::{ComputeBackend, CpuBackend, SimdLevel, ValidatingBackend};
+///
+/// let scalar = CpuBackend::with_threads(1).unwrap().with_simd_level(SimdLevel::Scalar).unwrap();
+/// let backend = ValidatingBackend::new(CpuBackend::new(), scalar);
+/// let y = backend.silu(&[-1.0, 0.0, 2.0]).unwrap();
+/// assert_eq!(y.len(), 3);
+/// assert!(backend.first_divergence().is_none());
+/// ```
+#[derive(Debug)]
+pub struct ValidatingBackend<A, B> {
+    primary: A,
+    reference: B,
+    tolerance: Tolerance,
+    on_divergence: OnDivergence,
+    name: String,
+    first: Mutex<Option<Divergence>>,
+    count: AtomicUsize,
+}
+
+impl<A: ComputeBackend, B: ComputeBackend> ValidatingBackend<A, B> {
+    /// Validate `primary` against `reference` with the default tolerance,
+    /// panicking on the first divergence.
+    pub fn new(primary: A, reference: B) -> Self {
+        let name = format!("validating({}, {})", primary.name(), reference.name());
+        ValidatingBackend {
+            primary,
+            reference,
+            tolerance: Tolerance::default(),
+            on_divergence: OnDivergence::default(),
+            name,
+            first: Mutex::new(None),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Set the tolerance outputs are compared with.
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set what happens when the backends disagree.
+    pub fn on_divergence(mut self, action: OnDivergence) -> Self {
+        self.on_divergence = action;
+        self
+    }
+
+    /// The backend whose results are returned.
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// The backend results are checked against.
+    pub fn reference(&self) -> &B {
+        &self.reference
+    }
+
+    /// The tolerance outputs are compared with.
+    pub fn tolerance(&self) -> Tolerance {
+        self.tolerance
+    }
+
+    /// The first divergence seen, if any.
+    pub fn first_divergence(&self) -> Option<Divergence> {
+        self.first.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Number of ops on which the backends have disagreed.
+    pub fn divergence_count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Compare the results of running an op on both backends.
+    ///
+    /// `outputs` pairs each primary output with the reference's copy. When
+    /// both backends fail, the primary's error is returned unchanged.
+    fn check(
+        &self,
+        op: &'static str,
+        inputs: impl FnOnce() -> Vec<(&'static str, Vec<usize>)>,
+        primary: Result<()>,
+        reference: Result<()>,
+        outputs: &[(&[f32], &[f32])],
+    ) -> Result<()> {
+        let failure = match (&primary, reference) {
+            (Err(_), Err(_)) => return primary,
+            (Ok(()), Err(e)) => Some(format!("reference backend failed: {}", e)),
+            (Err(e), Ok(())) => Some(format!("primary backend failed: {}", e)),
+            (Ok(()), Ok(())) => None,
+        };
+
+        let divergence = if let Some(failure) = failure {
+            Divergence {
+                op,
+                inputs: inputs(),
+                max_abs_error: f32::INFINITY,
+                max_rel_error: f32::INFINITY,
+                index: None,
+                failure: Some(failure),
+            }
+        } else {
+            let mut diverged = false;
+            let (mut max_abs, mut max_rel, mut worst) = (0.0f32, 0.0f32, 0);
+            let pairs = outputs
+                .iter()
+                .flat_map(|(got, want)| got.iter().zip(want.iter()));
+            for (i, (&got, &want)) in pairs.enumerate() {
+                if self.tolerance.allows(got, want) {
+                    continue;
+                }
+                // NaN against a number counts as an infinite error.
+                let abs = (got - want).abs();
+                let abs = if abs.is_nan() { f32::INFINITY } else { abs };
+                if !diverged || abs > max_abs {
+                    max_abs = abs;
+                    worst = i;
+                }
+                max_rel = max_rel.max(abs / want.abs());
+                diverged = true;
+            }
+            if !diverged {
+                return primary;
+            }
+            Divergence {
+                op,
+                inputs: inputs(),
+                max_abs_error: max_abs,
+                max_rel_error: max_rel,
+                index: Some(worst),
+                failure: None,
+            }
+        };
+
+        self.report(divergence)?;
+        primary
+    }
+
+    fn report(&self, divergence: Divergence) -> Result<()> {
+        let is_first = self.count.fetch_add(1, Ordering::Relaxed) == 0;
+        {
+            let mut first = self.first.lock().unwrap_or_else(|e| e.into_inner());
+            if first.is_none() {
+                *first = Some(divergence.clone());
+            }
+        }
+        match self.on_divergence {
+            OnDivergence::Panic => panic!("{}: backends diverged on {}", self.name, divergence),
+            OnDivergence::Log => {
+                if is_first {
+                    eprintln!("{}: backends diverged on {}", self.name, divergence);
+                }
+                Ok(())
+            }
+            OnDivergence::Error => Err(TensorError::Other(format!(
+                "backends diverged on {}",
+                divergence
+            ))),
+        }
+    }
+}
+
+/// `[len / row_len, row_len]`, the shape of a batch of rows.
+fn rows(len: usize, row_len: usize) -> Vec<usize> {
+    vec![len / row_len.max(1), row_len]
+}
+
+impl<A: ComputeBackend, B: ComputeBackend> ComputeBackend for ValidatingBackend<A, B> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Only what both backends support, since every op runs on both.
+    fn capabilities(&self) -> Capabilities {
+        self.primary
+            .capabilities()
+            .intersect(&self.reference.capabilities())
+    }
+
+    fn matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.matmul_into(a, b, out, m, k, n);
+        let r = self.reference.matmul_into(a, b, &mut want, m, k, n);
+        let inputs = || vec![("a", vec![m, k]), ("b", vec![k, n])];
+        self.check("matmul", inputs, p, r, &[(out, &want)])
+    }
+
+    fn batched_matmul_into(
+        &self,
+        a: &[f32],
+        b: &[f32],
+        out: &mut [f32],
+        params: &MatmulParams,
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.batched_matmul_into(a, b, out, params);
+        let r = self.reference.batched_matmul_into(a, b, &mut want, params);
+        let inputs = || {
+            let (m, k, n) = (params.m, params.k, params.n);
+            let b_dims = if params.transpose_b { [n, k] } else { [k, n] };
+            vec![
+                ("a", vec![params.a_batch, m, k]),
+                ("b", vec![params.b_batch, b_dims[0], b_dims[1]]),
+            ]
+        };
+        self.check("batched_matmul", inputs, p, r, &[(out, &want)])
+    }
+
+    fn matmul_quantized_into(
+        &self,
+        w: &[u8],
+        dtype: DType,
+        x: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.matmul_quantized_into(w, dtype, x, out, m, k);
+        let r = self
+            .reference
+            .matmul_quantized_into(w, dtype, x, &mut want, m, k);
+        let inputs = || vec![("w", vec![m, k]), ("x", vec![k])];
+        self.check("matmul_quantized", inputs, p, r, &[(out, &want)])
+    }
+
+    fn matmul_f16_into(
+        &self,
+        a: &[u8],
+        b: &[f32],
+        out: &mut [f32],
+        m: usize,
+        k: usize,
+        n: usize,
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.matmul_f16_into(a, b, out, m, k, n);
+        let r = self.reference.matmul_f16_into(a, b, &mut want, m, k, n);
+        let inputs = || vec![("a", vec![m, k]), ("b", vec![k, n])];
+        self.check("matmul_f16", inputs, p, r, &[(out, &want)])
+    }
+
+    fn add_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        let mut want = a.to_vec();
+        let p = self.primary.add_inplace(a, b);
+        let r = self.reference.add_inplace(&mut want, b);
+        let inputs = || vec![("a", vec![want.len()]), ("b", vec![b.len()])];
+        self.check("add", inputs, p, r, &[(a, &want)])
+    }
+
+    fn mul_inplace(&self, a: &mut [f32], b: &[f32]) -> Result<()> {
+        let mut want = a.to_vec();
+        let p = self.primary.mul_inplace(a, b);
+        let r = self.reference.mul_inplace(&mut want, b);
+        let inputs = || vec![("a", vec![want.len()]), ("b", vec![b.len()])];
+        self.check("mul", inputs, p, r, &[(a, &want)])
+    }
+
+    fn scale_inplace(&self, a: &mut [f32], s: f32) -> Result<()> {
+        let mut want = a.to_vec();
+        let p = self.primary.scale_inplace(a, s);
+        let r = self.reference.scale_inplace(&mut want, s);
+        let inputs = || vec![("a", vec![want.len()])];
+        self.check("scale", inputs, p, r, &[(a, &want)])
+    }
+
+    fn binary_into(
+        &self,
+        op: BinaryOp,
+        a: &[f32],
+        a_layout: &Layout,
+        b: &[f32],
+        b_layout: &Layout,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.binary_into(op, a, a_layout, b, b_layout, out);
+        let r = self
+            .reference
+            .binary_into(op, a, a_layout, b, b_layout, &mut want);
+        let inputs = || {
+            vec![
+                ("a", a_layout.shape().dims().to_vec()),
+                ("b", b_layout.shape().dims().to_vec()),
+            ]
+        };
+        self.check("binary", inputs, p, r, &[(out, &want)])
+    }
+
+    fn reduce_into(
+        &self,
+        op: ReduceOp,
+        x: &[f32],
+        layout: &Layout,
+        dim: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.reduce_into(op, x, layout, dim, out);
+        let r = self.reference.reduce_into(op, x, layout, dim, &mut want);
+        let inputs = || vec![("x", layout.shape().dims().to_vec()), ("dim", vec![dim])];
+        self.check("reduce", inputs, p, r, &[(out, &want)])
+    }
+
+    fn rms_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.rms_norm_into(x, weight, eps, hidden_size, out);
+        let r = self
+            .reference
+            .rms_norm_into(x, weight, eps, hidden_size, &mut want);
+        let inputs = || {
+            vec![
+                ("x", rows(x.len(), hidden_size)),
+                ("weight", vec![weight.len()]),
+            ]
+        };
+        self.check("rms_norm", inputs, p, r, &[(out, &want)])
+    }
+
+    fn softmax_into(&self, x: &[f32], n_vocab: usize, out: &mut [f32]) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.softmax_into(x, n_vocab, out);
+        let r = self.reference.softmax_into(x, n_vocab, &mut want);
+        let inputs = || vec![("x", rows(x.len(), n_vocab))];
+        self.check("softmax", inputs, p, r, &[(out, &want)])
+    }
+
+    fn rope_inplace(
+        &self,
+        q: &mut [f32],
+        k: &mut [f32],
+        rope: &RopeTable,
+        pos: usize,
+        n_heads_q: usize,
+        n_heads_k: usize,
+    ) -> Result<()> {
+        let mut want_q = q.to_vec();
+        let mut want_k = k.to_vec();
+        let p = self
+            .primary
+            .rope_inplace(q, k, rope, pos, n_heads_q, n_heads_k);
+        let r =
+            self.reference
+                .rope_inplace(&mut want_q, &mut want_k, rope, pos, n_heads_q, n_heads_k);
+        let inputs = || {
+            let head_dim = rope.config().head_dim;
+            vec![
+                ("q", rows(want_q.len(), head_dim)),
+                ("k", rows(want_k.len(), head_dim)),
+            ]
+        };
+        self.check("rope", inputs, p, r, &[(q, &want_q), (k, &want_k)])
+    }
+
+    fn attention_into(
+        &self,
+        q: &[f32],
+        kv: KvView<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.attention_into(q, kv, q_pos, params, out);
+        let r = self
+            .reference
+            .attention_into(q, kv, q_pos, params, &mut want);
+        let inputs = || {
+            let q_dim = (params.n_heads * params.head_dim).max(1);
+            vec![
+                ("q", vec![q.len() / q_dim, params.n_heads, params.head_dim]),
+                ("kv", vec![kv.n_positions, params.kv_dim()]),
+            ]
+        };
+        self.check("attention", inputs, p, r, &[(out, &want)])
+    }
+
+    fn attention_f16_into(
+        &self,
+        q: &[f32],
+        kv: KvViewF16<'_>,
+        q_pos: usize,
+        params: &AttentionParams,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self.primary.attention_f16_into(q, kv, q_pos, params, out);
+        let r = self
+            .reference
+            .attention_f16_into(q, kv, q_pos, params, &mut want);
+        let inputs = || {
+            let q_dim = (params.n_heads * params.head_dim).max(1);
+            vec![
+                ("q", vec![q.len() / q_dim, params.n_heads, params.head_dim]),
+                ("kv", vec![kv.n_positions, params.kv_dim()]),
+            ]
+        };
+        self.check("attention_f16", inputs, p, r, &[(out, &want)])
+    }
+
+    fn silu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let mut want = x.to_vec();
+        let p = self.primary.silu_inplace(x);
+        let r = self.reference.silu_inplace(&mut want);
+        let inputs = || vec![("x", vec![want.len()])];
+        self.check("silu", inputs, p, r, &[(x, &want)])
+    }
+
+    fn gelu_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let mut want = x.to_vec();
+        let p = self.primary.gelu_inplace(x);
+        let r = self.reference.gelu_inplace(&mut want);
+        let inputs = || vec![("x", vec![want.len()])];
+        self.check("gelu", inputs, p, r, &[(x, &want)])
+    }
+
+    fn gelu_erf_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let mut want = x.to_vec();
+        let p = self.primary.gelu_erf_inplace(x);
+        let r = self.reference.gelu_erf_inplace(&mut want);
+        let inputs = || vec![("x", vec![want.len()])];
+        self.check("gelu_erf", inputs, p, r, &[(x, &want)])
+    }
+
+    fn geglu_inplace(&self, gate: &mut [f32], up: &[f32]) -> Result<()> {
+        let mut want = gate.to_vec();
+        let p = self.primary.geglu_inplace(gate, up);
+        let r = self.reference.geglu_inplace(&mut want, up);
+        let inputs = || vec![("gate", vec![want.len()]), ("up", vec![up.len()])];
+        self.check("geglu", inputs, p, r, &[(gate, &want)])
+    }
+
+    fn relu2_inplace(&self, x: &mut [f32]) -> Result<()> {
+        let mut want = x.to_vec();
+        let p = self.primary.relu2_inplace(x);
+        let r = self.reference.relu2_inplace(&mut want);
+        let inputs = || vec![("x", vec![want.len()])];
+        self.check("relu2", inputs, p, r, &[(x, &want)])
+    }
+
+    fn softcap_inplace(&self, x: &mut [f32], cap: f32) -> Result<()> {
+        let mut want = x.to_vec();
+        let p = self.primary.softcap_inplace(x, cap);
+        let r = self.reference.softcap_inplace(&mut want, cap);
+        let inputs = || vec![("x", vec![want.len()])];
+        self.check("softcap", inputs, p, r, &[(x, &want)])
+    }
+
+    fn layer_norm_into(
+        &self,
+        x: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        eps: f32,
+        hidden_size: usize,
+        out: &mut [f32],
+    ) -> Result<()> {
+        let mut want = out.to_vec();
+        let p = self
+            .primary
+            .layer_norm_into(x, weight, bias, eps, hidden_size, out);
+        let r = self
+            .reference
+            .layer_norm_into(x, weight, bias, eps, hidden_size, &mut want);
+        let inputs = || {
+            vec![
+                ("x", rows(x.len(), hidden_size)),
+                ("weight", vec![weight.len()]),
+            ]
+        };
+        self.check("layer_norm", inputs, p, r, &[(out, &want)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CpuBackend, SimdLevel};
+
+    fn backend(action: OnDivergence) -> ValidatingBackend<CpuBackend, CpuBackend> {
+        let scalar = CpuBackend::with_threads(1)
+            .unwrap()
+            .with_simd_level(SimdLevel::Scalar)
+            .unwrap();
+        ValidatingBackend::new(CpuBackend::with_threads(2).unwrap(), scalar).on_divergence(action)
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let tol = Tolerance::new(1e-3, 1e-2);
+        assert!(tol.allows(1.0, 1.0105));
+        assert!(!tol.allows(1.0, 1.02));
+        assert!(tol.allows(f32::NAN, f32::NAN));
+        assert!(tol.allows(f32::INFINITY, f32::INFINITY));
+        assert!(!tol.allows(f32::NAN, 0.0));
+        assert!(!Tolerance::exact().allows(1.0, 1.0 + f32::EPSILON));
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let backend = backend(OnDivergence::Panic);
+        let a: Vec<f32> = (0..96).map(|i| (i as f32 * 0.37).sin()).collect();
+        let b: Vec<f32> = (0..48).map(|i| (i as f32 * 0.11).cos()).collect();
+        backend.matmul(&a, &b, 8, 12, 4).unwrap();
+        backend.softmax(&a, 12).unwrap();
+        backend.rms_norm(&a, &b[..12], 1e-5, 12).unwrap();
+        backend.silu(&a).unwrap();
+        assert_eq!(backend.divergence_count(), 0);
+        assert!(backend.first_divergence().is_none());
+    }
+
+    #[test]
+    fn test_reports_worst_element() {
+        let backend = backend(OnDivergence::Log);
+        let inputs = || vec![("x", vec![2, 2])];
+        let got = [1.0, 2.0, 3.5, 4.0];
+        let want = [1.0, 2.1, 3.0, 4.0];
+        backend
+            .check("test", inputs, Ok(()), Ok(()), &[(&got, &want)])
+            .unwrap();
+        let d = backend.first_divergence().unwrap();
+        assert_eq!(d.op, "test");
+        assert_eq!(d.inputs, vec![("x", vec![2, 2])]);
+        assert_eq!(d.index, Some(2));
+        assert_eq!(d.max_abs_error, 0.5);
+        assert!(d.to_string().starts_with("test(x=[2, 2]): max abs error"));
+
+        // Later divergences are counted but the first one is kept.
+        backend
+            .check("other", inputs, Ok(()), Ok(()), &[(&got, &want)])
+            .unwrap();
+        assert_eq!(backend.divergence_count(), 2);
+        assert_eq!(backend.first_divergence().unwrap().op, "test");
+    }
+
+    #[test]
+    fn test_error_mode() {
+        let backend = backend(OnDivergence::Error);
+        let inputs = Vec::new;
+        let failed = Err(TensorError::Other("boom".into()));
+        let err = backend
+            .check("op", inputs, Ok(()), failed, &[])
+            .unwrap_err();
+        assert!(err.to_string().contains("reference backend failed"));
+
+        // Both failing is not a divergence.
+        let both = backend.check(
+            "op",
+            inputs,
+            Err(TensorError::Other("a".into())),
+            Err(TensorError::Other("b".into())),
+            &[],
+        );
+        assert!(both.is_err());
+        assert_eq!(backend.divergence_count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "backends diverged on op")]
+    fn test_panic_mode() {
+        let backend = backend(OnDivergence::Panic);
+        let _ = backend.check("op", Vec::new, Ok(()), Ok(()), &[(&[1.0], &[2.0])]);
+    }
+}
@@ -66,6 +66,41 @@ typedef bool (*IRStreamCallback)(const char *token, void *user_data);
  */
 IRStatus ir_context_create(IRBackendType _backend, IRContext **ctx_out);
 
+/**
+ * Create a new inference context with an explicit CPU worker count.
+ *
+ * Behaves like `ir_context_create`, but the CPU backend runs on
+ * `n_threads` workers. Passing 0 uses one worker per available core.
+ * Returns `IRStatus::ErrorInternal` if the workers cannot be spawned.
+ *
+ * # Safety
+ *
+ * `ctx_out` must be a valid, non-null pointer to a `*mut IRContext`.
+ */
+IRStatus ir_context_create_with_threads(IRBackendType _backend,
+                                        uint32_t n_threads,
+                                        IRContext **ctx_out);
+
+/**
+ * Create a new inference context that composes several backends.
+ *
+ * Each op runs on the first of the `n_backends` backends in `backends`
+ * that supports it, and on a CPU backend (one worker per core) when none
+ * does, so a backend that implements only some ops can be listed ahead of
+ * the CPU. Passing no backends behaves like `ir_context_create`.
+ * Returns `IRStatus::ErrorInvalidArgument` if a listed backend is not
+ * available in this build.
+ *
+ * # Safety
+ *
+ * `backends` must point to `n_backends` valid `IRBackendType` values, or
+ * may be null when `n_backends` is 0. `ctx_out` must be a valid, non-null
+ * pointer to a `*mut IRContext`.
+ */
+IRStatus ir_context_create_with_backends(const IRBackendType *backends,
+                                         uintptr_t n_backends,
+                                         IRContext **ctx_out);
+
 /**
  * Destroy a context previously created by `ir_context_create`.
  *
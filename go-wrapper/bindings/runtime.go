@@ -71,6 +71,37 @@ func NewContext(backend BackendType) (*Context, error) {
 	return &Context{ctx: ctx}, nil
 }
 
+// NewContextWithThreads creates a new inference context whose CPU backend
+// uses nThreads workers. Zero uses one worker per available core.
+func NewContextWithThreads(backend BackendType, nThreads uint32) (*Context, error) {
+	var ctx *C.IRContext
+	status := C.ir_context_create_with_threads(C.IRBackendType(backend), C.uint32_t(nThreads), &ctx)
+	if status != C.IR_STATUS_OK {
+		return nil, fmt.Errorf("failed to create context: %s", LastError())
+	}
+	return &Context{ctx: ctx}, nil
+}
+
+// NewContextWithBackends creates a new inference context that runs each op
+// on the first of backends that supports it, falling back to the CPU when
+// none does. With no backends it behaves like NewContext(BackendCPU).
+func NewContextWithBackends(backends ...BackendType) (*Context, error) {
+	var ctx *C.IRContext
+	var cBackends *C.IRBackendType
+	if len(backends) > 0 {
+		types := make([]C.IRBackendType, len(backends))
+		for i, b := range backends {
+			types[i] = C.IRBackendType(b)
+		}
+		cBackends = &types[0]
+	}
+	status := C.ir_context_create_with_backends(cBackends, C.uintptr_t(len(backends)), &ctx)
+	if status != C.IR_STATUS_OK {
+		return nil, fmt.Errorf("failed to create context: %s", LastError())
+	}
+	return &Context{ctx: ctx}, nil
+}
+
 // Close destroys the underlying context and frees resources.
 func (c *Context) Close() {
 	if c.ctx != nil {